@@ -0,0 +1,90 @@
+//! Heap memory of counting k-mers with packed keys against sequence keys,
+//! measured by counting what the global allocator hands out. Protein k-mers
+//! are always stored as sequences, and ACGT are residues too, so counting the
+//! same bases as protein isolates the key layout. Run with
+//! `cargo bench --bench key_memory`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use papro_rusty::kmer::Alphabet;
+use papro_rusty::KmerCounter;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+impl CountingAllocator {
+    fn grew(&self, bytes: usize) {
+        let current = CURRENT.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        PEAK.fetch_max(current, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            self.grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            if new_size >= layout.size() {
+                self.grew(new_size - layout.size());
+            } else {
+                CURRENT.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+            }
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Distinct k-mers of `bases`, and the bytes a counter holds once it has
+/// counted them and at its peak while counting
+fn measure(k: usize, alphabet: Alphabet, bases: &[u8]) -> (usize, usize, usize) {
+    let before = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+
+    let counter = KmerCounter::new(k).with_alphabet(alphabet);
+    counter.count_sequence(bases).unwrap();
+    let held = CURRENT.load(Ordering::Relaxed) - before;
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+    (counter.unique_kmers(), held, peak)
+}
+
+fn main() {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let bases: Vec<u8> = (0..1_000_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            b"ACGT"[(state & 0b11) as usize]
+        })
+        .collect();
+
+    // k=21 packs into a u64 and k=33 into a u128
+    println!("k\tkeys\tunique_kmers\tbytes_held\tpeak_bytes\tbytes_per_kmer");
+    for k in [21, 33] {
+        let mut per_kmer = Vec::new();
+        for (alphabet, keys) in [(Alphabet::Dna, "packed"), (Alphabet::Protein, "sequence")] {
+            let (unique, held, peak) = measure(k, alphabet, &bases);
+            let bytes_per_kmer = held as f64 / unique as f64;
+            println!("{}\t{}\t{}\t{}\t{}\t{:.1}", k, keys, unique, held, peak, bytes_per_kmer);
+            per_kmer.push(bytes_per_kmer);
+        }
+        println!("k={}: sequence keys use {:.1}x the memory of packed keys", k, per_kmer[1] / per_kmer[0]);
+    }
+}
@@ -1,4 +1,6 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use std::io::Write;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -11,15 +13,30 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub verbose: bool,
 
-    /// Number of threads to use
+    /// Number of threads to use (default: one per CPU)
     #[arg(short, long, global = true)]
     pub threads: Option<usize>,
 
     /// Path to log file
     #[arg(long, global = true)]
     pub log_file: Option<PathBuf>,
+
+    /// Answer yes to every confirmation prompt (for unattended runs)
+    #[arg(short, long, global = true)]
+    pub yes: bool,
+
+    /// Only log warnings and errors, and hide progress bars
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// TOML file of default options; flags given on the command line take
+    /// precedence (default: .papro.toml in the working directory, if present)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
 }
 
+// Parsed once per run, like `DatabaseSubcommand`
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Create and manage reference profiles
@@ -27,6 +44,14 @@ pub enum Commands {
 
     /// Analyze samples against reference profiles
     Analyze(AnalyzeCommand),
+
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -40,6 +65,8 @@ pub struct DatabaseCommand {
     pub command: DatabaseSubcommand,
 }
 
+// Parsed once per run, so the size of the largest subcommand does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum DatabaseSubcommand {
 
@@ -48,10 +75,22 @@ pub enum DatabaseSubcommand {
 
     /// Create a new profile
     Create {
-        /// Input FASTA/FASTQ files
-        #[arg(required = true)]
+        /// Input FASTA/FASTQ files (BAM/SAM/CRAM with the `bam` feature)
+        #[arg(required_unless_present = "from_counts")]
         input_files: Vec<PathBuf>,
 
+        /// Build the profile from a `kmer<TAB>count` dump (`jellyfish dump -c`,
+        /// `kmc_dump`) instead of reading sequences; k is taken from the dump.
+        /// Count without canonical k-mers (no `jellyfish -C`, `kmc -b`) so
+        /// both orientations match samples.
+        #[arg(long, conflicts_with_all = [
+            "input_files", "kmer_size", "strict", "paired", "min_quality", "append",
+            "homopolymer_compress", "respect_masking", "expand_iupac", "alphabet",
+            "subsample_fraction", "subsample_seed", "max_reads", "max_kmers", "seed", "minimizer_window",
+            "region", "unmapped_only",
+        ])]
+        from_counts: Option<PathBuf>,
+
         /// K-mer size to use
         #[arg(short, long, default_value = "21")]
         kmer_size: usize,
@@ -67,6 +106,130 @@ pub enum DatabaseSubcommand {
         /// Skip existing files instead of erroring
         #[arg(long)]
         skip_existing: bool,
+
+        /// Drop sequences containing ambiguous bases instead of splitting them
+        #[arg(long)]
+        strict: bool,
+
+        /// Treat input files as consecutive R1/R2 pairs
+        #[arg(long)]
+        paired: bool,
+
+        /// Read an input file (or pair) every time it is given instead of once
+        #[arg(long)]
+        allow_duplicates: bool,
+
+        /// Minimum Phred base quality; lower-quality bases break k-mers (FASTQ only)
+        #[arg(long, default_value = "0")]
+        min_quality: u8,
+
+        /// Number of hashes in the stored MinHash sketch (0 disables sketching)
+        #[arg(long, default_value = "1000", conflicts_with = "append")]
+        sketch_size: usize,
+
+        /// Add the input files to an existing profile instead of erroring; the
+        /// profile keeps the count, entropy, sketch and Bloom filter settings
+        /// it was created with
+        #[arg(long)]
+        append: bool,
+
+        /// Drop k-mers seen fewer than this many times (likely sequencing errors)
+        #[arg(long, default_value = "1", conflicts_with = "append")]
+        min_count: usize,
+
+        /// Collapse homopolymer runs before k-mer extraction (for noisy long reads)
+        #[arg(long)]
+        homopolymer_compress: bool,
+
+        /// Skip soft-masked (lowercase) regions instead of counting them
+        #[arg(long)]
+        respect_masking: bool,
+
+        /// Count windows with IUPAC codes (R, Y, ...) as each k-mer they could be,
+        /// at fractional weight; windows with more than 16 expansions are skipped
+        #[arg(long, conflicts_with = "seed")]
+        expand_iupac: bool,
+
+        /// Residues of the input sequences; protein profiles only match protein samples
+        #[arg(long, value_enum, default_value = "dna")]
+        alphabet: Alphabet,
+
+        /// Keep this fraction of reads (or pairs), chosen deterministically by --subsample-seed
+        #[arg(long, default_value = "1.0")]
+        subsample_fraction: f64,
+
+        /// Seed for --subsample-fraction; the same seed keeps the same reads
+        #[arg(long, default_value = "0")]
+        subsample_seed: u64,
+
+        /// Stop after this many kept reads (or pairs) across all inputs
+        #[arg(long)]
+        max_reads: Option<usize>,
+
+        /// Read only alignments overlapping this region (e.g. `chr1:1000-2000`)
+        /// of indexed BAM/SAM/CRAM input
+        #[arg(long, conflicts_with = "paired")]
+        region: Option<String>,
+
+        /// Read only the unmapped reads of BAM/SAM/CRAM input
+        #[arg(long, conflicts_with = "paired")]
+        unmapped_only: bool,
+
+        /// Stop adding distinct k-mers past this many, so diverse or contaminated
+        /// input cannot exhaust memory; a warning flags the truncation
+        #[arg(long)]
+        max_kmers: Option<usize>,
+
+        /// Drop low-complexity k-mers whose base composition Shannon entropy (bits, 0-2) is below this
+        #[arg(long, default_value = "0.0", conflicts_with = "append")]
+        min_entropy: f64,
+
+        /// Weight k-mer frequencies by count (occurrence) or each distinct k-mer
+        /// equally (presence). Samples are compared with each profile the same way.
+        #[arg(long, value_enum, default_value = "occurrence", conflicts_with = "append")]
+        normalization: Normalization,
+
+        /// Free-text description stored with the profile
+        #[arg(long, conflicts_with = "append")]
+        description: Option<String>,
+
+        /// False-positive rate of the stored Bloom filter (0 disables it)
+        #[arg(long, default_value = "0.01", conflicts_with = "append")]
+        bloom_fp_rate: f64,
+
+        /// Spaced seed pattern (e.g. 1101011) with one 1 per k-mer base
+        #[arg(long)]
+        seed: Option<String>,
+
+        /// Store only the smallest k-mer of each window of this many consecutive
+        /// k-mers; larger windows store fewer k-mers at some loss of sensitivity
+        #[arg(short = 'w', long, conflicts_with_all = ["seed", "expand_iupac"])]
+        minimizer_window: Option<usize>,
+
+        /// Name of an existing parent profile at a broader taxonomy level
+        #[arg(long)]
+        parent: Option<String>,
+
+        /// Count and filter the input and print a summary of the profile
+        /// without writing it to the database
+        #[arg(long, conflicts_with_all = ["append", "from_counts"])]
+        dry_run: bool,
+
+        /// Save the counts after each input file (or pair) to
+        /// `<database>.<name>.k<k>.checkpoint`, so an interrupted build can
+        /// be continued with --resume; removed once the profile is stored
+        #[arg(long, conflicts_with_all = ["append", "from_counts", "dry_run"])]
+        checkpoint: bool,
+
+        /// Continue an interrupted --checkpoint build, skipping the inputs its
+        /// checkpoint covers; implies --checkpoint
+        #[arg(long, conflicts_with_all = ["append", "from_counts", "dry_run"])]
+        resume: bool,
+
+        /// Write a JSON manifest of the inputs, their checksums, the settings
+        /// and the stored profile's content hash to this file
+        #[arg(long, conflicts_with = "dry_run")]
+        manifest: Option<PathBuf>,
     },
 
     /// List profiles in database
@@ -85,11 +248,143 @@ pub enum DatabaseSubcommand {
         /// Name of profile to remove
         name: String,
 
-        /// Force removal without confirmation
+        /// K-mer size of the profile, for names profiled at several
+        #[arg(short, long)]
+        kmer_size: Option<usize>,
+
+        /// Force removal without confirmation (same as the global --yes)
         #[arg(short, long)]
         force: bool,
     },
 
+    /// Show metadata for a single profile
+    Info {
+        /// Profile name
+        name: String,
+
+        /// K-mer size of the profile, for names profiled at several
+        #[arg(short, long)]
+        kmer_size: Option<usize>,
+
+        /// Number of most and least frequent k-mers to show
+        #[arg(long, default_value = "5")]
+        top: usize,
+    },
+
+    /// Print a profile's k-mers by descending frequency
+    Kmers {
+        /// Profile name
+        name: String,
+
+        /// K-mer size of the profile, for names profiled at several
+        #[arg(short, long)]
+        kmer_size: Option<usize>,
+
+        /// Only print the N most frequent k-mers
+        #[arg(long)]
+        top: Option<usize>,
+
+        /// Only print k-mers with at least this frequency
+        #[arg(long, default_value = "0.0")]
+        min_freq: f64,
+    },
+
+    /// Rename a profile
+    Rename {
+        /// Current profile name
+        old: String,
+
+        /// New profile name
+        new: String,
+    },
+
+    /// Set the parent of a profile, checked as by link, or clear it
+    SetParent {
+        /// Profile to update
+        name: String,
+
+        /// Parent profile name (clears the parent if omitted)
+        parent: Option<String>,
+    },
+
+    /// Link a profile under its parent one or more taxonomy levels up
+    /// (Strain -> Species -> Genus), refusing links that would form a cycle
+    Link {
+        /// Child profile name
+        child: String,
+
+        /// Parent profile name, at a broader level than the child
+        parent: String,
+    },
+
+    /// Print the dissimilarity of two profiles' k-mer compositions
+    Distance {
+        /// First profile name
+        profile_a: String,
+
+        /// Second profile name
+        profile_b: String,
+
+        /// K-mer size of the profiles, for names profiled at several
+        #[arg(short, long)]
+        kmer_size: Option<usize>,
+
+        /// Dissimilarity to compute
+        #[arg(long, value_enum, default_value = "bray-curtis")]
+        metric: DistanceMetric,
+    },
+
+    /// Write pairwise distances between all profiles as a matrix
+    Matrix {
+        /// Output file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Dissimilarity to compute
+        #[arg(long, value_enum, default_value = "bray-curtis")]
+        metric: DistanceMetric,
+
+        /// Only include profiles at this taxonomic level
+        #[arg(short, long, value_enum)]
+        level: Option<TaxonomyLevel>,
+
+        /// Only include profiles built with this k-mer size
+        #[arg(short, long)]
+        kmer_size: Option<usize>,
+
+        /// Matrix format
+        #[arg(short, long, value_enum, default_value = "phylip")]
+        format: MatrixFormat,
+    },
+
+    /// Compare a profile, as a pseudo-sample of its stored k-mers, with every
+    /// other profile to spot redundant or mislabeled references
+    Crossval {
+        /// Profile name
+        name: String,
+
+        /// K-mer size of the profile, for names profiled at several
+        #[arg(short, long)]
+        kmer_size: Option<usize>,
+
+        /// Compare with profiles at this taxonomic level (default: the profile's own)
+        #[arg(short, long, value_enum)]
+        level: Option<TaxonomyLevel>,
+
+        /// Only print the N most similar profiles
+        #[arg(long, default_value = "5")]
+        top: usize,
+
+        /// Flag profiles holding at least this fraction of the profile's k-mers
+        /// as potential duplicates
+        #[arg(long, default_value = "0.9")]
+        duplicate_coverage: f64,
+
+        /// Decimal places of the printed scores (default: 6)
+        #[arg(long, value_name = "N")]
+        precision: Option<usize>,
+    },
+
     /// Export profiles
     Export {
         /// Names of profiles to export (exports all if none specified)
@@ -99,21 +394,105 @@ pub enum DatabaseSubcommand {
         #[arg(short, long)]
         output: PathBuf,
 
-        /// Export format (fasta or json)
+        /// Export format (fasta, tsv, json or mash)
         #[arg(short, long, value_enum, default_value = "fasta")]
         format: ExportFormat,
+
+        /// Only export profiles built with this k-mer size
+        #[arg(short, long)]
+        kmer_size: Option<usize>,
+
+        /// Decimal places of exported frequencies (default: 6); also rounds
+        /// JSON frequencies, which are otherwise written in full
+        #[arg(long, value_name = "N")]
+        precision: Option<usize>,
+    },
+
+    /// Merge all profiles from another database into this one
+    Merge {
+        /// Database to copy profiles from
+        other: PathBuf,
+
+        /// What to do when a profile name already exists
+        #[arg(long, value_enum, default_value = "rename")]
+        on_conflict: OnConflict,
+    },
+
+    /// Print the k-mer multiplicity spectrum of input files (no database needed)
+    Histogram {
+        /// Input FASTA/FASTQ files
+        #[arg(required = true)]
+        input_files: Vec<PathBuf>,
+
+        /// K-mer size to use
+        #[arg(short, long, default_value = "21")]
+        kmer_size: usize,
+
+        /// Highest multiplicity with its own row; higher counts share this row
+        #[arg(long, default_value = "100")]
+        max_bin: usize,
+
+        /// Emit plain TSV (numeric bins, no padding) for plotting
+        #[arg(long)]
+        tsv: bool,
     },
 
     /// Show database statistics
-    Stats,
+    Stats {
+        /// Include per-profile and k-mer level statistics
+        #[arg(long)]
+        detailed: bool,
+    },
+
+    /// Reclaim space after removals and refresh indices (VACUUM, ANALYZE, PRAGMA optimize)
+    Optimize {
+        /// Also index k-mers across profiles, for queries looking a k-mer up in
+        /// every profile; analysis does not use it, and it adds about 30% to
+        /// the database size
+        #[arg(long)]
+        kmer_index: bool,
+
+        /// Drop the index added by --kmer-index
+        #[arg(long, conflicts_with = "kmer_index")]
+        drop_kmer_index: bool,
+    },
+
+    /// Store DNA profiles' k-mers once for both strands (the smaller of each
+    /// k-mer and its reverse complement); profiles already canonical are skipped
+    Canonicalize {
+        /// Profile name (default: every DNA profile without a spaced seed)
+        name: Option<String>,
+
+        /// K-mer size of the profile, for names profiled at several
+        #[arg(short, long, requires = "name")]
+        kmer_size: Option<usize>,
+    },
+
+    /// Check a single profile's k-mers against its content hash
+    Verify {
+        /// Profile name
+        name: String,
+
+        /// K-mer size of the profile, for names profiled at several
+        #[arg(short, long)]
+        kmer_size: Option<usize>,
+    },
 
     /// Validate database integrity
-    Validate,
+    Validate {
+        /// Also fail when warnings are found
+        #[arg(long)]
+        strict: bool,
+
+        /// How far a profile's k-mer frequencies may sum from 1.0
+        #[arg(long, default_value = "0.01")]
+        freq_tolerance: f64,
+    },
 }
 
 #[derive(Parser, Debug)]
 pub struct AnalyzeCommand {
-    /// Input FASTA/FASTQ files to analyze
+    /// Input FASTA/FASTQ files to analyze (BAM/SAM/CRAM with the `bam` feature)
     #[arg(required = true)]
     pub input_files: Vec<PathBuf>,
 
@@ -129,25 +508,228 @@ pub struct AnalyzeCommand {
     #[arg(short, long, value_enum, default_value = "species")]
     pub level: TaxonomyLevel,
 
+    /// Compare only against these profiles (comma-separated), which must exist at --level
+    #[arg(long, value_delimiter = ',', conflicts_with = "hierarchical")]
+    pub profiles: Vec<String>,
+
+    /// Compare only against the profiles named in this file, one per line
+    #[arg(long, conflicts_with = "hierarchical")]
+    pub profiles_file: Option<PathBuf>,
+
     /// Minimum similarity score (0.0-1.0)
     #[arg(long, default_value = "0.80")]
     pub min_similarity: f64,
 
+    /// Similarity score used for the minimum threshold and ranking
+    #[arg(long, value_enum, default_value = "coverage")]
+    pub metric: Metric,
+
     /// Minimum number of shared k-mers
     #[arg(long, default_value = "100")]
     pub min_shared_kmers: usize,
 
+    /// Hold --min-shared-kmers to the abundance-weighted shared count, which
+    /// weights each shared k-mer by the smaller of its sample and profile frequency
+    #[arg(long)]
+    pub weighted_shared: bool,
+
     /// Generate detailed report
     #[arg(long)]
     pub detailed: bool,
 
+    /// Write each profile's detailed section to its own `{sample}_{profile}_detailed.tsv`
+    #[arg(long, requires = "detailed")]
+    pub split_detailed: bool,
+
+    /// Output file collecting every detailed section; JSON formats embed them instead
+    #[arg(long, default_value = "detailed.tsv", conflicts_with = "split_detailed")]
+    pub detailed_output: PathBuf,
+
+    /// Write the k-mers shared by every match, and each match's discriminating
+    /// k-mers, to this TSV file
+    #[arg(long)]
+    pub core_kmers: Option<PathBuf>,
+
+    /// Write, per sample, how many of its k-mers found in the database are
+    /// held by 1, 2, ... profiles to this TSV file
+    #[arg(long)]
+    pub specificity: Option<PathBuf>,
+
+    /// Write a JSON manifest of the inputs, their checksums, the database's
+    /// content hash and every setting to this file once the run completes
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+
+    /// Drop sequences containing ambiguous bases instead of splitting them
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Treat input files as consecutive R1/R2 pairs, one sample per pair
+    #[arg(long)]
+    pub paired: bool,
+
+    /// Analyze an input file (or pair) every time it is given instead of once
+    #[arg(long)]
+    pub allow_duplicates: bool,
+
+    /// Pool all input files into a single sample instead of one sample per file
+    #[arg(long)]
+    pub merge: bool,
+
+    /// Name used to label the sample in reports (default: first input file's stem)
+    #[arg(long)]
+    pub sample_name: Option<String>,
+
+    /// Minimum Phred base quality; lower-quality bases break k-mers (FASTQ only)
+    #[arg(long, default_value = "0")]
+    pub min_quality: u8,
+
+    /// Collapse homopolymer runs; must match how the profiles were built
+    #[arg(long)]
+    pub homopolymer_compress: bool,
+
+    /// Skip soft-masked (lowercase) regions instead of counting them
+    #[arg(long)]
+    pub respect_masking: bool,
+
+    /// Residues of the sample sequences; must match the profiles' alphabet
+    #[arg(long, value_enum, default_value = "dna")]
+    pub alphabet: Alphabet,
+
+    /// Keep this fraction of reads (or pairs), chosen deterministically by --subsample-seed
+    #[arg(long, default_value = "1.0")]
+    pub subsample_fraction: f64,
+
+    /// Seed for --subsample-fraction; the same seed keeps the same reads
+    #[arg(long, default_value = "0")]
+    pub subsample_seed: u64,
+
+    /// Stop after this many kept reads (or pairs) across all inputs
+    #[arg(long)]
+    pub max_reads: Option<usize>,
+
+    /// Read only alignments overlapping this region (e.g. `chr1:1000-2000`)
+    /// of indexed BAM/SAM/CRAM input
+    #[arg(long, conflicts_with = "paired")]
+    pub region: Option<String>,
+
+    /// Read only the unmapped reads of BAM/SAM/CRAM input
+    #[arg(long, conflicts_with = "paired")]
+    pub unmapped_only: bool,
+
+    /// Stop adding distinct k-mers past this many per sample, so diverse or
+    /// contaminated input cannot exhaust memory; a warning flags the truncation
+    #[arg(long)]
+    pub max_kmers: Option<usize>,
+
+    /// Pre-filter profiles by MinHash sketch before exact comparison
+    #[arg(long)]
+    pub sketch: bool,
+
+    /// Minimum estimated Jaccard similarity to pass the sketch filter
+    #[arg(long, default_value = "0.01")]
+    pub sketch_threshold: f64,
+
+    /// Load every profile's k-mers into memory once and compare all samples
+    /// from there; faster for many samples, at the cost of RAM
+    #[arg(long)]
+    pub in_memory: bool,
+
     /// Output file for sample information (TSV format)
     #[arg(long, default_value = "sample_info.tsv")]
     pub sample_info: PathBuf,
 
     /// Output file for matches summary (TSV format)
     #[arg(long, default_value = "matches.tsv")]
-    pub matches: PathBuf
+    pub matches: PathBuf,
+
+    /// Output format for the matches report
+    #[arg(short, long, value_enum, default_value = "text")]
+    pub format: OutputFormat,
+
+    /// Decimal places of frequencies and scores in the reports (default: 6);
+    /// also rounds JSON numbers, which are otherwise written in full
+    #[arg(long, value_name = "N")]
+    pub precision: Option<usize>,
+
+    /// Spaced seed pattern; must match the seed the profiles were built with
+    #[arg(long)]
+    pub seed: Option<String>,
+
+    /// Minimizer window; must match the window the profiles were built with
+    #[arg(short = 'w', long, conflicts_with = "seed")]
+    pub minimizer_window: Option<usize>,
+
+    /// Minimum containment (shared fraction of the smaller k-mer set, 0.0-1.0)
+    #[arg(long, default_value = "0.0")]
+    pub min_containment: f64,
+
+    /// Minimum confidence score for a match (0.0-1.0), applied alongside
+    /// --min-similarity, --min-shared-kmers and --min-containment
+    #[arg(long, default_value = "0.0")]
+    pub min_confidence: f64,
+
+    /// Flag matches whose GC fraction differs from the sample's by more than
+    /// this (0.0-1.0), a hint of contamination or a mislabeled reference
+    #[arg(long, default_value = "0.05")]
+    pub max_gc_diff: f64,
+
+    /// Fail instead of warning when profiles were built by an incompatible
+    /// papro-rusty version, or by one that predates version tracking
+    #[arg(long)]
+    pub strict_versions: bool,
+
+    /// Relative weight of sample coverage in the confidence score
+    #[arg(long, default_value = "1.0")]
+    pub weight_coverage: f64,
+
+    /// Relative weight of uniqueness (shared k-mers found in no other profile) in the confidence score
+    #[arg(long, default_value = "1.0")]
+    pub weight_uniqueness: f64,
+
+    /// Relative weight of sample/profile size agreement in the confidence score
+    #[arg(long, default_value = "1.0")]
+    pub weight_size: f64,
+
+    /// Score profiles only by k-mers unique to them at --level (for strain calls)
+    #[arg(long, conflicts_with = "hierarchical")]
+    pub markers_only: bool,
+
+    /// Skip the uniqueness lookups and score confidence from coverage and size
+    /// only; uniqueness is reported as NA (null in JSON)
+    #[arg(long, visible_alias = "no-uniqueness", conflicts_with_all = ["markers_only", "detailed", "core_kmers"])]
+    pub fast: bool,
+
+    /// Estimate each match's share of the sample, splitting shared k-mers by EM
+    #[arg(long, conflicts_with = "hierarchical")]
+    pub abundance: bool,
+
+    /// Rescore each match against this many resamples of the sample's k-mers
+    /// and report the mean and 95% interval of its coverage and confidence.
+    /// Each replicate costs about as much as the analysis itself.
+    #[arg(long, value_name = "N", conflicts_with_all = ["hierarchical", "markers_only"])]
+    pub bootstrap: Option<usize>,
+
+    /// Seed for --bootstrap; the same seed draws the same resamples
+    #[arg(long, default_value = "0", requires = "bootstrap")]
+    pub bootstrap_seed: u64,
+
+    /// Report the species and genus implied by each match's parent links,
+    /// with coverage pooled over the matches under each
+    #[arg(long, conflicts_with = "hierarchical")]
+    pub rollup: bool,
+
+    /// Classify from Strain up to Genus, rolling up ambiguous assignments
+    #[arg(long)]
+    pub hierarchical: bool,
+
+    /// Minimum confidence for a match to count during hierarchical classification
+    #[arg(long, default_value = "0.5")]
+    pub rollup_confidence: f64,
+
+    /// Only report the N highest-ranked matches per sample (0 reports all)
+    #[arg(long)]
+    pub top: Option<usize>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
@@ -161,13 +743,68 @@ pub enum TaxonomyLevel {
 pub enum ExportFormat {
     Fasta,
     Tsv,
+    Json,
+    /// MinHash sketch in the JSON layout of `mash info -d`, using Mash's hash
+    Mash,
+    /// All selected profiles in a single profiles.parquet file
+    Parquet,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum Metric {
+    Coverage,
+    Cosine,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum Alphabet {
+    Dna,
+    /// 20 standard amino acids; `*` and `X` break k-mers
+    Protein,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum Normalization {
+    /// Each k-mer's count over the total count
+    Occurrence,
+    /// Each distinct k-mer weighted equally, for presence/absence profiling
+    Presence,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum DistanceMetric {
+    BrayCurtis,
+    Jaccard,
+    Cosine,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum MatrixFormat {
+    /// Relaxed PHYLIP distance matrix
+    Phylip,
+    Tsv,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum OnConflict {
+    Skip,
+    Rename,
+    Error,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
 pub enum OutputFormat {
     Text,
     Json,
+    /// One JSON object per match, each on its own line (NDJSON)
+    JsonLines,
     Tsv,
+    /// Same columns as tsv, comma-separated with quoting
+    Csv,
+    /// Kraken2-style report (pct, clade k-mers, taxon k-mers, rank, name)
+    KrakenReport,
+    /// Krona text input for `ktImportText` (magnitude, sample, lineage)
+    Krona,
 }
 
 impl From<TaxonomyLevel> for crate::profile::TaxonomyLevel {
@@ -180,10 +817,66 @@ impl From<TaxonomyLevel> for crate::profile::TaxonomyLevel {
     }
 }
 
+impl From<Normalization> for crate::profile::Normalization {
+    fn from(normalization: Normalization) -> Self {
+        match normalization {
+            Normalization::Occurrence => Self::Occurrence,
+            Normalization::Presence => Self::Presence,
+        }
+    }
+}
+
+impl From<Metric> for crate::profile::SimilarityMetric {
+    fn from(metric: Metric) -> Self {
+        match metric {
+            Metric::Coverage => Self::Coverage,
+            Metric::Cosine => Self::Cosine,
+        }
+    }
+}
+
+impl From<Alphabet> for crate::kmer::Alphabet {
+    fn from(alphabet: Alphabet) -> Self {
+        match alphabet {
+            Alphabet::Dna => Self::Dna,
+            Alphabet::Protein => Self::Protein,
+        }
+    }
+}
+
+impl From<DistanceMetric> for crate::profile::DistanceMetric {
+    fn from(metric: DistanceMetric) -> Self {
+        match metric {
+            DistanceMetric::BrayCurtis => Self::BrayCurtis,
+            DistanceMetric::Jaccard => Self::Jaccard,
+            DistanceMetric::Cosine => Self::Cosine,
+        }
+    }
+}
+
+impl From<OnConflict> for crate::db::MergeConflict {
+    fn from(policy: OnConflict) -> Self {
+        match policy {
+            OnConflict::Skip => Self::Skip,
+            OnConflict::Rename => Self::Rename,
+            OnConflict::Error => Self::Error,
+        }
+    }
+}
+
+/// Write the completion script for `shell` to `writer`
+pub fn write_completions(shell: Shell, writer: &mut dyn Write) -> std::io::Result<()> {
+    // clap_complete panics on write errors, so render into memory first
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut script);
+    writer.write_all(&script)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use clap::CommandFactory;
 
     #[test]
     fn verify_cli() {
@@ -197,4 +890,31 @@ mod tests {
             crate::profile::TaxonomyLevel::Species
         ));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_completions_generate_for_each_shell() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let mut script = Vec::new();
+            write_completions(shell, &mut script).unwrap();
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("papro-rusty"), "{shell} script names the binary");
+            assert!(script.contains("analyze"), "{shell} script covers subcommands");
+        }
+    }
+
+    #[test]
+    fn test_quiet_conflicts_with_verbose() {
+        assert!(Cli::try_parse_from(["papro-rusty", "-q", "db", "stats"]).unwrap().quiet);
+        assert!(Cli::try_parse_from(["papro-rusty", "-q", "-v", "db", "stats"]).is_err());
+    }
+
+    #[test]
+    fn test_append_keeps_creation_settings() {
+        let create = ["papro-rusty", "db", "create", "-n", "Ecoli", "-l", "species", "--append", "reads.fa"];
+        assert!(Cli::try_parse_from(create).is_ok());
+        for flag in [["--min-count", "2"], ["--min-entropy", "1.0"], ["--sketch-size", "10"],
+                     ["--bloom-fp-rate", "0.1"], ["--description", "note"]] {
+            assert!(Cli::try_parse_from(create.iter().chain(&flag)).is_err(), "{} with --append", flag[0]);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Defaults read from a TOML config file.
+//!
+//! Precedence is command line, then config file, then the built-in default:
+//! a config value only replaces a flag the user did not pass.
+//!
+//! ```toml
+//! [analyze]
+//! kmer_size = 31
+//! min_similarity = 0.7
+//! min_shared_kmers = 100
+//!
+//! [db]
+//! database = "profiles.db"
+//! kmer_size = 31
+//! ```
+
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+use crate::cli::{AnalyzeCommand, Cli, Commands, DatabaseCommand, DatabaseSubcommand};
+
+/// Config file picked up from the working directory when `--config` is not given
+pub const DEFAULT_CONFIG_FILE: &str = ".papro.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub analyze: AnalyzeDefaults,
+    #[serde(default)]
+    pub db: DatabaseDefaults,
+}
+
+/// Defaults for `analyze`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnalyzeDefaults {
+    pub kmer_size: Option<usize>,
+    pub min_similarity: Option<f64>,
+    pub min_shared_kmers: Option<usize>,
+    pub weighted_shared: Option<bool>,
+    pub min_containment: Option<f64>,
+    pub min_confidence: Option<f64>,
+    pub weight_coverage: Option<f64>,
+    pub weight_uniqueness: Option<f64>,
+    pub weight_size: Option<f64>,
+}
+
+/// Defaults for `db`; `kmer_size` applies to `db create`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseDefaults {
+    pub database: Option<PathBuf>,
+    pub kmer_size: Option<usize>,
+}
+
+impl Config {
+    /// Read `path`, or `.papro.toml` in the working directory if it exists.
+    /// Returns `None` when there is no config to apply.
+    pub fn load(path: Option<&Path>) -> Result<Option<Self>> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => Path::new(DEFAULT_CONFIG_FILE),
+            None => return Ok(None),
+        };
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config = toml::from_str(&text)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        Ok(Some(config))
+    }
+
+    /// Fill in every option of `cli` that was not given on the command line.
+    /// `matches` must be the matches `cli` was parsed from.
+    pub fn apply(&self, cli: &mut Cli, matches: &ArgMatches) {
+        match (&mut cli.command, matches.subcommand()) {
+            (Commands::Analyze(cmd), Some(("analyze", matches))) => self.analyze.apply(cmd, matches),
+            (Commands::DB(cmd), Some(("db", matches))) => self.db.apply(cmd, matches),
+            _ => {}
+        }
+    }
+}
+
+impl AnalyzeDefaults {
+    fn apply(&self, cmd: &mut AnalyzeCommand, matches: &ArgMatches) {
+        set_default(&mut cmd.kmer_size, self.kmer_size, matches, "kmer_size");
+        set_default(&mut cmd.min_similarity, self.min_similarity, matches, "min_similarity");
+        set_default(&mut cmd.min_shared_kmers, self.min_shared_kmers, matches, "min_shared_kmers");
+        set_default(&mut cmd.weighted_shared, self.weighted_shared, matches, "weighted_shared");
+        set_default(&mut cmd.min_containment, self.min_containment, matches, "min_containment");
+        set_default(&mut cmd.min_confidence, self.min_confidence, matches, "min_confidence");
+        set_default(&mut cmd.weight_coverage, self.weight_coverage, matches, "weight_coverage");
+        set_default(&mut cmd.weight_uniqueness, self.weight_uniqueness, matches, "weight_uniqueness");
+        set_default(&mut cmd.weight_size, self.weight_size, matches, "weight_size");
+    }
+}
+
+impl DatabaseDefaults {
+    fn apply(&self, cmd: &mut DatabaseCommand, matches: &ArgMatches) {
+        set_default(&mut cmd.database, self.database.clone(), matches, "database");
+        // A counts dump carries its own k
+        if let (DatabaseSubcommand::Create { kmer_size, from_counts: None, .. }, Some(("create", matches))) =
+            (&mut cmd.command, matches.subcommand())
+        {
+            set_default(kmer_size, self.kmer_size, matches, "kmer_size");
+        }
+    }
+}
+
+/// Replace `field` with the config value unless the flag was passed
+fn set_default<T>(field: &mut T, value: Option<T>, matches: &ArgMatches, id: &str) {
+    if let Some(value) = value {
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            *field = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn parse(args: &[&str], config: &Config) -> Result<Cli> {
+        let matches = Cli::command().try_get_matches_from(args)?;
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        config.apply(&mut cli, &matches);
+        Ok(cli)
+    }
+
+    #[test]
+    fn test_config_fills_only_absent_flags() -> Result<()> {
+        let config: Config = toml::from_str(
+            "[analyze]\nmin_similarity = 0.5\nkmer_size = 31\n\n[db]\ndatabase = \"from_config.db\"\n"
+        )?;
+
+        let cli = parse(&["papro-rusty", "analyze", "-d", "p.db", "--min-similarity", "0.9", "s.fa"], &config)?;
+        let Commands::Analyze(cmd) = cli.command else { panic!("expected analyze") };
+        assert_eq!(cmd.min_similarity, 0.9);
+        assert_eq!(cmd.kmer_size, 31);
+        // Built-in default when neither sets it
+        assert_eq!(cmd.min_shared_kmers, 100);
+
+        let cli = parse(&["papro-rusty", "db", "list"], &config)?;
+        let Commands::DB(cmd) = cli.command else { panic!("expected db") };
+        assert_eq!(cmd.database, PathBuf::from("from_config.db"));
+
+        let cli = parse(&["papro-rusty", "db", "-d", "given.db", "list"], &config)?;
+        let Commands::DB(cmd) = cli.command else { panic!("expected db") };
+        assert_eq!(cmd.database, PathBuf::from("given.db"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("[analyze]\nmin_similarty = 0.5\n").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::io::{FastxReader, ReaderStats};
+use crate::kmer::{CounterSnapshot, KmerCounter};
+
+/// Counts of the inputs read so far by an interrupted profile build
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    /// Every input path of the build, to tell a checkpoint of other inputs apart
+    inputs: Vec<String>,
+    /// Inputs (files or R1/R2 pairs) counted so far, in reader order
+    completed: usize,
+    counter: CounterSnapshot,
+    stats: ReaderStats,
+}
+
+impl Checkpoint {
+    fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open checkpoint {}", path.display()))?;
+        bincode::deserialize_from(BufReader::new(file))
+            .with_context(|| format!("Checkpoint {} is unreadable; remove it to start over", path.display()))
+    }
+
+    /// Write next to `path` and rename over it, so an interruption mid-write
+    /// leaves the previous checkpoint intact
+    fn save(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("partial");
+        let mut writer = BufWriter::new(
+            File::create(&partial).with_context(|| format!("Failed to create checkpoint {}", partial.display()))?,
+        );
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+        std::fs::rename(&partial, path)
+            .with_context(|| format!("Failed to write checkpoint {}", path.display()))
+    }
+}
+
+/// Count the reader's inputs one at a time, saving the counter to `path`
+/// after each. With `resume`, a checkpoint already at `path` is restored and
+/// the inputs it covers are skipped. The checkpoint is left in place.
+pub(crate) fn count_with_checkpoint(
+    counter: &KmerCounter,
+    reader: &FastxReader,
+    path: &Path,
+    resume: bool,
+) -> Result<ReaderStats> {
+    if reader.selects_across_inputs() {
+        bail!("Subsampling and --max-reads pick reads across all inputs, so they cannot be checkpointed");
+    }
+    let inputs: Vec<String> = reader.paths().iter().map(|path| path.display().to_string()).collect();
+
+    let mut completed = 0;
+    let mut stats = ReaderStats::default();
+    if path.exists() {
+        if !resume {
+            bail!(
+                "Checkpoint {} is left from an interrupted build; pass --resume to continue it, or remove it",
+                path.display()
+            );
+        }
+        let checkpoint = Checkpoint::load(path)?;
+        if checkpoint.inputs != inputs {
+            bail!("Checkpoint {} was saved for other input files", path.display());
+        }
+        counter.restore(checkpoint.counter)
+            .with_context(|| format!("Checkpoint {} was saved for another k-mer size", path.display()))?;
+        completed = checkpoint.completed;
+        stats = checkpoint.stats;
+        info!("Resuming from {}: {} of {} inputs already counted", path.display(), completed, reader.input_count());
+    } else if resume {
+        warn!("No checkpoint at {}; counting every input", path.display());
+    }
+
+    for index in completed..reader.input_count() {
+        stats.files.extend(counter.count_from_reader(&reader.input(index))?.files);
+        Checkpoint {
+            inputs: inputs.clone(),
+            completed: index + 1,
+            counter: counter.snapshot(),
+            stats: stats.clone(),
+        }.save(path)?;
+    }
+    Ok(stats)
+}
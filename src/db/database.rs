@@ -1,14 +1,26 @@
-use rayon::iter::IntoParallelIterator;
-use rusqlite::{params, Connection, OptionalExtension};
-use anyhow::Result;
-use std::path::{Path, PathBuf};
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
 use log::{info, warn};
+use sha2::{Digest, Sha256};
 
-use super::schemas::initialize_schema;
-use super::types::{DatabaseStats, ProfileSummary};
-use crate::io::FastxReader;
-use crate::kmer::KmerCounter;
-use crate::profile::{Profile, TaxonomyLevel};
+use super::checkpoint::count_with_checkpoint;
+use super::schemas::{has_column, initialize_schema, OPTIONAL_PROFILE_COLUMNS};
+use super::types::{Canonicalization, DatabaseStats, MergeConflict, MergeSummary, OptimizeSummary, ProfileOptions, ProfileSummary};
+use crate::io::{read_kmer_counts, FastxReader, ReaderStats};
+use crate::kmer::{
+    canonical_counts, estimate_coverage, shannon_entropy, Alphabet, BloomFilter, KmerCounter, MinHashSketch, SpacedSeed,
+    MAX_IUPAC_EXPANSIONS,
+};
+use crate::profile::{Normalization, Profile, TaxonomyLevel, TOOL_VERSION};
+
+/// Rows per multi-row k-mer INSERT (3 parameters each, well under SQLite's limit)
+const KMER_INSERT_BATCH: usize = 500;
+
+/// A profile whose frequencies sum to less than 1 minus this (or minus the
+/// validation tolerance, if larger) has lost k-mers rather than drifted
+const LOST_KMERS_DEFICIT: f64 = 0.1;
 
 pub struct Database {
     conn: Connection,
@@ -18,145 +30,610 @@ impl Database {
     /// Create a new database or open existing one
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let conn = Connection::open(path)?;
+
+        // WAL avoids an fsync per transaction and lets readers run alongside
+        // a writer; NORMAL sync is safe with WAL. Negative cache size is in KiB.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "cache_size", -64_000)?;
+
         initialize_schema(&conn)?;
+        backfill_content_hashes(&conn)?;
         Ok(Database { conn })
     }
 
-    /// Create a profile from multiple FASTA/FASTQ files
+    /// Give up the database's connection, e.g. to analyze an in-memory
+    /// (`":memory:"`) database with `ProfileAnalyzer::from_connection`, which
+    /// cannot reopen it by path
+    pub fn into_connection(self) -> Connection {
+        self.conn
+    }
+
+    /// Create a profile from all files of a FASTA/FASTQ reader, keeping k-mers
+    /// seen at least `min_count` times with at least `min_entropy` bits of base
+    /// composition entropy, and storing a MinHash sketch alongside it
     pub fn create_profile(
         &mut self,
-        input_files: Vec<PathBuf>,
-        kmer_size: usize,
-        level: TaxonomyLevel,
+        reader: &FastxReader,
+        name: String,
+        options: &ProfileOptions,
+    ) -> Result<(Profile, ReaderStats)> {
+        let (profile, counts, stats) = count_profile(reader, name, options, None)?;
+        self.ensure_profile_absent(&profile.name, options.kmer_size)?;
+
+        let profile = self.insert_counted_profile(profile, counts, options)?;
+        Ok((profile, stats))
+    }
+
+    /// Like `create_profile`, but saving the counts to `checkpoint` after each
+    /// input file (or pair) so that an interrupted build can continue with
+    /// `resume` instead of starting over. The checkpoint is removed once the
+    /// profile is stored.
+    pub fn create_profile_with_checkpoint(
+        &mut self,
+        reader: &FastxReader,
+        name: String,
+        options: &ProfileOptions,
+        checkpoint: &Path,
+        resume: bool,
+    ) -> Result<(Profile, ReaderStats)> {
+        self.ensure_profile_absent(&name, options.kmer_size)?;
+        let (profile, counts, stats) = count_profile(reader, name, options, Some((checkpoint, resume)))?;
+
+        let profile = self.insert_counted_profile(profile, counts, options)?;
+        std::fs::remove_file(checkpoint)
+            .with_context(|| format!("Failed to remove checkpoint {}", checkpoint.display()))?;
+        Ok((profile, stats))
+    }
+
+    /// Build the profile `create_profile` would store, running the same
+    /// counting and filtering but writing nothing to the database
+    pub fn preview_profile(
+        &self,
+        reader: &FastxReader,
         name: String,
+        options: &ProfileOptions,
+    ) -> Result<(Profile, ReaderStats)> {
+        let (profile, counts, stats) = count_profile(reader, name, options, None)?;
+        Ok((build_profile(profile, counts, options), stats))
+    }
+
+    /// Create a profile from a `kmer<TAB>count` dump of an external counter
+    /// (Jellyfish, KMC) instead of reading sequences. `k` is taken from the
+    /// dump; `options.kmer_size` and `options.seed` are not used.
+    pub fn create_profile_from_counts(
+        &mut self,
+        path: &Path,
+        name: String,
+        options: &ProfileOptions,
     ) -> Result<Profile> {
-        // Initialize k-mer counter
-        let counter = KmerCounter::new(kmer_size);
-        
-        // Process all input files
-        info!("Processing {} input files...", input_files.len());
-        for (idx, file) in input_files.iter().enumerate() {
-            info!("Processing file {}/{}: {}", 
-                idx + 1, 
-                input_files.len(), 
-                file.display()
-            );
-            
-            let reader = FastxReader::new(vec![file.clone()]);
-            let mut sequences = Vec::new();
-            reader.process_all(|sequence, _id| {
-                sequences.push(sequence.to_vec());
-                Ok(())
-            })?;
+        let (kmer_size, counts) = read_kmer_counts(path)?;
+        self.ensure_profile_absent(&name, kmer_size)?;
+
+        let mut profile = Profile::new(name, options.level.clone(), kmer_size);
+        profile.source_files = vec![path.display().to_string()];
+
+        self.insert_counted_profile(profile, counts, options)
+    }
+
+    fn ensure_profile_absent(&self, name: &str, k: usize) -> Result<()> {
+        if self.profile_id(name, Some(k))?.is_some() {
+            return Err(anyhow::anyhow!("Profile {} already exists in database at k={}", name, k));
+        }
+        Ok(())
+    }
+
+    /// Id of the profile called `name`, at k-mer size `k` if given. A name
+    /// profiled at several k-mer sizes needs `k` to pick one of them.
+    fn profile_id(&self, name: &str, k: Option<usize>) -> Result<Option<i64>> {
+        let profiles: Vec<(i64, i64)> = self.conn
+            .prepare("SELECT id, k FROM profiles WHERE name = ?1 AND (?2 IS NULL OR k = ?2) ORDER BY k")?
+            .query_map(params![name, k], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        match profiles.as_slice() {
+            [] => Ok(None),
+            [(id, _)] => Ok(Some(*id)),
+            _ => {
+                let sizes: Vec<String> = profiles.iter().map(|(_, k)| k.to_string()).collect();
+                Err(anyhow::anyhow!(
+                    "Profile {} exists at k={}; select one with --kmer-size",
+                    name, sizes.join(", ")
+                ))
+            }
+        }
+    }
 
-            counter.count_sequences(sequences.into_par_iter())?;
+    /// Build a new profile from raw k-mer counts and insert it
+    fn insert_counted_profile(
+        &mut self,
+        profile: Profile,
+        counts: HashMap<String, f64>,
+        options: &ProfileOptions,
+    ) -> Result<Profile> {
+        let profile = build_profile(profile, counts, options);
+        if profile.frequencies.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Profile {} has no k-mers to store: none were counted, or --min-count/--min-entropy removed them all",
+                profile.name
+            ));
         }
+        self.add_profile(&profile)?;
+        Ok(profile)
+    }
 
-        info!("Found {} unique k-mers across all files", counter.unique_kmers());
+    /// Add k-mers from new input files to an existing profile. Stored counts
+    /// are re-derived from frequency × total_kmers, merged with the new counts,
+    /// and the profile's frequencies, total and sketch are rewritten. The
+    /// profile's own count and entropy filters apply to the merged counts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_profile(
+        &mut self,
+        reader: &FastxReader,
+        name: &str,
+        level: TaxonomyLevel,
+        kmer_size: usize,
+        seed: Option<&SpacedSeed>,
+        minimizer_window: Option<usize>,
+        max_unique_kmers: Option<usize>,
+    ) -> Result<(Profile, ReaderStats)> {
+        let mut profile = self.get_profile(name, Some(kmer_size))?
+            .ok_or_else(|| anyhow::anyhow!("Profile {} not found at k={}", name, kmer_size))?;
+
+        if profile.level != level {
+            return Err(anyhow::anyhow!(
+                "Level mismatch: profile {} is a {} profile, requested {}",
+                name, profile.level, level
+            ));
+        }
 
-        // Check if profile already exists
-        let exists: bool = self.conn.query_row(
-            "SELECT 1 FROM profiles WHERE name = ?",
-            params![&name],
-            |_| Ok(true)
-        ).unwrap_or(false);
+        if profile.seed.as_deref() != seed.map(SpacedSeed::pattern) {
+            return Err(anyhow::anyhow!(
+                "Seed mismatch: profile {} has seed {}, requested seed {}",
+                name,
+                profile.seed.as_deref().unwrap_or("none"),
+                seed.map(SpacedSeed::pattern).unwrap_or("none")
+            ));
+        }
+
+        if profile.minimizer_window != minimizer_window {
+            return Err(anyhow::anyhow!(
+                "Minimizer window mismatch: profile {} has window {}, requested window {}",
+                name,
+                format_minimizer_window(profile.minimizer_window),
+                format_minimizer_window(minimizer_window)
+            ));
+        }
+
+        if profile.homopolymer_compressed != reader.compresses_homopolymers() {
+            return Err(anyhow::anyhow!(
+                "Homopolymer compression mismatch: profile {} was built {} it",
+                name,
+                if profile.homopolymer_compressed { "with" } else { "without" }
+            ));
+        }
+
+        if profile.alphabet != reader.sequence_alphabet() {
+            return Err(anyhow::anyhow!(
+                "Alphabet mismatch: profile {} is {}, input was read as {}",
+                name, profile.alphabet, reader.sequence_alphabet()
+            ));
+        }
+
+        // Equal weights keep no trace of the counts the new input would add to
+        if profile.normalization == Normalization::Presence {
+            return Err(anyhow::anyhow!(
+                "Profile {} has presence-normalized frequencies, from which its counts cannot be recovered; \
+                 rebuild it from all of its input files instead",
+                name
+            ));
+        }
+
+        let (counter, stats) = count_input(reader, kmer_size, seed, minimizer_window, max_unique_kmers, None)?;
+        let mut new_counts = counter.get_weighted_counts();
+        if profile.canonical {
+            new_counts = canonical_counts(new_counts);
+        }
+        if let Some(min_entropy) = profile.min_entropy {
+            drop_low_complexity(&mut new_counts, min_entropy);
+        }
 
-        if exists {
-            return Err(anyhow::anyhow!("Profile {} already exists in database", name));
+        // Recover raw counts from the stored frequencies
+        let stored_total = profile.total_kmers as f64;
+        let mut counts: HashMap<String, f64> = profile.frequencies
+            .drain()
+            .map(|(kmer, frequency)| (kmer, (frequency * stored_total).round()))
+            .collect();
+        for (kmer, count) in new_counts {
+            *counts.entry(kmer).or_insert(0.0) += count;
         }
+        // Like build_profile, estimate coverage before the count filter
+        profile.estimated_coverage = estimate_coverage(counts.values().map(|count| count.round() as usize));
+        // Stored k-mers already passed the count filter; new ones must reach it
+        // across old and new input together
+        if let Some(min_count) = profile.min_count {
+            drop_low_count(&mut counts, min_count);
+        }
+
+        let total_kmers: f64 = counts.values().sum();
+        profile.frequencies = counts.into_iter()
+            .map(|(kmer, count)| (kmer, count / total_kmers))
+            .collect();
+        profile.total_kmers = total_kmers.round() as usize;
+        profile.source_files.extend(reader.paths().iter().map(|path| path.display().to_string()));
+
+        let tx = self.conn.transaction()?;
+
+        let (profile_id, sketch_size): (i64, Option<i64>) = tx.query_row(
+            "SELECT p.id, s.sketch_size FROM profiles p
+             LEFT JOIN sketches s ON s.profile_id = p.id
+             WHERE p.name = ?1 AND p.k = ?2",
+            params![name, kmer_size],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        )?;
 
-        // Create profile
-        let mut profile = Profile::new(name, level, kmer_size);
+        tx.execute(
+            "UPDATE profiles SET total_kmers = ?2, source_files = ?3, estimated_coverage = ?4, content_hash = ?5
+             WHERE id = ?1",
+            params![
+                profile_id,
+                profile.total_kmers,
+                source_files_json(&profile.source_files)?,
+                profile.estimated_coverage,
+                frequencies_hash(&profile.frequencies),
+            ],
+        )?;
+        profile.bloom = replace_kmers(&tx, profile_id, &profile.frequencies)?;
 
-        // Calculate frequencies from total counts
-        let total_kmers = counter.total_kmers() as f64;
-        for (kmer, count) in counter.get_counts() {
-            let frequency = count as f64 / total_kmers;
-            profile.frequencies.insert(kmer, frequency);
+        if let Some(sketch_size) = sketch_size {
+            let sketch = MinHashSketch::from_kmers(
+                profile.frequencies.keys().map(|kmer| kmer.as_bytes()),
+                sketch_size as usize,
+            );
+            let hashes: Vec<u8> = sketch.hashes()
+                .iter()
+                .flat_map(|hash| hash.to_le_bytes())
+                .collect();
+            tx.execute(
+                "UPDATE sketches SET hashes = ?2 WHERE profile_id = ?1",
+                params![profile_id, hashes],
+            )?;
+            profile.sketch = Some(sketch);
         }
-        profile.total_kmers = counter.total_kmers();
 
+        tx.commit()?;
         info!(
-            "Created profile with {} k-mers from {} files", 
-            profile.frequencies.len(),
-            input_files.len()
+            "Updated profile {}: {} k-mers, {} total",
+            name, profile.frequencies.len(), profile.total_kmers
         );
 
-        // Add profile to database
-        self.add_profile(&profile)?;
-        
-        Ok(profile)
+        Ok((profile, stats))
+    }
+
+    /// Store each of a profile's k-mers once for both strands, as the smaller
+    /// of itself and its reverse complement, summing the two frequencies.
+    /// Profiles already canonical, or that cannot be, are left unchanged.
+    pub fn canonicalize_profile(&mut self, name: &str, k: Option<usize>) -> Result<Canonicalization> {
+        let profile_id = self.profile_id(name, k)?
+            .ok_or_else(|| anyhow::anyhow!("Profile {} not found", name))?;
+        let (canonical, alphabet, seed, minimizer_window): (bool, String, Option<String>, Option<usize>) =
+            self.conn.query_row(
+                "SELECT COALESCE(canonical, 0), COALESCE(alphabet, 'dna'), seed, minimizer_window
+                 FROM profiles WHERE id = ?",
+                params![profile_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            )?;
+        if canonical {
+            return Ok(Canonicalization::AlreadyCanonical);
+        }
+        if alphabet != Alphabet::Dna.to_string() {
+            return Ok(Canonicalization::Unsupported(format!("{} k-mers have no reverse complement", alphabet)));
+        }
+        if let Some(seed) = seed {
+            return Ok(Canonicalization::Unsupported(format!(
+                "k-mers of spaced seed {} cannot be matched across strands", seed
+            )));
+        }
+        if let Some(w) = minimizer_window {
+            return Ok(Canonicalization::Unsupported(format!(
+                "minimizers of window {} were chosen on one strand only", w
+            )));
+        }
+
+        let mut profile = self.get_profile(name, k)?
+            .ok_or_else(|| anyhow::anyhow!("Profile {} not found", name))?;
+        let stranded = profile.frequencies.len();
+        profile.frequencies = canonical_counts(profile.frequencies.drain());
+        // A k-mer folded with its reverse complement is still one distinct k-mer
+        if profile.normalization == Normalization::Presence {
+            let frequency = 1.0 / profile.frequencies.len() as f64;
+            profile.frequencies.values_mut().for_each(|value| *value = frequency);
+        }
+
+        let tx = self.conn.transaction()?;
+        // Every k-mer is still counted once, so total_kmers stays as it was
+        tx.execute(
+            "UPDATE profiles SET canonical = 1, content_hash = ?2 WHERE id = ?1",
+            params![profile_id, frequencies_hash(&profile.frequencies)],
+        )?;
+        replace_kmers(&tx, profile_id, &profile.frequencies)?;
+        tx.commit()?;
+
+        info!(
+            "Canonicalized profile {} (k={}): {} k-mers, {} before",
+            name, profile.k, profile.frequencies.len(), stranded
+        );
+        Ok(Canonicalization::Folded)
     }
 
     /// Add a new profile to the database
     pub fn add_profile(&mut self, profile: &Profile) -> Result<()> {
         // Check if profile already exists
-        let exists: bool = self.conn.query_row(
-            "SELECT 1 FROM profiles WHERE name = ?",
-            params![profile.name],
-            |_| Ok(true)
-        ).unwrap_or(false);
-
-        if exists {
-            warn!("Profile {} already exists in database", profile.name);
+        if self.profile_id(&profile.name, Some(profile.k))?.is_some() {
+            warn!("Profile {} already exists in database at k={}", profile.name, profile.k);
             return Ok(());
         }
 
         let tx = self.conn.transaction()?;
+
+        // A parent is held to the same rules as `link_profiles`
+        if let Some(parent) = &profile.parent {
+            check_parent_link(&tx, &profile.name, &profile.level, parent)?;
+        }
         
         // Insert profile
         tx.execute(
-            "INSERT INTO profiles (name, taxonomy_level, k, total_kmers)
-             VALUES (?1, ?2, ?3, ?4)",
+            "INSERT INTO profiles (name, taxonomy_level, k, total_kmers, parent, description, source_files, seed,
+                                   min_entropy, homopolymer_compressed, estimated_coverage, alphabet, content_hash,
+                                   created_by_version, canonical, minimizer_window, normalization, min_count)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
             params![
                 profile.name,
                 profile.level.to_string(),
                 profile.k,
                 profile.total_kmers,
+                profile.parent,
+                profile.description,
+                source_files_json(&profile.source_files)?,
+                profile.seed,
+                profile.min_entropy,
+                profile.homopolymer_compressed,
+                profile.estimated_coverage,
+                profile.alphabet.to_string(),
+                frequencies_hash(&profile.frequencies),
+                TOOL_VERSION,
+                profile.canonical,
+                profile.minimizer_window,
+                profile.normalization.to_string(),
+                profile.min_count,
             ],
         )?;
 
         let profile_id = tx.last_insert_rowid();
 
         // Insert k-mers
-        {
-            let mut stmt = tx.prepare(
-                "INSERT INTO kmers (profile_id, kmer, frequency)
-                 VALUES (?1, ?2, ?3)"
+        insert_kmers(&tx, profile_id, &profile.frequencies)?;
+
+        // Insert sketch
+        if let Some(sketch) = &profile.sketch {
+            let hashes: Vec<u8> = sketch.hashes()
+                .iter()
+                .flat_map(|hash| hash.to_le_bytes())
+                .collect();
+            tx.execute(
+                "INSERT INTO sketches (profile_id, sketch_size, hashes)
+                 VALUES (?1, ?2, ?3)",
+                params![profile_id, sketch.size(), hashes],
             )?;
+        }
 
-            for (kmer, frequency) in &profile.frequencies {
-                stmt.execute(params![profile_id, kmer, frequency])?;
-            }
+        if let Some(bloom) = &profile.bloom {
+            save_bloom(&tx, profile_id, bloom)?;
         }
 
+        add_occurrences(&tx, profile_id)?;
+
         tx.commit()?;
         info!("Added profile {} to database", profile.name);
         Ok(())
     }
 
-    /// Remove a profile from the database
-    pub fn remove_profile(&mut self, name: &str) -> Result<bool> {
+    /// Merge every profile from another database into this one. The merge
+    /// runs in a single transaction, so any failure leaves this database unchanged.
+    pub fn merge(&mut self, other: &Path, on_conflict: MergeConflict) -> Result<MergeSummary> {
+        if !other.exists() {
+            return Err(anyhow::anyhow!("Database {} does not exist", other.display()));
+        }
+
+        self.conn.execute(
+            "ATTACH DATABASE ?1 AS other",
+            params![other.to_string_lossy()],
+        )?;
+        let result = self.merge_attached(on_conflict);
+        self.conn.execute("DETACH DATABASE other", [])?;
+
+        let summary = result?;
+        info!(
+            "Merged {}: {} profiles added ({} renamed), {} skipped",
+            other.display(), summary.added, summary.renamed, summary.skipped
+        );
+        Ok(summary)
+    }
+
+    fn merge_attached(&mut self, on_conflict: MergeConflict) -> Result<MergeSummary> {
+        let tx = self.conn.transaction()?;
+        let mut summary = MergeSummary::default();
+
+        let has_sketches: bool = tx.query_row(
+            "SELECT COUNT(*) FROM other.sqlite_master WHERE type='table' AND name='sketches'",
+            [],
+            |row| row.get::<_, i64>(0)
+        )? > 0;
+
+        let has_blooms: bool = tx.query_row(
+            "SELECT COUNT(*) FROM other.sqlite_master WHERE type='table' AND name='bloom'",
+            [],
+            |row| row.get::<_, i64>(0)
+        )? > 0;
+
+        // Older databases may lack some metadata columns
+        let mut metadata_columns = Vec::new();
+        for (column, _) in OPTIONAL_PROFILE_COLUMNS {
+            if has_column(&tx, "other", "profiles", column)? {
+                metadata_columns.push(*column);
+            }
+        }
+
+        // Incoming names added under another name, and the profiles copied,
+        // so links between incoming profiles follow the renames
+        let mut renamed_names: HashMap<String, String> = HashMap::new();
+        let mut copied_ids = Vec::new();
+
+        let source_profiles: Vec<(i64, String, String, i64, i64, Option<String>)> = tx.prepare(
+            "SELECT id, name, taxonomy_level, k, total_kmers, created_at
+             FROM other.profiles ORDER BY id"
+        )?.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+        })?.collect::<rusqlite::Result<_>>()?;
+
+        for (source_id, name, level, k, total_kmers, created_at) in source_profiles {
+            let name_exists = |candidate: &str| -> rusqlite::Result<bool> {
+                tx.query_row(
+                    "SELECT 1 FROM profiles WHERE name = ?1 AND k = ?2",
+                    params![candidate, k],
+                    |_| Ok(true)
+                ).optional().map(|found| found.is_some())
+            };
+
+            let target_name = if !name_exists(&name)? {
+                name
+            } else {
+                match on_conflict {
+                    MergeConflict::Skip => {
+                        warn!("Profile {} already exists at k={}, skipping", name, k);
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    MergeConflict::Error => {
+                        return Err(anyhow::anyhow!("Profile {} already exists at k={} in target database", name, k));
+                    }
+                    MergeConflict::Rename => {
+                        let mut suffix = 2;
+                        while name_exists(&format!("{}_{}", name, suffix))? {
+                            suffix += 1;
+                        }
+                        let renamed = format!("{}_{}", name, suffix);
+                        info!("Profile {} already exists at k={}, adding as {}", name, k, renamed);
+                        summary.renamed += 1;
+                        renamed_names.entry(name).or_insert_with(|| renamed.clone());
+                        renamed
+                    }
+                }
+            };
+
+            tx.execute(
+                "INSERT INTO profiles (name, taxonomy_level, k, total_kmers, created_at)
+                 VALUES (?1, ?2, ?3, ?4, COALESCE(?5, CURRENT_TIMESTAMP))",
+                params![target_name, level, k, total_kmers, created_at],
+            )?;
+            let target_id = tx.last_insert_rowid();
+            copied_ids.push(target_id);
+
+            for column in &metadata_columns {
+                tx.execute(
+                    &format!(
+                        "UPDATE profiles SET {0} = (SELECT {0} FROM other.profiles WHERE id = ?2)
+                         WHERE id = ?1",
+                        column
+                    ),
+                    params![target_id, source_id],
+                )?;
+            }
+
+            // Re-link k-mers and sketch to the new profile id
+            tx.execute(
+                "INSERT INTO kmers (profile_id, kmer, frequency)
+                 SELECT ?1, kmer, frequency FROM other.kmers WHERE profile_id = ?2",
+                params![target_id, source_id],
+            )?;
+            add_occurrences(&tx, target_id)?;
+            if has_sketches {
+                tx.execute(
+                    "INSERT INTO sketches (profile_id, sketch_size, hashes)
+                     SELECT ?1, sketch_size, hashes FROM other.sketches WHERE profile_id = ?2",
+                    params![target_id, source_id],
+                )?;
+            }
+            if has_blooms {
+                tx.execute(
+                    "INSERT INTO bloom (profile_id, num_bits, num_hashes, fp_rate, bits)
+                     SELECT ?1, num_bits, num_hashes, fp_rate, bits FROM other.bloom WHERE profile_id = ?2",
+                    params![target_id, source_id],
+                )?;
+            }
+
+            summary.added += 1;
+        }
+
+        for (old, new) in &renamed_names {
+            for &id in &copied_ids {
+                tx.execute(
+                    "UPDATE profiles SET parent = ?3 WHERE id = ?1 AND parent = ?2",
+                    params![id, old, new],
+                )?;
+            }
+        }
+
+        // Profiles from databases predating content hashes arrive without one
+        backfill_content_hashes(&tx)?;
+
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// Remove a profile from the database, at k-mer size `k` if the name has several.
+    /// Once no profile has the name, profiles naming it as their parent are unlinked.
+    pub fn remove_profile(&mut self, name: &str, k: Option<usize>) -> Result<bool> {
+        let profile_id = self.profile_id(name, k)?;
         let tx = self.conn.transaction()?;
-        
-        let profile_id: Option<i64> = tx.query_row(
-            "SELECT id FROM profiles WHERE name = ?",
-            params![name],
-            |row| row.get(0)
-        ).optional()?;
 
         if let Some(id) = profile_id {
-            // Delete k-mers first (foreign key constraint)
+            remove_occurrences(&tx, id)?;
+
+            // Delete k-mers, sketch and Bloom filter first (foreign key constraint)
             tx.execute(
                 "DELETE FROM kmers WHERE profile_id = ?",
                 params![id]
             )?;
+            tx.execute(
+                "DELETE FROM sketches WHERE profile_id = ?",
+                params![id]
+            )?;
+            tx.execute(
+                "DELETE FROM bloom WHERE profile_id = ?",
+                params![id]
+            )?;
             
             // Delete profile
             tx.execute(
                 "DELETE FROM profiles WHERE id = ?",
                 params![id]
             )?;
+
+            let name_remains = tx.query_row(
+                "SELECT 1 FROM profiles WHERE name = ? LIMIT 1",
+                params![name],
+                |_| Ok(true)
+            ).optional()?.is_some();
+            if !name_remains {
+                let unlinked = tx.execute(
+                    "UPDATE profiles SET parent = NULL WHERE parent = ?",
+                    params![name]
+                )?;
+                if unlinked > 0 {
+                    warn!("Unlinked {} profiles from their removed parent {}", unlinked, name);
+                }
+            }
             
             tx.commit()?;
             info!("Removed profile {} from database", name);
@@ -166,12 +643,145 @@ impl Database {
         }
     }
 
-    /// Get a profile by name
-    pub fn get_profile(&self, name: &str) -> Result<Option<Profile>> {
-        let profile_result = self.conn.query_row(
-            "SELECT taxonomy_level, k, total_kmers 
-             FROM profiles WHERE name = ?",
+    /// Add or drop the index on kmers(kmer). Analysis does not need it, but it
+    /// turns a full scan into a lookup for queries finding a k-mer across
+    /// profiles, at about 30% more database size.
+    pub fn set_kmer_index(&self, enabled: bool) -> Result<()> {
+        if enabled {
+            self.conn.execute("CREATE INDEX IF NOT EXISTS idx_kmers_kmer ON kmers(kmer)", [])?;
+        } else {
+            self.conn.execute("DROP INDEX IF EXISTS idx_kmers_kmer", [])?;
+        }
+        Ok(())
+    }
+
+    /// Reclaim free pages left by removed profiles, rebuild indices and refresh
+    /// the query planner statistics
+    pub fn optimize(&self) -> Result<OptimizeSummary> {
+        // Checkpoint first so sizes count every page, not just those already in the main file
+        self.checkpoint()?;
+        let bytes_before = self.size_in_bytes()?;
+
+        self.conn.execute_batch("VACUUM; ANALYZE; PRAGMA optimize;")?;
+        self.checkpoint()?;
+
+        let summary = OptimizeSummary {
+            bytes_before,
+            bytes_after: self.size_in_bytes()?,
+        };
+        info!("Optimized database: {} -> {} bytes", summary.bytes_before, summary.bytes_after);
+        Ok(summary)
+    }
+
+    /// Copy the WAL back into the main database file and truncate it
+    fn checkpoint(&self) -> Result<()> {
+        self.conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    fn size_in_bytes(&self) -> Result<u64> {
+        let size: i64 = self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0)
+        )?;
+        Ok(size as u64)
+    }
+
+    /// Rename a profile at every k-mer size it was built with. Returns false if
+    /// no profile has the old name.
+    pub fn rename_profile(&mut self, old: &str, new: &str) -> Result<bool> {
+        let tx = self.conn.transaction()?;
+
+        let new_exists = tx.query_row(
+            "SELECT 1 FROM profiles WHERE name = ?",
+            params![new],
+            |_| Ok(true)
+        ).optional()?.is_some();
+
+        if new_exists {
+            return Err(anyhow::anyhow!("Cannot rename {} to {}: profile {} already exists", old, new, new));
+        }
+
+        // K-mers reference profiles by id, so only the profile row changes,
+        // along with the parent links naming it
+        let updated = tx.execute(
+            "UPDATE profiles SET name = ?2 WHERE name = ?1",
+            params![old, new],
+        )?;
+        if updated > 0 {
+            tx.execute(
+                "UPDATE profiles SET parent = ?2 WHERE parent = ?1",
+                params![old, new],
+            )?;
+        }
+        tx.commit()?;
+
+        if updated > 0 {
+            info!("Renamed profile {} to {}", old, new);
+        }
+        Ok(updated > 0)
+    }
+
+    /// Clear the parent of a profile at every k-mer size it was built with.
+    /// Returns false if the profile doesn't exist.
+    pub fn clear_parent(&mut self, name: &str) -> Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE profiles SET parent = NULL WHERE name = ?1",
             params![name],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Make `parent` the parent of `child`. Both must exist, the parent at a
+    /// broader taxonomy level than the child, and the link must not form a cycle.
+    pub fn link_profiles(&mut self, child: &str, parent: &str) -> Result<()> {
+        let child_level = profile_level(&self.conn, child)?
+            .ok_or_else(|| anyhow::anyhow!("Profile {} not found", child))?;
+        check_parent_link(&self.conn, child, &child_level, parent)?;
+        self.conn.execute(
+            "UPDATE profiles SET parent = ?2 WHERE name = ?1",
+            params![child, parent],
+        )?;
+        Ok(())
+    }
+
+    /// Number of a profile's k-mers found in no other profile
+    pub fn unique_kmer_count(&self, name: &str, k: Option<usize>) -> Result<usize> {
+        let profile_id = self.profile_id(name, k)?
+            .ok_or_else(|| anyhow::anyhow!("Profile {} not found", name))?;
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM kmers
+             JOIN kmer_occurrence USING (kmer)
+             WHERE profile_id = ? AND profile_count = 1",
+            params![profile_id],
+            |row| row.get(0)
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Whether a profile called `name` exists, at k-mer size `k` if given
+    pub fn has_profile(&self, name: &str, k: Option<usize>) -> Result<bool> {
+        let exists = self.conn.query_row(
+            "SELECT 1 FROM profiles WHERE name = ?1 AND (?2 IS NULL OR k = ?2) LIMIT 1",
+            params![name, k],
+            |_| Ok(true)
+        ).optional()?;
+        Ok(exists.is_some())
+    }
+
+    /// Get a profile by name, at k-mer size `k` if the name has several
+    pub fn get_profile(&self, name: &str, k: Option<usize>) -> Result<Option<Profile>> {
+        let Some(profile_id) = self.profile_id(name, k)? else {
+            return Ok(None);
+        };
+        let profile_result = self.conn.query_row(
+            "SELECT taxonomy_level, k, total_kmers, parent, description, source_files, seed, min_entropy,
+                    COALESCE(homopolymer_compressed, 0), COALESCE(estimated_coverage, 0.0), created_at,
+                    COALESCE(alphabet, 'dna'), created_by_version, COALESCE(canonical, 0), minimizer_window,
+                    COALESCE(normalization, 'occurrence'), min_count
+             FROM profiles WHERE id = ?",
+            params![profile_id],
             |row| {
                 let level_str: String = row.get(0)?;
                 let level = match level_str.as_str() {
@@ -181,26 +791,49 @@ impl Database {
                     _ => return Err(rusqlite::Error::InvalidParameterName(level_str)),
                 };
 
-                Ok((level, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
+                let mut profile = Profile::new(
+                    name.to_string(),
+                    level,
+                    row.get::<_, i64>(1)? as usize,
+                );
+                profile.total_kmers = row.get::<_, i64>(2)? as usize;
+                profile.parent = row.get(3)?;
+                profile.description = row.get(4)?;
+                if let Some(json) = row.get::<_, Option<String>>(5)? {
+                    profile.source_files = serde_json::from_str(&json).map_err(|e| {
+                        rusqlite::Error::FromSqlConversionFailure(5, rusqlite::types::Type::Text, Box::new(e))
+                    })?;
+                }
+                profile.seed = row.get(6)?;
+                profile.min_entropy = row.get(7)?;
+                profile.homopolymer_compressed = row.get(8)?;
+                profile.estimated_coverage = row.get(9)?;
+                profile.created_at = row.get(10)?;
+                let alphabet: String = row.get(11)?;
+                profile.alphabet = Alphabet::from_name(&alphabet)
+                    .ok_or(rusqlite::Error::InvalidParameterName(alphabet))?;
+                profile.created_by_version = row.get(12)?;
+                profile.canonical = row.get(13)?;
+                profile.minimizer_window = row.get(14)?;
+                let normalization: String = row.get(15)?;
+                profile.normalization = Normalization::from_name(&normalization)
+                    .ok_or(rusqlite::Error::InvalidParameterName(normalization))?;
+                profile.min_count = row.get(16)?;
+
+                Ok(profile)
             }
         ).optional()?;
 
-        if let Some((level, k, total_kmers)) = profile_result {
-            let mut profile = Profile::new(
-                name.to_string(),
-                level,
-                k as usize,
-            );
-            profile.total_kmers = total_kmers as usize;
+        if let Some(mut profile) = profile_result {
 
             // Get k-mers
             let mut stmt = self.conn.prepare(
                 "SELECT kmer, frequency 
                  FROM kmers 
-                 WHERE profile_id = (SELECT id FROM profiles WHERE name = ?)"
+                 WHERE profile_id = ?"
             )?;
 
-            let kmers = stmt.query_map(params![name], |row| {
+            let kmers = stmt.query_map(params![profile_id], |row| {
                 Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?))
             })?;
 
@@ -209,6 +842,20 @@ impl Database {
                 profile.frequencies.insert(kmer, freq);
             }
 
+            // Get the stored sketch (hashes as little-endian u64s)
+            profile.sketch = self.conn.query_row(
+                "SELECT sketch_size, hashes FROM sketches WHERE profile_id = ?",
+                params![profile_id],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+            ).optional()?
+            .map(|(sketch_size, bytes)| {
+                let hashes = bytes
+                    .chunks_exact(8)
+                    .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                    .collect();
+                MinHashSketch::from_hashes(sketch_size as usize, hashes)
+            });
+
             Ok(Some(profile))
         } else {
             Ok(None)
@@ -222,11 +869,11 @@ impl Database {
                 "SELECT name, taxonomy_level, k, total_kmers, created_at 
                  FROM profiles 
                  WHERE taxonomy_level = ?
-                 ORDER BY name",
+                 ORDER BY name, k",
             None => 
                 "SELECT name, taxonomy_level, k, total_kmers, created_at 
                  FROM profiles 
-                 ORDER BY name",
+                 ORDER BY name, k",
         };
 
         let mut stmt = self.conn.prepare(query)?;
@@ -256,8 +903,11 @@ impl Database {
         Ok(profiles)
     }
 
+    /// Number of k-mers listed in detailed statistics
+    const TOP_POLYMORPHIC_KMERS: usize = 10;
+
     /// Get database statistics
-    pub fn get_statistics(&self) -> Result<DatabaseStats> {
+    pub fn get_statistics(&self, detailed: bool) -> Result<DatabaseStats> {
         let total_profiles: i64 = self.conn.query_row(
             "SELECT COUNT(*) FROM profiles",
             [],
@@ -278,42 +928,146 @@ impl Database {
             Ok((row.get(0)?, row.get(1)?))
         })?.collect::<rusqlite::Result<_>>()?;
 
-        Ok(DatabaseStats {
+        let mut stats = DatabaseStats {
             total_profiles: total_profiles as usize,
             total_kmers: total_kmers as usize,
             profiles_by_level: level_counts.into_iter()
                 .map(|(level, count)| (level, count as usize))
                 .collect(),
-        })
-    }
+            kmers_by_profile: Vec::new(),
+            profiles_by_kmer_size: Vec::new(),
+            mean_frequency: 0.0,
+            median_frequency: 0.0,
+            shared_kmers: 0,
+            most_polymorphic_kmers: Vec::new(),
+        };
 
-    pub fn validate(&self) -> Result<ValidationReport> {
-        let mut report = ValidationReport::default();
+        if detailed {
+            self.add_detailed_statistics(&mut stats)?;
+        }
 
-        // 1. Check table existence and schema
-        self.validate_schema(&mut report)?;
+        Ok(stats)
+    }
 
-        // 2. Check data integrity
-        self.validate_data(&mut report)?;
+    fn add_detailed_statistics(&self, stats: &mut DatabaseStats) -> Result<()> {
+        stats.kmers_by_profile = self.conn.prepare(
+            "SELECT p.name, COUNT(k.kmer)
+             FROM profiles p
+             LEFT JOIN kmers k ON k.profile_id = p.id
+             GROUP BY p.id
+             ORDER BY p.name"
+        )?.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as usize))
+        })?.collect::<rusqlite::Result<_>>()?;
 
-        // 3. Check referential integrity
-        self.validate_references(&mut report)?;
+        stats.profiles_by_kmer_size = self.conn.prepare(
+            "SELECT k, COUNT(*) FROM profiles GROUP BY k ORDER BY k"
+        )?.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)? as usize, row.get::<_, i64>(1)? as usize))
+        })?.collect::<rusqlite::Result<_>>()?;
 
-        Ok(report)
-    }
+        stats.mean_frequency = self.conn.query_row(
+            "SELECT COALESCE(AVG(frequency), 0.0) FROM kmers",
+            [],
+            |row| row.get(0)
+        )?;
 
-    fn validate_schema(&self, report: &mut ValidationReport) -> Result<()> {
-        // Check if tables exist
-        let tables = self.conn.query_row(
-            "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name IN ('profiles', 'kmers')",
+        // Middle row for odd counts, average of the two middle rows for even counts
+        stats.median_frequency = self.conn.query_row(
+            "SELECT COALESCE(AVG(frequency), 0.0) FROM (
+                SELECT frequency FROM kmers
+                ORDER BY frequency
+                LIMIT 2 - (SELECT COUNT(*) FROM kmers) % 2
+                OFFSET (SELECT (COUNT(*) - 1) / 2 FROM kmers)
+             )",
             [],
-            |row| row.get::<_, i64>(0)
+            |row| row.get(0)
         )?;
 
-        if tables != 2 {
-            report.add_error("Missing required tables (profiles and/or kmers)");
-            return Ok(());
-        }
+        let shared_kmers: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM kmer_occurrence WHERE profile_count > 1",
+            [],
+            |row| row.get(0)
+        )?;
+        stats.shared_kmers = shared_kmers as usize;
+
+        stats.most_polymorphic_kmers = self.conn.prepare(
+            "SELECT kmer, profile_count FROM kmer_occurrence
+             WHERE profile_count > 1
+             ORDER BY profile_count DESC, kmer
+             LIMIT ?"
+        )?.query_map(params![Self::TOP_POLYMORPHIC_KMERS as i64], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as usize))
+        })?.collect::<rusqlite::Result<_>>()?;
+
+        Ok(())
+    }
+
+    /// Recompute a profile's content hash from its stored k-mers, returning
+    /// it if it matches the hash recorded when the profile was written
+    pub fn verify_profile(&self, name: &str, k: Option<usize>) -> Result<String> {
+        let profile_id = self.profile_id(name, k)?
+            .ok_or_else(|| anyhow::anyhow!("Profile {} not found", name))?;
+        let stored: Option<String> = self.conn.query_row(
+            "SELECT content_hash FROM profiles WHERE id = ?",
+            params![profile_id],
+            |row| row.get(0)
+        )?;
+
+        let computed = stored_content_hash(&self.conn, profile_id)?;
+        match stored {
+            Some(stored) if stored == computed => Ok(computed),
+            Some(stored) => Err(anyhow::anyhow!(
+                "Profile {} k-mers do not match its content hash (stored {}, computed {})",
+                name, stored, computed
+            )),
+            None => Err(anyhow::anyhow!("Profile {} has no content hash", name)),
+        }
+    }
+
+    /// Hash of every profile's identity and k-mers; see [`database_content_hash`]
+    pub fn content_hash(&self) -> Result<String> {
+        database_content_hash(&self.conn)
+    }
+
+    /// Check the database's schema, data and references. A profile's k-mer
+    /// frequencies may sum to anywhere within `freq_tolerance` of 1.
+    pub fn validate(&self, freq_tolerance: f64) -> Result<ValidationReport> {
+        if !freq_tolerance.is_finite() || freq_tolerance < 0.0 {
+            return Err(anyhow::anyhow!("Frequency tolerance must be non-negative, got {}", freq_tolerance));
+        }
+        let mut report = ValidationReport::default();
+
+        // 1. Check table existence and schema
+        self.validate_schema(&mut report)?;
+
+        // 2. Check data integrity
+        self.validate_data(&mut report, freq_tolerance)?;
+
+        // 3. Check referential integrity
+        self.validate_references(&mut report)?;
+
+        // 4. Check stored k-mers against their content hashes
+        self.validate_content_hashes(&mut report)?;
+
+        // 5. Check profiles at each level share their k-mer sizes
+        self.validate_kmer_sizes(&mut report)?;
+
+        Ok(report)
+    }
+
+    fn validate_schema(&self, report: &mut ValidationReport) -> Result<()> {
+        // Check if tables exist
+        let tables = self.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name IN ('profiles', 'kmers')",
+            [],
+            |row| row.get::<_, i64>(0)
+        )?;
+
+        if tables != 2 {
+            report.add_error("Missing required tables (profiles and/or kmers)");
+            return Ok(());
+        }
 
         // Verify profiles table schema
         let profile_cols = self.conn.query_row(
@@ -342,7 +1096,7 @@ impl Database {
         Ok(())
     }
 
-    fn validate_data(&self, report: &mut ValidationReport) -> Result<()> {
+    fn validate_data(&self, report: &mut ValidationReport, freq_tolerance: f64) -> Result<()> {
         // Check taxonomy levels are valid
         let invalid_levels: Vec<String> = self.conn.prepare(
             "SELECT DISTINCT taxonomy_level FROM profiles 
@@ -379,7 +1133,8 @@ impl Database {
             report.add_error("Found k-mers with invalid frequencies");
         }
 
-        // Check frequency sums per profile approximately equal 1
+        // Check frequency sums per profile approximately equal 1, telling
+        // floating-point drift apart from a sum well short of 1
         let mut stmt = self.conn.prepare(
             "SELECT profile_id, SUM(frequency) FROM kmers GROUP BY profile_id"
         )?;
@@ -390,10 +1145,16 @@ impl Database {
 
         for sum in sums {
             let (profile_id, freq_sum) = sum?;
-            if (freq_sum - 1.0).abs() > 0.01 {
+            let deviation = freq_sum - 1.0;
+            if -deviation > freq_tolerance.max(LOST_KMERS_DEFICIT) {
+                report.add_error(format!(
+                    "Profile {} has total frequency sum of {:.6}, {:.6} short of 1.0; k-mers appear to be missing",
+                    profile_id, freq_sum, -deviation
+                ));
+            } else if deviation.abs() > freq_tolerance {
                 report.add_error(format!(
-                    "Profile {} has total frequency sum of {:.6} (expected ≈1.0)",
-                    profile_id, freq_sum
+                    "Profile {} has total frequency sum of {:.6}, off 1.0 by {:+.6} (tolerance {})",
+                    profile_id, freq_sum, deviation, freq_tolerance
                 ));
             }
         }
@@ -430,8 +1191,458 @@ impl Database {
             ));
         }
 
+        // Check each parent link names an existing profile
+        let dangling = self.conn.prepare(
+            "SELECT DISTINCT name, parent FROM profiles p
+             WHERE parent IS NOT NULL AND NOT EXISTS (SELECT 1 FROM profiles q WHERE q.name = p.parent)
+             ORDER BY name"
+        )?.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        for (name, parent) in dangling {
+            report.add_error(format!("Profile {} has parent {}, which does not exist", name, parent));
+        }
+
         Ok(())
     }
+
+    fn validate_content_hashes(&self, report: &mut ValidationReport) -> Result<()> {
+        let profiles: Vec<(i64, String, Option<String>)> = self.conn.prepare(
+            "SELECT id, name, content_hash FROM profiles ORDER BY name"
+        )?.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+        for (profile_id, name, stored) in profiles {
+            let computed = stored_content_hash(&self.conn, profile_id)?;
+            match stored {
+                Some(stored) if stored == computed => {}
+                Some(stored) => report.add_error(format!(
+                    "Profile {} k-mers do not match its content hash (stored {}, computed {})",
+                    name, stored, computed
+                )),
+                None => report.add_warning(format!("Profile {} has no content hash", name)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Warn about levels whose profiles were built at different k-mer sizes.
+    /// A sample is only compared with profiles of its own k, so any profile
+    /// missing that size silently drops out; an organism stored at every size
+    /// used on its level is fine.
+    fn validate_kmer_sizes(&self, report: &mut ValidationReport) -> Result<()> {
+        let profiles: Vec<(String, String, usize)> = self.conn.prepare(
+            "SELECT taxonomy_level, name, k FROM profiles ORDER BY taxonomy_level, k"
+        )?.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+        let mut levels: BTreeMap<String, BTreeMap<usize, HashSet<String>>> = BTreeMap::new();
+        for (level, name, k) in profiles {
+            levels.entry(level).or_default().entry(k).or_default().insert(name);
+        }
+
+        for (level, sizes) in levels {
+            let names: HashSet<&String> = sizes.values().flatten().collect();
+            if sizes.values().all(|at_size| at_size.len() == names.len()) {
+                continue;
+            }
+            let counts: Vec<String> = sizes.iter()
+                .map(|(k, at_size)| format!("k={} ({} profiles)", k, at_size.len()))
+                .collect();
+            report.add_warning(format!(
+                "{} profiles use different k-mer sizes: {}; samples are only compared with profiles of their own k",
+                level, counts.join(", ")
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// SHA-256 over `kmer:frequency` lines, hex encoded. The k-mers must come in
+/// byte order so the hash does not depend on how they were stored.
+fn content_hash<'a>(kmers: impl IntoIterator<Item = (&'a str, f64)>) -> String {
+    let mut hasher = Sha256::new();
+    for (kmer, frequency) in kmers {
+        hasher.update(format!("{}:{}\n", kmer, frequency));
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Content hash of an in-memory profile's frequencies
+fn frequencies_hash(frequencies: &HashMap<String, f64>) -> String {
+    let mut kmers: Vec<(&str, f64)> = frequencies.iter()
+        .map(|(kmer, &frequency)| (kmer.as_str(), frequency))
+        .collect();
+    kmers.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    content_hash(kmers)
+}
+
+/// Content hash of a profile's k-mers as stored (SQLite's default collation
+/// orders TEXT bytewise, matching `str` ordering)
+fn stored_content_hash(conn: &Connection, profile_id: i64) -> Result<String> {
+    let mut stmt = conn.prepare("SELECT kmer, frequency FROM kmers WHERE profile_id = ? ORDER BY kmer")?;
+    let kmers = stmt.query_map(params![profile_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?))
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(content_hash(kmers.iter().map(|(kmer, frequency)| (kmer.as_str(), *frequency))))
+}
+
+/// SHA-256 over each profile's name, k-mer size and content hash, in name
+/// order, so two databases holding the same profiles hash alike however they
+/// were built
+pub(crate) fn database_content_hash(conn: &Connection) -> Result<String> {
+    let profiles: Vec<(i64, String, i64, Option<String>)> = conn
+        .prepare("SELECT id, name, k, content_hash FROM profiles ORDER BY name, k")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut hasher = Sha256::new();
+    for (profile_id, name, k, hash) in profiles {
+        let hash = match hash {
+            Some(hash) => hash,
+            None => stored_content_hash(conn, profile_id)?,
+        };
+        hasher.update(format!("{}\t{}\t{}\n", name, k, hash));
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hash the k-mers of profiles written before content hashes existed
+fn backfill_content_hashes(conn: &Connection) -> Result<()> {
+    let missing: Vec<i64> = conn.prepare("SELECT id FROM profiles WHERE content_hash IS NULL")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    info!("Computing content hashes for {} profiles", missing.len());
+    for profile_id in missing {
+        conn.execute(
+            "UPDATE profiles SET content_hash = ?2 WHERE id = ?1",
+            params![profile_id, stored_content_hash(conn, profile_id)?],
+        )?;
+    }
+    Ok(())
+}
+
+/// Remove k-mers whose base composition entropy is below `min_entropy`
+fn drop_low_complexity<V>(counts: &mut HashMap<String, V>, min_entropy: f64) {
+    let observed = counts.len();
+    counts.retain(|kmer, _| shannon_entropy(kmer.as_bytes()) >= min_entropy);
+    if counts.len() < observed {
+        info!(
+            "Dropped {} of {} k-mers with entropy below {}",
+            observed - counts.len(),
+            observed,
+            min_entropy
+        );
+    }
+}
+
+/// Check that `parent` may be the parent of `child`, a profile at `child_level`:
+/// it must exist at a broader level and must not already descend from `child`
+fn check_parent_link(conn: &Connection, child: &str, child_level: &TaxonomyLevel, parent: &str) -> Result<()> {
+    if parent == child {
+        return Err(anyhow::anyhow!("Profile {} cannot be its own parent", child));
+    }
+    let parent_level = profile_level(conn, parent)?
+        .ok_or_else(|| anyhow::anyhow!("Parent profile {} not found", parent))?;
+    let mut broader = std::iter::successors(child_level.parent_level(), TaxonomyLevel::parent_level);
+    if !broader.any(|level| level == parent_level) {
+        return Err(anyhow::anyhow!(
+            "Cannot link {} ({}) under {} ({}): a parent must be at a broader level",
+            child, child_level, parent, parent_level
+        ));
+    }
+
+    // Walk up from the new parent; meeting the profile means it would become its own ancestor
+    let mut ancestor = Some(parent.to_string());
+    let mut seen = HashSet::new();
+    while let Some(current) = ancestor {
+        if current == child {
+            return Err(anyhow::anyhow!(
+                "Cannot make {} the parent of {}: {} already descends from {}, which would form a cycle",
+                parent, child, parent, child
+            ));
+        }
+        if !seen.insert(current.clone()) {
+            break;
+        }
+        ancestor = parent_of(conn, &current)?;
+    }
+    Ok(())
+}
+
+fn parent_of(conn: &Connection, name: &str) -> Result<Option<String>> {
+    let parent = conn.query_row(
+        "SELECT parent FROM profiles WHERE name = ? LIMIT 1",
+        params![name],
+        |row| row.get::<_, Option<String>>(0)
+    ).optional()?;
+    Ok(parent.flatten())
+}
+
+fn profile_level(conn: &Connection, name: &str) -> Result<Option<TaxonomyLevel>> {
+    let level: Option<String> = conn.query_row(
+        "SELECT taxonomy_level FROM profiles WHERE name = ? LIMIT 1",
+        params![name],
+        |row| row.get(0)
+    ).optional()?;
+    level.map(|level| match level.as_str() {
+        "Genus" => Ok(TaxonomyLevel::Genus),
+        "Species" => Ok(TaxonomyLevel::Species),
+        "Strain" => Ok(TaxonomyLevel::Strain),
+        _ => Err(anyhow::anyhow!("Profile {} has unknown taxonomy level {}", name, level)),
+    }).transpose()
+}
+
+/// Remove k-mers seen fewer than `min_count` times
+fn drop_low_count(counts: &mut HashMap<String, f64>, min_count: usize) {
+    let observed = counts.len();
+    // A fractional sighting from an expanded IUPAC window still counts as one
+    counts.retain(|_, count| count.ceil() >= min_count as f64);
+    if counts.len() < observed {
+        info!(
+            "Dropped {} of {} k-mers seen fewer than {} times",
+            observed - counts.len(),
+            observed,
+            min_count
+        );
+    }
+}
+
+/// Fill in a new profile's frequencies from raw k-mer counts, applying the
+/// count and entropy filters, and build its sketch and Bloom filter
+fn build_profile(mut profile: Profile, mut counts: HashMap<String, f64>, options: &ProfileOptions) -> Profile {
+    profile.description = options.description.clone();
+    profile.parent = options.parent.clone();
+    profile.min_count = (options.min_count > 1).then_some(options.min_count);
+    profile.min_entropy = (options.min_entropy > 0.0).then_some(options.min_entropy);
+
+    // Drop low-count k-mers, then calculate frequencies over the retained k-mers
+    profile.estimated_coverage = estimate_coverage(counts.values().map(|count| count.round() as usize));
+    info!("Estimated coverage: {}x", profile.estimated_coverage);
+    drop_low_count(&mut counts, options.min_count);
+    if let Some(min_entropy) = profile.min_entropy {
+        drop_low_complexity(&mut counts, min_entropy);
+    }
+
+    let total_kmers: f64 = counts.values().sum();
+    let distinct_kmers = counts.len() as f64;
+    profile.normalization = options.normalization;
+    for (kmer, count) in counts {
+        let frequency = match options.normalization {
+            Normalization::Occurrence => count / total_kmers,
+            Normalization::Presence => 1.0 / distinct_kmers,
+        };
+        profile.frequencies.insert(kmer, frequency);
+    }
+    profile.total_kmers = total_kmers.round() as usize;
+    if options.sketch_size > 0 {
+        profile.sketch = Some(MinHashSketch::from_kmers(
+            profile.frequencies.keys().map(|kmer| kmer.as_bytes()),
+            options.sketch_size,
+        ));
+    }
+    if options.bloom_fp_rate > 0.0 {
+        profile.bloom = Some(BloomFilter::from_kmers(
+            profile.frequencies.keys().map(|kmer| kmer.as_bytes()),
+            options.bloom_fp_rate,
+        ));
+    }
+
+    info!(
+        "Built profile with {} k-mers from {} files",
+        profile.frequencies.len(),
+        profile.source_files.len()
+    );
+
+    profile
+}
+
+fn format_minimizer_window(window: Option<usize>) -> String {
+    window.map_or("none".to_string(), |w| w.to_string())
+}
+
+/// Count a reader's k-mers into a new, not yet filtered profile carrying the
+/// reader's settings, returning the raw counts alongside it
+fn count_profile(
+    reader: &FastxReader,
+    name: String,
+    options: &ProfileOptions,
+    checkpoint: Option<(&Path, bool)>,
+) -> Result<(Profile, HashMap<String, f64>, ReaderStats)> {
+    let (counter, stats) = count_input(
+        reader,
+        options.kmer_size,
+        options.seed.as_ref(),
+        options.minimizer_window,
+        options.max_unique_kmers,
+        checkpoint,
+    )?;
+
+    let mut profile = Profile::new(name, options.level.clone(), options.kmer_size);
+    profile.seed = options.seed.as_ref().map(|seed| seed.to_string());
+    profile.minimizer_window = options.minimizer_window;
+    profile.homopolymer_compressed = reader.compresses_homopolymers();
+    profile.alphabet = reader.sequence_alphabet();
+    profile.source_files = reader.paths()
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+
+    Ok((profile, counter.get_weighted_counts(), stats))
+}
+
+/// Count the k-mers of every input sequence, expanding IUPAC codes if the
+/// reader keeps them, and saving progress to a checkpoint (path and whether
+/// to resume from it) if given
+fn count_input(
+    reader: &FastxReader,
+    kmer_size: usize,
+    seed: Option<&SpacedSeed>,
+    minimizer_window: Option<usize>,
+    max_unique_kmers: Option<usize>,
+    checkpoint: Option<(&Path, bool)>,
+) -> Result<(KmerCounter, ReaderStats)> {
+    let counter = KmerCounter::new(kmer_size)
+        .with_alphabet(reader.sequence_alphabet())
+        .with_seed(seed.cloned())
+        .with_minimizer_window(minimizer_window)
+        .with_iupac_expansion(reader.expands_iupac())
+        .with_max_unique_kmers(max_unique_kmers);
+
+    info!("Processing {} input files...", reader.file_count());
+    let stats = match checkpoint {
+        Some((path, resume)) => count_with_checkpoint(&counter, reader, path, resume)?,
+        None => counter.count_from_reader(reader)?,
+    };
+    info!("Found {} unique k-mers across all files", counter.unique_kmers());
+
+    let window = seed.map_or(kmer_size, SpacedSeed::span);
+    if counter.sequences_counted() > 0 && counter.short_sequences() == counter.sequences_counted() {
+        return Err(anyhow::anyhow!(
+            "No {}-mers found: all {} sequences are shorter than {} bases; use a smaller -k",
+            kmer_size,
+            counter.sequences_counted(),
+            window
+        ));
+    }
+    if counter.mostly_short_sequences() {
+        warn!(
+            "{} of {} sequences are shorter than {} bases and yield no k-mers; -k {} may be too large for these reads",
+            counter.short_sequences(),
+            counter.sequences_counted(),
+            window,
+            kmer_size
+        );
+    }
+    if counter.oversized_windows() > 0 {
+        warn!(
+            "Skipped {} windows with IUPAC codes expanding to more than {} k-mers",
+            counter.oversized_windows(),
+            MAX_IUPAC_EXPANSIONS
+        );
+    }
+    if counter.is_truncated() {
+        warn!(
+            "K-mer set truncated at --max-kmers {}: later distinct k-mers were dropped",
+            max_unique_kmers.unwrap_or_default()
+        );
+    }
+    Ok((counter, stats))
+}
+
+/// Insert a profile's k-mers using multi-row statements of `KMER_INSERT_BATCH` rows.
+/// Rows go in k-mer order so the primary key index is appended to rather than
+/// split at random pages.
+fn insert_kmers(conn: &Connection, profile_id: i64, frequencies: &HashMap<String, f64>) -> Result<()> {
+    let mut rows: Vec<(&String, &f64)> = frequencies.iter().collect();
+    rows.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    for chunk in rows.chunks(KMER_INSERT_BATCH) {
+        let placeholders = vec!["(?, ?, ?)"; chunk.len()].join(", ");
+        let mut stmt = conn.prepare_cached(&format!(
+            "INSERT INTO kmers (profile_id, kmer, frequency) VALUES {}",
+            placeholders
+        ))?;
+
+        let mut values: Vec<&dyn ToSql> = Vec::with_capacity(chunk.len() * 3);
+        for (kmer, frequency) in chunk {
+            values.push(&profile_id);
+            values.push(kmer);
+            values.push(frequency);
+        }
+        stmt.execute(values.as_slice())?;
+    }
+    Ok(())
+}
+
+/// Swap a stored profile's k-mers for `frequencies`, keeping the occurrence
+/// cache in step and rebuilding its Bloom filter if it has one
+fn replace_kmers(conn: &Connection, profile_id: i64, frequencies: &HashMap<String, f64>) -> Result<Option<BloomFilter>> {
+    remove_occurrences(conn, profile_id)?;
+    conn.execute("DELETE FROM kmers WHERE profile_id = ?", params![profile_id])?;
+    insert_kmers(conn, profile_id, frequencies)?;
+    add_occurrences(conn, profile_id)?;
+
+    let bloom_fp_rate: Option<f64> = conn.query_row(
+        "SELECT fp_rate FROM bloom WHERE profile_id = ?",
+        params![profile_id],
+        |row| row.get(0)
+    ).optional()?;
+    let Some(fp_rate) = bloom_fp_rate else {
+        return Ok(None);
+    };
+    let bloom = BloomFilter::from_kmers(frequencies.keys().map(|kmer| kmer.as_bytes()), fp_rate);
+    save_bloom(conn, profile_id, &bloom)?;
+    Ok(Some(bloom))
+}
+
+/// Store or replace the Bloom filter of a profile
+fn save_bloom(conn: &Connection, profile_id: i64, bloom: &BloomFilter) -> Result<()> {
+    let bits: Vec<u8> = bloom.words()
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+    conn.execute(
+        "INSERT OR REPLACE INTO bloom (profile_id, num_bits, num_hashes, fp_rate, bits)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![profile_id, bloom.num_bits() as i64, bloom.num_hashes(), bloom.fp_rate(), bits],
+    )?;
+    Ok(())
+}
+
+/// Serialize source file paths as a JSON array, or NULL when there are none
+fn source_files_json(source_files: &[String]) -> Result<Option<String>> {
+    if source_files.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::to_string(source_files)?))
+}
+
+/// Count every k-mer of a newly inserted profile in the occurrence cache
+fn add_occurrences(conn: &Connection, profile_id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO kmer_occurrence (kmer, profile_count)
+         SELECT kmer, 1 FROM kmers WHERE profile_id = ?1 AND true
+         ON CONFLICT(kmer) DO UPDATE SET profile_count = profile_count + 1",
+        params![profile_id],
+    )?;
+    Ok(())
+}
+
+/// Uncount every k-mer of a profile about to be deleted from the occurrence cache
+fn remove_occurrences(conn: &Connection, profile_id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE kmer_occurrence SET profile_count = profile_count - 1
+         WHERE kmer IN (SELECT kmer FROM kmers WHERE profile_id = ?1)",
+        params![profile_id],
+    )?;
+    conn.execute("DELETE FROM kmer_occurrence WHERE profile_count <= 0", [])?;
+    Ok(())
 }
 
 #[derive(Default, Debug)]
@@ -488,11 +1699,1065 @@ mod tests {
 
         db.add_profile(&profile)?;
 
-        let retrieved = db.get_profile("Test_Species")?.unwrap();
+        let retrieved = db.get_profile("Test_Species", None)?.unwrap();
         assert_eq!(retrieved.name, profile.name);
         assert_eq!(retrieved.level, profile.level);
         assert_eq!(retrieved.frequencies.len(), profile.frequencies.len());
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sketch_stored_with_profile() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let fasta_path = dir.path().join("ref.fasta");
+        std::fs::write(&fasta_path, ">ref\nACGTACGGTTCAGTTAGGCA\n")?;
+
+        let mut db = Database::new(&db_path)?;
+        let (profile, _) = db.create_profile(
+            &FastxReader::new(vec![fasta_path]),
+            "Test_Species".to_string(),
+            &ProfileOptions {
+                kmer_size: 5,
+                level: TaxonomyLevel::Species,
+                sketch_size: 8,
+                min_count: 1,
+                description: None,
+                parent: None,
+                bloom_fp_rate: 0.0,
+                seed: None,
+                minimizer_window: None,
+                min_entropy: 0.0,
+                max_unique_kmers: None,
+                normalization: Normalization::Occurrence,
+            },
+        )?;
+
+        let (sketch_size, hashes): (i64, Vec<u8>) = db.conn.query_row(
+            "SELECT sketch_size, hashes FROM sketches",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        )?;
+        assert_eq!(sketch_size, 8);
+        assert_eq!(hashes.len(), profile.sketch.unwrap().hashes().len() * 8);
+
+        assert!(db.remove_profile("Test_Species", None)?);
+        let remaining: i64 = db.conn.query_row("SELECT COUNT(*) FROM sketches", [], |row| row.get(0))?;
+        assert_eq!(remaining, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_databases() -> Result<()> {
+        let dir = tempdir()?;
+        let target_path = dir.path().join("target.db");
+        let source_path = dir.path().join("source.db");
+
+        let profile = |name: &str| {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 4);
+            profile.frequencies.insert("AAAA".to_string(), 0.25);
+            profile.frequencies.insert("CCCC".to_string(), 0.75);
+            profile.total_kmers = 4;
+            profile
+        };
+
+        let mut target = Database::new(&target_path)?;
+        target.add_profile(&profile("Shared"))?;
+
+        let mut source = Database::new(&source_path)?;
+        source.add_profile(&profile("Shared"))?;
+        source.add_profile(&profile("New"))?;
+        drop(source);
+
+        let summary = target.merge(&source_path, MergeConflict::Rename)?;
+        assert_eq!(summary.added, 2);
+        assert_eq!(summary.renamed, 1);
+
+        let renamed = target.get_profile("Shared_2", None)?.unwrap();
+        assert_eq!(renamed.frequencies.get("CCCC"), Some(&0.75));
+        assert!(target.get_profile("New", None)?.is_some());
+
+        let summary = target.merge(&source_path, MergeConflict::Skip)?;
+        assert_eq!(summary.added, 0);
+        assert_eq!(summary.skipped, 2);
+
+        // A conflict error rolls back the whole merge
+        assert!(target.merge(&source_path, MergeConflict::Error).is_err());
+        assert_eq!(target.list_profiles(None)?.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_profile() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+
+        for name in ["First", "Second"] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 4);
+            profile.frequencies.insert("AAAA".to_string(), 1.0);
+            profile.total_kmers = 1;
+            db.add_profile(&profile)?;
+        }
+
+        assert!(db.rename_profile("First", "Renamed")?);
+        assert!(db.get_profile("First", None)?.is_none());
+        assert_eq!(db.get_profile("Renamed", None)?.unwrap().frequencies.len(), 1);
+
+        assert!(!db.rename_profile("Missing", "Other")?);
+        assert!(db.rename_profile("Renamed", "Second").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parent_links_follow_renames_and_removals() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        for (name, level, parent, k) in [
+            ("E_coli", TaxonomyLevel::Species, None, 4),
+            ("E_coli", TaxonomyLevel::Species, None, 5),
+            ("E_coli_K12", TaxonomyLevel::Strain, Some("E_coli"), 4),
+        ] {
+            let mut profile = Profile::new(name.to_string(), level, k);
+            profile.parent = parent.map(str::to_string);
+            profile.frequencies.insert("A".repeat(k), 1.0);
+            profile.total_kmers = 1;
+            db.add_profile(&profile)?;
+        }
+        let parent = |db: &Database| -> Result<Option<String>> {
+            Ok(db.get_profile("E_coli_K12", None)?.unwrap().parent)
+        };
+
+        db.rename_profile("E_coli", "Escherichia_coli")?;
+        assert_eq!(parent(&db)?.as_deref(), Some("Escherichia_coli"));
+
+        // The link holds while the name remains at another k
+        db.remove_profile("Escherichia_coli", Some(4))?;
+        assert_eq!(parent(&db)?.as_deref(), Some("Escherichia_coli"));
+        db.remove_profile("Escherichia_coli", Some(5))?;
+        assert_eq!(parent(&db)?, None);
+
+        // Links left dangling by older versions are reported
+        db.conn.execute("UPDATE profiles SET parent = 'Missing' WHERE name = 'E_coli_K12'", [])?;
+        let errors = db.validate(0.01)?.errors().to_vec();
+        assert!(errors.iter().any(|error| error.contains("parent Missing, which does not exist")), "{:?}", errors);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_rename_keeps_parent_links() -> Result<()> {
+        let dir = tempdir()?;
+        let mut target = Database::new(dir.path().join("target.db"))?;
+        let source_path = dir.path().join("source.db");
+        let mut source = Database::new(&source_path)?;
+
+        let profile = |name: &str, level, parent: Option<&str>| {
+            let mut profile = Profile::new(name.to_string(), level, 4);
+            profile.parent = parent.map(str::to_string);
+            profile.frequencies.insert("AAAA".to_string(), 1.0);
+            profile.total_kmers = 1;
+            profile
+        };
+        target.add_profile(&profile("E_coli", TaxonomyLevel::Species, None))?;
+        source.add_profile(&profile("E_coli", TaxonomyLevel::Species, None))?;
+        source.add_profile(&profile("E_coli_K12", TaxonomyLevel::Strain, Some("E_coli")))?;
+        drop(source);
+
+        target.merge(&source_path, MergeConflict::Rename)?;
+        let strain = target.get_profile("E_coli_K12", None)?.unwrap();
+        assert_eq!(strain.parent.as_deref(), Some("E_coli_2"));
+        assert_eq!(target.get_profile("E_coli", None)?.unwrap().parent, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_profile_appends_counts() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let first = dir.path().join("first.fasta");
+        let second = dir.path().join("second.fasta");
+        std::fs::write(&first, ">a\nAAAAA\n")?;
+        std::fs::write(&second, ">b\nAAAACCCC\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 4,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 1,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        db.create_profile(&FastxReader::new(vec![first]), "Test".to_string(), &options)?;
+        let (updated, _) = db.update_profile(&FastxReader::new(vec![second.clone()]), "Test", TaxonomyLevel::Species, 4, None, None, None)?;
+
+        // 2 AAAA from the first file, 1 AAAA + 4 others from the second
+        assert_eq!(updated.total_kmers, 7);
+        let stored = db.get_profile("Test", None)?.unwrap();
+        assert_eq!(stored.total_kmers, 7);
+        assert!((stored.frequencies["AAAA"] - 3.0 / 7.0).abs() < 1e-9);
+        assert!((stored.frequencies["CCCC"] - 1.0 / 7.0).abs() < 1e-9);
+
+        assert!(db.update_profile(&FastxReader::new(vec![second]), "Test", TaxonomyLevel::Species, 5, None, None, None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_profile_keeps_min_count() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let first = dir.path().join("first.fasta");
+        let second = dir.path().join("second.fasta");
+        std::fs::write(&first, ">a\nAAAAAA\n")?;
+        std::fs::write(&second, ">b\nAAAAACCCC\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 4,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 2,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        db.create_profile(&FastxReader::new(vec![first]), "Test".to_string(), &options)?;
+        assert_eq!(db.get_profile("Test", None)?.unwrap().min_count, Some(2));
+
+        // The new file's singleton k-mers stay out; AAAA gains its 2 sightings
+        let (updated, _) = db.update_profile(&FastxReader::new(vec![second.clone()]), "Test", TaxonomyLevel::Species, 4, None, None, None)?;
+        assert_eq!(updated.frequencies.keys().collect::<Vec<_>>(), ["AAAA"]);
+        assert_eq!(updated.total_kmers, 5);
+
+        assert!(db.update_profile(&FastxReader::new(vec![second]), "Test", TaxonomyLevel::Genus, 4, None, None, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_occurrence_counts_follow_profiles() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+
+        let profile = |name: &str, kmers: &[&str]| {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 4);
+            for kmer in kmers {
+                profile.frequencies.insert(kmer.to_string(), 1.0 / kmers.len() as f64);
+            }
+            profile.total_kmers = kmers.len();
+            profile
+        };
+        let occurrence = |db: &Database, kmer: &str| -> Result<Option<i64>> {
+            Ok(db.conn.query_row(
+                "SELECT profile_count FROM kmer_occurrence WHERE kmer = ?",
+                params![kmer],
+                |row| row.get(0)
+            ).optional()?)
+        };
+
+        db.add_profile(&profile("First", &["AAAA", "CCCC"]))?;
+        db.add_profile(&profile("Second", &["AAAA", "GGGG"]))?;
+        assert_eq!(occurrence(&db, "AAAA")?, Some(2));
+        assert_eq!(occurrence(&db, "CCCC")?, Some(1));
+        assert_eq!(occurrence(&db, "GGGG")?, Some(1));
+        assert_eq!(db.unique_kmer_count("Second", None)?, 1);
+
+        db.remove_profile("First", None)?;
+        assert_eq!(occurrence(&db, "AAAA")?, Some(1));
+        assert_eq!(occurrence(&db, "CCCC")?, None);
+        assert_eq!(occurrence(&db, "GGGG")?, Some(1));
+        assert_eq!(db.unique_kmer_count("Second", None)?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parent_checked_when_stored_and_cleared() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+
+        let mut species = Profile::new("E_coli".to_string(), TaxonomyLevel::Species, 4);
+        species.frequencies.insert("AAAA".to_string(), 1.0);
+        species.total_kmers = 1;
+        db.add_profile(&species)?;
+
+        // A profile whose parent is rejected is not stored at all
+        let mut strain = Profile::new("E_coli_K12".to_string(), TaxonomyLevel::Strain, 4);
+        strain.frequencies.insert("AAAA".to_string(), 1.0);
+        strain.total_kmers = 1;
+        strain.parent = Some("E_colli".to_string());
+        assert!(db.add_profile(&strain).is_err());
+        assert!(!db.has_profile("E_coli_K12", None)?);
+        strain.parent = Some("E_coli_K12".to_string());
+        assert!(db.add_profile(&strain).is_err());
+
+        strain.parent = Some("E_coli".to_string());
+        db.add_profile(&strain)?;
+        assert_eq!(db.get_profile("E_coli_K12", None)?.unwrap().parent.as_deref(), Some("E_coli"));
+
+        assert!(db.clear_parent("E_coli_K12")?);
+        assert_eq!(db.get_profile("E_coli_K12", None)?.unwrap().parent, None);
+        assert!(!db.clear_parent("Missing")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_profiles_checks_levels_and_cycles() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        for (name, level) in [
+            ("Escherichia", TaxonomyLevel::Genus),
+            ("E_coli", TaxonomyLevel::Species),
+            ("E_coli_K12", TaxonomyLevel::Strain),
+        ] {
+            let mut profile = Profile::new(name.to_string(), level, 4);
+            profile.frequencies.insert("AAAA".to_string(), 1.0);
+            profile.total_kmers = 1;
+            db.add_profile(&profile)?;
+        }
+
+        db.link_profiles("E_coli_K12", "E_coli")?;
+        db.link_profiles("E_coli", "Escherichia")?;
+        assert_eq!(db.get_profile("E_coli", None)?.unwrap().parent.as_deref(), Some("Escherichia"));
+
+        // Parents must be broader, and must exist
+        assert!(db.link_profiles("Escherichia", "E_coli").is_err());
+        assert!(db.link_profiles("E_coli", "Missing").is_err());
+
+        // A link stored before levels were checked can still close a cycle
+        db.clear_parent("E_coli")?;
+        db.conn.execute("UPDATE profiles SET parent = 'E_coli' WHERE name = 'Escherichia'", [])?;
+        let err = db.link_profiles("E_coli", "Escherichia").unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+        assert_eq!(db.get_profile("E_coli", None)?.unwrap().parent, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detailed_statistics() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+
+        for (name, kmers) in [
+            ("A", vec![("AAAA", 0.5), ("CCCC", 0.5)]),
+            ("B", vec![("AAAA", 0.2), ("GGGG", 0.3), ("TTTT", 0.5)]),
+        ] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 4);
+            for (kmer, freq) in kmers {
+                profile.frequencies.insert(kmer.to_string(), freq);
+            }
+            profile.total_kmers = 10;
+            db.add_profile(&profile)?;
+        }
+
+        let basic = db.get_statistics(false)?;
+        assert!(basic.kmers_by_profile.is_empty());
+
+        let stats = db.get_statistics(true)?;
+        assert_eq!(stats.kmers_by_profile, vec![("A".to_string(), 2), ("B".to_string(), 3)]);
+        assert_eq!(stats.profiles_by_kmer_size, vec![(4, 2)]);
+        assert!((stats.mean_frequency - 0.4).abs() < 1e-9);
+        assert!((stats.median_frequency - 0.5).abs() < 1e-9);
+        assert_eq!(stats.shared_kmers, 1);
+        assert_eq!(stats.most_polymorphic_kmers, vec![("AAAA".to_string(), 2)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_count_drops_singletons() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let fasta_path = dir.path().join("ref.fasta");
+        // AAA x3, AAC x1
+        std::fs::write(&fasta_path, ">a\nAAAAAC\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 3,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 2,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        let (profile, _) = db.create_profile(&FastxReader::new(vec![fasta_path]), "Test".to_string(), &options)?;
+
+        assert_eq!(profile.frequencies.len(), 1);
+        assert_eq!(profile.total_kmers, 3);
+        assert!((profile.frequencies["AAA"] - 1.0).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_minimizer_window_stored_with_profile() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let first = dir.path().join("first.fasta");
+        std::fs::write(&first, ">a\nTGCAT\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 3,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 1,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: Some(3),
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        db.create_profile(&FastxReader::new(vec![first.clone()]), "Test".to_string(), &options)?;
+
+        let profile = db.get_profile("Test", None)?.unwrap();
+        assert_eq!(profile.minimizer_window, Some(3));
+        assert_eq!(profile.frequencies.keys().collect::<Vec<_>>(), vec!["CAT"]);
+
+        let reader = FastxReader::new(vec![first]);
+        assert!(db.update_profile(&reader, "Test", TaxonomyLevel::Species, 3, None, None, None).is_err());
+        db.update_profile(&reader, "Test", TaxonomyLevel::Species, 3, None, Some(3), None)?;
+        assert!(matches!(db.canonicalize_profile("Test", None)?, Canonicalization::Unsupported(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preview_profile_writes_nothing() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let fasta_path = dir.path().join("ref.fasta");
+        std::fs::write(&fasta_path, ">a\nAAAAACGGT\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 3,
+            level: TaxonomyLevel::Species,
+            sketch_size: 8,
+            min_count: 2,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.01,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        let reader = FastxReader::new(vec![fasta_path]);
+        let (preview, stats) = db.preview_profile(&reader, "Test".to_string(), &options)?;
+        assert_eq!(stats.total_bases(), 9);
+        assert!(!db.has_profile("Test", None)?);
+        assert!(db.list_profiles(None)?.is_empty());
+
+        let (profile, _) = db.create_profile(&reader, "Test".to_string(), &options)?;
+        assert_eq!(preview.frequencies, profile.frequencies);
+        assert_eq!(preview.total_kmers, profile.total_kmers);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_metadata() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let first = dir.path().join("first.fasta");
+        let second = dir.path().join("second.fasta");
+        std::fs::write(&first, ">a\nACGTACGT\n")?;
+        std::fs::write(&second, ">b\nTTTTCCCC\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 4,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 1,
+            description: Some("Reference assembly".to_string()),
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        db.create_profile(&FastxReader::new(vec![first.clone()]), "Test".to_string(), &options)?;
+        db.update_profile(&FastxReader::new(vec![second.clone()]), "Test", TaxonomyLevel::Species, 4, None, None, None)?;
+
+        let stored = db.get_profile("Test", None)?.unwrap();
+        assert_eq!(stored.description.as_deref(), Some("Reference assembly"));
+        assert_eq!(stored.source_files, vec![
+            first.display().to_string(),
+            second.display().to_string(),
+        ]);
+
+        // Profiles added without metadata keep NULL columns
+        let mut bare = Profile::new("Bare".to_string(), TaxonomyLevel::Species, 4);
+        bare.frequencies.insert("AAAA".to_string(), 1.0);
+        db.add_profile(&bare)?;
+        let stored = db.get_profile("Bare", None)?.unwrap();
+        assert_eq!(stored.description, None);
+        assert!(stored.source_files.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_stored_and_rebuilt() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let first = dir.path().join("first.fasta");
+        let second = dir.path().join("second.fasta");
+        std::fs::write(&first, ">a\nACGTACGT\n")?;
+        std::fs::write(&second, ">b\nTTTTCCCC\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 4,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 1,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.05,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        db.create_profile(&FastxReader::new(vec![first]), "Test".to_string(), &options)?;
+        let fp_rate: f64 = db.conn.query_row("SELECT fp_rate FROM bloom", [], |row| row.get(0))?;
+        assert_eq!(fp_rate, 0.05);
+
+        // Appending rebuilds the filter so new k-mers are members
+        let (updated, _) = db.update_profile(&FastxReader::new(vec![second]), "Test", TaxonomyLevel::Species, 4, None, None, None)?;
+        let bloom = updated.bloom.unwrap();
+        assert!(bloom.contains(b"ACGT"));
+        assert!(bloom.contains(b"TTTT"));
+
+        assert!(db.remove_profile("Test", None)?);
+        let remaining: i64 = db.conn.query_row("SELECT COUNT(*) FROM bloom", [], |row| row.get(0))?;
+        assert_eq!(remaining, 0);
+
+        Ok(())
+    }
+
+    /// Times storing a 1M k-mer profile (release: ~6.9s with single-row inserts
+    /// and default pragmas, ~2.8s with WAL, NORMAL sync and sorted batched inserts).
+    /// Run with `cargo test --release bench_add_profile_1m_kmers -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_add_profile_1m_kmers() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("bench.db"))?;
+
+        let mut profile = Profile::new("Bench".to_string(), TaxonomyLevel::Species, 21);
+        for i in 0..1_000_000 {
+            profile.frequencies.insert(format!("{:021}", i), 1e-6);
+        }
+        profile.total_kmers = 1_000_000;
+
+        let start = std::time::Instant::now();
+        db.add_profile(&profile)?;
+        println!("Stored {} k-mers in {:?}", profile.frequencies.len(), start.elapsed());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_optimize_reclaims_removed_profiles() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+
+        for name in ["A", "B"] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 21);
+            for i in 0..5_000 {
+                profile.frequencies.insert(format!("{}{:020}", name, i), 1.0 / 5_000.0);
+            }
+            profile.total_kmers = 5_000;
+            db.add_profile(&profile)?;
+        }
+        assert!(db.remove_profile("A", None)?);
+
+        let summary = db.optimize()?;
+        assert!(summary.bytes_after < summary.bytes_before);
+        assert_eq!(summary.bytes_after, std::fs::metadata(&db_path)?.len());
+        assert_eq!(db.get_profile("B", None)?.unwrap().frequencies.len(), 5_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_entropy_drops_low_complexity() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let fasta_path = dir.path().join("ref.fasta");
+        std::fs::write(&fasta_path, ">a\nAAAAAAAA\n>b\nACGTTGCA\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 8,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 1,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 1.5,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        db.create_profile(&FastxReader::new(vec![fasta_path]), "Test".to_string(), &options)?;
+
+        let profile = db.get_profile("Test", None)?.unwrap();
+        assert_eq!(profile.frequencies.len(), 1);
+        assert!((profile.frequencies["ACGTTGCA"] - 1.0).abs() < 1e-9);
+        assert_eq!(profile.min_entropy, Some(1.5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_iupac_weights_profile() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let fasta_path = dir.path().join("consensus.fasta");
+        std::fs::write(&fasta_path, ">consensus\nACRTG\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 3,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 1,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        let reader = FastxReader::new(vec![fasta_path]).expand_iupac(true);
+        db.create_profile(&reader, "Consensus".to_string(), &options)?;
+
+        // Three windows, each split evenly over two k-mers
+        let profile = db.get_profile("Consensus", None)?.unwrap();
+        assert_eq!(profile.total_kmers, 3);
+        assert_eq!(profile.frequencies.len(), 6);
+        assert!(profile.frequencies.values().all(|&freq| (freq - 1.0 / 6.0).abs() < 1e-9));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hash_detects_tampering() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+
+        let mut profile = Profile::new("Hashed".to_string(), TaxonomyLevel::Species, 4);
+        profile.frequencies.insert("AAAA".to_string(), 0.25);
+        profile.frequencies.insert("CCCC".to_string(), 0.75);
+        profile.total_kmers = 4;
+        db.add_profile(&profile)?;
+
+        // The hash written from memory matches the one recomputed from storage
+        let hash = db.verify_profile("Hashed", None)?;
+        assert_eq!(hash, frequencies_hash(&profile.frequencies));
+        assert!(!db.validate(0.01)?.has_errors());
+
+        db.conn.execute("UPDATE kmers SET frequency = 0.5 WHERE kmer = 'AAAA'", [])?;
+        assert!(db.verify_profile("Hashed", None).is_err());
+        assert!(db.validate(0.01)?.has_errors());
+        assert!(db.verify_profile("Missing", None).is_err());
+
+        // Profiles written before the column existed are hashed on open
+        db.conn.execute("UPDATE profiles SET content_hash = NULL", [])?;
+        drop(db);
+        let db = Database::new(&db_path)?;
+        assert_ne!(db.verify_profile("Hashed", None)?, hash);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_profile_from_counts() -> Result<()> {
+        let dir = tempdir()?;
+        let counts_path = dir.path().join("counts.tsv");
+        std::fs::write(&counts_path, "AAAAC\t1\nACGTA\t3\nCCCCG\t4\n")?;
+
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let options = ProfileOptions {
+            kmer_size: 21,
+            level: TaxonomyLevel::Species,
+            sketch_size: 8,
+            min_count: 2,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        let profile = db.create_profile_from_counts(&counts_path, "Dumped".to_string(), &options)?;
+
+        // k comes from the dump, and --min-count applies as for sequence input
+        assert_eq!(profile.k, 5);
+        assert_eq!(profile.total_kmers, 7);
+        let stored = db.get_profile("Dumped", None)?.unwrap();
+        assert_eq!(stored.k, 5);
+        assert_eq!(stored.frequencies.len(), 2);
+        assert!((stored.frequencies["CCCCG"] - 4.0 / 7.0).abs() < 1e-12);
+        assert!(db.create_profile_from_counts(&counts_path, "Dumped".to_string(), &options).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_at_several_kmer_sizes() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.db");
+
+        // A database from before profiles were keyed by name and k
+        let conn = Connection::open(&path)?;
+        conn.execute(
+            "CREATE TABLE profiles (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                taxonomy_level TEXT NOT NULL,
+                k INTEGER NOT NULL,
+                total_kmers INTEGER NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        conn.execute("INSERT INTO profiles (name, taxonomy_level, k, total_kmers) VALUES ('Ecoli', 'Species', 3, 1)", [])?;
+        conn.execute(
+            "CREATE TABLE kmers (
+                profile_id INTEGER,
+                kmer TEXT NOT NULL,
+                frequency REAL NOT NULL,
+                FOREIGN KEY(profile_id) REFERENCES profiles(id)
+            )",
+            [],
+        )?;
+        conn.execute("INSERT INTO kmers VALUES (1, 'AAC', 1.0)", [])?;
+        drop(conn);
+
+        let mut db = Database::new(&path)?;
+        // The migration leaves foreign key enforcement as a new connection has it
+        let foreign_keys = |conn: &Connection| -> rusqlite::Result<bool> {
+            conn.pragma_query_value(None, "foreign_keys", |row| row.get(0))
+        };
+        assert_eq!(foreign_keys(&db.conn)?, foreign_keys(&Connection::open_in_memory()?)?);
+        let mut profile = Profile::new("Ecoli".to_string(), TaxonomyLevel::Species, 4);
+        profile.frequencies.insert("AACG".to_string(), 1.0);
+        profile.total_kmers = 1;
+        db.add_profile(&profile)?;
+
+        assert!(db.get_profile("Ecoli", None).is_err());
+        assert_eq!(db.get_profile("Ecoli", Some(3))?.unwrap().frequencies.len(), 1);
+        assert!(db.get_profile("Ecoli", Some(4))?.unwrap().frequencies.contains_key("AACG"));
+        assert!(db.has_profile("Ecoli", None)? && !db.has_profile("Ecoli", Some(5))?);
+
+        assert!(db.remove_profile("Ecoli", Some(3))?);
+        assert_eq!(db.get_profile("Ecoli", None)?.unwrap().k, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_frequency_tolerance() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+
+        let mut profile = Profile::new("Drifted".to_string(), TaxonomyLevel::Species, 4);
+        profile.frequencies.insert("AAAA".to_string(), 0.5);
+        profile.frequencies.insert("CCCC".to_string(), 0.47);
+        profile.total_kmers = 4;
+        db.add_profile(&profile)?;
+
+        let errors = db.validate(0.01)?.errors().to_vec();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("off 1.0 by -0.030000"), "{}", errors[0]);
+        assert!(!db.validate(0.05)?.has_errors());
+
+        // A sum well short of 1 is lost k-mers, not drift
+        db.conn.execute("DELETE FROM kmers WHERE kmer = 'CCCC'", [])?;
+        let errors = db.validate(0.05)?.errors().to_vec();
+        assert!(errors.iter().any(|error| error.contains("k-mers appear to be missing")), "{:?}", errors);
+
+        assert!(db.validate(-1.0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_mixed_kmer_sizes() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+
+        let profile = |name: &str, k: usize| {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, k);
+            profile.frequencies.insert("A".repeat(k), 1.0);
+            profile.total_kmers = 1;
+            profile
+        };
+        let mixed_sizes = |db: &Database| -> Result<Vec<String>> {
+            Ok(db.validate(0.01)?.warnings().iter()
+                .filter(|warning| warning.contains("different k-mer sizes"))
+                .cloned()
+                .collect())
+        };
+
+        // One organism at two sizes is a deliberate multi-k profile
+        db.add_profile(&profile("Both", 3))?;
+        db.add_profile(&profile("Both", 4))?;
+        assert!(mixed_sizes(&db)?.is_empty());
+
+        db.add_profile(&profile("Short", 3))?;
+        let warnings = mixed_sizes(&db)?;
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("k=3 (2 profiles), k=4 (1 profiles)"), "{}", warnings[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonicalize_profile() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+
+        // AAC and GTT are the two strands of one k-mer
+        let mut profile = Profile::new("Stranded".to_string(), TaxonomyLevel::Species, 3);
+        profile.frequencies.insert("AAC".to_string(), 0.25);
+        profile.frequencies.insert("GTT".to_string(), 0.25);
+        profile.frequencies.insert("TTT".to_string(), 0.5);
+        profile.total_kmers = 4;
+        db.add_profile(&profile)?;
+        let mut protein = Profile::new("Protein".to_string(), TaxonomyLevel::Species, 3);
+        protein.alphabet = Alphabet::Protein;
+        protein.frequencies.insert("MKV".to_string(), 1.0);
+        protein.total_kmers = 1;
+        db.add_profile(&protein)?;
+
+        assert_eq!(db.canonicalize_profile("Stranded", None)?, Canonicalization::Folded);
+        let canonical = db.get_profile("Stranded", None)?.unwrap();
+        assert!(canonical.canonical);
+        assert_eq!(canonical.total_kmers, 4);
+        assert_eq!(canonical.frequencies.len(), 2);
+        assert!((canonical.frequencies["AAC"] - 0.5).abs() < 1e-12);
+        assert!((canonical.frequencies["AAA"] - 0.5).abs() < 1e-12);
+        db.verify_profile("Stranded", None)?;
+        assert_eq!(db.unique_kmer_count("Stranded", None)?, 2);
+
+        // A second run changes nothing
+        assert_eq!(db.canonicalize_profile("Stranded", None)?, Canonicalization::AlreadyCanonical);
+        assert_eq!(db.get_profile("Stranded", None)?.unwrap().frequencies, canonical.frequencies);
+
+        assert!(matches!(db.canonicalize_profile("Protein", None)?, Canonicalization::Unsupported(_)));
+        assert!(!db.get_profile("Protein", None)?.unwrap().canonical);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_database_hash_follows_profiles_not_insertion_order() -> Result<()> {
+        let dir = tempdir()?;
+        let profile = |name: &str, kmer: &str| {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 4);
+            profile.frequencies.insert(kmer.to_string(), 1.0);
+            profile.total_kmers = 1;
+            profile
+        };
+
+        let mut first = Database::new(dir.path().join("first.db"))?;
+        first.add_profile(&profile("A", "AAAA"))?;
+        first.add_profile(&profile("B", "CCCC"))?;
+        let mut second = Database::new(dir.path().join("second.db"))?;
+        second.add_profile(&profile("B", "CCCC"))?;
+        second.add_profile(&profile("A", "AAAA"))?;
+        assert_eq!(first.content_hash()?, second.content_hash()?);
+
+        second.remove_profile("B", None)?;
+        second.add_profile(&profile("B", "GGGG"))?;
+        assert_ne!(first.content_hash()?, second.content_hash()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalization_modes_weight_kmers() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let fasta_path = dir.path().join("ref.fasta");
+        // AAA x3, AAC x1
+        std::fs::write(&fasta_path, ">a\nAAAAAC\n")?;
+
+        let mut options = ProfileOptions {
+            kmer_size: 3,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 1,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        let reader = FastxReader::new(vec![fasta_path]);
+        db.create_profile(&reader, "Occurrence".to_string(), &options)?;
+        options.normalization = Normalization::Presence;
+        db.create_profile(&reader, "Presence".to_string(), &options)?;
+
+        let occurrence = db.get_profile("Occurrence", None)?.unwrap();
+        assert_eq!(occurrence.normalization, Normalization::Occurrence);
+        assert!((occurrence.frequencies["AAA"] - 0.75).abs() < 1e-9);
+        assert!((occurrence.frequencies["AAC"] - 0.25).abs() < 1e-9);
+
+        let presence = db.get_profile("Presence", None)?.unwrap();
+        assert_eq!(presence.normalization, Normalization::Presence);
+        assert_eq!(presence.total_kmers, 4);
+        assert!((presence.frequencies["AAA"] - 0.5).abs() < 1e-9);
+        assert!((presence.frequencies["AAC"] - 0.5).abs() < 1e-9);
+        assert!(!presence.is_comparable(&occurrence));
+
+        // Appending counts would need them, and a presence profile keeps none
+        assert!(db.update_profile(&reader, "Presence", TaxonomyLevel::Species, 3, None, None, None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_shorter_than_k_are_refused() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let fasta_path = dir.path().join("reads.fasta");
+        std::fs::write(&fasta_path, ">r1\nACGTACGTAC\n>r2\nTTGCAACGTA\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 11,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 1,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        let err = db.create_profile(&FastxReader::new(vec![fasta_path]), "Short".to_string(), &options)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("all 2 sequences are shorter than 11 bases"));
+        assert!(db.list_profiles(None)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_resumes_after_counted_inputs() -> Result<()> {
+        let dir = tempdir()?;
+        let mut db = Database::new(dir.path().join("test.db"))?;
+        let first = dir.path().join("first.fasta");
+        let second = dir.path().join("second.fasta");
+        let checkpoint = dir.path().join("test.db.Resumed.k3.checkpoint");
+        std::fs::write(&first, ">a\nACGTACGA\n")?;
+
+        let options = ProfileOptions {
+            kmer_size: 3,
+            level: TaxonomyLevel::Species,
+            sketch_size: 0,
+            min_count: 1,
+            description: None,
+            parent: None,
+            bloom_fp_rate: 0.0,
+            seed: None,
+            minimizer_window: None,
+            min_entropy: 0.0,
+            max_unique_kmers: None,
+            normalization: Normalization::Occurrence,
+        };
+        let reader = FastxReader::new(vec![first.clone(), second.clone()]);
+
+        // The second file is missing, so the build stops after the first
+        assert!(db.create_profile_with_checkpoint(&reader, "Resumed".to_string(), &options, &checkpoint, false).is_err());
+        assert!(checkpoint.exists());
+        let err = db.create_profile_with_checkpoint(&reader, "Resumed".to_string(), &options, &checkpoint, false)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--resume"));
+
+        // A resumed build takes the first file's counts from the checkpoint
+        std::fs::write(&second, ">b\nTTGCA\n")?;
+        std::fs::write(&first, ">a\nGGGGGG\n")?;
+        let (resumed, stats) = db.create_profile_with_checkpoint(&reader, "Resumed".to_string(), &options, &checkpoint, true)?;
+        assert!(!checkpoint.exists());
+        assert_eq!(stats.files.len(), 2);
+
+        std::fs::write(&first, ">a\nACGTACGA\n")?;
+        let (expected, _) = db.create_profile(&reader, "Expected".to_string(), &options)?;
+        assert_eq!(resumed.frequencies, expected.frequencies);
+        assert_eq!(resumed.total_kmers, 9);
+
+        Ok(())
+    }
+    #[test]
+    fn test_kmer_index_is_opt_in() -> Result<()> {
+        let dir = tempdir()?;
+        let db = Database::new(dir.path().join("test.db"))?;
+        let has_index = |db: &Database| -> Result<bool> {
+            Ok(db.conn.query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = 'idx_kmers_kmer'",
+                [],
+                |row| row.get::<_, i64>(0)
+            )? == 1)
+        };
+        assert!(!has_index(&db)?);
+
+        db.set_kmer_index(true)?;
+        assert!(has_index(&db)?);
+        let plan: String = db.conn.query_row(
+            "EXPLAIN QUERY PLAN SELECT profile_id FROM kmers WHERE kmer = 'ACGT'",
+            [],
+            |row| row.get(3)
+        )?;
+        assert!(plan.contains("idx_kmers_kmer"), "{plan}");
+
+        db.set_kmer_index(false)?;
+        assert!(!has_index(&db)?);
+
+        Ok(())
+    }
+}
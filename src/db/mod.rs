@@ -1,5 +1,9 @@
+mod checkpoint;
 mod database;
 mod schemas;
 mod types;
 
-pub use database::Database;
\ No newline at end of file
+pub use database::Database;
+pub(crate) use database::database_content_hash;
+pub(crate) use schemas::is_schema_current;
+pub use types::{Canonicalization, MergeConflict, ProfileOptions};
\ No newline at end of file
@@ -1,18 +1,125 @@
 use rusqlite::{Connection, Result};
 
-pub(crate) fn initialize_schema(conn: &Connection) -> Result<()> {
-    // Create profiles table
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS profiles (
+/// Nullable profile columns added after the original schema, with their types
+pub(crate) const OPTIONAL_PROFILE_COLUMNS: &[(&str, &str)] = &[
+    ("parent", "TEXT"),
+    ("description", "TEXT"),
+    ("source_files", "TEXT"),
+    ("seed", "TEXT"),
+    ("min_entropy", "REAL"),
+    ("homopolymer_compressed", "INTEGER"),
+    ("estimated_coverage", "REAL"),
+    ("alphabet", "TEXT"),
+    ("content_hash", "TEXT"),
+    ("created_by_version", "TEXT"),
+    ("canonical", "INTEGER"),
+    ("minimizer_window", "INTEGER"),
+    ("normalization", "TEXT"),
+    ("min_count", "INTEGER"),
+];
+
+/// Whether `table` in the attached database `schema` has a column named `column`
+pub(crate) fn has_column(conn: &Connection, schema: &str, table: &str, column: &str) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1, ?2) WHERE name = ?3",
+        [table, schema, column],
+        |row| row.get(0)
+    )?;
+    Ok(count > 0)
+}
+
+/// Whether every migration in `initialize_schema` has already been applied, for
+/// read-only handles that cannot migrate the database themselves
+pub(crate) fn is_schema_current(conn: &Connection) -> Result<bool> {
+    let later_tables: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master
+         WHERE type = 'table' AND name IN ('sketches', 'bloom', 'kmer_occurrence')",
+        [],
+        |row| row.get(0)
+    )?;
+    if later_tables < 3 || !has_name_k_key(conn)? {
+        return Ok(false);
+    }
+    for (column, _) in OPTIONAL_PROFILE_COLUMNS {
+        if !has_column(conn, "main", "profiles", column)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Columns of the profiles table before any optional ones; a name may be
+/// profiled once per k-mer size
+fn profiles_table(table: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (
             id INTEGER PRIMARY KEY,
-            name TEXT NOT NULL UNIQUE,
+            name TEXT NOT NULL,
             taxonomy_level TEXT NOT NULL,
             k INTEGER NOT NULL,
             total_kmers INTEGER NOT NULL,
-            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(name, k)
         )",
+        table
+    )
+}
+
+/// Whether the profiles table is keyed by name and k rather than by name alone
+fn has_name_k_key(conn: &Connection) -> Result<bool> {
+    let sql: String = conn.query_row(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'profiles'",
         [],
+        |row| row.get(0)
     )?;
+    Ok(sql.contains("UNIQUE(name, k)"))
+}
+
+/// Add metadata columns to a profiles table created before they existed
+fn add_optional_columns(conn: &Connection, table: &str) -> Result<()> {
+    for (column, column_type) in OPTIONAL_PROFILE_COLUMNS {
+        if !has_column(conn, "main", table, column)? {
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, column_type), [])?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn initialize_schema(conn: &Connection) -> Result<()> {
+    // Create profiles table
+    conn.execute(&profiles_table("profiles"), [])?;
+    add_optional_columns(conn, "profiles")?;
+
+    // Databases from before multiple k-mer sizes per name had `name` UNIQUE.
+    // SQLite cannot drop a constraint, so the table is rebuilt with the same
+    // ids, which the other tables refer to. Foreign key enforcement is
+    // switched off meanwhile (outside the transaction, where the pragma has
+    // effect) so the old table can be dropped from under them, then put back
+    // as the connection had it; it depends on how SQLite was built.
+    if !has_name_k_key(conn)? {
+        let columns = ["id", "name", "taxonomy_level", "k", "total_kmers", "created_at"]
+            .into_iter()
+            .chain(OPTIONAL_PROFILE_COLUMNS.iter().map(|(column, _)| *column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let foreign_keys: bool = conn.pragma_query_value(None, "foreign_keys", |row| row.get(0))?;
+        conn.pragma_update(None, "foreign_keys", false)?;
+        let rebuilt = (|| -> Result<()> {
+            let tx = conn.unchecked_transaction()?;
+            tx.execute(&profiles_table("profiles_rekeyed"), [])?;
+            add_optional_columns(&tx, "profiles_rekeyed")?;
+            tx.execute(
+                &format!("INSERT INTO profiles_rekeyed ({0}) SELECT {0} FROM profiles", columns),
+                [],
+            )?;
+            tx.execute("DROP TABLE profiles", [])?;
+            tx.execute("ALTER TABLE profiles_rekeyed RENAME TO profiles", [])?;
+            tx.commit()?;
+            Ok(())
+        })();
+        conn.pragma_update(None, "foreign_keys", foreign_keys)?;
+        rebuilt?;
+    }
 
     // Create kmers table
     conn.execute(
@@ -26,7 +133,58 @@ pub(crate) fn initialize_schema(conn: &Connection) -> Result<()> {
         [],
     )?;
 
-    // Create indices
+    // Create sketches table (MinHash hashes stored as little-endian u64s)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sketches (
+            profile_id INTEGER PRIMARY KEY,
+            sketch_size INTEGER NOT NULL,
+            hashes BLOB NOT NULL,
+            FOREIGN KEY(profile_id) REFERENCES profiles(id)
+        )",
+        [],
+    )?;
+
+    // Create Bloom filter table (bit words stored as little-endian u64s)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS bloom (
+            profile_id INTEGER PRIMARY KEY,
+            num_bits INTEGER NOT NULL,
+            num_hashes INTEGER NOT NULL,
+            fp_rate REAL NOT NULL,
+            bits BLOB NOT NULL,
+            FOREIGN KEY(profile_id) REFERENCES profiles(id)
+        )",
+        [],
+    )?;
+
+    // Create k-mer occurrence cache (number of profiles containing each k-mer)
+    let has_occurrence_table: bool = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='kmer_occurrence'",
+        [],
+        |row| row.get::<_, i64>(0)
+    )? > 0;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS kmer_occurrence (
+            kmer TEXT PRIMARY KEY,
+            profile_count INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Backfill the cache for databases created before it existed
+    if !has_occurrence_table {
+        conn.execute(
+            "INSERT INTO kmer_occurrence (kmer, profile_count)
+             SELECT kmer, COUNT(DISTINCT profile_id) FROM kmers GROUP BY kmer",
+            [],
+        )?;
+    }
+
+    // Create indices. kmers(kmer) is only indexed on request (db optimize
+    // --kmer-index): per-k-mer lookups across profiles go through
+    // kmer_occurrence's primary key, and the index adds about 30% to
+    // the database size.
     conn.execute(
         "CREATE INDEX IF NOT EXISTS idx_kmers_profile 
          ON kmers(profile_id)",
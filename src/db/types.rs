@@ -1,4 +1,5 @@
-use crate::profile::TaxonomyLevel;
+use crate::kmer::SpacedSeed;
+use crate::profile::{Normalization, TaxonomyLevel};
 
 /// Summary of a profile for listing
 #[derive(Debug)]
@@ -10,10 +11,83 @@ pub struct ProfileSummary {
     pub created_at: String,
 }
 
+/// Settings for building a profile from sequence files
+#[derive(Debug, Clone)]
+pub struct ProfileOptions {
+    pub kmer_size: usize,
+    pub level: TaxonomyLevel,
+    /// Number of hashes in the stored MinHash sketch (0 disables sketching)
+    pub sketch_size: usize,
+    /// K-mers seen fewer times than this are dropped as likely sequencing errors
+    pub min_count: usize,
+    /// Free-text note stored with the profile
+    pub description: Option<String>,
+    /// Profile one taxonomy level up, held to the rules of `Database::link_profiles`
+    pub parent: Option<String>,
+    /// False-positive rate of the stored Bloom filter (0 disables it)
+    pub bloom_fp_rate: f64,
+    /// Spaced seed selecting the k-mer positions within each window
+    pub seed: Option<SpacedSeed>,
+    /// Keep only the minimizer of each window of this many k-mers (all k-mers if None)
+    pub minimizer_window: Option<usize>,
+    /// K-mers with a lower base-composition Shannon entropy are dropped (0 disables it)
+    pub min_entropy: f64,
+    /// Stop adding distinct k-mers once this many are counted (no cap if None)
+    pub max_unique_kmers: Option<usize>,
+    /// Whether frequencies weight k-mers by count or equally
+    pub normalization: Normalization,
+}
+
 /// Database statistics
 #[derive(Debug)]
 pub struct DatabaseStats {
     pub total_profiles: usize,
     pub total_kmers: usize,
     pub profiles_by_level: Vec<(String, usize)>,
-}
\ No newline at end of file
+    /// Detailed statistics, only populated when requested
+    pub kmers_by_profile: Vec<(String, usize)>,
+    pub profiles_by_kmer_size: Vec<(usize, usize)>,
+    pub mean_frequency: f64,
+    pub median_frequency: f64,
+    /// K-mers present in more than one profile
+    pub shared_kmers: usize,
+    /// K-mers present in the most profiles, with their profile counts
+    pub most_polymorphic_kmers: Vec<(String, usize)>,
+}
+
+/// How to handle a profile name that already exists when merging
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// Keep the existing profile and skip the incoming one
+    Skip,
+    /// Add the incoming profile under a suffixed name (`_2`, `_3`, ...)
+    Rename,
+    /// Abort the merge, leaving the target unchanged
+    Error,
+}
+
+/// What canonicalizing a profile did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Canonicalization {
+    /// Both strands of each k-mer were folded together
+    Folded,
+    /// The profile was canonical already and was left alone
+    AlreadyCanonical,
+    /// Protein or spaced-seed k-mers have no strand to fold; holds the reason
+    Unsupported(String),
+}
+
+/// Outcome of merging one database into another
+#[derive(Debug, Default)]
+pub struct MergeSummary {
+    pub added: usize,
+    pub renamed: usize,
+    pub skipped: usize,
+}
+
+/// Database size before and after `Database::optimize`
+#[derive(Debug, Default)]
+pub struct OptimizeSummary {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
@@ -0,0 +1,194 @@
+use std::path::Path;
+use anyhow::{bail, Result};
+
+use super::reader::{FastxReader, FileStats, ReadTally};
+
+#[cfg(feature = "bam")]
+use anyhow::Context;
+#[cfg(feature = "bam")]
+use bio::alphabets::dna::revcomp;
+#[cfg(feature = "bam")]
+use log::info;
+#[cfg(feature = "bam")]
+use needletail::Sequence;
+#[cfg(feature = "bam")]
+use rust_htslib::bam;
+
+#[cfg(feature = "bam")]
+use super::reader::{RecordCounts, PHRED_OFFSET};
+#[cfg(feature = "bam")]
+use crate::kmer::Alphabet;
+
+/// Quality byte htslib stores for a record without base qualities
+#[cfg(feature = "bam")]
+const MISSING_QUALITY: u8 = 0xff;
+
+impl FastxReader {
+    /// Process the primary alignments of a BAM, SAM or CRAM file, passing each
+    /// read to the callback as it came off the sequencer
+    #[cfg(feature = "bam")]
+    pub(super) fn process_alignments<F>(&self, path: &Path, tally: &mut ReadTally, callback: &mut F) -> Result<FileStats>
+    where
+        F: FnMut(&[u8], &str) -> Result<()>
+    {
+        info!("Processing alignments: {}", path.display());
+        if self.sequence_alphabet() == Alphabet::Protein {
+            bail!("{} holds nucleotide reads and cannot be read with --alphabet protein", path.display());
+        }
+
+        let mut counts = RecordCounts::default();
+        let mut stats = FileStats { path: path.display().to_string(), ..FileStats::default() };
+        match &self.region {
+            Some(region) => {
+                let mut reader = bam::IndexedReader::from_path(path).with_context(|| {
+                    format!("Failed to open {} with its index; --region needs a .bai, .csi or .crai next to it", path.display())
+                })?;
+                reader.fetch(region.as_str())
+                    .with_context(|| format!("Region {} is not a reference region of {}", region, path.display()))?;
+                self.read_alignments(&mut reader, tally, &mut stats, &mut counts, callback)?;
+            }
+            None => {
+                let mut reader = bam::Reader::from_path(path)
+                    .with_context(|| format!("Failed to open file: {}", path.display()))?;
+                self.read_alignments(&mut reader, tally, &mut stats, &mut counts, callback)?;
+            }
+        }
+        // htslib reads the file itself, so its bytes are counted once it is done
+        self.progress.inc(std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0));
+
+        Ok(self.finish_file(path, counts, stats))
+    }
+
+    /// Without htslib, alignment files are refused with a hint to rebuild
+    #[cfg(not(feature = "bam"))]
+    pub(super) fn process_alignments<F>(&self, path: &Path, _tally: &mut ReadTally, _callback: &mut F) -> Result<FileStats>
+    where
+        F: FnMut(&[u8], &str) -> Result<()>
+    {
+        bail!(
+            "{} is a BAM/SAM/CRAM file, but this build cannot read alignments; rebuild with `--features bam`",
+            path.display()
+        )
+    }
+
+    /// Pass the reads of every primary record the filters keep to the callback.
+    /// Secondary and supplementary records repeat a primary read and are skipped.
+    #[cfg(feature = "bam")]
+    fn read_alignments<R, F>(
+        &self,
+        reader: &mut R,
+        tally: &mut ReadTally,
+        stats: &mut FileStats,
+        counts: &mut RecordCounts,
+        callback: &mut F,
+    ) -> Result<()>
+    where
+        R: bam::Read,
+        F: FnMut(&[u8], &str) -> Result<()>
+    {
+        let mut record = bam::Record::new();
+        while let Some(result) = reader.read(&mut record) {
+            result.context("Failed to parse alignment record")?;
+            if record.is_secondary() || record.is_supplementary() || record.seq_len() == 0 {
+                continue;
+            }
+            if self.unmapped_only && !record.is_unmapped() {
+                continue;
+            }
+            if self.reached_max_reads(tally) {
+                break;
+            }
+            if !self.sample_next(tally) {
+                continue;
+            }
+
+            let (bases, quality) = sequenced_read(&record);
+            let sequence = self.preprocess(bases.as_slice().normalize(self.expand_iupac), quality.as_deref());
+            let id = String::from_utf8_lossy(record.qname());
+            self.emit_record(&sequence, &id, stats, counts, callback)?;
+        }
+        Ok(())
+    }
+}
+
+/// A record's bases and Phred+33 qualities in sequencing orientation, undoing
+/// the reverse complement stored for reads aligned to the reverse strand
+#[cfg(feature = "bam")]
+fn sequenced_read(record: &bam::Record) -> (Vec<u8>, Option<Vec<u8>>) {
+    let bases = record.seq().as_bytes();
+    let quality = (record.qual().first() != Some(&MISSING_QUALITY))
+        .then(|| record.qual().iter().map(|&score| score.saturating_add(PHRED_OFFSET)).collect::<Vec<u8>>());
+    if !record.is_reverse() {
+        return (bases, quality);
+    }
+    (revcomp(&bases), quality.map(|quality| quality.into_iter().rev().collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    /// One forward read, one reverse read, one unmapped read and a secondary
+    /// alignment repeating the forward read
+    #[cfg(feature = "bam")]
+    const ALIGNMENTS: &str = "\
+@HD\tVN:1.6\tSO:unsorted
+@SQ\tSN:ref\tLN:100
+fwd\t0\tref\t1\t60\t8M\t*\t0\t0\tAACCGGTA\tIIIIIIII
+rev\t16\tref\t20\t60\t8M\t*\t0\t0\tAAAACCCG\tIIIIIII#
+unmapped\t4\t*\t0\t0\t*\t*\t0\t0\tGATTACAG\t*
+fwd\t256\tref\t50\t0\t8M\t*\t0\t0\tAACCGGTA\tIIIIIIII
+";
+
+    fn read_all(reader: &FastxReader) -> Result<Vec<(String, String)>> {
+        let mut reads = Vec::new();
+        reader.process_all(|sequence, id| {
+            reads.push((id.to_string(), String::from_utf8_lossy(sequence).into_owned()));
+            Ok(())
+        })?;
+        Ok(reads)
+    }
+
+    #[cfg(feature = "bam")]
+    #[test]
+    fn test_alignments_are_read_in_sequencing_orientation() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("reads.sam");
+        File::create(&path)?.write_all(ALIGNMENTS.as_bytes())?;
+
+        let reads = read_all(&FastxReader::new(vec![&path]))?;
+        assert_eq!(reads, vec![
+            ("fwd".to_string(), "AACCGGTA".to_string()),
+            ("rev".to_string(), "CGGGTTTT".to_string()),
+            ("unmapped".to_string(), "GATTACAG".to_string()),
+        ]);
+
+        // The low-quality last base of the reverse read is its first as sequenced
+        let reads = read_all(&FastxReader::new(vec![&path]).min_quality(20).strict(true))?;
+        assert!(reads.iter().all(|(id, _)| id != "rev"));
+
+        let reads = read_all(&FastxReader::new(vec![&path]).unmapped_only(true))?;
+        assert_eq!(reads, vec![("unmapped".to_string(), "GATTACAG".to_string())]);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "bam"))]
+    #[test]
+    fn test_alignments_need_bam_feature() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("reads.bam");
+        File::create(&path)?.write_all(b"BAM\x01")?;
+
+        let error = read_all(&FastxReader::new(vec![&path])).unwrap_err();
+        assert!(format!("{:#}", error).contains("--features bam"));
+
+        // Alignment filters make no sense for FASTA/FASTQ input
+        let fasta = dir.path().join("reads.fa");
+        writeln!(File::create(&fasta)?, ">read\nACGT")?;
+        assert!(read_all(&FastxReader::new(vec![&fasta]).unmapped_only(true)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use indicatif::ProgressBar;
+use log::info;
+
+use super::reader::open_maybe_compressed;
+use crate::kmer::Alphabet;
+
+/// Read a `kmer<whitespace>count` dump, as written by `jellyfish dump -c` or
+/// `kmc_dump`, returning the shared k-mer length and the count of each k-mer.
+/// K-mers listed more than once have their counts summed.
+pub fn read_kmer_counts(path: &Path) -> Result<(usize, HashMap<String, f64>)> {
+    let reader = BufReader::new(
+        open_maybe_compressed(path, &ProgressBar::hidden())
+            .with_context(|| format!("Failed to open file: {}", path.display()))?
+    );
+
+    let mut kmer_size = None;
+    let mut counts: HashMap<String, f64> = HashMap::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+        let line_number = index + 1;
+        let mut fields = line.split_whitespace();
+        let (kmer, count) = match (fields.next(), fields.next(), fields.next()) {
+            (None, _, _) => continue,
+            (Some(kmer), Some(count), None) => (kmer.to_ascii_uppercase(), count),
+            _ => bail!("{}:{}: expected `kmer<TAB>count`, got {:?}", path.display(), line_number, line),
+        };
+
+        if !kmer.bytes().all(|base| Alphabet::Dna.is_valid(base)) {
+            bail!("{}:{}: k-mer {} contains non-ACGT characters", path.display(), line_number, kmer);
+        }
+        let count: u64 = count.parse()
+            .with_context(|| format!("{}:{}: invalid count {:?}", path.display(), line_number, count))?;
+
+        match kmer_size {
+            None => kmer_size = Some(kmer.len()),
+            Some(k) if k != kmer.len() => bail!(
+                "{}:{}: k-mer {} has length {}, but earlier k-mers have length {}",
+                path.display(), line_number, kmer, kmer.len(), k
+            ),
+            Some(_) => {}
+        }
+        if count > 0 {
+            *counts.entry(kmer).or_insert(0.0) += count as f64;
+        }
+    }
+
+    let Some(kmer_size) = kmer_size else {
+        bail!("{} contains no k-mer counts", path.display());
+    };
+    info!("Read {} distinct {}-mers from {}", counts.len(), kmer_size, path.display());
+    Ok((kmer_size, counts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_kmer_counts() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("counts.txt");
+        std::fs::write(&path, "ACGT\t3\nacga 2\n\nACGT\t1\nTTTT\t0\n")?;
+
+        let (k, counts) = read_kmer_counts(&path)?;
+        assert_eq!(k, 4);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["ACGT"], 4.0);
+        assert_eq!(counts["ACGA"], 2.0);
+
+        std::fs::write(&path, "ACGT\t3\nACG\t2\n")?;
+        let error = read_kmer_counts(&path).unwrap_err().to_string();
+        assert!(error.contains(":2:"), "{}", error);
+
+        std::fs::write(&path, "ACNT\t3\n")?;
+        assert!(read_kmer_counts(&path).is_err());
+        std::fs::write(&path, "ACGT\tmany\n")?;
+        assert!(read_kmer_counts(&path).is_err());
+        std::fs::write(&path, "")?;
+        assert!(read_kmer_counts(&path).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! JSON manifest of a run: the tool version, command line, inputs with their
+//! checksums, the database's content hash and every setting, so results can
+//! be traced to exactly what produced them.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::profile::TOOL_VERSION;
+
+#[derive(Debug, Serialize)]
+pub struct RunManifest {
+    pub version: &'static str,
+    /// Arguments the tool was invoked with, program name first
+    pub command: Vec<String>,
+    pub started_at: String,
+    pub finished_at: String,
+    pub inputs: Vec<InputFile>,
+    pub database: DatabaseRecord,
+    /// Thresholds, weights and other settings the run used
+    pub parameters: serde_json::Value,
+    /// Profiles the run wrote
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<ProfileRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatabaseRecord {
+    pub path: PathBuf,
+    /// Hash of the profiles' names, k-mer sizes and k-mers, not of the file
+    pub content_hash: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileRecord {
+    pub name: String,
+    pub k: usize,
+    pub content_hash: String,
+}
+
+impl RunManifest {
+    /// Manifest of a run that started at `started_at` and finishes now.
+    /// Each input file is read once to checksum it.
+    pub fn new(
+        started_at: SystemTime,
+        inputs: &[PathBuf],
+        database: DatabaseRecord,
+        parameters: serde_json::Value,
+    ) -> Result<Self> {
+        Ok(RunManifest {
+            version: TOOL_VERSION,
+            command: std::env::args().collect(),
+            started_at: humantime::format_rfc3339_seconds(started_at).to_string(),
+            finished_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            inputs: inputs.iter().map(|path| InputFile::new(path)).collect::<Result<_>>()?,
+            database,
+            parameters,
+            profiles: Vec::new(),
+        })
+    }
+
+    pub fn with_profile(mut self, profile: ProfileRecord) -> Self {
+        self.profiles.push(profile);
+        self
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create manifest {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl InputFile {
+    fn new(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open {} for its checksum", path.display()))?;
+        let size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut hasher = Sha256::new();
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(InputFile { path: path.to_path_buf(), size, sha256: format!("{:x}", hasher.finalize()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_manifest_records_input_checksums() -> Result<()> {
+        let dir = tempdir()?;
+        let input = dir.path().join("sample.fa");
+        std::fs::write(&input, "abc")?;
+
+        let database = DatabaseRecord { path: dir.path().join("p.db"), content_hash: "0".repeat(64) };
+        let manifest = RunManifest::new(SystemTime::now(), std::slice::from_ref(&input), database, serde_json::json!({"k": 21}))?
+            .with_profile(ProfileRecord { name: "E_coli".to_string(), k: 21, content_hash: "1".repeat(64) });
+        let path = dir.path().join("manifest.json");
+        manifest.write(&path)?;
+
+        let written: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(written["version"], TOOL_VERSION);
+        assert_eq!(written["inputs"][0]["size"], 3);
+        assert_eq!(
+            written["inputs"][0]["sha256"],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(written["parameters"]["k"], 21);
+        assert_eq!(written["profiles"][0]["name"], "E_coli");
+        assert!(written["started_at"].as_str().unwrap().ends_with('Z'));
+        Ok(())
+    }
+}
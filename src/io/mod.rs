@@ -1,5 +1,15 @@
-pub mod reader;
-pub mod writer;
+mod bam;
+mod counts;
+mod manifest;
+mod reader;
+mod writer;
 
-pub use reader::FastxReader;
-pub use writer::output_analysis;
\ No newline at end of file
+pub(crate) use counts::read_kmer_counts;
+pub use manifest::{DatabaseRecord, InputFile, ProfileRecord, RunManifest};
+pub(crate) use reader::{splitmix64, unit_interval};
+pub use reader::{dedup_pairs, dedup_paths, FastxReader, FileStats, ReaderStats};
+pub use writer::{
+    export_profile_fasta, export_profile_json, export_profile_mash, export_profile_tsv, output_analysis,
+    output_classification, output_core_kmers, output_kmer_specificity, output_lineage_rollup, output_reader_stats,
+    write_distance_matrix, ParquetExporter, ReportOptions, ReportWriters, DEFAULT_PRECISION,
+};
@@ -1,11 +1,107 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
-use anyhow::{Result, Context};
-use needletail::{parse_fastx_file, Sequence};
-use log::{info, warn};
+use anyhow::{bail, Result, Context};
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+use needletail::{parse_fastx_reader, Sequence};
+use needletail::parser::{FastxReader as RecordReader, SequenceRecord};
+use log::{debug, info, warn};
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
 
-/// Represents a FASTA/FASTQ sequence reader that can handle multiple files
+use crate::kmer::{iupac_bases, Alphabet};
+
+/// Magic bytes at the start of every gzip member
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Magic bytes at the start of every zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Magic bytes at the start of an xz stream
+const XZ_MAGIC: [u8; 6] = [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00];
+
+/// Offset of Phred+33 encoded FASTQ quality characters
+pub(super) const PHRED_OFFSET: u8 = 33;
+
+/// Represents a FASTA/FASTQ sequence reader that can handle multiple files,
+/// and BAM/SAM/CRAM alignments when built with the `bam` feature
+#[derive(Clone)]
 pub struct FastxReader {
-    files: Vec<PathBuf>
+    files: Vec<PathBuf>,
+    /// R1/R2 file pairs for paired-end input
+    pairs: Vec<(PathBuf, PathBuf)>,
+    /// Drop whole sequences containing non-ACGT bases instead of splitting them
+    strict: bool,
+    /// Bases with a Phred score below this are treated as ambiguous
+    min_quality: u8,
+    /// Collapse runs of identical bases to one base before k-mer extraction
+    homopolymer_compress: bool,
+    /// Treat soft-masked (lowercase) bases as ambiguous instead of uppercasing them
+    respect_masking: bool,
+    /// Keep IUPAC ambiguity codes in sequences for the counter to expand
+    pub(super) expand_iupac: bool,
+    /// Residues accepted in k-mers
+    alphabet: Alphabet,
+    /// Fraction of records (or pairs) kept by deterministic subsampling
+    subsample_fraction: f64,
+    /// Seed selecting which records subsampling keeps
+    subsample_seed: u64,
+    /// Stop after this many kept records (or pairs) across all inputs
+    max_reads: Option<usize>,
+    /// Read only alignments overlapping this samtools-style region of BAM/SAM/CRAM input
+    pub(super) region: Option<String>,
+    /// Read only unmapped records of BAM/SAM/CRAM input
+    pub(super) unmapped_only: bool,
+    /// Advanced by the bytes read from each input file
+    pub(super) progress: ProgressBar,
+}
+
+/// Records and bases read from one input file (or R1/R2 pair)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileStats {
+    pub path: String,
+    /// Records passed to the callback, before any splitting at ambiguous bases
+    pub records: usize,
+    /// Records dropped in strict mode for containing invalid characters
+    pub skipped_invalid: usize,
+    /// Bases in the passed records, after preprocessing
+    pub bases: usize,
+}
+
+/// Per-file summary of a pass over all inputs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReaderStats {
+    pub files: Vec<FileStats>,
+}
+
+impl ReaderStats {
+    /// Records passed across all files
+    pub fn total_records(&self) -> usize {
+        self.files.iter().map(|file| file.records).sum()
+    }
+
+    /// Bases passed across all files
+    pub fn total_bases(&self) -> usize {
+        self.files.iter().map(|file| file.bases).sum()
+    }
+}
+
+/// Running record counts for subsampling across all input files
+#[derive(Default)]
+pub(super) struct ReadTally {
+    seen: usize,
+    kept: usize,
+}
+
+/// Sequences passed on, dropped and split while reading one input file
+#[derive(Default)]
+pub(super) struct RecordCounts {
+    sequences: usize,
+    invalid: usize,
+    split: usize,
 }
 
 impl FastxReader {
@@ -16,61 +112,574 @@ impl FastxReader {
             .collect();
         
         FastxReader {
-            files
+            files,
+            pairs: Vec::new(),
+            strict: false,
+            min_quality: 0,
+            homopolymer_compress: false,
+            respect_masking: false,
+            expand_iupac: false,
+            alphabet: Alphabet::Dna,
+            subsample_fraction: 1.0,
+            subsample_seed: 0,
+            max_reads: None,
+            region: None,
+            unmapped_only: false,
+            progress: ProgressBar::hidden(),
+        }
+    }
+
+    /// Create a new FastxReader from R1/R2 file pairs
+    pub fn new_paired(pairs: Vec<(PathBuf, PathBuf)>) -> Self {
+        FastxReader {
+            files: Vec::new(),
+            pairs,
+            strict: false,
+            min_quality: 0,
+            homopolymer_compress: false,
+            respect_masking: false,
+            expand_iupac: false,
+            alphabet: Alphabet::Dna,
+            subsample_fraction: 1.0,
+            subsample_seed: 0,
+            max_reads: None,
+            region: None,
+            unmapped_only: false,
+            progress: ProgressBar::hidden(),
         }
     }
 
-    /// Process each sequence in all input files
-    pub fn process_all<F>(&self, mut callback: F) -> Result<()>
+    /// Number of input files, counting both mates of each pair
+    pub fn file_count(&self) -> usize {
+        self.files.len() + 2 * self.pairs.len()
+    }
+
+    /// Number of inputs: single files plus R1/R2 pairs
+    pub fn input_count(&self) -> usize {
+        self.files.len() + self.pairs.len()
+    }
+
+    /// A reader of only the input at `index`, counting single files before
+    /// pairs as the full reader does, with the same settings
+    pub fn input(&self, index: usize) -> FastxReader {
+        let (files, pairs) = match index.checked_sub(self.files.len()) {
+            None => (vec![self.files[index].clone()], Vec::new()),
+            Some(pair) => (Vec::new(), vec![self.pairs[pair].clone()]),
+        };
+        FastxReader { files, pairs, ..self.clone() }
+    }
+
+    /// Whether subsampling or `max_reads` pick reads by a tally running across
+    /// all inputs, so reading the inputs one at a time would keep other reads
+    pub fn selects_across_inputs(&self) -> bool {
+        self.subsample_fraction < 1.0 || self.max_reads.is_some()
+    }
+
+    /// All input paths, with both mates of each pair in order
+    pub fn paths(&self) -> Vec<&Path> {
+        self.files.iter()
+            .map(PathBuf::as_path)
+            .chain(self.pairs.iter().flat_map(|(r1, r2)| [r1.as_path(), r2.as_path()]))
+            .collect()
+    }
+
+    /// Drop any sequence containing a non-ACGT base rather than splitting it
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Mask FASTQ bases below a Phred quality threshold as ambiguous, so no
+    /// k-mer window includes them (0 disables masking)
+    pub fn min_quality(mut self, min_quality: u8) -> Self {
+        self.min_quality = min_quality;
+        self
+    }
+
+    /// Collapse homopolymer runs (`AAAA` -> `A`) after quality masking, to
+    /// suppress the run-length errors typical of long reads
+    pub fn homopolymer_compress(mut self, homopolymer_compress: bool) -> Self {
+        self.homopolymer_compress = homopolymer_compress;
+        self
+    }
+
+    /// Skip soft-masked (lowercase) regions such as repeat-masked reference
+    /// sequence; by default they are uppercased and counted like any other base
+    pub fn respect_masking(mut self, respect_masking: bool) -> Self {
+        self.respect_masking = respect_masking;
+        self
+    }
+
+    /// Pass IUPAC ambiguity codes (R, Y, ..., but not N) through to callbacks
+    /// instead of splitting sequences at them, for a counter that expands them
+    pub fn expand_iupac(mut self, expand_iupac: bool) -> Self {
+        self.expand_iupac = expand_iupac;
+        self
+    }
+
+    /// Read sequences as `alphabet`. Protein sequences are only uppercased,
+    /// since nucleotide normalization would turn most residues into `N`.
+    pub fn alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.alphabet = alphabet;
+        self
+    }
+
+    /// Keep a deterministic `fraction` of records (pairs for paired input),
+    /// chosen by hashing each record's index with `seed`
+    pub fn subsample(mut self, fraction: f64, seed: u64) -> Self {
+        self.subsample_fraction = fraction;
+        self.subsample_seed = seed;
+        self
+    }
+
+    /// Stop reading once this many records (or pairs) have been kept
+    pub fn max_reads(mut self, max_reads: Option<usize>) -> Self {
+        self.max_reads = max_reads;
+        self
+    }
+
+    /// Read only alignments overlapping a region such as `chr1:1000-2000`
+    /// from BAM/SAM/CRAM input, which must then be indexed
+    pub fn region(mut self, region: Option<String>) -> Self {
+        self.region = region;
+        self
+    }
+
+    /// Read only the unmapped records of BAM/SAM/CRAM input
+    pub fn unmapped_only(mut self, unmapped_only: bool) -> Self {
+        self.unmapped_only = unmapped_only;
+        self
+    }
+
+    /// Whether reads are filtered by alignment, which only BAM/SAM/CRAM input has
+    fn filters_alignments(&self) -> bool {
+        self.region.is_some() || self.unmapped_only
+    }
+
+    /// Report input bytes read (before decompression) on `progress`, whose
+    /// length is set to the combined size of the input files
+    pub fn progress(mut self, progress: ProgressBar) -> Self {
+        let total_bytes = self.paths().iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        progress.set_length(total_bytes);
+        self.progress = progress;
+        self
+    }
+
+    /// Whether sequences are homopolymer-compressed before reaching callbacks
+    pub fn compresses_homopolymers(&self) -> bool {
+        self.homopolymer_compress
+    }
+
+    /// Whether sequences keep IUPAC ambiguity codes for expansion (DNA only)
+    pub fn expands_iupac(&self) -> bool {
+        self.expand_iupac && self.alphabet == Alphabet::Dna
+    }
+
+    /// Residues sequences are read as
+    pub fn sequence_alphabet(&self) -> Alphabet {
+        self.alphabet
+    }
+
+    /// Process each sequence in all input files, returning what was read
+    /// from each
+    pub fn process_all<F>(&self, mut callback: F) -> Result<ReaderStats>
     where
         F: FnMut(&[u8], &str) -> Result<()>
     {
+        let mut tally = ReadTally::default();
+        let mut stats = ReaderStats::default();
         for file in &self.files {
-            self.process_file(file, &mut callback)
+            if self.reached_max_reads(&tally) {
+                break;
+            }
+            let file_stats = self.process_file(file, &mut tally, &mut callback)
                 .with_context(|| format!("Failed to process file: {}", file.display()))?;
+            stats.files.push(file_stats);
         }
-        Ok(())
+        self.report_subsampling(&tally, "reads");
+        Ok(stats)
+    }
+
+    /// Process each read pair in all paired input files. Mates are
+    /// normalized but not split at ambiguous bases; in strict mode a pair is
+    /// dropped if either mate contains one.
+    pub fn process_all_paired<F>(&self, mut callback: F) -> Result<ReaderStats>
+    where
+        F: FnMut(&[u8], &[u8], &str) -> Result<()>
+    {
+        let mut tally = ReadTally::default();
+        let mut stats = ReaderStats::default();
+        for (r1, r2) in &self.pairs {
+            if self.reached_max_reads(&tally) {
+                break;
+            }
+            let pair_stats = self.process_pair(r1, r2, &mut tally, &mut callback)
+                .with_context(|| format!("Failed to process pair: {} / {}", r1.display(), r2.display()))?;
+            stats.files.push(pair_stats);
+        }
+        self.report_subsampling(&tally, "read pairs");
+        Ok(stats)
+    }
+
+    /// Feed every ACGT-only sequence segment from single-end and paired
+    /// inputs to the callback
+    pub fn for_each_sequence<F>(&self, mut callback: F) -> Result<ReaderStats>
+    where
+        F: FnMut(&[u8]) -> Result<()>
+    {
+        let mut stats = self.process_all(|sequence, _id| callback(sequence))?;
+        let paired = self.process_all_paired(|mate1, mate2, _id| {
+            for segment in self.valid_segments(mate1).chain(self.valid_segments(mate2)) {
+                callback(segment)?;
+            }
+            Ok(())
+        })?;
+        stats.files.extend(paired.files);
+        Ok(stats)
+    }
+
+    /// Process a single pair of R1/R2 FASTA/FASTQ files in lockstep
+    fn process_pair<F>(&self, r1_path: &Path, r2_path: &Path, tally: &mut ReadTally, callback: &mut F) -> Result<FileStats>
+    where
+        F: FnMut(&[u8], &[u8], &str) -> Result<()>
+    {
+        info!("Processing pair: {} / {}", r1_path.display(), r2_path.display());
+        if is_alignment_file(r1_path) || is_alignment_file(r2_path) {
+            bail!("BAM/SAM/CRAM input holds both mates of each pair; give it without --paired");
+        }
+        if self.filters_alignments() {
+            bail!("--region and --unmapped-only filter BAM/SAM/CRAM input, which is not read as pairs");
+        }
+
+        let mut r1_reader = open_fastx(r1_path, &self.progress)
+            .with_context(|| format!("Failed to open file: {}", r1_path.display()))?;
+        let mut r2_reader = open_fastx(r2_path, &self.progress)
+            .with_context(|| format!("Failed to open file: {}", r2_path.display()))?;
+
+        let mut num_pairs = 0;
+        let mut num_invalid = 0;
+        let mut num_bases = 0;
+
+        loop {
+            let (r1_record, r2_record) = match (r1_reader.next(), r2_reader.next()) {
+                (Some(r1), Some(r2)) => (
+                    r1.with_context(|| "Failed to parse R1 sequence record")?,
+                    r2.with_context(|| "Failed to parse R2 sequence record")?,
+                ),
+                (None, None) => break,
+                (Some(_), None) => bail!(
+                    "Mismatched record counts: {} has more records than {} (after {} pairs)",
+                    r1_path.display(), r2_path.display(), num_pairs
+                ),
+                (None, Some(_)) => bail!(
+                    "Mismatched record counts: {} has more records than {} (after {} pairs)",
+                    r2_path.display(), r1_path.display(), num_pairs
+                ),
+            };
+
+            if self.reached_max_reads(tally) {
+                break;
+            }
+            if !self.sample_next(tally) {
+                continue;
+            }
+
+            let mate1 = self.preprocess(self.normalize(&r1_record), r1_record.qual());
+            let mate2 = self.preprocess(self.normalize(&r2_record), r2_record.qual());
+            let id = String::from_utf8_lossy(r1_record.id());
+
+            if self.strict && !(mate1.iter().all(|&b| self.is_valid_base(b)) && mate2.iter().all(|&b| self.is_valid_base(b))) {
+                num_invalid += 1;
+                continue;
+            }
+
+            callback(&mate1, &mate2, &id)?;
+            num_pairs += 1;
+            num_bases += mate1.len() + mate2.len();
+        }
+
+        info!("Processed {} read pairs from {} / {}", num_pairs, r1_path.display(), r2_path.display());
+        if num_invalid > 0 {
+            warn!("Skipped {} read pairs containing invalid characters", num_invalid);
+        }
+
+        Ok(FileStats {
+            path: format!("{} / {}", r1_path.display(), r2_path.display()),
+            records: num_pairs,
+            skipped_invalid: num_invalid,
+            bases: num_bases,
+        })
     }
 
     /// Process a single FASTA/FASTQ file
-    fn process_file<F>(&self, path: &Path, callback: &mut F) -> Result<()>
+    fn process_file<F>(&self, path: &Path, tally: &mut ReadTally, callback: &mut F) -> Result<FileStats>
     where
         F: FnMut(&[u8], &str) -> Result<()>
     {
+        if is_alignment_file(path) {
+            return self.process_alignments(path, tally, callback);
+        }
+        if self.filters_alignments() {
+            bail!("--region and --unmapped-only filter BAM/SAM/CRAM input, but {} is not an alignment file", path.display());
+        }
         info!("Processing file: {}", path.display());
         
-        let mut reader = parse_fastx_file(path)
+        let mut reader = open_fastx(path, &self.progress)
             .with_context(|| format!("Failed to open file: {}", path.display()))?;
         
-        let mut num_sequences = 0;
-        let mut num_invalid = 0;
+        let mut counts = RecordCounts::default();
+        let mut stats = FileStats { path: path.display().to_string(), ..FileStats::default() };
 
         while let Some(record) = reader.next() {
             let record = record.with_context(|| "Failed to parse sequence record")?;
-            
-            // Normalize sequence to uppercase and process
-            let sequence = record.normalize(false);
-            let id = String::from_utf8_lossy(record.id());
-            
-            // Check for invalid characters (non-ACGT)
-            if sequence.iter().any(|&b| !matches!(b, b'A' | b'C' | b'G' | b'T')) {
-                num_invalid += 1;
+            if self.reached_max_reads(tally) {
+                break;
+            }
+            if !self.sample_next(tally) {
                 continue;
             }
+            
+            // Normalize sequence to uppercase, mask low-quality bases and compress runs
+            let sequence = self.preprocess(self.normalize(&record), record.qual());
+            let id = String::from_utf8_lossy(record.id());
+            self.emit_record(&sequence, &id, &mut stats, &mut counts, callback)?;
+        }
 
-            callback(&sequence, &id)?;
-            num_sequences += 1;
+        Ok(self.finish_file(path, counts, stats))
+    }
+
+    /// Pass a preprocessed record to the callback whole, or split at its
+    /// ambiguous bases, or drop it when strict
+    pub(super) fn emit_record<F>(
+        &self,
+        sequence: &[u8],
+        id: &str,
+        stats: &mut FileStats,
+        counts: &mut RecordCounts,
+        callback: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(&[u8], &str) -> Result<()>
+    {
+        // Check for invalid characters (non-ACGT)
+        if sequence.iter().all(|&b| self.is_valid_base(b)) {
+            callback(sequence, id)?;
+            counts.sequences += 1;
+            stats.records += 1;
+            stats.bases += sequence.len();
+            return Ok(());
         }
 
-        info!("Processed {} sequences from {}", num_sequences, path.display());
-        if num_invalid > 0 {
-            warn!("Skipped {} sequences containing invalid characters", num_invalid);
+        if self.strict {
+            counts.invalid += 1;
+            return Ok(());
         }
 
+        stats.records += 1;
+        stats.bases += sequence.len();
+
+        // Split into maximal ACGT-only runs at each ambiguous base
+        counts.split += 1;
+        for (idx, segment) in self.valid_segments(sequence).enumerate() {
+            callback(segment, &format!("{}:{}", id, idx))?;
+            counts.sequences += 1;
+        }
         Ok(())
     }
+
+    /// Log what reading a file passed on and record the dropped sequences
+    pub(super) fn finish_file(&self, path: &Path, counts: RecordCounts, mut stats: FileStats) -> FileStats {
+        info!("Processed {} sequences from {}", counts.sequences, path.display());
+        if counts.invalid > 0 {
+            warn!("Skipped {} sequences containing invalid characters", counts.invalid);
+        }
+        if counts.split > 0 {
+            info!("Split {} sequences at ambiguous bases", counts.split);
+        }
+
+        stats.skipped_invalid = counts.invalid;
+        stats
+    }
+
+    pub(super) fn reached_max_reads(&self, tally: &ReadTally) -> bool {
+        self.max_reads.is_some_and(|max_reads| tally.kept >= max_reads)
+    }
+
+    /// Count the next record and decide whether subsampling keeps it
+    pub(super) fn sample_next(&self, tally: &mut ReadTally) -> bool {
+        let index = tally.seen as u64;
+        tally.seen += 1;
+
+        let keep = self.subsample_fraction >= 1.0
+            || unit_interval(splitmix64(self.subsample_seed ^ splitmix64(index))) < self.subsample_fraction;
+        if keep {
+            tally.kept += 1;
+        }
+        keep
+    }
+
+    fn report_subsampling(&self, tally: &ReadTally, unit: &str) {
+        if tally.seen > 0 && (self.subsample_fraction < 1.0 || self.max_reads.is_some()) {
+            info!(
+                "Subsampling kept {} of {} {} ({} skipped)",
+                tally.kept, tally.seen, unit, tally.seen - tally.kept
+            );
+        }
+    }
+
+    /// Maximal runs of valid bases in a sequence
+    fn valid_segments<'s>(&'s self, sequence: &'s [u8]) -> impl Iterator<Item = &'s [u8]> + 's {
+        sequence
+            .split(|&b| !self.is_valid_base(b))
+            .filter(|segment| !segment.is_empty())
+    }
+
+    /// A residue of the alphabet, or an IUPAC ambiguity code kept for expansion
+    fn is_valid_base(&self, base: u8) -> bool {
+        self.alphabet.is_valid(base) || (self.expands_iupac() && iupac_bases(base).is_some())
+    }
+
+    /// Uppercase a record's sequence and strip line breaks. When respecting
+    /// masking, lowercase bases become `N` first so no k-mer spans them.
+    fn normalize<'a>(&self, record: &'a SequenceRecord<'a>) -> Cow<'a, [u8]> {
+        if self.alphabet == Alphabet::Protein {
+            let mask = self.alphabet.mask_symbol();
+            return record.seq().iter()
+                .map(|&residue| match residue {
+                    _ if self.respect_masking && residue.is_ascii_lowercase() => mask,
+                    _ => residue.to_ascii_uppercase(),
+                })
+                .collect();
+        }
+        if !self.respect_masking {
+            return record.normalize(self.expand_iupac);
+        }
+        let masked: Vec<u8> = record.raw_seq().iter()
+            .map(|&base| if base.is_ascii_lowercase() { b'N' } else { base })
+            .collect();
+        Cow::Owned(masked.as_slice().normalize(self.expand_iupac).into_owned())
+    }
+
+    /// Apply quality masking and, if enabled, homopolymer compression
+    pub(super) fn preprocess<'a>(&self, sequence: Cow<'a, [u8]>, quality: Option<&[u8]>) -> Cow<'a, [u8]> {
+        let sequence = self.mask_low_quality(sequence, quality);
+        if self.homopolymer_compress {
+            Cow::Owned(compress_homopolymers(&sequence))
+        } else {
+            sequence
+        }
+    }
+
+    /// Mask bases whose Phred score is below the minimum quality (`N`, or `X` for protein)
+    fn mask_low_quality<'a>(&self, sequence: Cow<'a, [u8]>, quality: Option<&[u8]>) -> Cow<'a, [u8]> {
+        match quality {
+            Some(quality) if self.min_quality > 0 => {
+                let mut masked = sequence.into_owned();
+                for (base, &score) in masked.iter_mut().zip(quality) {
+                    if score.saturating_sub(PHRED_OFFSET) < self.min_quality {
+                        *base = self.alphabet.mask_symbol();
+                    }
+                }
+                Cow::Owned(masked)
+            }
+            _ => sequence,
+        }
+    }
+}
+
+/// SplitMix64 finalizer, a cheap well-mixed hash of a 64-bit value
+pub(crate) fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Map a hash onto [0, 1) using its top 53 bits
+pub(crate) fn unit_interval(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Collapse each run of identical bases to a single base
+fn compress_homopolymers(sequence: &[u8]) -> Vec<u8> {
+    let mut compressed = sequence.to_vec();
+    compressed.dedup();
+    compressed
+}
+
+/// Whether a path names BAM, SAM or CRAM alignments, by extension
+pub(super) fn is_alignment_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ["bam", "sam", "cram"].iter().any(|format| ext.eq_ignore_ascii_case(format)))
+}
+
+/// Open a FASTA/FASTQ file through [`open_maybe_compressed`]
+fn open_fastx(path: &Path, progress: &ProgressBar) -> Result<Box<dyn RecordReader>> {
+    Ok(parse_fastx_reader(open_maybe_compressed(path, progress)?)?)
+}
+
+/// Open a file as a byte stream, decompressing gzip (including multi-member
+/// bgzip output), zstd or xz when detected by extension or magic bytes.
+/// Bytes are counted on `progress` as they come off disk.
+pub(crate) fn open_maybe_compressed(path: &Path, progress: &ProgressBar) -> Result<Box<dyn Read + Send>> {
+    let mut file = BufReader::new(progress.wrap_read(File::open(path)?));
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let header = file.fill_buf()?;
+
+    let reader: Box<dyn Read + Send> = if extension == Some("gz") || header.starts_with(&GZIP_MAGIC) {
+        debug!("Decompressing gzip input: {}", path.display());
+        Box::new(MultiGzDecoder::new(file))
+    } else if extension == Some("zst") || header.starts_with(&ZSTD_MAGIC) {
+        debug!("Decompressing zstd input: {}", path.display());
+        Box::new(zstd::Decoder::with_buffer(file)?)
+    } else if extension == Some("xz") || header.starts_with(&XZ_MAGIC) {
+        debug!("Decompressing xz input: {}", path.display());
+        Box::new(XzDecoder::new_multi_decoder(file))
+    } else {
+        Box::new(file)
+    };
+
+    Ok(reader)
+}
+
+/// Drop input files given more than once, keeping the first. Paths are
+/// compared after canonicalization, so `a.fa`, `./a.fa` and a symlink to it
+/// are one file; paths that cannot be resolved are compared as given.
+pub fn dedup_paths(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    files.into_iter()
+        .filter(|file| first_occurrence(&mut seen, &[file]))
+        .collect()
+}
+
+/// Drop R1/R2 pairs given more than once, as [`dedup_paths`] does for files
+pub fn dedup_pairs(pairs: Vec<(PathBuf, PathBuf)>) -> Vec<(PathBuf, PathBuf)> {
+    let mut seen = HashSet::new();
+    pairs.into_iter()
+        .filter(|(r1, r2)| first_occurrence(&mut seen, &[r1, r2]))
+        .collect()
+}
+
+fn first_occurrence(seen: &mut HashSet<Vec<PathBuf>>, paths: &[&PathBuf]) -> bool {
+    let key = paths.iter()
+        .map(|path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf()))
+        .collect();
+    if seen.insert(key) {
+        return true;
+    }
+    let display: Vec<_> = paths.iter().map(|path| path.display().to_string()).collect();
+    warn!(
+        "Input {} was given more than once and is read once; pass --allow-duplicates to count every copy",
+        display.join(" / ")
+    );
+    false
 }
 
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,4 +741,363 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_split_on_ambiguous_bases() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test.fasta");
+        let mut file = File::create(&file_path)?;
+
+        writeln!(file, ">seq1\nACGTNGGCCNNTTAN")?;
+
+        let reader = FastxReader::new(vec![file_path]);
+        let mut sequences = Vec::new();
+        let mut ids = Vec::new();
+
+        reader.process_all(|seq, id| {
+            sequences.push(seq.to_vec());
+            ids.push(id.to_string());
+            Ok(())
+        })?;
+
+        assert_eq!(sequences, vec![b"ACGT".to_vec(), b"GGCC".to_vec(), b"TTA".to_vec()]);
+        assert_eq!(ids, vec!["seq1:0", "seq1:1", "seq1:2"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_drops_ambiguous_sequences() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test.fasta");
+        let mut file = File::create(&file_path)?;
+
+        writeln!(file, ">seq1\nACGTNGGCC\n>seq2\nGTCA")?;
+
+        let reader = FastxReader::new(vec![file_path]).strict(true);
+        let mut sequences = Vec::new();
+
+        reader.process_all(|seq, _id| {
+            sequences.push(seq.to_vec());
+            Ok(())
+        })?;
+
+        assert_eq!(sequences, vec![b"GTCA".to_vec()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_stats_per_file() -> Result<()> {
+        let dir = tempdir()?;
+        let first = dir.path().join("first.fasta");
+        let second = dir.path().join("second.fasta");
+        std::fs::write(&first, ">seq1\nACGTNGGCC\n>seq2\nGTCA\n")?;
+        std::fs::write(&second, ">seq3\nACGTACGT\n")?;
+
+        let stats = FastxReader::new(vec![first.clone(), second.clone()])
+            .strict(true)
+            .process_all(|_, _| Ok(()))?;
+
+        assert_eq!(stats.files, vec![
+            FileStats { path: first.display().to_string(), records: 1, skipped_invalid: 1, bases: 4 },
+            FileStats { path: second.display().to_string(), records: 1, skipped_invalid: 0, bases: 8 },
+        ]);
+        assert_eq!(stats.total_records(), 2);
+        assert_eq!(stats.total_bases(), 12);
+
+        // Without strict mode the ambiguous record is split, not skipped
+        let stats = FastxReader::new(vec![first]).process_all(|_, _| Ok(()))?;
+        assert_eq!(stats.files[0].records, 2);
+        assert_eq!(stats.files[0].skipped_invalid, 0);
+        assert_eq!(stats.files[0].bases, 13);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_matches_plain() -> Result<()> {
+        use crate::kmer::KmerCounter;
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let dir = tempdir()?;
+        let contents = ">seq1\nACGTACGTTTGACCA\n>seq2\nGGCATTACGATCAGT\n";
+
+        let plain_path = dir.path().join("test.fasta");
+        std::fs::write(&plain_path, contents)?;
+
+        // Write each record as its own gzip member, as bgzip does
+        let gz_path = dir.path().join("test.fasta.gz");
+        let mut gz_file = File::create(&gz_path)?;
+        for record in [">seq1\nACGTACGTTTGACCA\n", ">seq2\nGGCATTACGATCAGT\n"] {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(record.as_bytes())?;
+            gz_file.write_all(&encoder.finish()?)?;
+        }
+
+        let count = |path: PathBuf| -> Result<KmerCounter> {
+            let counter = KmerCounter::new(5);
+            FastxReader::new(vec![path]).process_all(|seq, _id| counter.count_sequence(seq))?;
+            Ok(counter)
+        };
+
+        let plain = count(plain_path)?;
+        let gzipped = count(gz_path)?;
+
+        assert_eq!(gzipped.total_kmers(), plain.total_kmers());
+        assert_eq!(gzipped.get_counts(), plain.get_counts());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_and_xz_match_plain() -> Result<()> {
+        use crate::kmer::KmerCounter;
+        use xz2::write::XzEncoder;
+
+        let dir = tempdir()?;
+        let contents = ">seq1\nACGTACGTTTGACCA\n>seq2\nGGCATTACGATCAGT\n";
+
+        let plain_path = dir.path().join("test.fasta");
+        std::fs::write(&plain_path, contents)?;
+
+        let zst_path = dir.path().join("test.fasta.zst");
+        std::fs::write(&zst_path, zstd::encode_all(contents.as_bytes(), 0)?)?;
+
+        let xz_path = dir.path().join("test.fasta.xz");
+        let mut encoder = XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(contents.as_bytes())?;
+        std::fs::write(&xz_path, encoder.finish()?)?;
+
+        // Detected by magic bytes even without the extension
+        let xz_no_ext = dir.path().join("test_xz.fasta");
+        std::fs::copy(&xz_path, &xz_no_ext)?;
+
+        let count = |path: PathBuf| -> Result<KmerCounter> {
+            let counter = KmerCounter::new(5);
+            FastxReader::new(vec![path]).process_all(|seq, _id| counter.count_sequence(seq))?;
+            Ok(counter)
+        };
+
+        let plain = count(plain_path)?;
+        for path in [zst_path, xz_path, xz_no_ext] {
+            let compressed = count(path)?;
+            assert_eq!(compressed.total_kmers(), plain.total_kmers());
+            assert_eq!(compressed.get_counts(), plain.get_counts());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_paired() -> Result<()> {
+        let dir = tempdir()?;
+        let r1_path = dir.path().join("sample_R1.fastq");
+        let r2_path = dir.path().join("sample_R2.fastq");
+        std::fs::write(&r1_path, "@read1/1\nACGT\n+\nIIII\n@read2/1\nGGCC\n+\nIIII\n")?;
+        std::fs::write(&r2_path, "@read1/2\nTTAA\n+\nIIII\n@read2/2\nCCGG\n+\nIIII\n")?;
+
+        let reader = FastxReader::new_paired(vec![(r1_path, r2_path)]);
+        let mut pairs = Vec::new();
+
+        reader.process_all_paired(|mate1, mate2, id| {
+            pairs.push((mate1.to_vec(), mate2.to_vec(), id.to_string()));
+            Ok(())
+        })?;
+
+        assert_eq!(pairs, vec![
+            (b"ACGT".to_vec(), b"TTAA".to_vec(), "read1/1".to_string()),
+            (b"GGCC".to_vec(), b"CCGG".to_vec(), "read2/1".to_string()),
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_paired_mismatched_counts() -> Result<()> {
+        let dir = tempdir()?;
+        let r1_path = dir.path().join("sample_R1.fasta");
+        let r2_path = dir.path().join("sample_R2.fasta");
+        std::fs::write(&r1_path, ">read1\nACGT\n>read2\nGGCC\n")?;
+        std::fs::write(&r2_path, ">read1\nTTAA\n")?;
+
+        let reader = FastxReader::new_paired(vec![(r1_path, r2_path)]);
+        let err = reader.process_all_paired(|_, _, _| Ok(())).unwrap_err();
+        let message = format!("{:#}", err);
+
+        assert!(message.contains("sample_R1.fasta has more records than"));
+        assert!(message.contains("sample_R2.fasta"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_quality_masks_bases() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test.fastq");
+        // '#' is Phred 2, 'I' is Phred 40
+        std::fs::write(&file_path, "@read1\nACGTACGT\n+\nIII#IIII\n")?;
+
+        let reader = FastxReader::new(vec![file_path]).min_quality(20);
+        let mut sequences = Vec::new();
+
+        reader.process_all(|seq, _id| {
+            sequences.push(seq.to_vec());
+            Ok(())
+        })?;
+
+        assert_eq!(sequences, vec![b"ACG".to_vec(), b"ACGT".to_vec()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_homopolymer_compression() -> Result<()> {
+        assert_eq!(compress_homopolymers(b"AAAATTTTG"), b"ATG");
+
+        let dir = tempdir()?;
+        let file_path = dir.path().join("long_reads.fasta");
+        std::fs::write(&file_path, ">read\nAAAATTTTG\n")?;
+
+        let reader = FastxReader::new(vec![file_path]).homopolymer_compress(true);
+        let counter = crate::kmer::KmerCounter::new(2);
+        counter.count_from_reader(&reader)?;
+
+        let mut kmers: Vec<_> = counter.get_counts().into_keys().collect();
+        kmers.sort();
+        assert_eq!(kmers, vec!["AT".to_string(), "TG".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_subsampling_is_deterministic() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("reads.fasta");
+        let mut file = File::create(&file_path)?;
+        for i in 0..1000 {
+            writeln!(file, ">read{}\nACGTACGT", i)?;
+        }
+
+        let kept_ids = |reader: FastxReader| -> Result<Vec<String>> {
+            let mut ids = Vec::new();
+            reader.process_all(|_seq, id| {
+                ids.push(id.to_string());
+                Ok(())
+            })?;
+            Ok(ids)
+        };
+
+        let first = kept_ids(FastxReader::new(vec![&file_path]).subsample(0.25, 7))?;
+        let second = kept_ids(FastxReader::new(vec![&file_path]).subsample(0.25, 7))?;
+        let other_seed = kept_ids(FastxReader::new(vec![&file_path]).subsample(0.25, 8))?;
+        assert_eq!(first, second);
+        assert_ne!(first, other_seed);
+        assert!((200..300).contains(&first.len()), "kept {}", first.len());
+
+        let capped = kept_ids(FastxReader::new(vec![&file_path]).subsample(0.25, 7).max_reads(Some(10)))?;
+        assert_eq!(capped, first[..10]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_soft_masked_bases() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("masked.fasta");
+        std::fs::write(&file_path, ">chr1\nACGTacgt\nacGGCC\n")?;
+
+        let sequences = |reader: FastxReader| -> Result<Vec<Vec<u8>>> {
+            let mut sequences = Vec::new();
+            reader.process_all(|seq, _id| {
+                sequences.push(seq.to_vec());
+                Ok(())
+            })?;
+            Ok(sequences)
+        };
+
+        // Lowercase is counted by default...
+        let all = sequences(FastxReader::new(vec![&file_path]))?;
+        assert_eq!(all, vec![b"ACGTACGTACGGCC".to_vec()]);
+
+        // ...and skipped when masking is respected
+        let unmasked = sequences(FastxReader::new(vec![&file_path]).respect_masking(true))?;
+        assert_eq!(unmasked, vec![b"ACGT".to_vec(), b"GGCC".to_vec()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_iupac_keeps_ambiguity_codes() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("consensus.fasta");
+        std::fs::write(&file_path, ">consensus\nACRTGnGGCC\n")?;
+
+        let sequences = |reader: FastxReader| -> Result<Vec<Vec<u8>>> {
+            let mut sequences = Vec::new();
+            reader.process_all(|seq, _id| {
+                sequences.push(seq.to_vec());
+                Ok(())
+            })?;
+            Ok(sequences)
+        };
+
+        // N still splits the sequence; R survives only when expanding
+        let expanded = sequences(FastxReader::new(vec![&file_path]).expand_iupac(true))?;
+        assert_eq!(expanded, vec![b"ACRTG".to_vec(), b"GGCC".to_vec()]);
+        let plain = sequences(FastxReader::new(vec![&file_path]))?;
+        assert_eq!(plain, vec![b"AC".to_vec(), b"TG".to_vec(), b"GGCC".to_vec()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_protein_alphabet() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("proteins.fasta");
+        std::fs::write(&file_path, ">p1\nMKVLW*\nmkXLL\n")?;
+
+        let reader = FastxReader::new(vec![file_path]).alphabet(Alphabet::Protein);
+        let mut sequences = Vec::new();
+        reader.process_all(|seq, _id| {
+            sequences.push(seq.to_vec());
+            Ok(())
+        })?;
+
+        // Stop codons and unknown residues split the sequence
+        assert_eq!(sequences, vec![b"MKVLW".to_vec(), b"MK".to_vec(), b"LL".to_vec()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_inputs_are_read_once() -> Result<()> {
+        use crate::kmer::KmerCounter;
+
+        let dir = tempdir()?;
+        let path = dir.path().join("test.fasta");
+        std::fs::write(&path, ">seq1\nACGTACGTTTGACCA\n")?;
+        // The same file through a `./` component
+        let dotted = dir.path().join(".").join("test.fasta");
+
+        let count = |files: Vec<PathBuf>| -> Result<KmerCounter> {
+            let counter = KmerCounter::new(5);
+            FastxReader::new(files).process_all(|seq, _id| counter.count_sequence(seq))?;
+            Ok(counter)
+        };
+
+        let single = count(vec![path.clone()])?;
+        let deduped = count(dedup_paths(vec![path.clone(), dotted.clone()]))?;
+        assert_eq!(deduped.total_kmers(), single.total_kmers());
+        assert_eq!(deduped.get_counts(), single.get_counts());
+
+        // Opting out reads both copies
+        let doubled = count(vec![path.clone(), dotted])?;
+        assert_eq!(doubled.total_kmers(), 2 * single.total_kmers());
+
+        let pairs = dedup_pairs(vec![(path.clone(), path.clone()), (path.clone(), path)]);
+        assert_eq!(pairs.len(), 1);
+        Ok(())
+    }
+}
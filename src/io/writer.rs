@@ -1,28 +1,826 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::fs::File;
 use std::io::{Seek, Write};
-use anyhow::Result;
-use crate::profile::types::ProfileMatch;
-use crate::profile::analyzer::ProfileAnalyzer;
-use crate::kmer::KmerCounter;
+use std::sync::Arc;
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use anyhow::{bail, Result};
+use serde::Serialize;
+use crate::cli::{MatrixFormat, OutputFormat};
+use crate::profile::{
+    AnalysisStatistics, Classification, CoreKmers, DetailedAnalysis, KmerSpecificity, LineageRollup, Profile,
+    ProfileAnalyzer, ProfileMatch, SharedKmer,
+};
+use super::reader::{FileStats, ReaderStats};
+use crate::kmer::{Alphabet, KmerCounter, MinHashSketch, DEFAULT_SKETCH_SIZE, MASH_HASH_SEED, MASH_MAX_32BIT_K};
 
-pub fn output_analysis(
+/// Decimal places of frequencies and scores when no precision is given
+pub const DEFAULT_PRECISION: usize = 6;
+
+/// Options controlling how analysis results are written
+#[derive(Debug, Clone, Copy)]
+pub struct ReportOptions {
+    pub format: OutputFormat,
+    pub detailed: bool,
+    /// Add an abundance column to TSV and text reports
+    pub abundance: bool,
+    /// Add a weighted shared k-mer column to TSV and text reports
+    pub weighted_shared: bool,
+    /// Add bootstrap interval columns to TSV and text reports
+    pub bootstrap: bool,
+    /// Decimal places of frequencies and scores; when set, JSON numbers are
+    /// rounded to it too instead of written in full
+    pub precision: Option<usize>,
+}
+
+impl ReportOptions {
+    /// Decimal places of tabular frequencies and scores
+    pub fn decimals(&self) -> usize {
+        self.precision.unwrap_or(DEFAULT_PRECISION)
+    }
+}
+
+/// JSON report for a single sample
+#[derive(Serialize)]
+struct SampleReport<'a> {
+    sample: &'a str,
+    total_kmers: usize,
+    unique_kmers: usize,
+    kmer_size: usize,
+    matches: Vec<MatchReport<'a>>,
+}
+
+/// JSON report for a single profile match
+#[derive(Serialize)]
+struct MatchReport<'a> {
+    #[serde(flatten)]
+    profile_match: &'a ProfileMatch,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detailed: Option<DetailedReport>,
+}
+
+/// One line of JSON Lines output: a match and the sample it was found in
+#[derive(Serialize)]
+struct MatchLine<'a> {
+    sample: &'a str,
+    #[serde(flatten)]
+    report: MatchReport<'a>,
+}
+
+/// JSON summary of a detailed analysis
+#[derive(Serialize)]
+struct DetailedReport {
+    statistics: AnalysisStatistics,
+    top_shared_kmers: Vec<SharedKmer>,
+}
+
+/// JSON report for a hierarchical classification
+#[derive(Serialize)]
+struct ClassificationReport<'a> {
+    sample: &'a str,
+    #[serde(flatten)]
+    classification: &'a Classification,
+}
+
+/// JSON report of the ancestors implied by a sample's matches
+#[derive(Serialize)]
+struct LineageReport<'a> {
+    sample: &'a str,
+    lineage_rollup: &'a [LineageRollup],
+}
+
+/// One line of JSON Lines output per ancestor implied by a sample's matches
+#[derive(Serialize)]
+struct LineageLine<'a> {
+    sample: &'a str,
+    #[serde(flatten)]
+    rollup: &'a LineageRollup,
+}
+
+/// JSON report of what was read from a sample's input files
+#[derive(Serialize)]
+struct InputReport<'a> {
+    sample: &'a str,
+    files: &'a [FileStats],
+}
+
+/// JSON document for an exported profile
+#[derive(Serialize)]
+struct ProfileExport<'a> {
+    name: &'a str,
+    level: String,
+    k: usize,
+    total_kmers: usize,
+    created_at: Option<&'a str>,
+    frequencies: OrderedFrequencies<'a>,
+}
+
+/// Sketch file in the JSON layout of `mash info -d`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MashExport<'a> {
+    kmer: usize,
+    alphabet: &'static str,
+    preserve_case: bool,
+    canonical: bool,
+    sketch_size: usize,
+    hash_type: &'static str,
+    hash_bits: u32,
+    hash_seed: u32,
+    sketches: [MashSketch<'a>; 1],
+}
+
+#[derive(Serialize)]
+struct MashSketch<'a> {
+    name: &'a str,
+    length: usize,
+    comment: &'a str,
+    hashes: &'a [u64],
+}
+
+/// K-mer frequencies serialized as a JSON object in a fixed order
+struct OrderedFrequencies<'a>(Vec<(&'a String, &'a f64)>);
+
+impl Serialize for OrderedFrequencies<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (kmer, frequency) in &self.0 {
+            map.serialize_entry(kmer, frequency)?;
+        }
+        map.end()
+    }
+}
+
+/// Write a profile as a JSON document, with k-mers sorted by frequency
+/// (descending) then sequence so repeated exports diff cleanly
+pub fn export_profile_json(profile: &Profile, precision: Option<usize>, writer: &mut impl Write) -> Result<()> {
+    let frequencies = profile.sorted_frequencies();
+
+    let export = ProfileExport {
+        name: &profile.name,
+        level: profile.level.to_string(),
+        k: profile.k,
+        total_kmers: profile.total_kmers,
+        created_at: profile.created_at.as_deref(),
+        frequencies: OrderedFrequencies(frequencies),
+    };
+
+    write_json_document(&export, precision, writer)
+}
+
+/// Write a profile's MinHash sketch in the JSON layout `mash info -d` prints,
+/// re-hashed with Mash's MurmurHash3 so it compares against Mash sketches.
+/// The sketch keeps the size of the profile's stored sketch; `length` is the
+/// profile's k-mer total, standing in for the genome length Mash records.
+pub fn export_profile_mash(profile: &Profile, writer: &mut impl Write) -> Result<()> {
+    if let Some(seed) = &profile.seed {
+        bail!("Profile {} uses spaced seed {}; Mash sketches need contiguous k-mers", profile.name, seed);
+    }
+    if profile.homopolymer_compressed {
+        bail!("Profile {} was homopolymer-compressed, which Mash sketches do not support", profile.name);
+    }
+    if let Some(w) = profile.minimizer_window {
+        bail!("Profile {} keeps only minimizers (window {}); Mash sketches need every k-mer", profile.name, w);
+    }
+
+    let sketch_size = profile.sketch.as_ref().map_or(DEFAULT_SKETCH_SIZE, MinHashSketch::size);
+    let canonical = profile.alphabet == Alphabet::Dna;
+    let sketch = MinHashSketch::from_kmers_mash(
+        profile.frequencies.keys().map(|kmer| kmer.as_bytes()),
+        sketch_size,
+        canonical,
+    );
+
+    let export = MashExport {
+        kmer: profile.k,
+        alphabet: match profile.alphabet {
+            Alphabet::Dna => "ACGT",
+            Alphabet::Protein => "ACDEFGHIKLMNPQRSTVWY",
+        },
+        preserve_case: false,
+        canonical,
+        sketch_size,
+        hash_type: "MurmurHash3_x64_128",
+        hash_bits: if profile.k > MASH_MAX_32BIT_K { 64 } else { 32 },
+        hash_seed: MASH_HASH_SEED,
+        sketches: [MashSketch {
+            name: &profile.name,
+            length: profile.total_kmers,
+            comment: profile.description.as_deref().unwrap_or(""),
+            hashes: sketch.hashes(),
+        }],
+    };
+
+    serde_json::to_writer_pretty(&mut *writer, &export)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Write a profile's k-mers as FASTA records headed by the profile name and
+/// frequency, in the same order as the JSON export
+pub fn export_profile_fasta(profile: &Profile, precision: Option<usize>, writer: &mut impl Write) -> Result<()> {
+    let decimals = precision.unwrap_or(DEFAULT_PRECISION);
+    for (kmer, frequency) in profile.sorted_frequencies() {
+        writeln!(writer, ">{} {:.*}", profile.name, decimals, frequency)?;
+        writeln!(writer, "{}", kmer)?;
+    }
+    Ok(())
+}
+
+/// Write a profile's k-mers as a `kmer`/`frequency` TSV, in the same order as the JSON export
+pub fn export_profile_tsv(profile: &Profile, precision: Option<usize>, writer: &mut impl Write) -> Result<()> {
+    let decimals = precision.unwrap_or(DEFAULT_PRECISION);
+    writeln!(writer, "kmer\tfrequency")?;
+    for (kmer, frequency) in profile.sorted_frequencies() {
+        writeln!(writer, "{}\t{:.*}", kmer, decimals, frequency)?;
+    }
+    Ok(())
+}
+
+/// Writes profiles' k-mers into one Parquet file with columns `profile_name`,
+/// `level`, `kmer`, `count` and `frequency`. Each profile is written as its
+/// own record batch, and row groups are flushed as they fill, so only the
+/// current profile and row group are held in memory.
+pub struct ParquetExporter<W: Write + Send> {
+    schema: SchemaRef,
+    writer: ArrowWriter<W>,
+}
+
+impl<W: Write + Send> ParquetExporter<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("profile_name", DataType::Utf8, false),
+            Field::new("level", DataType::Utf8, false),
+            Field::new("kmer", DataType::Utf8, false),
+            Field::new("count", DataType::UInt64, true),
+            Field::new("frequency", DataType::Float64, false),
+        ]));
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+        Ok(ParquetExporter { schema, writer })
+    }
+
+    /// Append a profile's k-mers, by descending frequency. Counts are
+    /// recovered from the frequencies, as in `db kmers`, and null for
+    /// presence profiles.
+    pub fn write_profile(&mut self, profile: &Profile) -> Result<()> {
+        let frequencies = profile.sorted_frequencies();
+        let rows = frequencies.len();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec![profile.name.as_str(); rows])),
+            Arc::new(StringArray::from(vec![profile.level.to_string(); rows])),
+            Arc::new(StringArray::from_iter_values(frequencies.iter().map(|(kmer, _)| kmer.as_str()))),
+            Arc::new(UInt64Array::from_iter(frequencies.iter().map(|(_, freq)| profile.kmer_count(**freq)))),
+            Arc::new(Float64Array::from_iter_values(frequencies.iter().map(|(_, freq)| **freq))),
+        ];
+        self.writer.write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+        Ok(())
+    }
+
+    /// Write the file footer; the file is unreadable without it
+    pub fn finish(self) -> Result<()> {
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+/// Write a symmetric distance matrix. PHYLIP output uses the relaxed layout
+/// (names of any length, whitespace in names replaced by underscores).
+pub fn write_distance_matrix(
+    names: &[String],
+    distances: &[Vec<f64>],
+    format: MatrixFormat,
+    writer: &mut impl Write,
+) -> Result<()> {
+    match format {
+        MatrixFormat::Phylip => {
+            writeln!(writer, "{}", names.len())?;
+            for (name, row) in names.iter().zip(distances) {
+                write!(writer, "{}", name.replace(char::is_whitespace, "_"))?;
+                for distance in row {
+                    write!(writer, " {:.6}", distance)?;
+                }
+                writeln!(writer)?;
+            }
+        }
+        MatrixFormat::Tsv => {
+            writeln!(writer, "name\t{}", names.join("\t"))?;
+            for (name, row) in names.iter().zip(distances) {
+                write!(writer, "{}", name)?;
+                for distance in row {
+                    write!(writer, "\t{:.6}", distance)?;
+                }
+                writeln!(writer)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Destinations of one analysis report
+pub struct ReportWriters<'a, W> {
+    pub sample: &'a mut W,
+    pub matches: &'a mut W,
+    /// Receives the detailed sections of every format but JSON, which embeds
+    /// them; without it each profile's section goes to its own
+    /// `{sample}_{profile}_detailed.tsv` (`--split-detailed`)
+    pub detailed: Option<&'a mut W>,
+}
+
+pub fn output_analysis<W: Write + Seek>(
     sample_name: &str,
     counter: &KmerCounter,
     matches: &[ProfileMatch],
-    detailed: bool,
+    options: ReportOptions,
     analyzer: &ProfileAnalyzer,
-    sample_writer: &mut impl Write,
-    matches_writer: &mut (impl Write + Seek),
+    writers: ReportWriters<'_, W>,
+) -> Result<()> {
+    let ReportWriters { sample: sample_writer, matches: matches_writer, detailed: detailed_writer } = writers;
+    match options.format {
+        OutputFormat::Json => return write_json(sample_name, counter, matches, options, analyzer, matches_writer),
+        OutputFormat::JsonLines => return write_json_lines(sample_name, counter, matches, options, analyzer, matches_writer),
+        OutputFormat::Tsv => write_tsv(sample_name, matches, options, matches_writer)?,
+        OutputFormat::Csv => write_csv(sample_name, matches, options, matches_writer)?,
+        OutputFormat::KrakenReport => write_kraken_report(matches, matches_writer)?,
+        OutputFormat::Krona => write_krona(sample_name, matches, analyzer, matches_writer)?,
+        OutputFormat::Text => write_text(sample_name, counter, matches, options, sample_writer, matches_writer)?,
+    }
+    // JSON embeds each match's detailed analysis; the other formats have no room for it
+    write_detailed_sections(sample_name, counter, matches, options, analyzer, detailed_writer)
+}
+
+/// Write the hierarchical assignment for a sample to the sample info writer
+pub fn output_classification(
+    sample_name: &str,
+    classification: &Classification,
+    format: OutputFormat,
+    writer: &mut (impl Write + Seek),
+) -> Result<()> {
+    let level = classification.level.as_ref().map(|level| level.to_string()).unwrap_or_else(|| "unassigned".to_string());
+    let name = classification.name.as_deref().unwrap_or("-");
+
+    match format {
+        OutputFormat::Json => {
+            let report = ClassificationReport { sample: sample_name, classification };
+            serde_json::to_writer_pretty(&mut *writer, &report)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::JsonLines => {
+            write_json_line(&ClassificationReport { sample: sample_name, classification }, None, writer)?;
+        }
+        OutputFormat::Tsv | OutputFormat::KrakenReport | OutputFormat::Krona => {
+            if writer.stream_position()? == 0 {
+                writeln!(writer, "sample\tassigned_level\tassigned_name\tevidence")?;
+            }
+            writeln!(writer, "{}\t{}\t{}\t{}", sample_name, level, name, classification.evidence.join("; "))?;
+        }
+        OutputFormat::Csv => {
+            let header = writer.stream_position()? == 0;
+            let mut csv_writer = csv::Writer::from_writer(&mut *writer);
+            if header {
+                csv_writer.write_record(["sample", "assigned_level", "assigned_name", "evidence"])?;
+            }
+            csv_writer.write_record([sample_name, &level, name, &classification.evidence.join("; ")])?;
+            csv_writer.flush()?;
+        }
+        OutputFormat::Text => {
+            writeln!(writer, "{:<30}\t{}", "Assigned level", level)?;
+            writeln!(writer, "{:<30}\t{}", "Assigned profile", name)?;
+            for evidence in &classification.evidence {
+                writeln!(writer, "{:<30}\t{}", "Rollup evidence", evidence)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the ancestors implied by a sample's matches, with their pooled
+/// coverage, to the sample info writer
+pub fn output_lineage_rollup(
+    sample_name: &str,
+    rollups: &[LineageRollup],
+    options: ReportOptions,
+    writer: &mut (impl Write + Seek),
+) -> Result<()> {
+    let level = |rollup: &LineageRollup| rollup.level.as_ref().map_or("-".to_string(), |level| level.to_string());
+    let header = ["sample", "level", "name", "shared_kmers", "sample_coverage", "matches"];
+    let decimals = options.decimals();
+
+    match options.format {
+        OutputFormat::Json => {
+            let report = LineageReport { sample: sample_name, lineage_rollup: rollups };
+            write_json_document(&report, options.precision, writer)?;
+        }
+        OutputFormat::JsonLines => {
+            for rollup in rollups {
+                write_json_line(&LineageLine { sample: sample_name, rollup }, options.precision, writer)?;
+            }
+        }
+        OutputFormat::Tsv | OutputFormat::KrakenReport | OutputFormat::Krona => {
+            if writer.stream_position()? == 0 {
+                writeln!(writer, "{}", header.join("\t"))?;
+            }
+            for rollup in rollups {
+                writeln!(writer, "{}\t{}\t{}\t{}\t{:.*}\t{}",
+                    sample_name, level(rollup), rollup.name, rollup.shared_kmers, decimals, rollup.sample_coverage,
+                    rollup.matches.join("; "))?;
+            }
+        }
+        OutputFormat::Csv => {
+            let header_needed = writer.stream_position()? == 0;
+            let mut csv_writer = csv::Writer::from_writer(&mut *writer);
+            if header_needed {
+                csv_writer.write_record(header)?;
+            }
+            for rollup in rollups {
+                csv_writer.write_record([
+                    sample_name.to_string(),
+                    level(rollup),
+                    rollup.name.clone(),
+                    rollup.shared_kmers.to_string(),
+                    format!("{:.*}", decimals, rollup.sample_coverage),
+                    rollup.matches.join("; "),
+                ])?;
+            }
+            csv_writer.flush()?;
+        }
+        OutputFormat::Text => {
+            for rollup in rollups {
+                writeln!(writer, "{:<30}\t{} ({:.2}% of sample k-mers, from {})",
+                    format!("Implied {}", level(rollup)),
+                    rollup.name,
+                    rollup.sample_coverage * 100.0,
+                    rollup.matches.join(", "))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the records and bases read from each of a sample's input files.
+/// Tabular formats keep a single table per file, so they leave it to the log.
+pub fn output_reader_stats(
+    sample_name: &str,
+    stats: &ReaderStats,
+    format: OutputFormat,
+    writer: &mut impl Write,
+) -> Result<()> {
+    match format {
+        OutputFormat::Json => {
+            let report = InputReport { sample: sample_name, files: &stats.files };
+            serde_json::to_writer_pretty(&mut *writer, &report)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::JsonLines => {
+            write_json_line(&InputReport { sample: sample_name, files: &stats.files }, None, writer)?;
+        }
+        OutputFormat::Text => {
+            for file in &stats.files {
+                writeln!(writer, "{:<30}\t{}", "Input file", file.path)?;
+                writeln!(writer, "{:<30}\t{}", "Records read", file.records)?;
+                writeln!(writer, "{:<30}\t{}", "Records skipped (invalid)", file.skipped_invalid)?;
+                writeln!(writer, "{:<30}\t{}", "Bases read", file.bases)?;
+            }
+        }
+        OutputFormat::Tsv | OutputFormat::Csv | OutputFormat::KrakenReport | OutputFormat::Krona => {}
+    }
+
+    Ok(())
+}
+
+/// Write a sample's core k-mers, set `core`, then each match's discriminating
+/// k-mers under its name, one `sample\tset\tkmer` row each
+pub fn output_core_kmers(sample_name: &str, core: &CoreKmers, writer: &mut (impl Write + Seek)) -> Result<()> {
+    if writer.stream_position()? == 0 {
+        writeln!(writer, "sample\tset\tkmer")?;
+    }
+    for kmer in &core.core {
+        writeln!(writer, "{}\tcore\t{}", sample_name, kmer)?;
+    }
+    for (name, kmers) in &core.discriminating {
+        for kmer in kmers {
+            writeln!(writer, "{}\t{}\t{}", sample_name, name, kmer)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write how many of a sample's shared k-mers are held by 1, 2, ... profiles,
+/// one `sample\tprofiles\tkmers\tfraction` row per bin; the fraction is of
+/// the sample's shared k-mers
+pub fn output_kmer_specificity(
+    sample_name: &str,
+    specificity: &KmerSpecificity,
+    precision: Option<usize>,
+    writer: &mut (impl Write + Seek),
+) -> Result<()> {
+    let decimals = precision.unwrap_or(DEFAULT_PRECISION);
+    if writer.stream_position()? == 0 {
+        writeln!(writer, "sample\tprofiles\tkmers\tfraction")?;
+    }
+    let shared = specificity.shared_kmers();
+    for &(profiles, kmers) in &specificity.bins {
+        writeln!(writer, "{}\t{}\t{}\t{:.*}", sample_name, profiles, kmers, decimals, kmers as f64 / shared as f64)?;
+    }
+
+    Ok(())
+}
+
+fn write_json(
+    sample_name: &str,
+    counter: &KmerCounter,
+    matches: &[ProfileMatch],
+    options: ReportOptions,
+    analyzer: &ProfileAnalyzer,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let sample_kmers = if options.detailed { counter.get_counts() } else { HashMap::new() };
+    let mut match_reports = Vec::with_capacity(matches.len());
+    for m in matches {
+        let detailed = if options.detailed {
+            detailed_report(analyzer, counter, &sample_kmers, &m.name)?
+        } else {
+            None
+        };
+        match_reports.push(MatchReport { profile_match: m, detailed });
+    }
+
+    let report = SampleReport {
+        sample: sample_name,
+        total_kmers: counter.total_kmers(),
+        unique_kmers: counter.unique_kmers(),
+        kmer_size: counter.kmer_size(),
+        matches: match_reports,
+    };
+
+    write_json_document(&report, options.precision, writer)
+}
+
+/// Write each match as its own JSON object on one line, serializing and
+/// writing them one at a time instead of building a document per sample
+fn write_json_lines(
+    sample_name: &str,
+    counter: &KmerCounter,
+    matches: &[ProfileMatch],
+    options: ReportOptions,
+    analyzer: &ProfileAnalyzer,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let sample_kmers = if options.detailed { counter.get_counts() } else { HashMap::new() };
+    for m in matches {
+        let detailed = if options.detailed {
+            detailed_report(analyzer, counter, &sample_kmers, &m.name)?
+        } else {
+            None
+        };
+        let line = MatchLine { sample: sample_name, report: MatchReport { profile_match: m, detailed } };
+        write_json_line(&line, options.precision, writer)?;
+    }
+    // Hand the sample's lines to a reading consumer before the next sample's
+    writer.flush()?;
+    Ok(())
+}
+
+/// A match's detailed analysis for JSON, keeping the ten shared k-mers most
+/// frequent in the sample
+fn detailed_report(
+    analyzer: &ProfileAnalyzer,
+    counter: &KmerCounter,
+    sample_kmers: &HashMap<String, usize>,
+    profile_name: &str,
+) -> Result<Option<DetailedReport>> {
+    Ok(analyzer.get_detailed_analysis(counter, sample_kmers, profile_name)?.map(|analysis| {
+        let mut shared_kmers = analysis.shared_kmers;
+        shared_kmers.sort_by(|a, b| b.sample_frequency.partial_cmp(&a.sample_frequency).unwrap());
+        shared_kmers.truncate(10);
+        DetailedReport {
+            statistics: analysis.statistics,
+            top_shared_kmers: shared_kmers,
+        }
+    }))
+}
+
+/// Write a pretty-printed JSON document and a newline, with every
+/// non-integer number rounded to `precision` decimal places if given
+fn write_json_document(value: &impl Serialize, precision: Option<usize>, writer: &mut impl Write) -> Result<()> {
+    write_json_value(value, precision, true, writer)
+}
+
+/// Write a value as compact JSON on a single line, rounded as `write_json_document` does
+fn write_json_line(value: &impl Serialize, precision: Option<usize>, writer: &mut impl Write) -> Result<()> {
+    write_json_value(value, precision, false, writer)
+}
+
+fn write_json_value(value: &impl Serialize, precision: Option<usize>, pretty: bool, writer: &mut impl Write) -> Result<()> {
+    let rounded = precision.map(|decimals| -> Result<serde_json::Value> {
+        let mut json = serde_json::to_value(value)?;
+        round_json_numbers(&mut json, decimals);
+        Ok(json)
+    }).transpose()?;
+    match (&rounded, pretty) {
+        (Some(json), true) => serde_json::to_writer_pretty(&mut *writer, json)?,
+        (Some(json), false) => serde_json::to_writer(&mut *writer, json)?,
+        (None, true) => serde_json::to_writer_pretty(&mut *writer, value)?,
+        (None, false) => serde_json::to_writer(&mut *writer, value)?,
+    }
+    writeln!(writer)?;
+    Ok(())
+}
+
+fn round_json_numbers(value: &mut serde_json::Value, decimals: usize) {
+    match value {
+        serde_json::Value::Number(number) if number.is_f64() => {
+            let scale = 10f64.powi(decimals.min(f64::DIGITS as usize) as i32);
+            let rounded = number.as_f64().map(|x| (x * scale).round() / scale);
+            if let Some(rounded) = rounded.and_then(serde_json::Number::from_f64) {
+                *number = rounded;
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(|value| round_json_numbers(value, decimals)),
+        serde_json::Value::Object(map) => map.values_mut().for_each(|value| round_json_numbers(value, decimals)),
+        _ => {}
+    }
+}
+
+/// Column names of the TSV and CSV match summaries
+fn match_columns(options: ReportOptions) -> Vec<&'static str> {
+    let mut columns = vec![
+        "sample", "name", "sample_coverage", "profile_coverage", "shared_kmers", "unique_matches",
+        "jaccard_similarity", "containment", "confidence_score", "sample_gc", "profile_gc", "gc_mismatch",
+    ];
+    if options.weighted_shared {
+        columns.push("weighted_shared_kmers");
+    }
+    if options.abundance {
+        columns.push("abundance");
+    }
+    if options.bootstrap {
+        columns.extend([
+            "sample_coverage_mean", "sample_coverage_low", "sample_coverage_high",
+            "confidence_mean", "confidence_low", "confidence_high",
+        ]);
+    }
+    columns
+}
+
+/// One match's fields, in `match_columns` order
+fn match_row(sample_name: &str, m: &ProfileMatch, options: ReportOptions) -> Vec<String> {
+    let decimals = options.decimals();
+    let mut row = vec![
+        sample_name.to_string(),
+        m.name.clone(),
+        format!("{:.*}", decimals, m.sample_coverage),
+        format!("{:.*}", decimals, m.profile_coverage),
+        m.shared_kmers.to_string(),
+        m.unique_matches.map_or("NA".to_string(), |count| count.to_string()),
+        format!("{:.*}", decimals, m.jaccard_similarity),
+        format!("{:.*}", decimals, m.containment),
+        format!("{:.*}", decimals, m.confidence_score),
+        format_gc(m.sample_gc, decimals),
+        format_gc(m.profile_gc, decimals),
+        if m.profile_gc.is_none() { "NA" } else if m.gc_mismatch { "yes" } else { "no" }.to_string(),
+    ];
+    if options.weighted_shared {
+        row.push(m.weighted_shared_kmers.map_or("NA".to_string(), |weighted| format!("{:.*}", decimals, weighted)));
+    }
+    if options.abundance {
+        row.push(format!("{:.*}", decimals, m.abundance.unwrap_or(0.0)));
+    }
+    if options.bootstrap {
+        match &m.bootstrap {
+            Some(bootstrap) => {
+                for interval in [bootstrap.sample_coverage, bootstrap.confidence_score] {
+                    row.extend([interval.mean, interval.low, interval.high].map(|value| format!("{:.*}", decimals, value)));
+                }
+            }
+            None => row.extend(std::iter::repeat_n("NA".to_string(), 6)),
+        }
+    }
+    row
+}
+
+fn write_tsv(
+    sample_name: &str,
+    matches: &[ProfileMatch],
+    options: ReportOptions,
+    writer: &mut (impl Write + Seek),
+) -> Result<()> {
+    // Only the first sample in a run gets the header row
+    if writer.stream_position()? == 0 {
+        writeln!(writer, "{}", match_columns(options).join("\t"))?;
+    }
+
+    for m in matches {
+        writeln!(writer, "{}", match_row(sample_name, m, options).join("\t"))?;
+    }
+
+    Ok(())
+}
+
+/// The TSV columns, comma-separated and quoted where a field holds a comma,
+/// quote or line break
+fn write_csv(
+    sample_name: &str,
+    matches: &[ProfileMatch],
+    options: ReportOptions,
+    writer: &mut (impl Write + Seek),
+) -> Result<()> {
+    let header = writer.stream_position()? == 0;
+    let mut csv_writer = csv::Writer::from_writer(&mut *writer);
+    if header {
+        csv_writer.write_record(match_columns(options))?;
+    }
+
+    for m in matches {
+        csv_writer.write_record(match_row(sample_name, m, options))?;
+    }
+    csv_writer.flush()?;
+
+    Ok(())
+}
+
+/// One Kraken2-style row per match. Clade k-mers are all shared k-mers and
+/// taxon k-mers those found in no other profile.
+fn write_kraken_report(matches: &[ProfileMatch], writer: &mut impl Write) -> Result<()> {
+    for m in matches {
+        let Some(unique_matches) = m.unique_matches else {
+            bail!("The kraken-report format needs unique k-mer counts, which --fast does not compute");
+        };
+        writeln!(writer, "{:.2}\t{}\t{}\t{}\t{}",
+            m.sample_coverage * 100.0,
+            m.shared_kmers,
+            unique_matches,
+            m.level.as_ref().map_or("U", |level| level.rank_code()),
+            m.name,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// A GC fraction for TSV output, or NA when it was not computed
+fn format_gc(gc: Option<f64>, decimals: usize) -> String {
+    gc.map_or("NA".to_string(), |gc| format!("{:.*}", decimals, gc))
+}
+
+/// Krona text input (`ktImportText`): a magnitude, then the sample and the
+/// match's lineage by `parent`, one wedge per level. The magnitude is the
+/// match's abundance in percent when estimated, else its sample coverage.
+fn write_krona(
+    sample_name: &str,
+    matches: &[ProfileMatch],
+    analyzer: &ProfileAnalyzer,
+    writer: &mut impl Write,
+) -> Result<()> {
+    for m in matches {
+        let magnitude = m.abundance.unwrap_or(m.sample_coverage) * 100.0;
+        writeln!(writer, "{:.4}\t{}\t{}", magnitude, sample_name, analyzer.lineage(&m.name)?.join("\t"))?;
+    }
+
+    Ok(())
+}
+
+fn write_text<W: Write + Seek>(
+    sample_name: &str,
+    counter: &KmerCounter,
+    matches: &[ProfileMatch],
+    options: ReportOptions,
+    sample_writer: &mut W,
+    matches_writer: &mut W,
 ) -> Result<()> {
     // Check if we need to write headers (if file is empty)
     if matches_writer.stream_position()? == 0 {
-        writeln!(sample_writer, "{:<30}\t{}", "Metric", "Value")?;
+        writeln!(sample_writer, "{:<30}\tValue", "Metric")?;
         writeln!(sample_writer, "{}", "-".repeat(50))?;
     
-        writeln!(matches_writer, "{:<40}\t{:<40}\t{:>10}\t{:>10}\t{:>10}\t{:>10}\t{:>10}",
-            "Name", "Sample", "Sample%", "Shared", "Unique%", "Size", "Confidence")?;
-        writeln!(matches_writer, "{}", "-".repeat(140))?;
+        write!(matches_writer, "{:<40}\t{:<40}\t{:>10}\t{:>10}\t{:>10}\t{:>10}\t{:>12}\t{:>10}\t{:>10}\t{:>10}",
+            "Name", "Sample", "Sample%", "Shared", "Unique%", "Size", "Containment", "Confidence", "SampleGC%", "ProfileGC%")?;
+        if options.weighted_shared {
+            write!(matches_writer, "\t{:>12}", "WeightShared")?;
+        }
+        if options.abundance {
+            write!(matches_writer, "\t{:>10}", "Abundance%")?;
+        }
+        if options.bootstrap {
+            write!(matches_writer, "\t{:>17}", "Confidence 95%")?;
+        }
+        writeln!(matches_writer)?;
+        let width = 177
+            + if options.weighted_shared { 13 } else { 0 }
+            + if options.abundance { 11 } else { 0 }
+            + if options.bootstrap { 18 } else { 0 };
+        writeln!(matches_writer, "{}", "-".repeat(width))?;
     }
 
     // Write sample information
@@ -31,56 +829,546 @@ pub fn output_analysis(
     writeln!(sample_writer, "{:<30}\t{}", "Unique k-mers", counter.unique_kmers())?;
     writeln!(sample_writer, "{:<30}\t{}", "K-mer size", counter.kmer_size())?;
 
-    // Write matches for this sample
+    // Write matches for this sample; each column keeps its own decimals
+    // unless --precision sets them all
+    let decimals = |default: usize| options.precision.unwrap_or(default);
+    let (percent, score) = (decimals(2), decimals(3));
     for m in matches {
-        writeln!(matches_writer, "{:<40}\t{:<40}\t{:>10.2}\t{:>10}\t{:>10.2}\t{:>10.3}\t{:>10.3}",
+        write!(matches_writer, "{:<40}\t{:<40}\t{:>10.*}\t{:>10}\t{:>10}\t{:>10.*}\t{:>12.*}\t{:>10.*}\t{:>10}\t{:>10}",
             m.name,
             sample_name,
-            m.sample_coverage * 100.0,
+            percent, m.sample_coverage * 100.0,
             m.shared_kmers,
-            m.uniqueness_score * 100.0,
-            m.size_ratio,
-            m.confidence_score,
+            m.uniqueness_score.map_or("NA".to_string(), |score| format!("{:.*}", percent, score * 100.0)),
+            score, m.size_ratio,
+            score, m.containment,
+            score, m.confidence_score,
+            m.sample_gc.map_or("NA".to_string(), |gc| format!("{:.*}", percent, gc * 100.0)),
+            m.profile_gc.map_or("NA".to_string(), |gc| format!("{:.*}{}", percent, gc * 100.0, if m.gc_mismatch { "!" } else { "" })),
         )?;
+        if options.weighted_shared {
+            let weighted = m.weighted_shared_kmers.map_or("NA".to_string(), |weighted| format!("{:.*}", decimals(1), weighted));
+            write!(matches_writer, "\t{:>12}", weighted)?;
+        }
+        if options.abundance {
+            write!(matches_writer, "\t{:>10.*}", percent, m.abundance.unwrap_or(0.0) * 100.0)?;
+        }
+        if options.bootstrap {
+            let interval = m.bootstrap.as_ref().map_or("NA".to_string(), |bootstrap| {
+                format!("{:.*}-{:.*}", score, bootstrap.confidence_score.low, score, bootstrap.confidence_score.high)
+            });
+            write!(matches_writer, "\t{:>17}", interval)?;
+        }
+        writeln!(matches_writer)?;
+    }
+
+    Ok(())
+}
 
-        // Write detailed analysis if requested
-        if detailed {
-            if let Some(analysis) = analyzer.get_detailed_analysis(counter, &m.name)? {
-                let detailed_path = PathBuf::from(format!("{}_{}_detailed.tsv", sample_name, m.name));
-                let mut detailed_writer = File::create(detailed_path)?;
-                
-                writeln!(detailed_writer, "Profile: {}", m.name)?;
-                writeln!(detailed_writer, "{}", "-".repeat(75))?;
-                
-                // Profile statistics
-                writeln!(detailed_writer, "Statistic\tValue")?;
-                writeln!(detailed_writer, "Total shared k-mers\t{}", analysis.statistics.total_shared)?;
-                writeln!(detailed_writer, "Total unique to reference\t{}", analysis.statistics.total_unique_reference)?;
-                writeln!(detailed_writer, "Total unique to sample\t{}", analysis.statistics.total_unique_sample)?;
-                writeln!(detailed_writer, "Sample coverage\t{:.6}", analysis.statistics.sample_coverage)?;
-                writeln!(detailed_writer, "Uniqueness score\t{:.6}", analysis.statistics.uniqueness_score)?;
-                writeln!(detailed_writer, "Size ratio\t{:.6}", analysis.statistics.size_ratio)?;
-                writeln!(detailed_writer, "Confidence score\t{:.6}", analysis.statistics.confidence_score)?;
-                writeln!(detailed_writer, "Profile unique k-mers\t{}", analysis.statistics.profile_unique_kmers)?;
-                writeln!(detailed_writer, "Shared unique k-mers\t{}", analysis.statistics.shared_unique_kmers)?;
-                writeln!(detailed_writer)?;
-
-                // Top shared k-mers
-                writeln!(detailed_writer, "\nTop Shared K-mers")?;
-                writeln!(detailed_writer, "K-mer\tSample%\tUnique\tFrequency")?;
-                let mut shared_kmers: Vec<_> = analysis.shared_kmers.iter().collect();
-                shared_kmers.sort_by(|a, b| b.sample_frequency.partial_cmp(&a.sample_frequency).unwrap());
-                for kmer in shared_kmers.iter().take(10) {
-                    writeln!(detailed_writer, "{}\t{:.6}\t{}\t{:.6}",
-                        kmer.sequence,
-                        kmer.sample_frequency * 100.0,
-                        if kmer.is_unique { "Yes" } else { "No" },
-                        kmer.sample_frequency
-                    )?;
+/// Write each match's detailed analysis, if requested, to the detailed
+/// writer, or without one to a file per profile
+fn write_detailed_sections<W: Write>(
+    sample_name: &str,
+    counter: &KmerCounter,
+    matches: &[ProfileMatch],
+    options: ReportOptions,
+    analyzer: &ProfileAnalyzer,
+    mut detailed_writer: Option<&mut W>,
+) -> Result<()> {
+    if !options.detailed {
+        return Ok(());
+    }
+    let sample_kmers = counter.get_counts();
+    for m in matches {
+        if let Some(analysis) = analyzer.get_detailed_analysis(counter, &sample_kmers, &m.name)? {
+            match detailed_writer.as_deref_mut() {
+                Some(writer) => {
+                    writeln!(writer, "Sample: {}", sample_name)?;
+                    write_detailed_section(&m.name, &analysis, options.decimals(), writer)?;
+                    writeln!(writer)?;
+                }
+                None => {
+                    let detailed_path = PathBuf::from(format!("{}_{}_detailed.tsv", sample_name, m.name));
+                    write_detailed_section(&m.name, &analysis, options.decimals(), &mut File::create(detailed_path)?)?;
                 }
             }
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// One profile's statistics and top shared k-mers
+fn write_detailed_section(name: &str, analysis: &DetailedAnalysis, decimals: usize, writer: &mut impl Write) -> Result<()> {
+    writeln!(writer, "Profile: {}", name)?;
+    writeln!(writer, "{}", "-".repeat(75))?;
+
+    // Profile statistics
+    writeln!(writer, "Statistic\tValue")?;
+    writeln!(writer, "Total shared k-mers\t{}", analysis.statistics.total_shared)?;
+    writeln!(writer, "Total unique to reference\t{}", analysis.statistics.total_unique_reference)?;
+    writeln!(writer, "Total unique to sample\t{}", analysis.statistics.total_unique_sample)?;
+    writeln!(writer, "Sample coverage\t{:.*}", decimals, analysis.statistics.sample_coverage)?;
+    writeln!(writer, "Uniqueness score\t{:.*}", decimals, analysis.statistics.uniqueness_score)?;
+    writeln!(writer, "Size ratio\t{:.*}", decimals, analysis.statistics.size_ratio)?;
+    writeln!(writer, "Confidence score\t{:.*}", decimals, analysis.statistics.confidence_score)?;
+    writeln!(writer, "Profile unique k-mers\t{}", analysis.statistics.profile_unique_kmers)?;
+    writeln!(writer, "Shared unique k-mers\t{}", analysis.statistics.shared_unique_kmers)?;
+    writeln!(writer)?;
+
+    // Top shared k-mers
+    writeln!(writer, "\nTop Shared K-mers")?;
+    writeln!(writer, "K-mer\tSample%\tUnique\tFrequency")?;
+    let mut shared_kmers: Vec<_> = analysis.shared_kmers.iter().collect();
+    shared_kmers.sort_by(|a, b| b.sample_frequency.partial_cmp(&a.sample_frequency).unwrap());
+    for kmer in shared_kmers.iter().take(10) {
+        writeln!(writer, "{}\t{:.*}\t{}\t{:.*}",
+            kmer.sequence,
+            decimals,
+            kmer.sample_frequency * 100.0,
+            if kmer.is_unique { "Yes" } else { "No" },
+            decimals,
+            kmer.sample_frequency
+        )?;
+    }
+
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{Normalization, TaxonomyLevel};
+
+    #[test]
+    fn test_kraken_report_rows() -> Result<()> {
+        let matches = vec![
+            ProfileMatch::new("E_coli".to_string(), 0.5, 40, 1.0, 0.25, 0.6)
+                .with_unique_matches(10)
+                .with_level(TaxonomyLevel::Species),
+            ProfileMatch::new("E_coli_K12".to_string(), 0.125, 8, 1.0, 0.5, 0.4)
+                .with_unique_matches(4)
+                .with_level(TaxonomyLevel::Strain),
+        ];
+
+        let mut output = Vec::new();
+        write_kraken_report(&matches, &mut output)?;
+
+        assert_eq!(
+            String::from_utf8(output)?,
+            "50.00\t40\t10\tS\tE_coli\n12.50\t8\t4\tS1\tE_coli_K12\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_quotes_names_and_matches_tsv_columns() -> Result<()> {
+        let matches = vec![
+            ProfileMatch::new("Escherichia coli, K-12".to_string(), 0.5, 40, 1.0, 0.25, 0.6)
+                .with_unique_matches(10),
+        ];
+
+        let mut tsv = std::io::Cursor::new(Vec::new());
+        let options = ReportOptions { format: OutputFormat::Tsv, detailed: false, abundance: false, weighted_shared: false, bootstrap: false, precision: None };
+        write_tsv("sample1", &matches, options, &mut tsv)?;
+        let mut csv = std::io::Cursor::new(Vec::new());
+        write_csv("sample1", &matches, options, &mut csv)?;
+        write_csv("sample2", &matches, options, &mut csv)?;
+
+        let tsv = String::from_utf8(tsv.into_inner())?;
+        let csv = String::from_utf8(csv.into_inner())?;
+        let tsv_lines: Vec<&str> = tsv.lines().collect();
+        let csv_lines: Vec<&str> = csv.lines().collect();
+
+        // One header for the whole file, with the TSV's columns
+        assert_eq!(csv_lines.len(), 3);
+        assert_eq!(csv_lines[0], tsv_lines[0].replace('\t', ","));
+        assert!(csv_lines[1].starts_with("sample1,\"Escherichia coli, K-12\",0.500000,"));
+        assert!(csv_lines[2].starts_with("sample2,"));
+
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let record = reader.records().next().unwrap()?;
+        assert_eq!(record.iter().collect::<Vec<_>>(), tsv_lines[1].split('\t').collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_profile_json_order() -> Result<()> {
+        let mut profile = Profile::new("Test".to_string(), TaxonomyLevel::Species, 3);
+        profile.frequencies.insert("GGG".to_string(), 0.25);
+        profile.frequencies.insert("CCC".to_string(), 0.5);
+        profile.frequencies.insert("AAA".to_string(), 0.25);
+        profile.total_kmers = 4;
+
+        let mut output = Vec::new();
+        export_profile_json(&profile, None, &mut output)?;
+        let json = String::from_utf8(output)?;
+
+        let ccc = json.find("\"CCC\"").unwrap();
+        let aaa = json.find("\"AAA\"").unwrap();
+        let ggg = json.find("\"GGG\"").unwrap();
+        assert!(ccc < aaa && aaa < ggg);
+
+        let value: serde_json::Value = serde_json::from_str(&json)?;
+        assert_eq!(value["name"], "Test");
+        assert_eq!(value["level"], "Species");
+        assert_eq!(value["k"], 3);
+        assert_eq!(value["frequencies"]["CCC"], 0.5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distance_matrix_formats() -> Result<()> {
+        let names = vec!["E coli".to_string(), "S_aureus".to_string()];
+        let distances = vec![vec![0.0, 0.25], vec![0.25, 0.0]];
+
+        let mut phylip = Vec::new();
+        write_distance_matrix(&names, &distances, MatrixFormat::Phylip, &mut phylip)?;
+        assert_eq!(
+            String::from_utf8(phylip)?,
+            "2\nE_coli 0.000000 0.250000\nS_aureus 0.250000 0.000000\n"
+        );
+
+        let mut tsv = Vec::new();
+        write_distance_matrix(&names, &distances, MatrixFormat::Tsv, &mut tsv)?;
+        assert_eq!(
+            String::from_utf8(tsv)?,
+            "name\tE coli\tS_aureus\nE coli\t0.000000\t0.250000\nS_aureus\t0.250000\t0.000000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exports_are_reproducible() -> Result<()> {
+        // Same k-mers inserted in opposite orders, so the maps iterate differently
+        let build = |kmers: Vec<(&str, f64)>| {
+            let mut profile = Profile::new("Test".to_string(), TaxonomyLevel::Species, 3);
+            profile.frequencies = kmers.into_iter().map(|(kmer, freq)| (kmer.to_string(), freq)).collect();
+            profile.total_kmers = 4;
+            profile
+        };
+        let kmers = vec![
+            ("AAA", 0.25), ("CCC", 0.25), ("GGG", 0.25), ("TTT", 0.25),
+            ("ACG", 0.5), ("CGT", 0.5), ("GTA", 0.125),
+        ];
+        let first = build(kmers.clone());
+        let second = build(kmers.into_iter().rev().collect());
+
+        let exports = |profile: &Profile| -> Result<Vec<Vec<u8>>> {
+            let mut outputs = vec![Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+            export_profile_fasta(profile, None, &mut outputs[0])?;
+            export_profile_tsv(profile, None, &mut outputs[1])?;
+            export_profile_json(profile, None, &mut outputs[2])?;
+            export_profile_mash(profile, &mut outputs[3])?;
+            Ok(outputs)
+        };
+        assert_eq!(exports(&first)?, exports(&second)?);
+
+        let mut fasta = Vec::new();
+        export_profile_fasta(&first, None, &mut fasta)?;
+        assert!(String::from_utf8(fasta)?.starts_with(">Test 0.500000\nACG\n>Test 0.500000\nCGT\n>Test 0.250000\nAAA\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mash_export() -> Result<()> {
+        let mut profile = Profile::new("Test".to_string(), TaxonomyLevel::Species, 21);
+        for kmer in ["ACGTAACGTTAGCATGCAACG", "TTGCAACGTAGCATGCAAGTA", "GGGCATGCTAGCTAGCTAGCA"] {
+            profile.frequencies.insert(kmer.to_string(), 1.0 / 3.0);
+        }
+        profile.total_kmers = 3;
+        profile.sketch = Some(MinHashSketch::from_hashes(2, Vec::new()));
+
+        let mut output = Vec::new();
+        export_profile_mash(&profile, &mut output)?;
+        let json: serde_json::Value = serde_json::from_slice(&output)?;
+        assert_eq!(json["kmer"], 21);
+        assert_eq!(json["hashBits"], 64);
+        assert_eq!(json["hashSeed"], 42);
+        assert_eq!(json["canonical"], true);
+        // The stored sketch's size carries over
+        assert_eq!(json["sketchSize"], 2);
+        let hashes = json["sketches"][0]["hashes"].as_array().unwrap();
+        assert_eq!(hashes.len(), 2);
+        assert!(hashes[0].as_u64() < hashes[1].as_u64());
+
+        profile.seed = Some("11011".to_string());
+        assert!(export_profile_mash(&profile, &mut Vec::new()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parquet_export() -> Result<()> {
+        use arrow_array::cast::AsArray;
+        use arrow_array::Array;
+        use arrow_array::types::UInt64Type;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let mut first = Profile::new("First".to_string(), TaxonomyLevel::Species, 3);
+        first.frequencies.insert("AAC".to_string(), 0.75);
+        first.frequencies.insert("ACG".to_string(), 0.25);
+        first.total_kmers = 4;
+        let mut second = Profile::new("Second".to_string(), TaxonomyLevel::Genus, 3);
+        second.frequencies.insert("TTT".to_string(), 1.0);
+        second.total_kmers = 2;
+        let mut third = Profile::new("Third".to_string(), TaxonomyLevel::Species, 3);
+        third.frequencies.insert("GGG".to_string(), 1.0);
+        third.total_kmers = 5;
+        third.normalization = Normalization::Presence;
+
+        let mut buffer = Vec::new();
+        let mut exporter = ParquetExporter::new(&mut buffer)?;
+        exporter.write_profile(&first)?;
+        exporter.write_profile(&second)?;
+        exporter.write_profile(&third)?;
+        exporter.finish()?;
+
+        let mut rows = Vec::new();
+        for batch in ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(buffer))?.build()? {
+            let batch = batch?;
+            let text = |column: &str| batch.column_by_name(column).unwrap().as_string::<i32>().clone();
+            let (names, levels, kmers) = (text("profile_name"), text("level"), text("kmer"));
+            let counts = batch.column_by_name("count").unwrap().as_primitive::<UInt64Type>().clone();
+            for row in 0..batch.num_rows() {
+                rows.push((
+                    names.value(row).to_string(),
+                    levels.value(row).to_string(),
+                    kmers.value(row).to_string(),
+                    counts.is_valid(row).then(|| counts.value(row)),
+                ));
+            }
+        }
+
+        let row = |name: &str, level: &str, kmer: &str, count| (name.to_string(), level.to_string(), kmer.to_string(), count);
+        assert_eq!(rows, [
+            row("First", "Species", "AAC", Some(3)),
+            row("First", "Species", "ACG", Some(1)),
+            row("Second", "Genus", "TTT", Some(2)),
+            row("Third", "Species", "GGG", None),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_krona_lineage_rows() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = crate::db::Database::new(&db_path)?;
+        for (name, level, parent) in [
+            ("Escherichia", TaxonomyLevel::Genus, None),
+            ("E_coli", TaxonomyLevel::Species, Some("Escherichia")),
+            ("E_coli_K12", TaxonomyLevel::Strain, Some("E_coli")),
+        ] {
+            let mut profile = Profile::new(name.to_string(), level, 3);
+            profile.frequencies.insert("AAA".to_string(), 1.0);
+            profile.total_kmers = 1;
+            profile.parent = parent.map(str::to_string);
+            db.add_profile(&profile)?;
+        }
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+
+        let mut abundant = ProfileMatch::new("E_coli_K12".to_string(), 0.5, 40, 1.0, 0.25, 0.6);
+        abundant.abundance = Some(0.75);
+        let matches = vec![
+            abundant,
+            ProfileMatch::new("Escherichia".to_string(), 0.125, 8, 1.0, 0.5, 0.4),
+        ];
+
+        let mut output = Vec::new();
+        write_krona("sample", &matches, &analyzer, &mut output)?;
+
+        assert_eq!(
+            String::from_utf8(output)?,
+            "75.0000\tsample\tEscherichia\tE_coli\tE_coli_K12\n12.5000\tsample\tEscherichia\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_detailed_sections_share_one_writer() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = crate::db::Database::new(&db_path)?;
+        for (name, kmer) in [("A_profile", "AAA"), ("C_profile", "CCC")] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 3);
+            profile.frequencies.insert(kmer.to_string(), 1.0);
+            profile.total_kmers = 1;
+            db.add_profile(&profile)?;
+        }
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAAACCCC")?;
+        let matches = vec![
+            ProfileMatch::new("A_profile".to_string(), 0.5, 1, 1.0, 1.0, 0.5),
+            ProfileMatch::new("C_profile".to_string(), 0.5, 1, 1.0, 1.0, 0.5),
+        ];
+        // Tabular reports have no room for the sections, so they go to the same writer
+        for format in [OutputFormat::Text, OutputFormat::Tsv, OutputFormat::Csv, OutputFormat::KrakenReport, OutputFormat::Krona] {
+            let options = ReportOptions { format, detailed: true, abundance: false, weighted_shared: false, bootstrap: false, precision: None };
+
+            let (mut sample, mut matches_out, mut detailed) =
+                (std::io::Cursor::new(Vec::new()), std::io::Cursor::new(Vec::new()), std::io::Cursor::new(Vec::new()));
+            output_analysis(
+                "sample",
+                &counter,
+                &matches,
+                options,
+                &analyzer,
+                ReportWriters { sample: &mut sample, matches: &mut matches_out, detailed: Some(&mut detailed) },
+            )?;
+
+            let detailed = String::from_utf8(detailed.into_inner())?;
+            assert!(detailed.starts_with("Sample: sample\nProfile: A_profile\n"), "{format:?}");
+            assert!(detailed.contains("\nSample: sample\nProfile: C_profile\n"), "{format:?}");
+            assert!(detailed.contains("AAA\t"));
+            assert!(detailed.contains("CCC\t"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_core_kmers_rows() -> Result<()> {
+        let core = CoreKmers {
+            core: vec!["AAA".to_string()],
+            discriminating: vec![
+                ("A_profile".to_string(), vec!["AAC".to_string()]),
+                ("C_profile".to_string(), Vec::new()),
+            ],
+        };
+
+        let mut output = std::io::Cursor::new(Vec::new());
+        output_core_kmers("s1", &core, &mut output)?;
+        output_core_kmers("s2", &core, &mut output)?;
+
+        assert_eq!(
+            String::from_utf8(output.into_inner())?,
+            "sample\tset\tkmer\ns1\tcore\tAAA\ns1\tA_profile\tAAC\ns2\tcore\tAAA\ns2\tA_profile\tAAC\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmer_specificity_rows() -> Result<()> {
+        let specificity = KmerSpecificity { bins: vec![(1, 3), (4, 1)] };
+
+        let mut output = std::io::Cursor::new(Vec::new());
+        output_kmer_specificity("s1", &specificity, None, &mut output)?;
+        output_kmer_specificity("s2", &KmerSpecificity::default(), None, &mut output)?;
+
+        assert_eq!(
+            String::from_utf8(output.into_inner())?,
+            "sample\tprofiles\tkmers\tfraction\ns1\t1\t3\t0.750000\ns1\t4\t1\t0.250000\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_precision_sets_decimal_places() -> Result<()> {
+        let matches = vec![ProfileMatch::new("E_coli".to_string(), 1.0 / 3.0, 40, 1.0, 0.25, 0.6)];
+        let options = ReportOptions {
+            format: OutputFormat::Tsv, detailed: false, abundance: false, weighted_shared: false, bootstrap: false, precision: Some(2),
+        };
+        let mut tsv = std::io::Cursor::new(Vec::new());
+        write_tsv("sample1", &matches, options, &mut tsv)?;
+        let tsv = String::from_utf8(tsv.into_inner())?;
+        assert!(tsv.lines().nth(1).unwrap().starts_with("sample1\tE_coli\t0.33\t"));
+
+        let mut profile = Profile::new("Test".to_string(), TaxonomyLevel::Species, 3);
+        profile.frequencies.insert("AAA".to_string(), 1.0 / 3.0);
+        profile.frequencies.insert("CCC".to_string(), 2.0 / 3.0);
+        let mut output = Vec::new();
+        export_profile_tsv(&profile, Some(3), &mut output)?;
+        assert_eq!(String::from_utf8(output)?, "kmer\tfrequency\nCCC\t0.667\nAAA\t0.333\n");
+
+        // JSON is rounded only when a precision is given, and integers are kept
+        let value = serde_json::json!({ "k": 21, "scores": [1.0 / 3.0, 0.5] });
+        let mut full = Vec::new();
+        write_json_document(&value, None, &mut full)?;
+        assert!(String::from_utf8(full)?.contains("0.3333333333333333"));
+        let mut rounded = Vec::new();
+        write_json_document(&value, Some(4), &mut rounded)?;
+        let rounded: serde_json::Value = serde_json::from_slice(&rounded)?;
+        assert_eq!(rounded, serde_json::json!({ "k": 21, "scores": [0.3333, 0.5] }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_lines_write_one_match_per_line() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = crate::db::Database::new(&db_path)?;
+        let mut profile = Profile::new("A_profile".to_string(), TaxonomyLevel::Species, 3);
+        profile.frequencies.insert("AAA".to_string(), 1.0);
+        profile.total_kmers = 1;
+        db.add_profile(&profile)?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAAACCCC")?;
+        let options = ReportOptions {
+            format: OutputFormat::JsonLines, detailed: true, abundance: false, weighted_shared: false, bootstrap: false, precision: None,
+        };
+
+        let mut matches_out = std::io::Cursor::new(Vec::new());
+        for (sample, name) in [("s1", "A_profile"), ("s1", "B_profile"), ("s2", "A_profile")] {
+            let matches = vec![ProfileMatch::new(name.to_string(), 0.5, 1, 1.0, 1.0, 0.5)];
+            let mut sample_out = std::io::Cursor::new(Vec::new());
+            output_analysis(
+                sample,
+                &counter,
+                &matches,
+                options,
+                &analyzer,
+                ReportWriters { sample: &mut sample_out, matches: &mut matches_out, detailed: None },
+            )?;
+        }
+
+        let output = String::from_utf8(matches_out.into_inner())?;
+        let lines: Vec<serde_json::Value> = output.lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<_>>()?;
+        assert_eq!(lines.len(), 3);
+        let fields = |line: &serde_json::Value| (line["sample"].clone(), line["name"].clone());
+        assert_eq!(fields(&lines[0]), (serde_json::json!("s1"), serde_json::json!("A_profile")));
+        assert_eq!(fields(&lines[2]), (serde_json::json!("s2"), serde_json::json!("A_profile")));
+        assert_eq!(lines[0]["shared_kmers"], 1);
+        // Detailed analysis rides along on the match's line when the profile exists
+        assert!(lines[0]["detailed"]["statistics"].is_object());
+        assert!(lines[1].get("detailed").is_none());
+        Ok(())
+    }
+    #[test]
+    fn test_precision_applies_to_text_table() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("test.db");
+        crate::db::Database::new(&db_path)?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let counter = KmerCounter::new(3);
+        let matches = vec![ProfileMatch::new("E_coli".to_string(), 1.0 / 3.0, 40, 1.0, 0.25, 0.6)];
+
+        let text_row = |precision: Option<usize>| -> Result<Vec<String>> {
+            let options = ReportOptions {
+                format: OutputFormat::Text, detailed: false, abundance: true, weighted_shared: false, bootstrap: false, precision,
+            };
+            let (mut sample_out, mut matches_out) = (std::io::Cursor::new(Vec::new()), std::io::Cursor::new(Vec::new()));
+            output_analysis(
+                "sample1",
+                &counter,
+                &matches,
+                options,
+                &analyzer,
+                ReportWriters { sample: &mut sample_out, matches: &mut matches_out, detailed: None },
+            )?;
+            let output = String::from_utf8(matches_out.into_inner())?;
+            Ok(output.lines().nth(2).unwrap().split('\t').map(|field| field.trim().to_string()).collect())
+        };
+
+        // Sample%, Size, Containment, Confidence and Abundance%
+        let columns = |row: &[String]| [2, 5, 6, 7, 10].map(|i| row[i].clone());
+        assert_eq!(columns(&text_row(None)?), ["33.33", "1.000", "0.000", "0.600", "0.00"]);
+        assert_eq!(columns(&text_row(Some(1))?), ["33.3", "1.0", "0.0", "0.6", "0.0"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use super::sketch::hash_kmer;
+
+/// Bloom filter over k-mer sequences. Membership queries never give false
+/// negatives, so counting hits gives an upper bound on the true overlap.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    num_bits: u64,
+    num_hashes: u32,
+    fp_rate: f64,
+    words: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Size a filter for `expected_items` k-mers at the given false-positive rate
+    pub fn with_rate(expected_items: usize, fp_rate: f64) -> Self {
+        let items = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(items * fp_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / items) * ln2).round().max(1.0) as u32;
+
+        BloomFilter {
+            num_bits,
+            num_hashes,
+            fp_rate,
+            words: vec![0; num_bits.div_ceil(64) as usize],
+        }
+    }
+
+    /// Build a filter containing every k-mer of an iterator
+    pub fn from_kmers<'a, I>(kmers: I, fp_rate: f64) -> Self
+    where
+        I: ExactSizeIterator<Item = &'a [u8]>,
+    {
+        let mut filter = Self::with_rate(kmers.len(), fp_rate);
+        for kmer in kmers {
+            filter.insert(kmer);
+        }
+        filter
+    }
+
+    /// Rebuild a filter from stored parts
+    pub fn from_parts(num_bits: u64, num_hashes: u32, fp_rate: f64, words: Vec<u64>) -> Self {
+        BloomFilter { num_bits, num_hashes, fp_rate, words }
+    }
+
+    pub fn insert(&mut self, kmer: &[u8]) {
+        for bit in bit_positions(kmer, self.num_hashes, self.num_bits) {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, kmer: &[u8]) -> bool {
+        bit_positions(kmer, self.num_hashes, self.num_bits)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    pub fn fp_rate(&self) -> f64 {
+        self.fp_rate
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+}
+
+/// Double hashing: bit i is h1 + i * h2, from the two halves of one hash
+fn bit_positions(kmer: &[u8], num_hashes: u32, num_bits: u64) -> impl Iterator<Item = u64> {
+    let hash = hash_kmer(kmer);
+    let h1 = hash & 0xffff_ffff;
+    let h2 = (hash >> 32) | 1;
+    (0..num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives() {
+        let kmers: Vec<String> = (0..1000).map(|i| format!("ACGT{:06}", i)).collect();
+        let filter = BloomFilter::from_kmers(kmers.iter().map(|k| k.as_bytes()), 0.01);
+
+        assert!(kmers.iter().all(|k| filter.contains(k.as_bytes())));
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let kmers: Vec<String> = (0..10_000).map(|i| format!("A{:08}", i)).collect();
+        let filter = BloomFilter::from_kmers(kmers.iter().map(|k| k.as_bytes()), 0.01);
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.contains(format!("C{:08}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_round_trip_parts() {
+        let filter = BloomFilter::from_kmers(vec![&b"ACGTA"[..], b"CGTAC"].into_iter(), 0.05);
+        let restored = BloomFilter::from_parts(
+            filter.num_bits(),
+            filter.num_hashes(),
+            filter.fp_rate(),
+            filter.words().to_vec(),
+        );
+
+        assert!(restored.contains(b"ACGTA"));
+        assert!(restored.contains(b"CGTAC"));
+        assert_eq!(restored.fp_rate(), 0.05);
+    }
+}
@@ -1,39 +1,348 @@
-use std::collections::HashMap;
-use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use anyhow::{anyhow, Result};
+use crossbeam::channel;
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use super::types::Kmer;
+use super::seed::SpacedSeed;
+use super::types::{encode_base, iupac_bases, Alphabet, Kmer, PackedKmer, PackedWord};
+use crate::io::{FastxReader, ReaderStats};
+
+/// Number of sequences sent to the counter per batch when streaming
+const BATCH_SIZE: usize = 4096;
+
+/// Number of batches that may be queued ahead of the counter
+const QUEUED_BATCHES: usize = 4;
+
+/// Highest multiplicity considered when locating the coverage peak
+const COVERAGE_MAX_BIN: usize = 10_000;
+
+/// Most concrete k-mers a window with IUPAC codes may expand into; windows
+/// with more (e.g. two `B`s and a `D`) are skipped
+pub const MAX_IUPAC_EXPANSIONS: usize = 16;
+
+/// Fraction of sequences shorter than the k-mer window above which the k-mer
+/// size is taken to be too large for the input
+const SHORT_SEQUENCE_FRACTION: f64 = 0.5;
+
+/// K-mer count storage, packed into integers when k is small enough: a `u64`
+/// up to k=32, a `u128` up to k=64, byte sequences beyond that
+enum KmerCounts {
+    Packed(DashMap<PackedKmer, usize>),
+    Packed128(DashMap<PackedKmer<u128>, usize>),
+    Sequence(DashMap<Kmer, usize>),
+}
+
+/// A counter's counts and tallies, saved so that counting can resume in a
+/// later process; see [`KmerCounter::snapshot`]
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CounterSnapshot {
+    k: usize,
+    counts: HashMap<String, usize>,
+    expanded: HashMap<String, f64>,
+    oversized_windows: usize,
+    truncated: bool,
+    sequences: usize,
+    short_sequences: usize,
+}
+
+impl CounterSnapshot {
+    /// A snapshot of k-mer counts alone, with no tallies of reads behind them
+    pub(crate) fn from_counts(k: usize, counts: HashMap<String, usize>) -> Self {
+        CounterSnapshot {
+            k,
+            counts,
+            expanded: HashMap::new(),
+            oversized_windows: 0,
+            truncated: false,
+            sequences: 0,
+            short_sequences: 0,
+        }
+    }
+}
 
 pub struct KmerCounter {
     k: usize,
-    counts: DashMap<Kmer, usize>,
+    counts: KmerCounts,
+    /// Spaced seed selecting which window positions form each k-mer
+    seed: Option<SpacedSeed>,
+    /// Expand windows containing IUPAC ambiguity codes into concrete k-mers
+    expand_iupac: bool,
+    /// Fractional counts from expanded windows, kept apart from the integer counts
+    expanded: DashMap<Kmer, f64>,
+    /// Ambiguous windows skipped for exceeding `MAX_IUPAC_EXPANSIONS`
+    oversized_windows: AtomicUsize,
+    /// Keep only the minimizer of each window of this many consecutive k-mers
+    minimizer_window: Option<usize>,
+    /// Stop adding distinct k-mers once this many are stored
+    max_unique_kmers: Option<usize>,
+    /// Distinct k-mers admitted under `max_unique_kmers`
+    admitted: AtomicUsize,
+    /// Set when a new k-mer was dropped because the cap was reached
+    truncated: AtomicBool,
+    /// Sequences passed to `count_sequence`
+    sequences: AtomicUsize,
+    /// Of those, sequences shorter than the k-mer window, which yield no k-mers
+    short_sequences: AtomicUsize,
+    /// Times `get_counts` has rebuilt the count map
+    #[cfg(test)]
+    counts_built: AtomicUsize,
 }
 
 impl KmerCounter {
     /// Create a new KmerCounter with specified k-mer size
     pub fn new(k: usize) -> Self {
+        let counts = if k <= PackedKmer::<u64>::MAX_K {
+            KmerCounts::Packed(DashMap::new())
+        } else if k <= PackedKmer::<u128>::MAX_K {
+            KmerCounts::Packed128(DashMap::new())
+        } else {
+            KmerCounts::Sequence(DashMap::new())
+        };
+
         KmerCounter {
             k,
-            counts: DashMap::new(),
+            counts,
+            seed: None,
+            expand_iupac: false,
+            expanded: DashMap::new(),
+            oversized_windows: AtomicUsize::new(0),
+            minimizer_window: None,
+            max_unique_kmers: None,
+            admitted: AtomicUsize::new(0),
+            truncated: AtomicBool::new(false),
+            sequences: AtomicUsize::new(0),
+            short_sequences: AtomicUsize::new(0),
+            #[cfg(test)]
+            counts_built: AtomicUsize::new(0),
+        }
+    }
+
+    /// Build k-mers from the care positions of a spaced seed instead of
+    /// contiguous windows. The seed must have exactly k care positions.
+    pub fn with_seed(mut self, seed: Option<SpacedSeed>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn seed(&self) -> Option<&SpacedSeed> {
+        self.seed.as_ref()
+    }
+
+    /// Count k-mers over `alphabet`. Protein k-mers cannot be packed into two
+    /// bits per residue, so they are always stored as sequences.
+    pub fn with_alphabet(mut self, alphabet: Alphabet) -> Self {
+        if alphabet == Alphabet::Protein {
+            self.counts = KmerCounts::Sequence(DashMap::new());
+        }
+        self
+    }
+
+    /// Count each window holding IUPAC ambiguity codes as every concrete k-mer
+    /// it could be, each weighted `1/expansions`. Contiguous k-mers only.
+    pub fn with_iupac_expansion(mut self, expand_iupac: bool) -> Self {
+        self.expand_iupac = expand_iupac;
+        self
+    }
+
+    /// Count only window minimizers: of every `w` consecutive k-mers, the
+    /// lexicographically smallest. Larger windows store fewer k-mers but leave
+    /// longer stretches of sequence without one. Contiguous k-mers only.
+    pub fn with_minimizer_window(mut self, w: Option<usize>) -> Self {
+        self.minimizer_window = w;
+        self
+    }
+
+    pub fn minimizer_window(&self) -> Option<usize> {
+        self.minimizer_window
+    }
+
+    /// Store at most `max_unique_kmers` distinct k-mers. Past the cap, k-mers
+    /// already stored are still counted but new ones are dropped, and
+    /// `is_truncated` reports it.
+    pub fn with_max_unique_kmers(mut self, max_unique_kmers: Option<usize>) -> Self {
+        self.max_unique_kmers = max_unique_kmers;
+        self
+    }
+
+    /// Create a counter that always stores k-mers as byte sequences
+    #[cfg(test)]
+    fn with_sequence_keys(k: usize) -> Self {
+        KmerCounter {
+            k,
+            counts: KmerCounts::Sequence(DashMap::new()),
+            seed: None,
+            expand_iupac: false,
+            expanded: DashMap::new(),
+            oversized_windows: AtomicUsize::new(0),
+            minimizer_window: None,
+            max_unique_kmers: None,
+            admitted: AtomicUsize::new(0),
+            truncated: AtomicBool::new(false),
+            sequences: AtomicUsize::new(0),
+            short_sequences: AtomicUsize::new(0),
+            #[cfg(test)]
+            counts_built: AtomicUsize::new(0),
         }
     }
 
     /// Count k-mers in a sequence
     pub fn count_sequence(&self, sequence: &[u8]) -> Result<()> {
+        self.sequences.fetch_add(1, Ordering::Relaxed);
+        let window = self.seed.as_ref().map_or(self.k, SpacedSeed::span);
+        if sequence.len() < window {
+            self.short_sequences.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        if let Some(seed) = &self.seed {
+            self.count_spaced(sequence, seed);
+            return Ok(());
+        }
+
+        self.count_contiguous(sequence)
+    }
+
+    /// Count the contiguous k-mers of a sequence, of any length
+    fn count_contiguous(&self, sequence: &[u8]) -> Result<()> {
         if sequence.len() < self.k {
             return Ok(());
         }
 
-        // Create windows of size k and count them
-        sequence.windows(self.k).for_each(|window| {
-            let kmer = Kmer::new(window);
-            self.counts.entry(kmer).and_modify(|count| *count += 1).or_insert(1);
-        });
+        if self.expand_iupac && sequence.iter().any(|&base| iupac_bases(base).is_some()) {
+            return self.count_ambiguous(sequence);
+        }
+
+        if let Some(w) = self.minimizer_window {
+            self.count_minimizers(sequence, w);
+            return Ok(());
+        }
+
+        match &self.counts {
+            KmerCounts::Packed(counts) => self.count_packed(counts, sequence),
+            KmerCounts::Packed128(counts) => self.count_packed(counts, sequence),
+            KmerCounts::Sequence(counts) => {
+                // Create windows of size k and count them
+                sequence.windows(self.k).for_each(|window| {
+                    self.increment(counts, Kmer::new(window));
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Roll the packed k-mer along the sequence, restarting after any base
+    /// that cannot be packed
+    fn count_packed<W: PackedWord>(&self, counts: &DashMap<PackedKmer<W>, usize>, sequence: &[u8]) {
+        let mut kmer = PackedKmer::default();
+        let mut valid = 0;
+        for &base in sequence {
+            let Some(code) = encode_base(base) else {
+                valid = 0;
+                continue;
+            };
+            kmer = kmer.push(code, self.k);
+            valid += 1;
+            if valid >= self.k {
+                self.increment(counts, kmer);
+            }
+        }
+    }
+
+    /// Count the ACGT stretches of a sequence as usual, then spread each window
+    /// holding ambiguity codes evenly over the concrete k-mers it stands for
+    fn count_ambiguous(&self, sequence: &[u8]) -> Result<()> {
+        for segment in sequence.split(|&base| encode_base(base).is_none()) {
+            self.count_contiguous(segment)?;
+        }
+
+        for window in sequence.windows(self.k) {
+            if window.iter().all(|&base| encode_base(base).is_some()) {
+                continue;
+            }
+            let Some(choices) = window.iter().map(|&base| base_choices(base)).collect::<Option<Vec<_>>>() else {
+                // Ns and other non-IUPAC characters still break k-mers
+                continue;
+            };
+
+            let expansions = choices.iter()
+                .try_fold(1usize, |product, bases| product.checked_mul(bases.len()))
+                .filter(|&expansions| expansions <= MAX_IUPAC_EXPANSIONS);
+            let Some(expansions) = expansions else {
+                self.oversized_windows.fetch_add(1, Ordering::Relaxed);
+                continue;
+            };
+
+            let weight = 1.0 / expansions as f64;
+            for_each_expansion(&choices, |kmer| {
+                match self.expanded.entry(Kmer::new(kmer)) {
+                    Entry::Occupied(mut entry) => *entry.get_mut() += weight,
+                    Entry::Vacant(entry) => {
+                        if self.admit() {
+                            entry.insert(weight);
+                        }
+                    }
+                }
+            });
+        }
 
         Ok(())
     }
 
+    /// Count each window minimizer once, however many consecutive windows share it
+    fn count_minimizers(&self, sequence: &[u8], w: usize) {
+        match &self.counts {
+            KmerCounts::Packed(counts) => self.count_packed_minimizers(counts, sequence, w),
+            KmerCounts::Packed128(counts) => self.count_packed_minimizers(counts, sequence, w),
+            KmerCounts::Sequence(counts) => {
+                for position in minimizer_positions(sequence, self.k, w) {
+                    self.increment(counts, Kmer::new(&sequence[position..position + self.k]));
+                }
+            }
+        }
+    }
+
+    fn count_packed_minimizers<W: PackedWord>(&self, counts: &DashMap<PackedKmer<W>, usize>, sequence: &[u8], w: usize) {
+        // Unpackable bases break k-mers, as in contiguous counting
+        for segment in sequence.split(|&base| encode_base(base).is_none()) {
+            for position in minimizer_positions(segment, self.k, w) {
+                let kmer = segment[position..position + self.k].iter()
+                    .fold(PackedKmer::default(), |kmer, &base| kmer.push(encode_base(base).unwrap_or(0), self.k));
+                self.increment(counts, kmer);
+            }
+        }
+    }
+
+    /// Count the care-position bases of every seed-length window
+    fn count_spaced(&self, sequence: &[u8], seed: &SpacedSeed) {
+        for window in sequence.windows(seed.span()) {
+            match &self.counts {
+                KmerCounts::Packed(counts) => self.count_packed_window(counts, window, seed),
+                KmerCounts::Packed128(counts) => self.count_packed_window(counts, window, seed),
+                KmerCounts::Sequence(counts) => {
+                    let key: Vec<u8> = seed.care_positions().iter().map(|&i| window[i]).collect();
+                    self.increment(counts, Kmer::new(&key));
+                }
+            }
+        }
+    }
+
+    /// Skip windows with an unpackable base at a care position
+    fn count_packed_window<W: PackedWord>(&self, counts: &DashMap<PackedKmer<W>, usize>, window: &[u8], seed: &SpacedSeed) {
+        let kmer = seed.care_positions()
+            .iter()
+            .try_fold(PackedKmer::default(), |kmer, &i| encode_base(window[i]).map(|code| kmer.push(code, self.k)));
+        if let Some(kmer) = kmer {
+            self.increment(counts, kmer);
+        }
+    }
+
     /// Process sequences in parallel using rayon
     pub fn count_sequences<I>(&self, sequences: I) -> Result<()>
     where
@@ -43,12 +352,180 @@ impl KmerCounter {
         Ok(())
     }
 
-    /// Get k-mer counts as a regular HashMap
+    /// Count one occurrence of a k-mer, unless it is new and the cap is reached
+    fn increment<K: Eq + Hash>(&self, counts: &DashMap<K, usize>, kmer: K) {
+        match counts.entry(kmer) {
+            Entry::Occupied(mut entry) => *entry.get_mut() += 1,
+            Entry::Vacant(entry) => {
+                if self.admit() {
+                    entry.insert(1);
+                }
+            }
+        }
+    }
+
+    /// Whether a new distinct k-mer may be stored under `max_unique_kmers`
+    fn admit(&self) -> bool {
+        let Some(max_unique_kmers) = self.max_unique_kmers else {
+            return true;
+        };
+        let admitted = self.admitted
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |admitted| {
+                (admitted < max_unique_kmers).then_some(admitted + 1)
+            })
+            .is_ok();
+        if !admitted {
+            self.truncated.store(true, Ordering::Relaxed);
+        }
+        admitted
+    }
+
+    /// Stream sequences from a reader into the counter in bounded batches,
+    /// so memory use scales with the batch size rather than the input size.
+    /// Returns the reader's per-file statistics.
+    pub fn count_from_reader(&self, reader: &FastxReader) -> Result<ReaderStats> {
+        self.count_batched(reader, BATCH_SIZE)
+    }
+
+    fn count_batched(&self, reader: &FastxReader, batch_size: usize) -> Result<ReaderStats> {
+        let (sender, receiver) = channel::bounded::<Vec<Vec<u8>>>(QUEUED_BATCHES);
+
+        std::thread::scope(|scope| {
+            // Parsing runs on its own thread so it never blocks a rayon worker
+            let producer = scope.spawn(move || -> Result<ReaderStats> {
+                let mut batch = Vec::with_capacity(batch_size);
+                let stats = reader.for_each_sequence(|sequence| {
+                    batch.push(sequence.to_vec());
+                    if batch.len() >= batch_size {
+                        sender.send(mem::replace(&mut batch, Vec::with_capacity(batch_size)))
+                            .map_err(|_| anyhow!("K-mer counter stopped accepting sequences"))?;
+                    }
+                    Ok(())
+                })?;
+                if !batch.is_empty() {
+                    sender.send(batch)
+                        .map_err(|_| anyhow!("K-mer counter stopped accepting sequences"))?;
+                }
+                Ok(stats)
+            });
+
+            for batch in receiver {
+                self.count_sequences(batch.into_par_iter())?;
+            }
+
+            producer.join().map_err(|_| anyhow!("Sequence reader thread panicked"))?
+        })
+    }
+
+    /// Get k-mer counts as a regular HashMap. This copies every k-mer, so
+    /// build it once per sample and share it.
     pub fn get_counts(&self) -> HashMap<String, usize> {
-        self.counts
-            .iter()
-            .map(|entry| (entry.key().sequence(), *entry.value()))
-            .collect()
+        #[cfg(test)]
+        self.counts_built.fetch_add(1, Ordering::Relaxed);
+        match &self.counts {
+            KmerCounts::Packed(counts) => counts
+                .iter()
+                .map(|entry| (entry.key().sequence(self.k), *entry.value()))
+                .collect(),
+            KmerCounts::Packed128(counts) => counts
+                .iter()
+                .map(|entry| (entry.key().sequence(self.k), *entry.value()))
+                .collect(),
+            KmerCounts::Sequence(counts) => counts
+                .iter()
+                .map(|entry| (entry.key().sequence(), *entry.value()))
+                .collect(),
+        }
+    }
+
+    /// K-mer counts including the fractional weights of expanded IUPAC windows.
+    /// Without expansion these equal `get_counts`.
+    pub fn get_weighted_counts(&self) -> HashMap<String, f64> {
+        let mut counts: HashMap<String, f64> = self.get_counts()
+            .into_iter()
+            .map(|(kmer, count)| (kmer, count as f64))
+            .collect();
+        for entry in self.expanded.iter() {
+            *counts.entry(entry.key().sequence()).or_insert(0.0) += *entry.value();
+        }
+        counts
+    }
+
+    /// Number of ambiguous windows skipped for expanding into more than
+    /// `MAX_IUPAC_EXPANSIONS` k-mers
+    pub fn oversized_windows(&self) -> usize {
+        self.oversized_windows.load(Ordering::Relaxed)
+    }
+
+    /// Whether new k-mers were dropped for exceeding `max_unique_kmers`
+    pub fn is_truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+
+    /// Copy of everything counted so far
+    pub(crate) fn snapshot(&self) -> CounterSnapshot {
+        CounterSnapshot {
+            k: self.k,
+            counts: self.get_counts(),
+            expanded: self.expanded.iter().map(|entry| (entry.key().sequence(), *entry.value())).collect(),
+            oversized_windows: self.oversized_windows(),
+            truncated: self.is_truncated(),
+            sequences: self.sequences_counted(),
+            short_sequences: self.short_sequences(),
+        }
+    }
+
+    /// Add a snapshot's counts and tallies to this counter, which should be
+    /// configured as the one the snapshot was taken from
+    pub(crate) fn restore(&self, snapshot: CounterSnapshot) -> Result<()> {
+        if snapshot.k != self.k {
+            return Err(anyhow!("Cannot restore {}-mer counts into a {}-mer counter", snapshot.k, self.k));
+        }
+        let invalid = |kmer: &str| anyhow!("Saved k-mer {} cannot be packed", kmer);
+        self.admitted.fetch_add(snapshot.counts.len(), Ordering::Relaxed);
+        for (kmer, count) in snapshot.counts {
+            match &self.counts {
+                KmerCounts::Packed(counts) => {
+                    *counts.entry(pack(kmer.as_bytes(), self.k).ok_or_else(|| invalid(&kmer))?).or_insert(0) += count;
+                }
+                KmerCounts::Packed128(counts) => {
+                    *counts.entry(pack(kmer.as_bytes(), self.k).ok_or_else(|| invalid(&kmer))?).or_insert(0) += count;
+                }
+                KmerCounts::Sequence(counts) => *counts.entry(Kmer::new(kmer.as_bytes())).or_insert(0) += count,
+            }
+        }
+        for (kmer, weight) in snapshot.expanded {
+            *self.expanded.entry(Kmer::new(kmer.as_bytes())).or_insert(0.0) += weight;
+        }
+        self.oversized_windows.fetch_add(snapshot.oversized_windows, Ordering::Relaxed);
+        if snapshot.truncated {
+            self.truncated.store(true, Ordering::Relaxed);
+        }
+        self.sequences.fetch_add(snapshot.sequences, Ordering::Relaxed);
+        self.short_sequences.fetch_add(snapshot.short_sequences, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Number of sequences counted
+    pub fn sequences_counted(&self) -> usize {
+        self.sequences.load(Ordering::Relaxed)
+    }
+
+    /// Number of sequences too short to hold a single k-mer (or seed window)
+    pub fn short_sequences(&self) -> usize {
+        self.short_sequences.load(Ordering::Relaxed)
+    }
+
+    /// Whether more than half of the sequences were too short for a k-mer,
+    /// suggesting k is too large for the reads
+    pub fn mostly_short_sequences(&self) -> bool {
+        let sequences = self.sequences_counted();
+        sequences > 0 && self.short_sequences() as f64 / sequences as f64 > SHORT_SEQUENCE_FRACTION
+    }
+
+    #[cfg(test)]
+    pub(crate) fn counts_built(&self) -> usize {
+        self.counts_built.load(Ordering::Relaxed)
     }
 
     /// Get the k-mer size
@@ -58,15 +535,134 @@ impl KmerCounter {
 
     /// Get the number of unique k-mers
     pub fn unique_kmers(&self) -> usize {
-        self.counts.len()
+        match &self.counts {
+            KmerCounts::Packed(counts) => counts.len(),
+            KmerCounts::Packed128(counts) => counts.len(),
+            KmerCounts::Sequence(counts) => counts.len(),
+        }
+    }
+
+    /// Number of distinct k-mers seen exactly n times, indexed by n. K-mers seen
+    /// `max_bin` or more times are all counted in the last bin.
+    pub fn histogram(&self, max_bin: usize) -> Vec<usize> {
+        match &self.counts {
+            KmerCounts::Packed(counts) => bin_counts(counts.iter().map(|entry| *entry.value()), max_bin),
+            KmerCounts::Packed128(counts) => bin_counts(counts.iter().map(|entry| *entry.value()), max_bin),
+            KmerCounts::Sequence(counts) => bin_counts(counts.iter().map(|entry| *entry.value()), max_bin),
+        }
     }
 
     /// Get the total number of k-mers (including duplicates)
     pub fn total_kmers(&self) -> usize {
-        self.counts.iter().map(|entry| *entry.value()).sum()
+        match &self.counts {
+            KmerCounts::Packed(counts) => counts.iter().map(|entry| *entry.value()).sum(),
+            KmerCounts::Packed128(counts) => counts.iter().map(|entry| *entry.value()).sum(),
+            KmerCounts::Sequence(counts) => counts.iter().map(|entry| *entry.value()).sum(),
+        }
     }
 }
 
+/// Pack a k-mer of ACGT bases, or None if it holds any other character
+fn pack<W: PackedWord>(kmer: &[u8], k: usize) -> Option<PackedKmer<W>> {
+    kmer.iter().try_fold(PackedKmer::default(), |packed, &base| Some(packed.push(encode_base(base)?, k)))
+}
+
+/// Start positions of the minimizers of every window of `w` consecutive
+/// k-mers, in order and without repeats: the lexicographically smallest k-mer
+/// of the window, the leftmost on ties. A sequence with fewer than `w` k-mers
+/// is one window.
+fn minimizer_positions(sequence: &[u8], k: usize, w: usize) -> Vec<usize> {
+    let mut positions = Vec::new();
+    if w == 0 || sequence.len() < k {
+        return positions;
+    }
+    let kmer_count = sequence.len() - k + 1;
+    let kmer = |i: usize| &sequence[i..i + k];
+
+    // Candidates in increasing position and non-decreasing k-mer order; the
+    // front is the current window's minimizer
+    let mut candidates: VecDeque<usize> = VecDeque::new();
+    for i in 0..kmer_count {
+        while candidates.back().is_some_and(|&j| kmer(j) > kmer(i)) {
+            candidates.pop_back();
+        }
+        candidates.push_back(i);
+        if candidates.front().is_some_and(|&j| j + w <= i) {
+            candidates.pop_front();
+        }
+        if i + 1 >= w.min(kmer_count) {
+            let minimizer = candidates[0];
+            if positions.last() != Some(&minimizer) {
+                positions.push(minimizer);
+            }
+        }
+    }
+    positions
+}
+
+/// Bases a window position may take: itself for ACGT, its expansion for an IUPAC code
+fn base_choices(base: u8) -> Option<&'static [u8]> {
+    match base {
+        b'A' => Some(b"A"),
+        b'C' => Some(b"C"),
+        b'G' => Some(b"G"),
+        b'T' => Some(b"T"),
+        _ => iupac_bases(base),
+    }
+}
+
+/// Call `f` with every sequence formed by picking one base from each position's choices
+fn for_each_expansion(choices: &[&[u8]], mut f: impl FnMut(&[u8])) {
+    let mut picks = vec![0; choices.len()];
+    let mut kmer: Vec<u8> = choices.iter().map(|bases| bases[0]).collect();
+    loop {
+        f(&kmer);
+
+        // Advance the rightmost position that has choices left, resetting those after it
+        let Some(position) = (0..choices.len()).rev().find(|&i| picks[i] + 1 < choices[i].len()) else {
+            return;
+        };
+        picks[position] += 1;
+        kmer[position] = choices[position][picks[position]];
+        for i in position + 1..choices.len() {
+            picks[i] = 0;
+            kmer[i] = choices[i][0];
+        }
+    }
+}
+
+/// Spectrum of k-mer counts: number of distinct k-mers seen n times, indexed by n,
+/// with `max_bin` and above sharing the last bin
+fn bin_counts(counts: impl Iterator<Item = usize>, max_bin: usize) -> Vec<usize> {
+    let mut bins = vec![0; max_bin + 1];
+    for count in counts {
+        bins[count.min(max_bin)] += 1;
+    }
+    bins
+}
+
+/// Estimate sequencing depth as the multiplicity of the main peak of the k-mer
+/// spectrum. The error peak is skipped by walking down from multiplicity 1 to
+/// the first valley; a spectrum that only falls (e.g. an assembly) gives 1.
+pub fn estimate_coverage(counts: impl Iterator<Item = usize>) -> f64 {
+    let bins = bin_counts(counts, COVERAGE_MAX_BIN);
+    // The last bin pools every higher multiplicity, so it is never a peak
+    let spectrum = &bins[..COVERAGE_MAX_BIN];
+
+    let mut valley = 1;
+    while valley + 1 < spectrum.len() && spectrum[valley + 1] <= spectrum[valley] {
+        valley += 1;
+    }
+    if valley + 1 >= spectrum.len() {
+        return if spectrum[1] > 0 { 1.0 } else { 0.0 };
+    }
+
+    let peak = (valley..spectrum.len())
+        .max_by_key(|&multiplicity| (spectrum[multiplicity], std::cmp::Reverse(multiplicity)))
+        .unwrap_or(valley);
+    peak as f64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -88,6 +684,13 @@ mod tests {
         counter.count_sequence(b"AT").unwrap(); // shorter than k
         assert_eq!(counter.unique_kmers(), 0);
         assert_eq!(counter.total_kmers(), 0);
+        assert_eq!(counter.short_sequences(), 1);
+        assert!(counter.mostly_short_sequences());
+
+        counter.count_sequence(b"ATG").unwrap();
+        counter.count_sequence(b"ATGC").unwrap();
+        assert_eq!(counter.sequences_counted(), 3);
+        assert!(!counter.mostly_short_sequences());
     }
 
     #[test]
@@ -127,4 +730,250 @@ mod tests {
         assert_eq!(counts.get("CG").unwrap(), &2);
         assert_eq!(counts.get("GA").unwrap(), &1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_count_from_reader_in_batches() -> Result<()> {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir()?;
+        let file_path = dir.path().join("test.fasta");
+        let mut file = std::fs::File::create(&file_path)?;
+        writeln!(file, ">seq1\nATCG\n>seq2\nCGAT\n>seq3\nATCG")?;
+
+        let reader = FastxReader::new(vec![file_path]);
+        let counter = KmerCounter::new(2);
+        counter.count_batched(&reader, 2)?;
+
+        let counts = counter.get_counts();
+        assert_eq!(counts.get("AT").unwrap(), &3);
+        assert_eq!(counts.get("TC").unwrap(), &2);
+        assert_eq!(counts.get("CG").unwrap(), &3);
+        assert_eq!(counts.get("GA").unwrap(), &1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_unique_kmers_truncates() {
+        // Pseudo-random bases, so nearly every 11-mer is distinct
+        let mut state = 42u64;
+        let sequence: Vec<u8> = (0..10_000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 62) as usize]
+            })
+            .collect();
+
+        let uncapped = KmerCounter::new(11);
+        uncapped.count_sequence(&sequence).unwrap();
+        assert!(uncapped.unique_kmers() > 100);
+        assert!(!uncapped.is_truncated());
+
+        let counter = KmerCounter::new(11).with_max_unique_kmers(Some(100));
+        counter.count_sequence(&sequence).unwrap();
+        assert_eq!(counter.unique_kmers(), 100);
+        assert!(counter.is_truncated());
+
+        // K-mers stored before the cap are still counted
+        counter.count_sequence(&sequence[..11]).unwrap();
+        let first = String::from_utf8(sequence[..11].to_vec()).unwrap();
+        assert_eq!(counter.get_counts()[&first], uncapped.get_counts()[&first] + 1);
+        assert_eq!(counter.unique_kmers(), 100);
+    }
+
+    #[test]
+    fn test_minimizer_of_known_window() {
+        // TGC, GCA, CAT: CAT is the smallest
+        assert_eq!(minimizer_positions(b"TGCAT", 3, 3), vec![2]);
+        // Windows GCA|CAT|ATG and CAT|ATG|TGA: ATG is shared, so counted once
+        assert_eq!(minimizer_positions(b"GCATGA", 3, 3), vec![2]);
+        // Ties go to the leftmost k-mer of each window
+        assert_eq!(minimizer_positions(b"AAAA", 2, 2), vec![0, 1]);
+        // w = 1 keeps every k-mer
+        assert_eq!(minimizer_positions(b"ACGTAC", 3, 1), vec![0, 1, 2, 3]);
+
+        for counter in [KmerCounter::new(3), KmerCounter::with_sequence_keys(3)] {
+            let counter = counter.with_minimizer_window(Some(3));
+            counter.count_sequence(b"TGCAT").unwrap();
+            let counts = counter.get_counts();
+            assert_eq!(counts.len(), 1);
+            assert_eq!(counts["CAT"], 1);
+        }
+    }
+
+    #[test]
+    fn test_smaller_minimizer_window_keeps_more_kmers() {
+        let mut state = 7u64;
+        let sequence: Vec<u8> = (0..5_000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 62) as usize]
+            })
+            .collect();
+
+        let retained = |w: usize| {
+            let counter = KmerCounter::new(15).with_minimizer_window(Some(w));
+            counter.count_sequence(&sequence).unwrap();
+            counter.unique_kmers()
+        };
+        let all = KmerCounter::new(15);
+        all.count_sequence(&sequence).unwrap();
+
+        assert_eq!(retained(1), all.unique_kmers());
+        assert!(retained(5) < retained(1));
+        assert!(retained(10) < retained(5));
+    }
+
+    #[test]
+    fn test_packed_matches_sequence_keys() {
+        let packed = KmerCounter::new(5);
+        let sequence = KmerCounter::with_sequence_keys(5);
+        for seq in [&b"ACGTACGGTTCAGT"[..], b"TTTTTGGCA"] {
+            packed.count_sequence(seq).unwrap();
+            sequence.count_sequence(seq).unwrap();
+        }
+
+        assert_eq!(packed.get_counts(), sequence.get_counts());
+        assert_eq!(packed.total_kmers(), sequence.total_kmers());
+    }
+
+    #[test]
+    fn test_wide_packed_matches_sequence_keys() {
+        let seq: Vec<u8> = (0..300u32).map(|i| b"ACGT"[(i * 7 + i / 5) as usize % 4]).collect();
+        for k in [33, 40, 64] {
+            let packed = KmerCounter::new(k);
+            let sequence = KmerCounter::with_sequence_keys(k);
+            for counter in [&packed, &sequence] {
+                counter.count_sequence(&seq).unwrap();
+                counter.count_sequence(b"ACGTNACGT").unwrap();
+            }
+
+            assert!(matches!(packed.counts, KmerCounts::Packed128(_)));
+            assert_eq!(packed.get_counts(), sequence.get_counts());
+            assert!(packed.get_counts().keys().all(|kmer| kmer.len() == k));
+        }
+        assert!(matches!(KmerCounter::new(65).counts, KmerCounts::Sequence(_)));
+    }
+
+    #[test]
+    fn test_packed_skips_ambiguous_windows() {
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"ACGNTTA").unwrap();
+
+        let counts = counter.get_counts();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts.get("ACG").unwrap(), &1);
+        assert_eq!(counts.get("TTA").unwrap(), &1);
+    }
+
+    #[test]
+    fn test_spaced_seed_ignores_dont_care_positions() {
+        let seed = SpacedSeed::parse("1101", 3).unwrap();
+        let counter = KmerCounter::new(3).with_seed(Some(seed.clone()));
+        // Windows ACGT and ACTT differ only at the don't-care position
+        counter.count_sequence(b"ACGT").unwrap();
+        counter.count_sequence(b"ACTT").unwrap();
+
+        let counts = counter.get_counts();
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts.get("ACT").unwrap(), &2);
+
+        let sequence = KmerCounter::with_sequence_keys(3).with_seed(Some(seed));
+        sequence.count_sequence(b"ACGT").unwrap();
+        sequence.count_sequence(b"ACTT").unwrap();
+        assert_eq!(sequence.get_counts(), counts);
+    }
+
+    #[test]
+    fn test_histogram_caps_last_bin() {
+        let counter = KmerCounter::new(2);
+        // AA x4, AC x1, CC x2, CG x1
+        counter.count_sequence(b"AAAAACCCG").unwrap();
+
+        assert_eq!(counter.histogram(3), vec![0, 2, 1, 1]);
+        assert_eq!(counter.histogram(5), vec![0, 2, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_estimate_coverage_skips_error_peak() {
+        // Many singleton errors, a valley at 3 and a main peak at 30x
+        let mut counts = vec![1; 500];
+        counts.extend(vec![2; 100]);
+        counts.extend(vec![3; 5]);
+        for depth in 20..=40usize {
+            let kmers = 200 - 10 * depth.abs_diff(30);
+            counts.extend(vec![depth; kmers]);
+        }
+        assert_eq!(estimate_coverage(counts.into_iter()), 30.0);
+
+        // An assembly sees every k-mer once
+        assert_eq!(estimate_coverage(vec![1; 100].into_iter()), 1.0);
+        assert_eq!(estimate_coverage(std::iter::empty()), 0.0);
+    }
+
+    #[test]
+    fn test_iupac_expansion() {
+        let counter = KmerCounter::new(3).with_iupac_expansion(true);
+        counter.count_sequence(b"ACRTG").unwrap();
+
+        // Each window holding R (A or G) splits its count over two k-mers
+        let mut counts: Vec<_> = counter.get_weighted_counts().into_iter().collect();
+        counts.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(counts, vec![
+            ("ACA".to_string(), 0.5),
+            ("ACG".to_string(), 0.5),
+            ("ATG".to_string(), 0.5),
+            ("CAT".to_string(), 0.5),
+            ("CGT".to_string(), 0.5),
+            ("GTG".to_string(), 0.5),
+        ]);
+
+        // B, D and V each stand for three bases: 27 expansions exceed the cap
+        counter.count_sequence(b"BDV").unwrap();
+        assert_eq!(counter.oversized_windows(), 1);
+
+        // Without expansion the ambiguous windows are not counted at all
+        let plain = KmerCounter::new(3);
+        plain.count_sequence(b"ACRTG").unwrap();
+        assert!(plain.get_weighted_counts().is_empty());
+    }
+
+    #[test]
+    fn test_protein_kmers() {
+        let counter = KmerCounter::new(3).with_alphabet(Alphabet::Protein);
+        counter.count_sequence(b"MKVLMKV").unwrap();
+
+        let counts = counter.get_counts();
+        assert_eq!(counts["MKV"], 2);
+        assert_eq!(counts["KVL"], 1);
+        assert_eq!(counter.total_kmers(), 5);
+    }
+
+    #[test]
+    fn test_snapshot_restores_counts_and_tallies() -> Result<()> {
+        for k in [3, 40] {
+            let sequence: Vec<u8> = b"ACGTTGCA".iter().copied().cycle().take(k + 4).collect();
+            // One window, split over the two k-mers R stands for
+            let mut ambiguous = sequence[..k].to_vec();
+            ambiguous[1] = b'R';
+
+            let counter = KmerCounter::new(k).with_iupac_expansion(true);
+            counter.count_sequence(&sequence)?;
+            counter.count_sequence(b"AC")?;
+            counter.count_sequence(&ambiguous)?;
+
+            let restored = KmerCounter::new(k).with_iupac_expansion(true);
+            restored.restore(counter.snapshot())?;
+            assert_eq!(restored.get_counts(), counter.get_counts());
+            assert_eq!(restored.get_weighted_counts(), counter.get_weighted_counts());
+            assert_eq!(restored.sequences_counted(), 3);
+            assert_eq!(restored.short_sequences(), 1);
+
+            // Counting goes on where the snapshot left off
+            restored.count_sequence(&sequence)?;
+            assert_eq!(restored.total_kmers(), 10);
+        }
+        assert!(KmerCounter::new(5).restore(KmerCounter::new(3).snapshot()).is_err());
+
+        Ok(())
+    }
+}
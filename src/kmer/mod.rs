@@ -1,4 +1,13 @@
+mod bloom;
 mod counter;
+mod seed;
+mod sketch;
 mod types;
 
-pub use counter::KmerCounter;
\ No newline at end of file
+pub use bloom::BloomFilter;
+pub use counter::KmerCounter;
+pub(crate) use counter::{estimate_coverage, CounterSnapshot, MAX_IUPAC_EXPANSIONS};
+pub use seed::SpacedSeed;
+pub use sketch::{MinHashSketch, DEFAULT_SKETCH_SIZE, MASH_HASH_SEED, MASH_MAX_32BIT_K};
+pub use types::Alphabet;
+pub(crate) use types::{canonical_counts, gc_fraction, iupac_bases, shannon_entropy};
@@ -0,0 +1,79 @@
+use std::fmt;
+use anyhow::{bail, Result};
+
+/// Spaced seed pattern such as `1101011`: each `1` is a care position that
+/// contributes a base to the k-mer key, each `0` a don't-care position that
+/// is skipped, so point mutations there leave the key unchanged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpacedSeed {
+    pattern: String,
+    care_positions: Vec<usize>,
+}
+
+impl SpacedSeed {
+    /// Parse a seed pattern whose number of care positions must equal `k`
+    pub fn parse(pattern: &str, k: usize) -> Result<Self> {
+        if pattern.is_empty() || !pattern.bytes().all(|c| c == b'0' || c == b'1') {
+            bail!("Seed pattern must contain only 0 and 1, got '{}'", pattern);
+        }
+        if !pattern.starts_with('1') || !pattern.ends_with('1') {
+            bail!("Seed pattern must start and end with a care position (1), got '{}'", pattern);
+        }
+
+        let care_positions: Vec<usize> = pattern.bytes()
+            .enumerate()
+            .filter(|(_, c)| *c == b'1')
+            .map(|(i, _)| i)
+            .collect();
+        if care_positions.len() != k {
+            bail!(
+                "Seed pattern '{}' has {} care positions but k={}",
+                pattern, care_positions.len(), k
+            );
+        }
+
+        Ok(SpacedSeed {
+            pattern: pattern.to_string(),
+            care_positions,
+        })
+    }
+
+    /// Length of the sequence window the seed covers
+    pub fn span(&self) -> usize {
+        self.pattern.len()
+    }
+
+    pub fn care_positions(&self) -> &[usize] {
+        &self.care_positions
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl fmt::Display for SpacedSeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_seed() {
+        let seed = SpacedSeed::parse("1101011", 5).unwrap();
+        assert_eq!(seed.span(), 7);
+        assert_eq!(seed.care_positions(), &[0, 1, 3, 5, 6]);
+    }
+
+    #[test]
+    fn test_reject_invalid_seeds() {
+        assert!(SpacedSeed::parse("1101011", 4).is_err());
+        assert!(SpacedSeed::parse("11x1", 3).is_err());
+        assert!(SpacedSeed::parse("0111", 3).is_err());
+        assert!(SpacedSeed::parse("", 0).is_err());
+    }
+}
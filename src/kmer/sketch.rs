@@ -0,0 +1,181 @@
+use std::collections::BinaryHeap;
+
+use super::types::canonical;
+
+/// Default number of hashes kept in a MinHash sketch
+pub const DEFAULT_SKETCH_SIZE: usize = 1000;
+
+/// Seed Mash passes to MurmurHash3
+pub const MASH_HASH_SEED: u32 = 42;
+
+/// Mash hashes k-mers longer than this with 64 bits, shorter ones with 32
+pub const MASH_MAX_32BIT_K: usize = 16;
+
+/// Bottom-s MinHash sketch over canonical k-mers
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinHashSketch {
+    size: usize,
+    /// Smallest hashes seen, sorted ascending
+    hashes: Vec<u64>,
+}
+
+impl MinHashSketch {
+    /// Build a sketch keeping the `size` smallest canonical k-mer hashes
+    pub fn from_kmers<'a, I>(kmers: I, size: usize) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        Self::bottom_hashes(kmers.into_iter().map(|kmer| hash_kmer(&canonical(kmer))), size)
+    }
+
+    /// Build a sketch with Mash's hash (MurmurHash3 seeded with 42: the low 64
+    /// bits of x64_128, or x86_32 for k <= 16) so its hashes can be compared
+    /// with Mash sketches. Protein k-mers are not `canonical`.
+    pub fn from_kmers_mash<'a, I>(kmers: I, size: usize, canonical_kmers: bool) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let hashes = kmers.into_iter().map(|kmer| {
+            let kmer = if canonical_kmers { canonical(kmer) } else { kmer.to_vec() };
+            mash_hash(&kmer)
+        });
+        Self::bottom_hashes(hashes, size)
+    }
+
+    /// Keep the `size` smallest of `hashes`
+    fn bottom_hashes(hashes: impl Iterator<Item = u64>, size: usize) -> Self {
+        // Max-heap of the smallest hashes seen so far
+        let mut heap = BinaryHeap::with_capacity(size + 1);
+        for hash in hashes {
+            if heap.len() < size {
+                heap.push(hash);
+            } else if heap.peek().is_some_and(|&max| hash < max) {
+                heap.pop();
+                heap.push(hash);
+            }
+        }
+
+        let mut hashes = heap.into_vec();
+        hashes.sort_unstable();
+        hashes.dedup();
+        MinHashSketch { size, hashes }
+    }
+
+    /// Rebuild a sketch from stored hashes
+    pub fn from_hashes(size: usize, mut hashes: Vec<u64>) -> Self {
+        hashes.sort_unstable();
+        MinHashSketch { size, hashes }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    /// Estimate the Jaccard similarity of the underlying k-mer sets from the
+    /// bottom hashes of their union
+    pub fn jaccard(&self, other: &MinHashSketch) -> f64 {
+        let size = self.size.min(other.size);
+        let (mut i, mut j) = (0, 0);
+        let mut union = 0;
+        let mut shared = 0;
+
+        while union < size && (i < self.hashes.len() || j < other.hashes.len()) {
+            match (self.hashes.get(i), other.hashes.get(j)) {
+                (Some(a), Some(b)) if a == b => {
+                    shared += 1;
+                    i += 1;
+                    j += 1;
+                }
+                (Some(a), Some(b)) if a < b => i += 1,
+                (Some(_), None) => i += 1,
+                _ => j += 1,
+            }
+            union += 1;
+        }
+
+        if union == 0 {
+            0.0
+        } else {
+            shared as f64 / union as f64
+        }
+    }
+}
+
+/// Mash's MurmurHash3 k-mer hash, so sketches interoperate with Mash's
+fn mash_hash(kmer: &[u8]) -> u64 {
+    // Reading from a slice cannot fail
+    if kmer.len() > MASH_MAX_32BIT_K {
+        murmur3::murmur3_x64_128(&mut &kmer[..], MASH_HASH_SEED).unwrap() as u64
+    } else {
+        murmur3::murmur3_32(&mut &kmer[..], MASH_HASH_SEED).unwrap() as u64
+    }
+}
+
+pub(super) fn hash_kmer(kmer: &[u8]) -> u64 {
+    // Finalize FxHash with a splitmix64 mix so the low bits are well spread
+    let mut hash = fxhash::hash64(kmer);
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmer::types::complement;
+
+    #[test]
+    fn test_canonical_orientation() {
+        assert_eq!(canonical(b"TTG"), b"CAA");
+        assert_eq!(canonical(b"ACG"), b"ACG");
+    }
+
+    #[test]
+    fn test_identical_sets() {
+        let kmers: Vec<&[u8]> = vec![b"ACGTA", b"CGTAC", b"GTACG", b"TACGT"];
+        let a = MinHashSketch::from_kmers(kmers.clone(), 10);
+        let b = MinHashSketch::from_kmers(kmers, 10);
+        assert!((a.jaccard(&b) - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_disjoint_sets() {
+        let a = MinHashSketch::from_kmers(vec![&b"AAAAA"[..], b"AAAAC"], 10);
+        let b = MinHashSketch::from_kmers(vec![&b"CCCCG"[..], b"CCCGC"], 10);
+        assert_eq!(a.jaccard(&b), 0.0);
+    }
+
+    #[test]
+    fn test_sketch_keeps_bottom_hashes() {
+        let kmers: Vec<Vec<u8>> = (0..100u32)
+            .map(|i| format!("{:08b}", i).replace('0', "A").replace('1', "C").into_bytes())
+            .collect();
+        let sketch = MinHashSketch::from_kmers(kmers.iter().map(|k| k.as_slice()), 10);
+        assert_eq!(sketch.hashes().len(), 10);
+        assert!(sketch.hashes().windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_mash_sketch_hashes() {
+        let forward: Vec<&[u8]> = vec![b"ACGTAACGTTAGCATGCAACG", b"TTGCAACGTAGCATGCAAGTA"];
+        let reverse: Vec<Vec<u8>> = forward.iter()
+            .map(|kmer| kmer.iter().rev().map(|&base| complement(base)).collect())
+            .collect();
+
+        // Canonical k-mers hash the same from either strand
+        let sketch = MinHashSketch::from_kmers_mash(forward.clone(), 10, true);
+        let reverse_sketch = MinHashSketch::from_kmers_mash(reverse.iter().map(|kmer| kmer.as_slice()), 10, true);
+        assert_eq!(sketch, reverse_sketch);
+        assert_ne!(sketch.hashes(), MinHashSketch::from_kmers(forward, 10).hashes());
+
+        // Short k-mers use 32-bit hashes, as Mash does
+        let short = MinHashSketch::from_kmers_mash(vec![&b"ACGTA"[..], b"CCGTA"], 10, true);
+        assert!(short.hashes().iter().all(|&hash| hash <= u32::MAX as u64));
+    }
+}
@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::ops::AddAssign;
 
 #[derive(Debug, Clone, Eq)]
 pub struct Kmer {
@@ -27,4 +29,288 @@ impl PartialEq for Kmer {
     fn eq(&self, other: &Self) -> bool {
         self.sequence == other.sequence
     }
-}
\ No newline at end of file
+}
+
+/// Integer a packed k-mer is stored in: `u64` holds up to 32 bases, `u128`
+/// up to 64
+pub trait PackedWord: Copy + Default + Eq + Hash {
+    const BITS: usize;
+
+    /// Shift in one two-bit code, keeping only the last `k` bases
+    fn shift_in(self, code: u64, k: usize) -> Self;
+
+    /// Two-bit code of the base `i` positions from the end
+    fn code_at(self, i: usize) -> u64;
+}
+
+impl PackedWord for u64 {
+    const BITS: usize = u64::BITS as usize;
+
+    fn shift_in(self, code: u64, k: usize) -> Self {
+        let bits = (self << 2) | code;
+        if 2 * k >= <Self as PackedWord>::BITS { bits } else { bits & ((1 << (2 * k)) - 1) }
+    }
+
+    fn code_at(self, i: usize) -> u64 {
+        (self >> (2 * i)) & 0b11
+    }
+}
+
+impl PackedWord for u128 {
+    const BITS: usize = u128::BITS as usize;
+
+    fn shift_in(self, code: u64, k: usize) -> Self {
+        let bits = (self << 2) | code as u128;
+        if 2 * k >= <Self as PackedWord>::BITS { bits } else { bits & ((1 << (2 * k)) - 1) }
+    }
+
+    fn code_at(self, i: usize) -> u64 {
+        ((self >> (2 * i)) & 0b11) as u64
+    }
+}
+
+/// K-mer packed at two bits per base (A=00, C=01, G=10, T=11), in a `u64`
+/// unless k needs the wider `u128`.
+/// The k-mer size is not stored, so decoding needs it passed back in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PackedKmer<W = u64>(W);
+
+impl<W: PackedWord> PackedKmer<W> {
+    /// Largest k-mer size that fits in a packed k-mer
+    pub const MAX_K: usize = W::BITS / 2;
+
+    /// Append a base's two-bit code, dropping the oldest base once the
+    /// k-mer holds `k`
+    pub fn push(self, code: u64, k: usize) -> Self {
+        PackedKmer(self.0.shift_in(code, k))
+    }
+
+    /// Decode back into a sequence string of length k
+    pub fn sequence(&self, k: usize) -> String {
+        (0..k)
+            .rev()
+            .map(|i| decode_base(self.0.code_at(i)) as char)
+            .collect()
+    }
+}
+
+/// Two-bit code for a nucleotide, or None for anything outside ACGT
+pub fn encode_base(base: u8) -> Option<u64> {
+    match base {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+/// Residues k-mers are built from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Alphabet {
+    #[default]
+    Dna,
+    Protein,
+}
+
+impl Alphabet {
+    /// Whether `symbol` may appear in a k-mer. Protein k-mers use the 20
+    /// standard amino acids, so stops (`*`) and unknowns (`X`) break them.
+    pub fn is_valid(self, symbol: u8) -> bool {
+        match self {
+            Alphabet::Dna => matches!(symbol, b'A' | b'C' | b'G' | b'T'),
+            Alphabet::Protein => b"ACDEFGHIKLMNPQRSTVWY".contains(&symbol),
+        }
+    }
+
+    /// Symbol written over masked positions so no k-mer spans them
+    pub fn mask_symbol(self) -> u8 {
+        match self {
+            Alphabet::Dna => b'N',
+            Alphabet::Protein => b'X',
+        }
+    }
+
+    /// Parse the name stored in the database (`dna` or `protein`)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dna" => Some(Alphabet::Dna),
+            "protein" => Some(Alphabet::Protein),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Alphabet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Alphabet::Dna => write!(f, "dna"),
+            Alphabet::Protein => write!(f, "protein"),
+        }
+    }
+}
+
+/// Concrete bases an IUPAC ambiguity code stands for (`R` -> `AG`), or None for
+/// ACGT and anything else. `N` is deliberately excluded: it marks missing
+/// sequence rather than a known ambiguity, so it still breaks k-mers.
+pub fn iupac_bases(code: u8) -> Option<&'static [u8]> {
+    match code {
+        b'R' => Some(b"AG"),
+        b'Y' => Some(b"CT"),
+        b'S' => Some(b"CG"),
+        b'W' => Some(b"AT"),
+        b'K' => Some(b"GT"),
+        b'M' => Some(b"AC"),
+        b'B' => Some(b"CGT"),
+        b'D' => Some(b"AGT"),
+        b'H' => Some(b"ACT"),
+        b'V' => Some(b"ACG"),
+        _ => None,
+    }
+}
+
+/// Shannon entropy (bits) of a k-mer's base composition: 0 for homopolymers
+/// such as `AAAA`, 1 for two-base repeats like `ATAT`, at most 2
+pub fn shannon_entropy(sequence: &[u8]) -> f64 {
+    let mut counts = [0usize; 4];
+    for &base in sequence {
+        if let Some(code) = encode_base(base) {
+            counts[code as usize] += 1;
+        }
+    }
+
+    let total: usize = counts.iter().sum();
+    counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Lexicographically smaller of a k-mer and its reverse complement
+pub fn canonical(kmer: &[u8]) -> Vec<u8> {
+    let reverse: Vec<u8> = kmer.iter().rev().map(|&base| complement(base)).collect();
+    if reverse.as_slice() < kmer {
+        reverse
+    } else {
+        kmer.to_vec()
+    }
+}
+
+/// Fold counts of both strands onto canonical k-mers, adding up a k-mer's
+/// count and its reverse complement's
+pub fn canonical_counts<K, V>(counts: impl IntoIterator<Item = (K, V)>) -> HashMap<String, V>
+where
+    K: AsRef<str>,
+    V: Default + AddAssign,
+{
+    let mut canonical_counts: HashMap<String, V> = HashMap::new();
+    for (kmer, count) in counts {
+        let kmer = String::from_utf8_lossy(&canonical(kmer.as_ref().as_bytes())).into_owned();
+        *canonical_counts.entry(kmer).or_default() += count;
+    }
+    canonical_counts
+}
+
+/// Complementary DNA base; anything else is returned unchanged
+pub fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        other => other,
+    }
+}
+
+/// Fraction of a sequence's ACGT bases that are G or C (0 if it has none)
+pub fn gc_fraction(sequence: &[u8]) -> f64 {
+    let (gc, total) = sequence.iter().fold((0usize, 0usize), |(gc, total), &base| match base {
+        b'G' | b'C' => (gc + 1, total + 1),
+        b'A' | b'T' => (gc, total + 1),
+        _ => (gc, total),
+    });
+    if total == 0 {
+        return 0.0;
+    }
+    gc as f64 / total as f64
+}
+
+fn decode_base(code: u64) -> u8 {
+    match code {
+        0 => b'A',
+        1 => b'C',
+        2 => b'G',
+        _ => b'T',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack<W: PackedWord>(sequence: &[u8]) -> Option<PackedKmer<W>> {
+        sequence.iter()
+            .try_fold(PackedKmer::default(), |kmer, &base| {
+                encode_base(base).map(|code| kmer.push(code, sequence.len()))
+            })
+    }
+
+    #[test]
+    fn test_packed_round_trip() {
+        let kmer = pack::<u64>(b"ACGTTGCA").unwrap();
+        assert_eq!(kmer.sequence(8), "ACGTTGCA");
+
+        let long = b"ACGTACGTACGTACGTACGTACGTACGTACGT";
+        assert_eq!(pack::<u64>(long).unwrap().sequence(32), "ACGTACGTACGTACGTACGTACGTACGTACGT");
+    }
+
+    #[test]
+    fn test_wide_packed_round_trip() {
+        let k40 = b"TTGCAACGTAGGCTAACGTTGCAACGTAGGCTAACGTTGA";
+        assert_eq!(PackedKmer::<u128>::MAX_K, 64);
+        assert_eq!(pack::<u128>(k40).unwrap().sequence(40), String::from_utf8_lossy(k40));
+
+        let k64 = b"TGCATGCATGCATGCATGCATGCATGCATGCAACGTACGTACGTACGTACGTACGTACGTACGT";
+        assert_eq!(pack::<u128>(k64).unwrap().sequence(64), String::from_utf8_lossy(k64));
+    }
+
+    #[test]
+    fn test_push_keeps_last_k_bases() {
+        let kmer = b"ACGTA".iter()
+            .fold(PackedKmer::<u64>::default(), |kmer, &base| kmer.push(encode_base(base).unwrap(), 3));
+        assert_eq!(kmer.sequence(3), "GTA");
+    }
+
+    #[test]
+    fn test_packed_rejects_ambiguous_bases() {
+        assert!(pack::<u64>(b"ACNT").is_none());
+    }
+
+    #[test]
+    fn test_shannon_entropy() {
+        assert_eq!(shannon_entropy(b"AAAAAAAA"), 0.0);
+        assert!((shannon_entropy(b"ATATATAT") - 1.0).abs() < 1e-12);
+        assert!((shannon_entropy(b"ACGTACGT") - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_gc_fraction() {
+        assert_eq!(gc_fraction(b"ATAT"), 0.0);
+        assert_eq!(gc_fraction(b"ACGT"), 0.5);
+        assert_eq!(gc_fraction(b"GCNN"), 1.0);
+        assert_eq!(gc_fraction(b"NNNN"), 0.0);
+    }
+
+    #[test]
+    fn test_alphabet_validity() {
+        assert!(Alphabet::Dna.is_valid(b'A'));
+        assert!(!Alphabet::Dna.is_valid(b'L'));
+        assert!(Alphabet::Protein.is_valid(b'L'));
+        assert!(!Alphabet::Protein.is_valid(b'X'));
+        assert!(!Alphabet::Protein.is_valid(b'*'));
+        assert_eq!(Alphabet::from_name(&Alphabet::Protein.to_string()), Some(Alphabet::Protein));
+    }
+}
@@ -0,0 +1,31 @@
+//! K-mer based pathogen profiling.
+//!
+//! Count a sample's k-mers with [`KmerCounter`], build reference profiles into
+//! a [`Database`], and match samples against them with [`ProfileAnalyzer`]:
+//!
+//! ```no_run
+//! use papro_rusty::{KmerCounter, ProfileAnalyzer, TaxonomyLevel};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let counter = KmerCounter::new(21);
+//! counter.count_sequence(b"ACGTACGTACGTACGTACGTACGTACGT")?;
+//! let analyzer = ProfileAnalyzer::new("profiles.db", 0.1, 10, TaxonomyLevel::Species)?;
+//! for m in analyzer.analyze_sample(&counter)? {
+//!     println!("{}\t{:.4}", m.name, m.confidence_score);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#[doc(hidden)]
+pub mod cli;
+#[doc(hidden)]
+pub mod config;
+pub mod db;
+pub mod io;
+pub mod kmer;
+pub mod profile;
+
+pub use db::Database;
+pub use kmer::KmerCounter;
+pub use profile::{Profile, ProfileAnalyzer, ProfileMatch, TaxonomyLevel};
@@ -1,28 +1,81 @@
-mod cli;
-mod db;
-mod profile;
-mod io;
-mod kmer;
-
 use anyhow::{Result, Context};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use log::{info, warn};
-use profile::analyzer::DetailedAnalysis;
-use profile::ProfileMatch;
-use std::io::Write;
+use papro_rusty::profile::{Classification, Profile, ProfileMatch};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{mpsc, Arc, OnceLock};
+use std::time::SystemTime;
 use rayon::prelude::*;
 
-use crate::cli::{Cli, Commands, DatabaseSubcommand, ExportFormat};
-use crate::db::Database;
-use crate::io::FastxReader;
-use crate::io::output_analysis;
-use crate::kmer::KmerCounter;
-use crate::profile::ProfileAnalyzer;
+use papro_rusty::cli::{self, Cli, Commands, DatabaseSubcommand, ExportFormat, OutputFormat};
+use papro_rusty::config::Config;
+use papro_rusty::db::{Canonicalization, Database, ProfileOptions};
+use papro_rusty::io::{dedup_pairs, dedup_paths, DatabaseRecord, FastxReader, ProfileRecord, ReaderStats, RunManifest};
+use papro_rusty::io::{
+    export_profile_fasta, export_profile_json, export_profile_mash, export_profile_tsv, output_analysis,
+    output_classification, output_core_kmers, output_kmer_specificity, output_lineage_rollup, output_reader_stats,
+    write_distance_matrix,
+    ParquetExporter, ReportOptions, ReportWriters, DEFAULT_PRECISION,
+};
+use papro_rusty::kmer::{Alphabet, KmerCounter, SpacedSeed};
+use papro_rusty::profile::{ConfidenceWeights, Normalization, ProfileAnalyzer, SimilarityMetric, TaxonomyLevel};
+
+/// Exit status of an analysis that ran successfully but matched no profile
+/// (errors exit with 1, as for every command)
+const NO_MATCHES_EXIT_CODE: u8 = 2;
+
+/// How an analysis that ran without errors turned out
+enum AnalysisOutcome {
+    /// At least one sample matched a profile
+    Matched,
+    /// No sample matched any profile
+    NoMatches,
+}
+
+/// Thread count the global rayon pool was built with, once it has been
+static THREAD_POOL_SIZE: OnceLock<usize> = OnceLock::new();
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// Builds the global rayon pool with `threads` workers, or one per CPU when
+/// not given. The pool can only be built once per process, so later calls
+/// keep the existing pool and warn if they asked for a different size.
+fn init_thread_pool(threads: Option<usize>) -> Result<usize> {
+    if threads == Some(0) {
+        return Err(anyhow::anyhow!("--threads must be at least 1"));
+    }
+    let threads = match threads {
+        Some(threads) => threads,
+        None => {
+            let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+            info!("Using {} threads (one per CPU; set with --threads)", cpus);
+            cpus
+        }
+    };
+
+    if let Some(&size) = THREAD_POOL_SIZE.get() {
+        if size != threads {
+            warn!("Thread pool already runs {} threads; ignoring a request for {}", size, threads);
+        }
+        return Ok(size);
+    }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .context("Failed to initialize thread pool")?;
+    Ok(*THREAD_POOL_SIZE.get_or_init(|| threads))
+}
+
+fn main() -> Result<ExitCode> {
+    // Config values fill in only the options left off the command line
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if let Some(config) = Config::load(cli.config.as_deref())? {
+        config.apply(&mut cli, &matches);
+    }
 
     // Set up logging
     let mut builder = env_logger::Builder::from_default_env();
@@ -32,26 +85,32 @@ fn main() -> Result<()> {
     }
     if cli.verbose {
         builder.filter_level(log::LevelFilter::Debug);
+    } else if cli.quiet {
+        builder.filter_level(log::LevelFilter::Warn);
     }
     builder.init();
 
     // Set up parallel processing
-    if let Some(threads) = cli.threads {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(threads)
-            .build_global()
-            .context("Failed to initialize thread pool")?;
-    }
+    init_thread_pool(cli.threads)?;
 
     match cli.command {
-        Commands::DB(db_cmd) => handle_db_command(db_cmd, cli.verbose)?,
-        Commands::Analyze(analyze_cmd) => handle_analyze_command(analyze_cmd, cli.verbose)?,
+        Commands::DB(db_cmd) => handle_db_command(db_cmd, cli.verbose, cli.yes, cli.quiet)?,
+        Commands::Analyze(analyze_cmd) => {
+            match handle_analyze_command(analyze_cmd, cli.verbose, cli.quiet)? {
+                AnalysisOutcome::Matched => {}
+                AnalysisOutcome::NoMatches => return Ok(ExitCode::from(NO_MATCHES_EXIT_CODE)),
+            }
+        }
+        Commands::Completions { shell } => {
+            cli::write_completions(shell, &mut std::io::stdout())
+                .context("Failed to write completion script")?;
+        }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
-fn handle_db_command(cmd: cli::DatabaseCommand, verbose: bool) -> Result<()> {
+fn handle_db_command(cmd: cli::DatabaseCommand, _verbose: bool, assume_yes: bool, quiet: bool) -> Result<()> {
     match cmd.command {
         DatabaseSubcommand::Init => {
             info!("Initializing database at {}", cmd.database.display());
@@ -61,15 +120,120 @@ fn handle_db_command(cmd: cli::DatabaseCommand, verbose: bool) -> Result<()> {
 
         DatabaseSubcommand::Create { 
             input_files, 
+            from_counts,
             kmer_size, 
             level, 
             name,
-            skip_existing 
+            skip_existing,
+            strict,
+            paired,
+            allow_duplicates,
+            min_quality,
+            sketch_size,
+            append,
+            parent,
+            dry_run,
+            checkpoint,
+            resume,
+            min_count,
+            homopolymer_compress,
+            respect_masking,
+            expand_iupac,
+            alphabet,
+            subsample_fraction,
+            subsample_seed,
+            max_reads,
+            region,
+            unmapped_only,
+            max_kmers,
+            min_entropy,
+            description,
+            bloom_fp_rate,
+            seed,
+            minimizer_window,
+            normalization,
+            manifest,
         } => {
+            let started_at = SystemTime::now();
+            let manifest_inputs = match &from_counts {
+                Some(counts_path) => vec![counts_path.clone()],
+                None => input_files.clone(),
+            };
+            let parameters = serde_json::json!({
+                "kmer_size": kmer_size,
+                "level": TaxonomyLevel::from(level).to_string(),
+                "paired": paired,
+                "strict": strict,
+                "min_quality": min_quality,
+                "min_count": min_count,
+                "min_entropy": min_entropy,
+                "homopolymer_compress": homopolymer_compress,
+                "respect_masking": respect_masking,
+                "expand_iupac": expand_iupac,
+                "alphabet": Alphabet::from(alphabet).to_string(),
+                "subsample_fraction": subsample_fraction,
+                "subsample_seed": subsample_seed,
+                "max_reads": max_reads,
+                "region": region,
+                "unmapped_only": unmapped_only,
+                "max_kmers": max_kmers,
+                "seed": seed,
+                "minimizer_window": minimizer_window,
+                "sketch_size": sketch_size,
+                "bloom_fp_rate": bloom_fp_rate,
+                "normalization": Normalization::from(normalization).to_string(),
+                "append": append,
+            });
+            let seed = seed.map(|pattern| SpacedSeed::parse(&pattern, kmer_size)).transpose()?;
+            check_minimizer_window(minimizer_window)?;
             let mut db = Database::new(&cmd.database)?;
+            let reader = if paired {
+                FastxReader::new_paired(input_pairs(input_files, allow_duplicates)?)
+            } else {
+                FastxReader::new(input_paths(input_files, allow_duplicates))
+            };
+            let file_count = reader.file_count();
+            check_subsample_fraction(subsample_fraction)?;
+            if alphabet == cli::Alphabet::Protein && (expand_iupac || min_entropy > 0.0) {
+                return Err(anyhow::anyhow!(
+                    "--expand-iupac and --min-entropy work on nucleotides and cannot be used with --alphabet protein"
+                ));
+            }
+            let reader = reader
+                .strict(strict)
+                .min_quality(min_quality)
+                .homopolymer_compress(homopolymer_compress)
+                .respect_masking(respect_masking)
+                .expand_iupac(expand_iupac)
+                .alphabet(alphabet.into())
+                .subsample(subsample_fraction, subsample_seed)
+                .max_reads(max_reads)
+                .region(region)
+                .unmapped_only(unmapped_only);
+            // A count dump is parsed without the reader, so it has no progress to show
+            let progress = ProgressBar::with_draw_target(None, progress_target(quiet || from_counts.is_some()))
+                .with_style(progress_style("{spinner} Reading input [{bar:40}] {bytes}/{total_bytes} ({eta})"));
+            let reader = reader.progress(progress.clone());
             
-            if db.get_profile(&name)?.is_some() {
-                if skip_existing {
+            // A count dump's k is only known once it is read, so any k counts
+            if db.has_profile(&name, from_counts.is_none().then_some(kmer_size))? {
+                if append {
+                    // Link first, so a rejected parent leaves the profile untouched
+                    if let Some(parent) = &parent {
+                        db.link_profiles(&name, parent)?;
+                    }
+                    info!("Appending {} input files to profile {}...", file_count, name);
+                    let (_, stats) = db.update_profile(&reader, &name, level.into(), kmer_size, seed.as_ref(), minimizer_window, max_kmers)?;
+                    progress.finish_and_clear();
+                    print_reader_stats(&stats);
+                    if let Some(path) = manifest {
+                        let (database, profile) = created_profile_records(&db, &cmd.database, &name, kmer_size)?;
+                        RunManifest::new(started_at, &manifest_inputs, database, parameters)?
+                            .with_profile(profile)
+                            .write(&path)?;
+                    }
+                    return Ok(());
+                } else if skip_existing {
                     warn!("Profile {} already exists, skipping", name);
                     return Ok(());
                 } else {
@@ -77,8 +241,49 @@ fn handle_db_command(cmd: cli::DatabaseCommand, verbose: bool) -> Result<()> {
                 }
             }
 
-            info!("Creating profile from {} input files...", input_files.len());
-            db.create_profile(input_files, kmer_size, level.into(), name)?;
+            let options = ProfileOptions {
+                kmer_size,
+                level: level.into(),
+                sketch_size,
+                min_count,
+                description,
+                parent,
+                bloom_fp_rate,
+                seed,
+                minimizer_window,
+                min_entropy,
+                max_unique_kmers: max_kmers,
+                normalization: normalization.into(),
+            };
+            if dry_run {
+                info!("Counting {} input files without writing (--dry-run)...", file_count);
+                let (profile, stats) = db.preview_profile(&reader, name, &options)?;
+                progress.finish_and_clear();
+                print_profile_preview(&profile);
+                print_reader_stats(&stats);
+                return Ok(());
+            }
+            let profile = if let Some(counts_path) = from_counts {
+                info!("Creating profile from k-mer counts in {}...", counts_path.display());
+                db.create_profile_from_counts(&counts_path, name.clone(), &options)?
+            } else {
+                info!("Creating profile from {} input files...", file_count);
+                let (profile, stats) = if checkpoint || resume {
+                    let path = checkpoint_path(&cmd.database, &name, kmer_size);
+                    db.create_profile_with_checkpoint(&reader, name.clone(), &options, &path, resume)?
+                } else {
+                    db.create_profile(&reader, name.clone(), &options)?
+                };
+                progress.finish_and_clear();
+                print_reader_stats(&stats);
+                profile
+            };
+            if let Some(path) = manifest {
+                let (database, profile) = created_profile_records(&db, &cmd.database, &name, profile.k)?;
+                RunManifest::new(started_at, &manifest_inputs, database, parameters)?
+                    .with_profile(profile)
+                    .write(&path)?;
+            }
         }
 
         DatabaseSubcommand::List { level, detailed } => {
@@ -101,7 +306,13 @@ fn handle_db_command(cmd: cli::DatabaseCommand, verbose: bool) -> Result<()> {
                 );
 
                 if detailed {
-                    if let Some(profile_data) = db.get_profile(&profile.name)? {
+                    if let Some(profile_data) = db.get_profile(&profile.name, Some(profile.k))? {
+                        println!("\n# Metadata for {}", profile.name);
+                        println!("description\t{}", profile_data.description.as_deref().unwrap_or("-"));
+                        println!("source_files\t{}", format_source_files(&profile_data.source_files));
+                        println!("estimated_coverage\t{:.1}", profile_data.estimated_coverage);
+                        println!("created_by_version\t{}", profile_data.created_by_version.as_deref().unwrap_or("-"));
+
                         println!("\n# Top k-mers for {}", profile.name);
                         println!("kmer\tfrequency");
                         let mut kmers: Vec<_> = profile_data.frequencies.iter().collect();
@@ -115,64 +326,245 @@ fn handle_db_command(cmd: cli::DatabaseCommand, verbose: bool) -> Result<()> {
             }
         }
 
-        DatabaseSubcommand::Remove { name, force } => {
+        DatabaseSubcommand::Remove { name, kmer_size, force } => {
             let mut db = Database::new(&cmd.database)?;
             
-            if !force {
-                print!("Are you sure you want to remove profile {}? [y/N] ", name);
-                std::io::stdout().flush()?;
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input)?;
-                if !input.trim().eq_ignore_ascii_case("y") {
-                    info!("Operation cancelled");
-                    return Ok(());
-                }
+            if !confirm(&format!("remove profile {}", name), force || assume_yes)? {
+                info!("Operation cancelled");
+                return Ok(());
             }
 
-            if db.remove_profile(&name)? {
+            if db.remove_profile(&name, kmer_size)? {
                 info!("Profile {} removed", name);
             } else {
                 warn!("Profile {} not found", name);
             }
         }
 
-        DatabaseSubcommand::Export { names, output, format } => {
+        DatabaseSubcommand::Info { name, kmer_size, top } => {
+            let db = Database::new(&cmd.database)?;
+            let Some(profile) = db.get_profile(&name, kmer_size)? else {
+                return Err(anyhow::anyhow!("Profile {} not found", name));
+            };
+
+            println!("metric\tvalue");
+            println!("name\t{}", profile.name);
+            println!("level\t{}", profile.level);
+            println!("k_size\t{}", profile.k);
+            println!("total_kmers\t{}", profile.total_kmers);
+            println!("distinct_kmers\t{}", profile.frequencies.len());
+            let unique_kmers = db.unique_kmer_count(&name, Some(profile.k))?;
+            println!("unique_kmers\t{}", unique_kmers);
+            println!("shared_kmers\t{}", profile.frequencies.len() - unique_kmers);
+            match profile.alphabet {
+                Alphabet::Dna => println!("gc_content\t{:.4}", profile.gc_content()),
+                Alphabet::Protein => println!("gc_content\t-"),
+            }
+            println!("parent\t{}", profile.parent.as_deref().unwrap_or("-"));
+            println!("seed\t{}", profile.seed.as_deref().unwrap_or("-"));
+            println!("minimizer_window\t{}", profile.minimizer_window.map_or("-".to_string(), |w| w.to_string()));
+            println!("alphabet\t{}", profile.alphabet);
+            println!("homopolymer_compressed\t{}", profile.homopolymer_compressed);
+            println!("canonical\t{}", profile.canonical);
+            println!("estimated_coverage\t{:.1}", profile.estimated_coverage);
+            println!("min_count\t{}", profile.min_count.map_or("-".to_string(), |c| c.to_string()));
+            println!("min_entropy\t{}", profile.min_entropy.map_or("-".to_string(), |e| e.to_string()));
+            println!("description\t{}", profile.description.as_deref().unwrap_or("-"));
+            println!("source_files\t{}", format_source_files(&profile.source_files));
+            println!("created_at\t{}", profile.created_at.as_deref().unwrap_or("-"));
+            println!("created_by_version\t{}", profile.created_by_version.as_deref().unwrap_or("-"));
+
+            let kmers = profile.sorted_frequencies();
+            println!("top_kmers\t{}", format_kmer_list(kmers.iter().take(top)));
+            println!("bottom_kmers\t{}", format_kmer_list(kmers.iter().rev().take(top)));
+        }
+
+        DatabaseSubcommand::Rename { old, new } => {
+            let mut db = Database::new(&cmd.database)?;
+
+            if !db.rename_profile(&old, &new)? {
+                warn!("Profile {} not found", old);
+            }
+        }
+
+        DatabaseSubcommand::SetParent { name, parent } => {
+            let mut db = Database::new(&cmd.database)?;
+
+            if let Some(parent) = parent {
+                db.link_profiles(&name, &parent)?;
+            } else if !db.clear_parent(&name)? {
+                warn!("Profile {} not found", name);
+            }
+        }
+
+        DatabaseSubcommand::Link { child, parent } => {
+            let mut db = Database::new(&cmd.database)?;
+            db.link_profiles(&child, &parent)?;
+            info!("Linked {} under {}", child, parent);
+        }
+
+        DatabaseSubcommand::Kmers { name, kmer_size, top, min_freq } => {
+            let db = Database::new(&cmd.database)?;
+            let Some(profile) = db.get_profile(&name, kmer_size)? else {
+                return Err(anyhow::anyhow!("Profile {} not found", name));
+            };
+
+            let mut kmers = profile.sorted_frequencies();
+            kmers.retain(|(_, freq)| **freq >= min_freq);
+            kmers.truncate(top.unwrap_or(kmers.len()));
+
+            // Raw counts are not stored; recover them from the frequency, if it has them
+            println!("kmer\tcount\tfrequency");
+            for (kmer, freq) in kmers {
+                let count = profile.kmer_count(*freq).map_or("NA".to_string(), |count| count.to_string());
+                println!("{}\t{}\t{:.6}", kmer, count, freq);
+            }
+        }
+
+        DatabaseSubcommand::Distance { profile_a, profile_b, kmer_size, metric } => {
+            let db = Database::new(&cmd.database)?;
+            let mut profiles = Vec::with_capacity(2);
+            for name in [&profile_a, &profile_b] {
+                let profile = db.get_profile(name, kmer_size)?
+                    .ok_or_else(|| anyhow::anyhow!("Profile {} not found", name))?;
+                profiles.push(profile);
+            }
+
+            if !profiles[0].is_comparable(&profiles[1]) {
+                return Err(anyhow::anyhow!(
+                    "Profiles {} and {} were built with different k-mer sizes, seeds, minimizer windows, alphabets, \
+                     strand handling, homopolymer compression or normalization",
+                    profile_a, profile_b
+                ));
+            }
+
+            println!("{:.6}", profiles[0].distance(&profiles[1], metric.into()));
+        }
+
+        DatabaseSubcommand::Matrix { output, metric, level, kmer_size, format } => {
+            let db = Database::new(&cmd.database)?;
+            let mut summaries = db.list_profiles(level.map(Into::into))?;
+            summaries.retain(|summary| kmer_size.is_none_or(|k| summary.k == k));
+            if summaries.len() < 2 {
+                return Err(anyhow::anyhow!("At least two profiles are needed for a distance matrix"));
+            }
+
+            let mut profiles = Vec::with_capacity(summaries.len());
+            for summary in &summaries {
+                let profile = db.get_profile(&summary.name, Some(summary.k))?
+                    .ok_or_else(|| anyhow::anyhow!("Profile {} not found", summary.name))?;
+                profiles.push(profile);
+            }
+
+            if let Some(mismatch) = profiles.iter().find(|p| !p.is_comparable(&profiles[0])) {
+                return Err(anyhow::anyhow!(
+                    "Profiles {} and {} were built with different k-mer sizes, seeds, minimizer windows, alphabets, \
+                     strand handling, homopolymer compression or normalization",
+                    profiles[0].name, mismatch.name
+                ));
+            }
+
+            // Fill the upper triangle and mirror it
+            let n = profiles.len();
+            let mut distances = vec![vec![0.0; n]; n];
+            for i in 0..n {
+                for j in (i + 1)..n {
+                    let distance = profiles[i].distance(&profiles[j], metric.into());
+                    distances[i][j] = distance;
+                    distances[j][i] = distance;
+                }
+                info!("Computed distances for {} of {} profiles", i + 1, n);
+            }
+
+            let names: Vec<String> = profiles.into_iter().map(|profile| profile.name).collect();
+            let mut file = File::create(&output)?;
+            write_distance_matrix(&names, &distances, format, &mut file)?;
+            info!("Wrote {}x{} distance matrix to {}", n, n, output.display());
+        }
+
+        DatabaseSubcommand::Crossval { name, kmer_size, level, top, duplicate_coverage, precision } => {
+            let db = Database::new(&cmd.database)?;
+            let profile = db.get_profile(&name, kmer_size)?
+                .ok_or_else(|| anyhow::anyhow!("Profile {} not found", name))?;
+            let level = level.map_or(profile.level.clone(), Into::into);
+            // Every stored profile counts, so only a score of zero rules one out
+            let analyzer = ProfileAnalyzer::new(&cmd.database, 0.0, 1, level)?;
+            let mut matches = analyzer.cross_validate(&profile)?;
+            matches.truncate(top);
+
+            let decimals = precision.unwrap_or(DEFAULT_PRECISION);
+            println!("name\tsample_coverage\tprofile_coverage\tjaccard_similarity\tshared_kmers\tconfidence_score\tduplicate");
+            for m in &matches {
+                let duplicate = m.sample_coverage >= duplicate_coverage;
+                println!("{}\t{:.*}\t{:.*}\t{:.*}\t{}\t{:.*}\t{}",
+                    m.name,
+                    decimals, m.sample_coverage,
+                    decimals, m.profile_coverage,
+                    decimals, m.jaccard_similarity,
+                    m.shared_kmers,
+                    decimals, m.confidence_score,
+                    if duplicate { "yes" } else { "no" },
+                );
+                if duplicate {
+                    warn!(
+                        "{} holds {:.1}% of {}'s k-mers; one may be redundant or mislabeled",
+                        m.name, m.sample_coverage * 100.0, name
+                    );
+                }
+            }
+        }
+
+        DatabaseSubcommand::Export { names, output, format, kmer_size, precision } => {
             let db = Database::new(&cmd.database)?;
             std::fs::create_dir_all(&output)?;
 
-            let profiles = if names.is_empty() {
+            let profiles: Vec<(String, Option<usize>)> = if names.is_empty() {
                 db.list_profiles(None)?
                     .into_iter()
-                    .map(|p| p.name)
+                    .filter(|p| kmer_size.is_none_or(|k| p.k == k))
+                    .map(|p| (p.name, Some(p.k)))
                     .collect()
             } else {
-                names
+                names.into_iter().map(|name| (name, kmer_size)).collect()
             };
 
-            for name in profiles {
-                if let Some(profile) = db.get_profile(&name)? {
+            // Parquet gathers every profile into one file, a profile at a time
+            if format == ExportFormat::Parquet {
+                let output_path = output.join("profiles.parquet");
+                let mut exporter = ParquetExporter::new(File::create(&output_path)?)?;
+                for (name, k) in &profiles {
+                    match db.get_profile(name, *k)? {
+                        Some(profile) => exporter.write_profile(&profile)?,
+                        None => warn!("Profile {} not found", name),
+                    }
+                }
+                exporter.finish()?;
+                info!("Exported {} profiles to {}", profiles.len(), output_path.display());
+                return Ok(());
+            }
+
+            for (index, (name, k)) in profiles.iter().enumerate() {
+                if let Some(profile) = db.get_profile(name, *k)? {
+                    // Names exported at several k-mer sizes get the size in the file name
+                    let repeated = profiles.iter().enumerate()
+                        .any(|(other, (other_name, _))| other != index && other_name == name);
+                    let stem = if repeated { format!("{}_k{}", name, profile.k) } else { name.clone() };
                     let file_name = match format {
-                        ExportFormat::Fasta => format!("{}.fasta", name),
-                        ExportFormat::Tsv => format!("{}.tsv", name),
+                        ExportFormat::Fasta => format!("{}.fasta", stem),
+                        ExportFormat::Tsv => format!("{}.tsv", stem),
+                        ExportFormat::Json => format!("{}.json", stem),
+                        ExportFormat::Mash => format!("{}.msh.json", stem),
+                        ExportFormat::Parquet => unreachable!("Parquet exports are written above"),
                     };
                     let output_path = output.join(file_name);
                     let mut file = File::create(&output_path)?;
 
                     match format {
-                        ExportFormat::Fasta => {
-                            for (kmer, freq) in &profile.frequencies {
-                                writeln!(file, ">{} {:.6}", name, freq)?;
-                                writeln!(file, "{}", kmer)?;
-                            }
-                        }
-                        ExportFormat::Tsv => {
-                            writeln!(file, "kmer\tfrequency")?;
-                            let mut kmers: Vec<_> = profile.frequencies.iter().collect();
-                            kmers.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
-                            for (kmer, freq) in kmers {
-                                writeln!(file, "{}\t{:.6}", kmer, freq)?;
-                            }
-                        }
+                        ExportFormat::Fasta => export_profile_fasta(&profile, precision, &mut file)?,
+                        ExportFormat::Tsv => export_profile_tsv(&profile, precision, &mut file)?,
+                        ExportFormat::Json => export_profile_json(&profile, precision, &mut file)?,
+                        ExportFormat::Mash => export_profile_mash(&profile, &mut file)?,
+                        ExportFormat::Parquet => unreachable!("Parquet exports are written above"),
                     }
                     info!("Exported profile {} to {}", name, output_path.display());
                 } else {
@@ -181,9 +573,47 @@ fn handle_db_command(cmd: cli::DatabaseCommand, verbose: bool) -> Result<()> {
             }
         }
 
-        DatabaseSubcommand::Stats => {
+        DatabaseSubcommand::Merge { other, on_conflict } => {
+            let mut db = Database::new(&cmd.database)?;
+            let summary = db.merge(&other, on_conflict.into())?;
+
+            println!("metric\tvalue");
+            println!("added\t{}", summary.added);
+            println!("renamed\t{}", summary.renamed);
+            println!("skipped\t{}", summary.skipped);
+        }
+
+        DatabaseSubcommand::Histogram { input_files, kmer_size, max_bin, tsv } => {
+            if max_bin == 0 {
+                return Err(anyhow::anyhow!("--max-bin must be at least 1"));
+            }
+
+            let counter = KmerCounter::new(kmer_size);
+            counter.count_from_reader(&FastxReader::new(input_files))?;
+            let bins = counter.histogram(max_bin);
+
+            if tsv {
+                println!("multiplicity\tnumber_of_kmers");
+                for (multiplicity, count) in bins.iter().enumerate().skip(1) {
+                    println!("{}\t{}", multiplicity, count);
+                }
+            } else {
+                println!("{:>12}\t{:>15}", "Multiplicity", "Distinct k-mers");
+                println!("{}", "-".repeat(32));
+                for (multiplicity, count) in bins.iter().enumerate().skip(1) {
+                    let label = if multiplicity == max_bin {
+                        format!(">={}", multiplicity)
+                    } else {
+                        multiplicity.to_string()
+                    };
+                    println!("{:>12}\t{:>15}", label, count);
+                }
+            }
+        }
+
+        DatabaseSubcommand::Stats { detailed } => {
             let db = Database::new(&cmd.database)?;
-            let stats = db.get_statistics()?;
+            let stats = db.get_statistics(detailed)?;
             
             println!("metric\tvalue");
             println!("total_profiles\t{}", stats.total_profiles);
@@ -194,13 +624,81 @@ fn handle_db_command(cmd: cli::DatabaseCommand, verbose: bool) -> Result<()> {
             for (level, count) in &stats.profiles_by_level {
                 println!("{}\t{}", level, count);
             }
+
+            if detailed {
+                println!("\n# Frequency summary");
+                println!("metric\tvalue");
+                println!("mean_frequency\t{:.6}", stats.mean_frequency);
+                println!("median_frequency\t{:.6}", stats.median_frequency);
+                println!("shared_kmers\t{}", stats.shared_kmers);
+
+                println!("\n# K-mers by profile");
+                println!("name\tkmers");
+                for (name, count) in &stats.kmers_by_profile {
+                    println!("{}\t{}", name, count);
+                }
+
+                println!("\n# Profiles by k-mer size");
+                println!("k\tcount");
+                for (k, count) in &stats.profiles_by_kmer_size {
+                    println!("{}\t{}", k, count);
+                }
+
+                println!("\n# Most polymorphic k-mers");
+                println!("kmer\tprofiles");
+                for (kmer, count) in &stats.most_polymorphic_kmers {
+                    println!("{}\t{}", kmer, count);
+                }
+            }
         }
 
-        DatabaseSubcommand::Validate => {
+        DatabaseSubcommand::Optimize { kmer_index, drop_kmer_index } => {
+            let db = Database::new(&cmd.database)?;
+            if kmer_index || drop_kmer_index {
+                db.set_kmer_index(kmer_index)?;
+            }
+            let summary = db.optimize()?;
+
+            println!("metric\tvalue");
+            println!("bytes_before\t{}", summary.bytes_before);
+            println!("bytes_after\t{}", summary.bytes_after);
+            println!("bytes_reclaimed\t{}", summary.bytes_before.saturating_sub(summary.bytes_after));
+        }
+
+        DatabaseSubcommand::Canonicalize { name, kmer_size } => {
+            let mut db = Database::new(&cmd.database)?;
+            let targets: Vec<(String, Option<usize>)> = match &name {
+                Some(name) => vec![(name.clone(), kmer_size)],
+                None => db.list_profiles(None)?
+                    .into_iter()
+                    .map(|summary| (summary.name, Some(summary.k)))
+                    .collect(),
+            };
+
+            for (profile, k) in targets {
+                match db.canonicalize_profile(&profile, k)? {
+                    Canonicalization::Folded => println!("Canonicalized profile {}", profile),
+                    Canonicalization::AlreadyCanonical => println!("Profile {} is already canonical", profile),
+                    // Only an explicitly named profile is worth failing over
+                    Canonicalization::Unsupported(reason) if name.is_some() => {
+                        return Err(anyhow::anyhow!("Cannot canonicalize profile {}: {}", profile, reason));
+                    }
+                    Canonicalization::Unsupported(reason) => println!("Skipped profile {}: {}", profile, reason),
+                }
+            }
+        }
+
+        DatabaseSubcommand::Verify { name, kmer_size } => {
+            let db = Database::new(&cmd.database)?;
+            let hash = db.verify_profile(&name, kmer_size)?;
+            println!("Profile {} verified (content hash {})", name, hash);
+        }
+
+        DatabaseSubcommand::Validate { strict, freq_tolerance } => {
             let db = Database::new(&cmd.database)?;
             info!("Validating database integrity...");
             
-            match db.validate() {
+            match db.validate(freq_tolerance) {
                 Ok(report) => {
                     if report.has_errors() {
                         println!("\nErrors found:");
@@ -223,6 +721,10 @@ fn handle_db_command(cmd: cli::DatabaseCommand, verbose: bool) -> Result<()> {
                     if report.has_errors() {
                         return Err(anyhow::anyhow!("Database validation failed"));
                     }
+
+                    if strict && report.has_warnings() {
+                        return Err(anyhow::anyhow!("Database validation failed: warnings found in strict mode"));
+                    }
                 }
                 Err(e) => {
                     return Err(anyhow::anyhow!("Database validation failed: {}", e));
@@ -236,13 +738,20 @@ fn handle_db_command(cmd: cli::DatabaseCommand, verbose: bool) -> Result<()> {
     Ok(())
 }
 
-fn handle_analyze_command(cmd: cli::AnalyzeCommand, verbose: bool) -> Result<()> {
-    // Open output files and write headers
+fn handle_analyze_command(cmd: cli::AnalyzeCommand, _verbose: bool, quiet: bool) -> Result<AnalysisOutcome> {
+    let started_at = SystemTime::now();
+    // Open output files; headers are written by output_analysis
     let mut sample_writer = File::create(&cmd.sample_info)?;
-    writeln!(sample_writer, "{:<30}\t{}", "Metric", "Value")?;
-    writeln!(sample_writer, "{}", "-".repeat(50))?;
-
     let mut matches_writer = File::create(&cmd.matches)?;
+    // Every report but JSON writes detailed sections outside the matches file
+    let json = matches!(cmd.format, OutputFormat::Json | OutputFormat::JsonLines);
+    let mut detailed_writer = if cmd.detailed && !cmd.split_detailed && !json {
+        Some(File::create(&cmd.detailed_output)?)
+    } else {
+        None
+    };
+    let mut core_writer = cmd.core_kmers.as_ref().map(File::create).transpose()?;
+    let mut specificity_writer = cmd.specificity.as_ref().map(File::create).transpose()?;
 
     // Process files in parallel
     let database_path = cmd.database.clone();
@@ -250,58 +759,475 @@ fn handle_analyze_command(cmd: cli::AnalyzeCommand, verbose: bool) -> Result<()>
     let min_shared_kmers = cmd.min_shared_kmers;
     let taxonomy_level = cmd.level;
     let kmer_size = cmd.kmer_size;
+    let sketch_threshold = cmd.sketch.then_some(cmd.sketch_threshold);
+    let metric = cmd.metric;
+    let min_containment = cmd.min_containment;
+    let min_confidence = cmd.min_confidence;
+    let weighted_shared = cmd.weighted_shared;
+    let max_gc_diff = cmd.max_gc_diff;
+    let max_kmers = cmd.max_kmers;
+    let profile_names = read_profile_names(&cmd.profiles, cmd.profiles_file.as_deref())?;
+    let confidence_weights = ConfidenceWeights::new(cmd.weight_coverage, cmd.weight_uniqueness, cmd.weight_size)?;
+    let uniqueness = !cmd.fast;
+    if cmd.fast {
+        if confidence_weights.only_uniqueness() {
+            return Err(anyhow::anyhow!(
+                "--fast scores confidence from coverage and size only; --weight-coverage and --weight-size cannot both be 0"
+            ));
+        }
+        if matches!(cmd.format, OutputFormat::KrakenReport) {
+            return Err(anyhow::anyhow!("The kraken-report format needs unique k-mer counts, which --fast does not compute"));
+        }
+        info!("Uniqueness will not be computed (--fast)");
+    }
+    let hierarchical = cmd.hierarchical.then_some(cmd.rollup_confidence);
+    let abundance = cmd.abundance;
+    let bootstrap = cmd.bootstrap.map(|replicates| (replicates, cmd.bootstrap_seed));
+    let markers_only = cmd.markers_only;
+    let seed = cmd.seed.as_deref()
+        .map(|pattern| SpacedSeed::parse(pattern, kmer_size))
+        .transpose()?;
+    let minimizer_window = cmd.minimizer_window;
+    check_minimizer_window(minimizer_window)?;
+    check_subsample_fraction(cmd.subsample_fraction)?;
 
-    let results: Vec<Result<(String, KmerCounter, Vec<ProfileMatch>)>> = cmd.input_files.par_iter()
-        .map(|file| -> Result<(String, KmerCounter, Vec<ProfileMatch>)> {
-            let analyzer = ProfileAnalyzer::new(
-                &database_path,
-                min_similarity,
-                min_shared_kmers,
-                taxonomy_level.into(),
-            )?;
-
-            let filename = file.file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string();
-            info!("Processing input file: {}", filename);
-
-            let counter = KmerCounter::new(kmer_size);
-            let reader = FastxReader::new(vec![file.clone()]);
-            let mut sequences = Vec::new();
-            reader.process_all(|sequence, _id| {
-                sequences.push(sequence.to_vec());
-                Ok(())
-            })?;
-
-            counter.count_sequences(sequences.into_par_iter())?;
-            info!("Found {} unique k-mers in sample {}", counter.unique_kmers(), filename);
-
-            let matches = analyzer.analyze_sample(&counter)?;
-            Ok((filename, counter, matches))
+    // One reader per sample, keyed by the stem of its (first) input file,
+    // or a single reader over every input when merging
+    let mut samples: Vec<(String, FastxReader)> = match (cmd.merge, cmd.paired) {
+        (true, true) => vec![(
+            file_stem(&cmd.input_files[0]),
+            FastxReader::new_paired(input_pairs(cmd.input_files.clone(), cmd.allow_duplicates)?),
+        )],
+        (true, false) => vec![(
+            file_stem(&cmd.input_files[0]),
+            FastxReader::new(input_paths(cmd.input_files.clone(), cmd.allow_duplicates)),
+        )],
+        (false, true) => input_pairs(cmd.input_files.clone(), cmd.allow_duplicates)?
+            .into_iter()
+            .map(|(r1, r2)| (file_stem(&r1), FastxReader::new_paired(vec![(r1, r2)])))
+            .collect(),
+        (false, false) => input_paths(cmd.input_files.clone(), cmd.allow_duplicates)
+            .into_iter()
+            .map(|file| (file_stem(&file), FastxReader::new(vec![file])))
+            .collect(),
+    };
+    if let Some(sample_name) = &cmd.sample_name {
+        if samples.len() > 1 {
+            return Err(anyhow::anyhow!(
+                "--sample-name labels a single sample but {} were given; use --merge to pool them",
+                samples.len()
+            ));
+        }
+        samples[0].0 = sample_name.clone();
+    }
+    let samples: Vec<(String, FastxReader)> = samples.into_iter()
+        .map(|(name, reader)| {
+            let reader = reader
+                .strict(cmd.strict)
+                .min_quality(cmd.min_quality)
+                .homopolymer_compress(cmd.homopolymer_compress)
+                .respect_masking(cmd.respect_masking)
+                .alphabet(cmd.alphabet.into())
+                .subsample(cmd.subsample_fraction, cmd.subsample_seed)
+                .max_reads(cmd.max_reads)
+                .region(cmd.region.clone())
+                .unmapped_only(cmd.unmapped_only);
+            (name, reader)
         })
         .collect();
 
-    // Write results using output_analysis
+    // Also used to write the results once every sample is analyzed
     let analyzer = ProfileAnalyzer::new(
         &cmd.database,
         cmd.min_similarity,
         cmd.min_shared_kmers,
         cmd.level.into(),
-    )?;
-
-    for result in results {
-        let (filename, counter, matches) = result?;
-        output_analysis(
-            &filename,
-            &counter,
-            &matches,
-            cmd.detailed,
-            &analyzer,
-            &mut sample_writer,
-            &mut matches_writer,
-        )?;
+    )?
+    .with_confidence_weights(confidence_weights)
+    .with_profile_names(profile_names.clone())
+    .with_alphabet(cmd.alphabet.into());
+    analyzer.check_profile_versions(cmd.strict_versions)?;
+    analyzer.check_profile_names()?;
+    let index = cmd.in_memory
+        .then(|| analyzer.load_index(kmer_size).map(Arc::new))
+        .transpose()?;
+
+    // Concurrent samples each get a line of their own
+    let progress = MultiProgress::with_draw_target(progress_target(quiet));
+
+    type SampleResult = (String, KmerCounter, ReaderStats, Vec<ProfileMatch>, Option<Classification>);
+    let analyze_sample = |(filename, reader): &(String, FastxReader)| -> Result<SampleResult> {
+        let mut analyzer = ProfileAnalyzer::new(
+            &database_path,
+            min_similarity,
+            min_shared_kmers,
+            taxonomy_level.into(),
+        )?
+        .with_metric(metric.into())
+        .with_min_containment(min_containment)
+        .with_min_confidence(min_confidence)
+        .with_weighted_shared(weighted_shared)
+        .with_confidence_weights(confidence_weights)
+        .with_uniqueness(uniqueness)
+        .with_max_gc_difference(max_gc_diff)
+        .with_profile_names(profile_names.clone())
+        .with_homopolymer_compression(reader.compresses_homopolymers())
+        .with_alphabet(reader.sequence_alphabet())
+        .with_progress(progress.add(
+            ProgressBar::new(0)
+                .with_style(progress_style("{prefix} [{bar:40}] {pos}/{len} {msg} profiles"))
+                .with_prefix(filename.clone())
+        ));
+        if let Some(threshold) = sketch_threshold {
+            analyzer = analyzer.with_sketch_filter(threshold);
+        }
+        if let Some(index) = &index {
+            analyzer = analyzer.with_index(Arc::clone(index));
+        }
+
+        info!("Processing input file: {}", filename);
+
+        let counter = KmerCounter::new(kmer_size)
+            .with_alphabet(reader.sequence_alphabet())
+            .with_seed(seed.clone())
+            .with_minimizer_window(minimizer_window)
+            .with_max_unique_kmers(max_kmers);
+        let stats = counter.count_from_reader(reader)?;
+        info!("Found {} unique k-mers in sample {}", counter.unique_kmers(), filename);
+        if counter.mostly_short_sequences() {
+            warn!(
+                "{} of {} sequences of sample {} are shorter than k={} and yield no k-mers; \
+                 analyze with a smaller -k against profiles built at that size",
+                counter.short_sequences(), counter.sequences_counted(), filename, kmer_size
+            );
+        }
+        if counter.unique_kmers() == 0 {
+            warn!(
+                "Sample {} yielded no {}-mers ({} records read): its reads may all be shorter than k, \
+                 or every base was filtered out (--min-quality, --strict, --respect-masking, subsampling)",
+                filename, kmer_size, stats.total_records()
+            );
+            return Ok((filename.clone(), counter, stats, Vec::new(), None));
+        }
+        if counter.is_truncated() {
+            warn!(
+                "K-mer set of sample {} truncated at --max-kmers {}: later distinct k-mers were dropped",
+                filename, max_kmers.unwrap_or_default()
+            );
+        }
+
+        if let Some(min_confidence) = hierarchical {
+            let (classification, matches) = analyzer.classify_hierarchical(&counter, min_confidence)?;
+            return Ok((filename.clone(), counter, stats, matches, Some(classification)));
+        }
+
+        let mut matches = if markers_only {
+            analyzer.analyze_markers(&counter)?
+        } else {
+            analyzer.analyze_sample(&counter)?
+        };
+        if abundance {
+            analyzer.estimate_abundances(&counter, &mut matches)?;
+        }
+        if let Some((replicates, seed)) = bootstrap {
+            analyzer.bootstrap_matches(&counter, &mut matches, replicates, seed)?;
+        }
+        Ok((filename.clone(), counter, stats, matches, None))
+    };
+
+    // Write results using output_analysis
+    let options = ReportOptions {
+        format: cmd.format,
+        detailed: cmd.detailed,
+        abundance: cmd.abundance,
+        weighted_shared: cmd.weighted_shared,
+        bootstrap: cmd.bootstrap.is_some(),
+        precision: cmd.precision,
+    };
+
+    let mut outcome = AnalysisOutcome::NoMatches;
+    // Samples are analyzed in parallel, and each is written as soon as it and
+    // every sample before it are done, so reports stream out in input order
+    std::thread::scope(|scope| -> Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        let (samples, analyze_sample) = (&samples, &analyze_sample);
+        scope.spawn(move || {
+            samples.par_iter().enumerate().for_each_with(sender, |sender, (i, sample)| {
+                // Sending fails only once writing has stopped on an error
+                let _ = sender.send((i, analyze_sample(sample)));
+            });
+        });
+
+        let mut finished = BTreeMap::new();
+        let mut next = 0;
+        for (i, result) in receiver {
+            finished.insert(i, result);
+            while let Some(result) = finished.remove(&next) {
+                next += 1;
+                let (filename, counter, stats, mut matches, classification) = result?;
+                if !matches.is_empty() {
+                    outcome = AnalysisOutcome::Matched;
+                }
+                // Matches arrive ranked, so the best calls are at the front
+                if let Some(top) = cmd.top.filter(|&top| top > 0 && top < matches.len()) {
+                    info!("Reporting the top {} of {} matches for {}", top, matches.len(), filename);
+                    matches.truncate(top);
+                }
+                output_analysis(
+                    &filename,
+                    &counter,
+                    &matches,
+                    options,
+                    &analyzer,
+                    ReportWriters {
+                        sample: &mut sample_writer,
+                        matches: &mut matches_writer,
+                        detailed: detailed_writer.as_mut(),
+                    },
+                )?;
+                if let Some(classification) = classification {
+                    output_classification(&filename, &classification, cmd.format, &mut sample_writer)?;
+                }
+                if cmd.rollup {
+                    let rollups = analyzer.rollup_lineages(&counter, &matches)?;
+                    output_lineage_rollup(&filename, &rollups, options, &mut sample_writer)?;
+                }
+                output_reader_stats(&filename, &stats, cmd.format, &mut sample_writer)?;
+                if let Some(writer) = core_writer.as_mut() {
+                    let core = analyzer.core_kmers(&counter, &counter.get_counts(), &matches)?;
+                    output_core_kmers(&filename, &core, writer)?;
+                }
+                if let Some(writer) = specificity_writer.as_mut() {
+                    let specificity = analyzer.kmer_specificity(&counter, &counter.get_counts())?;
+                    output_kmer_specificity(&filename, &specificity, cmd.precision, writer)?;
+                }
+
+            }
+        }
+        Ok(())
+    })?;
+
+    if let Some(path) = &cmd.manifest {
+        let database = DatabaseRecord { path: cmd.database.clone(), content_hash: analyzer.database_hash()? };
+        RunManifest::new(started_at, &cmd.input_files, database, analyze_parameters(&cmd))?.write(path)?;
+        info!("Wrote run manifest to {}", path.display());
     }
 
+    Ok(outcome)
+}
+
+/// Where `db create --checkpoint` saves its progress: next to the database,
+/// one file per profile name and k-mer size
+fn checkpoint_path(database: &Path, name: &str, k: usize) -> PathBuf {
+    let mut path = database.as_os_str().to_owned();
+    path.push(format!(".{}.k{}.checkpoint", name, k));
+    PathBuf::from(path)
+}
+
+/// The database and stored profile as recorded in a `db create` manifest
+fn created_profile_records(db: &Database, path: &Path, name: &str, k: usize) -> Result<(DatabaseRecord, ProfileRecord)> {
+    let profile = ProfileRecord { name: name.to_string(), k, content_hash: db.verify_profile(name, Some(k))? };
+    let database = DatabaseRecord { path: path.to_path_buf(), content_hash: db.content_hash()? };
+    Ok((database, profile))
+}
+
+/// Settings of an analysis as recorded in its manifest
+fn analyze_parameters(cmd: &cli::AnalyzeCommand) -> serde_json::Value {
+    serde_json::json!({
+        "kmer_size": cmd.kmer_size,
+        "level": TaxonomyLevel::from(cmd.level).to_string(),
+        "profiles": cmd.profiles,
+        "profiles_file": cmd.profiles_file,
+        "metric": SimilarityMetric::from(cmd.metric).to_string(),
+        "min_similarity": cmd.min_similarity,
+        "min_shared_kmers": cmd.min_shared_kmers,
+        "weighted_shared": cmd.weighted_shared,
+        "min_containment": cmd.min_containment,
+        "min_confidence": cmd.min_confidence,
+        "weight_coverage": cmd.weight_coverage,
+        "weight_uniqueness": cmd.weight_uniqueness,
+        "weight_size": cmd.weight_size,
+        "max_gc_diff": cmd.max_gc_diff,
+        "uniqueness": !cmd.fast,
+        "sketch_threshold": cmd.sketch.then_some(cmd.sketch_threshold),
+        "in_memory": cmd.in_memory,
+        "seed": cmd.seed,
+        "minimizer_window": cmd.minimizer_window,
+        "alphabet": Alphabet::from(cmd.alphabet).to_string(),
+        "paired": cmd.paired,
+        "merge": cmd.merge,
+        "strict": cmd.strict,
+        "min_quality": cmd.min_quality,
+        "homopolymer_compress": cmd.homopolymer_compress,
+        "respect_masking": cmd.respect_masking,
+        "subsample_fraction": cmd.subsample_fraction,
+        "subsample_seed": cmd.subsample_seed,
+        "max_reads": cmd.max_reads,
+        "region": cmd.region,
+        "unmapped_only": cmd.unmapped_only,
+        "max_kmers": cmd.max_kmers,
+        "markers_only": cmd.markers_only,
+        "hierarchical": cmd.hierarchical.then_some(cmd.rollup_confidence),
+        "abundance": cmd.abundance,
+        "bootstrap": cmd.bootstrap,
+        "bootstrap_seed": cmd.bootstrap_seed,
+        "top": cmd.top,
+    })
+}
+
+/// Print what `db create` would store for a profile
+fn print_profile_preview(profile: &Profile) {
+    println!("metric\tvalue");
+    println!("name\t{}", profile.name);
+    println!("level\t{}", profile.level);
+    println!("k_size\t{}", profile.k);
+    println!("total_kmers\t{}", profile.total_kmers);
+    println!("distinct_kmers\t{}", profile.frequencies.len());
+    // Rows the profile would add to the kmers table
+    println!("kmer_rows\t{}", profile.frequencies.len());
+    match profile.alphabet {
+        Alphabet::Dna => println!("gc_content\t{:.4}", profile.gc_content()),
+        Alphabet::Protein => println!("gc_content\t-"),
+    }
+    println!("estimated_coverage\t{:.1}", profile.estimated_coverage);
+    println!("source_files\t{}", format_source_files(&profile.source_files));
+}
+
+/// Print the records and bases read from each input file
+fn print_reader_stats(stats: &ReaderStats) {
+    println!("file\trecords\tskipped_invalid\tbases");
+    for file in &stats.files {
+        println!("{}\t{}\t{}\t{}", file.path, file.records, file.skipped_invalid, file.bases);
+    }
+}
+
+/// Profile names given with --profiles and in --profiles-file, or `None` to
+/// compare against every profile. Blank lines and `#` comments in the file are skipped.
+fn read_profile_names(names: &[String], file: Option<&Path>) -> Result<Option<Vec<String>>> {
+    let mut selected: Vec<String> = names.iter()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if let Some(file) = file {
+        let contents = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read profile names from {}", file.display()))?;
+        selected.extend(contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string));
+    }
+    if names.is_empty() && file.is_none() {
+        return Ok(None);
+    }
+    if selected.is_empty() {
+        return Err(anyhow::anyhow!("--profiles and --profiles-file name no profiles"));
+    }
+    selected.sort();
+    selected.dedup();
+    Ok(Some(selected))
+}
+
+/// Draw progress bars on stderr, unless `quiet` is set or stderr is not a
+/// terminal; the log lines then remain the only progress report
+fn progress_target(quiet: bool) -> ProgressDrawTarget {
+    if quiet || !std::io::stderr().is_terminal() {
+        ProgressDrawTarget::hidden()
+    } else {
+        ProgressDrawTarget::stderr()
+    }
+}
+
+fn progress_style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("Progress bar template is valid")
+        .progress_chars("=> ")
+}
+
+/// Group input files into consecutive R1/R2 pairs
+fn pair_files(files: Vec<PathBuf>) -> Result<Vec<(PathBuf, PathBuf)>> {
+    if !files.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!(
+            "Paired input requires an even number of files (R1 R2 ...), got {}",
+            files.len()
+        ));
+    }
+
+    Ok(files.chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
+}
+
+/// Input files without repeats, unless `--allow-duplicates`
+fn input_paths(files: Vec<PathBuf>, allow_duplicates: bool) -> Vec<PathBuf> {
+    if allow_duplicates { files } else { dedup_paths(files) }
+}
+
+/// R1/R2 pairs of the input files without repeated pairs, unless `--allow-duplicates`
+fn input_pairs(files: Vec<PathBuf>, allow_duplicates: bool) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let pairs = pair_files(files)?;
+    Ok(if allow_duplicates { pairs } else { dedup_pairs(pairs) })
+}
+
+fn check_subsample_fraction(fraction: f64) -> Result<()> {
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        return Err(anyhow::anyhow!("--subsample-fraction must be in (0, 1], got {}", fraction));
+    }
+    Ok(())
+}
+
+fn check_minimizer_window(window: Option<usize>) -> Result<()> {
+    if window == Some(0) {
+        return Err(anyhow::anyhow!("--minimizer-window must be at least 1"));
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Ask the user to confirm `action` on stdin, unless `assume_yes` answers for them.
+/// Fails instead of blocking when stdin is not a terminal.
+fn confirm(action: &str, assume_yes: bool) -> Result<bool> {
+    if assume_yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "Refusing to {} without confirmation: stdin is not a terminal; re-run with --yes", action
+        ));
+    }
+
+    print!("Are you sure you want to {}? [y/N] ", action);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(input.trim().eq_ignore_ascii_case("y"))
+}
+
+fn format_source_files(source_files: &[String]) -> String {
+    if source_files.is_empty() {
+        "-".to_string()
+    } else {
+        source_files.join(",")
+    }
+}
+
+/// `kmer:frequency` pairs joined by commas, or `-` when empty
+fn format_kmer_list<'a>(kmers: impl Iterator<Item = &'a (&'a String, &'a f64)>) -> String {
+    let formatted: Vec<_> = kmers.map(|(kmer, freq)| format!("{}:{:.6}", kmer, freq)).collect();
+    if formatted.is_empty() {
+        "-".to_string()
+    } else {
+        formatted.join(",")
+    }
+}
+
+/// File name without its format extension, ignoring a compression suffix
+/// (`sample.fastq.gz` -> `sample`)
+fn file_stem(path: &Path) -> String {
+    let path = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz" | "zst" | "xz") => path.with_extension(""),
+        _ => path.to_path_buf(),
+    };
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
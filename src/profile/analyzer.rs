@@ -1,15 +1,63 @@
-use std::collections::{HashMap, HashSet};
-use anyhow::{Result, Context};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use anyhow::{bail, Result, Context};
 use log::{debug, info, warn};
-use rusqlite::{Connection, params, OptionalExtension};
-use super::types::{ProfileMatch, TaxonomyLevel};
-use crate::kmer::KmerCounter;
+use indicatif::ProgressBar;
+use rusqlite::{Connection, OpenFlags, params, params_from_iter, OptionalExtension};
+use rusqlite::types::Value;
+use serde::Serialize;
+use super::index::{KmerOverlap, ProfileIndex};
+use super::types::{
+    is_compatible_version, Bootstrap, BootstrapInterval, Classification, ConfidenceWeights, LineageRollup, Normalization, Profile, ProfileMatch, SimilarityMetric, TaxonomyLevel, TOOL_VERSION,
+};
+use crate::db::{database_content_hash, is_schema_current};
+use crate::io::{splitmix64, unit_interval};
+use crate::kmer::{
+    canonical_counts, gc_fraction, Alphabet, BloomFilter, CounterSnapshot, KmerCounter, MinHashSketch, SpacedSeed,
+    DEFAULT_SKETCH_SIZE,
+};
+
+/// Upper bound on EM rounds when estimating abundances
+const ABUNDANCE_MAX_ITERATIONS: usize = 1000;
+
+/// EM stops once no abundance moves by more than this between rounds
+const ABUNDANCE_TOLERANCE: f64 = 1e-9;
+
+/// Default largest GC fraction difference between a sample and a matched
+/// profile before the match is flagged
+pub const DEFAULT_MAX_GC_DIFFERENCE: f64 = 0.05;
 
 pub struct ProfileAnalyzer {
     conn: Connection,
     min_similarity: f64,
     min_shared_kmers: usize,
+    /// Hold the abundance-weighted shared k-mer count to `min_shared_kmers`
+    weighted_shared: bool,
     taxonomy_level: TaxonomyLevel,
+    /// Minimum estimated Jaccard for a profile to get an exact comparison
+    sketch_threshold: Option<f64>,
+    /// Score compared against `min_similarity`
+    metric: SimilarityMetric,
+    /// Minimum shared fraction of the smaller k-mer set
+    min_containment: f64,
+    /// Minimum confidence score
+    min_confidence: f64,
+    /// Whether the sample's reads were homopolymer-compressed
+    homopolymer_compressed: bool,
+    /// Residues the sample's reads were read as
+    alphabet: Alphabet,
+    /// Advanced once per profile compared
+    progress: ProgressBar,
+    /// Weights of the confidence score's components
+    confidence_weights: ConfidenceWeights,
+    /// Whether to look up how many profiles share each k-mer
+    uniqueness: bool,
+    /// Largest sample vs profile GC difference before a match is flagged
+    max_gc_difference: f64,
+    /// Compare only these profiles, if set
+    profile_names: Option<Vec<String>>,
+    /// Profiles' k-mers held in memory, if loaded
+    index: Option<Arc<ProfileIndex>>,
 }
 
 impl ProfileAnalyzer {
@@ -19,87 +67,618 @@ impl ProfileAnalyzer {
         min_shared_kmers: usize,
         taxonomy_level: TaxonomyLevel,
     ) -> Result<Self> {
-        let conn = Connection::open(database_path)
+        // The analyzer never writes, so a read-only handle lets analyses run
+        // concurrently and avoids creating an empty database on a typo
+        let database_path = database_path.as_ref();
+        if database_path == std::path::Path::new(":memory:") {
+            bail!(
+                "An in-memory database cannot be reopened; analyze it with \
+                 ProfileAnalyzer::from_connection(database.into_connection(), ..)"
+            );
+        }
+        if !database_path.exists() {
+            bail!("Database {} does not exist", database_path.display());
+        }
+        let conn = Connection::open_with_flags(database_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
             .context("Failed to open database connection")?;
-        
+        Self::with_connection(conn, &database_path.display().to_string(), min_similarity, min_shared_kmers, taxonomy_level)
+    }
+
+    /// Analyze against an already open database connection, such as an
+    /// in-memory database built with [`Database`](crate::db::Database) and
+    /// handed over with `Database::into_connection`
+    pub fn from_connection(
+        conn: Connection,
+        min_similarity: f64,
+        min_shared_kmers: usize,
+        taxonomy_level: TaxonomyLevel,
+    ) -> Result<Self> {
+        let name = conn.path().filter(|path| !path.is_empty()).unwrap_or(":memory:").to_string();
+        Self::with_connection(conn, &name, min_similarity, min_shared_kmers, taxonomy_level)
+    }
+
+    fn with_connection(
+        conn: Connection,
+        name: &str,
+        min_similarity: f64,
+        min_shared_kmers: usize,
+        taxonomy_level: TaxonomyLevel,
+    ) -> Result<Self> {
+        if !is_schema_current(&conn)? {
+            bail!(
+                "Database {} uses an older schema; run any `db` command on it (e.g. `db stats`) to upgrade it",
+                name
+            );
+        }
+
         Ok(ProfileAnalyzer {
             conn,
             min_similarity,
             min_shared_kmers,
+            weighted_shared: false,
             taxonomy_level,
+            sketch_threshold: None,
+            metric: SimilarityMetric::Coverage,
+            min_containment: 0.0,
+            min_confidence: 0.0,
+            homopolymer_compressed: false,
+            alphabet: Alphabet::Dna,
+            progress: ProgressBar::hidden(),
+            confidence_weights: ConfidenceWeights::default(),
+            uniqueness: true,
+            max_gc_difference: DEFAULT_MAX_GC_DIFFERENCE,
+            profile_names: None,
+            index: None,
         })
     }
 
+    /// Select which similarity score is held to `min_similarity` and used for ranking
+    pub fn with_metric(mut self, metric: SimilarityMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Require at least this fraction of the smaller of the sample and profile
+    /// k-mer sets to be shared
+    pub fn with_min_containment(mut self, min_containment: f64) -> Self {
+        self.min_containment = min_containment;
+        self
+    }
+
+    /// Hold `min_shared_kmers` to the abundance-weighted shared k-mer count
+    /// instead of counting every shared k-mer once. The weighted count sums
+    /// the smaller of the sample's and the profile's frequency over shared
+    /// k-mers, scaled by the sample's distinct k-mers so that identical
+    /// uniform k-mer sets score their shared count.
+    pub fn with_weighted_shared(mut self, weighted_shared: bool) -> Self {
+        self.weighted_shared = weighted_shared;
+        self
+    }
+
+    /// Require a match's confidence score to reach `min_confidence`, on top of
+    /// the similarity, shared k-mer and containment thresholds
+    pub fn with_min_confidence(mut self, min_confidence: f64) -> Self {
+        self.min_confidence = min_confidence;
+        self
+    }
+
+    /// Declare that sample reads were homopolymer-compressed; profiles must
+    /// have been built the same way
+    pub fn with_homopolymer_compression(mut self, homopolymer_compressed: bool) -> Self {
+        self.homopolymer_compressed = homopolymer_compressed;
+        self
+    }
+
+    /// Declare the alphabet the sample was read as; profiles of the other one are an error
+    pub fn with_alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.alphabet = alphabet;
+        self
+    }
+
+    /// Weight the coverage, uniqueness and size components of the confidence score
+    pub fn with_confidence_weights(mut self, weights: ConfidenceWeights) -> Self {
+        self.confidence_weights = weights;
+        self
+    }
+
+    /// Skip the k-mer occurrence lookups behind uniqueness; matches then carry
+    /// no uniqueness and are scored on coverage and size alone
+    pub fn with_uniqueness(mut self, uniqueness: bool) -> Self {
+        self.uniqueness = uniqueness;
+        self
+    }
+
+    /// Flag matches whose profile GC fraction differs from the sample's by
+    /// more than `max_difference`, a hint of contamination or a mislabeled reference
+    pub fn with_max_gc_difference(mut self, max_difference: f64) -> Self {
+        self.max_gc_difference = max_difference;
+        self
+    }
+
+    /// Compare samples only against the named profiles, still at the
+    /// analyzer's level; `None` compares against every profile
+    pub fn with_profile_names(mut self, profile_names: Option<Vec<String>>) -> Self {
+        self.profile_names = profile_names;
+        self
+    }
+
+    /// Read the k-mers of every k=`k` profile in the analyzer's alphabet into
+    /// memory, to share between the analyzers of many samples through
+    /// [`with_index`](Self::with_index)
+    pub fn load_index(&self, k: usize) -> Result<ProfileIndex> {
+        ProfileIndex::load(&self.conn, k, self.alphabet)
+    }
+
+    /// Compare samples with profiles from an in-memory index instead of
+    /// reading their k-mers from the database. The sample's k-mer size must
+    /// be the index's.
+    pub fn with_index(mut self, index: Arc<ProfileIndex>) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Report the profiles compared at each taxonomy level on `progress`
+    pub fn with_progress(mut self, progress: ProgressBar) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Use stored MinHash sketches as a first-pass filter, only running the
+    /// exact comparison for profiles whose estimated Jaccard reaches `threshold`
+    pub fn with_sketch_filter(mut self, threshold: f64) -> Self {
+        self.sketch_threshold = Some(threshold);
+        self
+    }
+
+    /// Estimate the Jaccard similarity between a sample sketch and a profile's
+    /// stored sketch, or None if the profile has no sketch
+    pub fn estimate_jaccard(&self, sample_sketch: &MinHashSketch, profile_id: i64) -> Result<Option<f64>> {
+        let stored: Option<(i64, Vec<u8>)> = self.conn.query_row(
+            "SELECT sketch_size, hashes FROM sketches WHERE profile_id = ?",
+            params![profile_id],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        ).optional()?;
+
+        let Some((sketch_size, bytes)) = stored else {
+            return Ok(None);
+        };
+
+        let hashes = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let profile_sketch = MinHashSketch::from_hashes(sketch_size as usize, hashes);
+
+        Ok(Some(sample_sketch.jaccard(&profile_sketch)))
+    }
+
+    /// Upper bound on the k-mers a sample shares with a profile, from the
+    /// profile's stored Bloom filter, or None if the profile has no filter
+    pub fn estimate_shared_kmers(&self, sample_kmers: &HashMap<String, usize>, profile_id: i64) -> Result<Option<usize>> {
+        let stored: Option<(i64, u32, f64, Vec<u8>)> = self.conn.query_row(
+            "SELECT num_bits, num_hashes, fp_rate, bits FROM bloom WHERE profile_id = ?",
+            params![profile_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        ).optional()?;
+
+        let Some((num_bits, num_hashes, fp_rate, bytes)) = stored else {
+            return Ok(None);
+        };
+
+        let words = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let bloom = BloomFilter::from_parts(num_bits as u64, num_hashes, fp_rate, words);
+
+        Ok(Some(sample_kmers.keys().filter(|kmer| bloom.contains(kmer.as_bytes())).count()))
+    }
+
+    /// Content hash of the database's profiles, as `Database::content_hash`
+    pub fn database_hash(&self) -> Result<String> {
+        database_content_hash(&self.conn)
+    }
+
     /// Analyze a sample against the database at the current taxonomy level
 pub fn analyze_sample(&self, counter: &KmerCounter) -> Result<Vec<ProfileMatch>> {
+    self.check_sample_protocol(counter)?;
+    let sample_kmers = counter.get_counts();
+    self.analyze_at_level(counter, &sample_kmers, &self.taxonomy_level)
+}
+
+ /// Compare a stored profile with every other profile at the analyzer's
+ /// level, as if its k-mers had been read from a sample. Each k-mer is
+ /// counted its frequency times the profile's total k-mers (at least once),
+ /// or once in a presence profile, which keeps no counts, and the analyzer takes on the profile's alphabet and homopolymer
+ /// compression. A close match under another name hints at a redundant or
+ /// mislabeled reference.
+ pub fn cross_validate(self, profile: &Profile) -> Result<Vec<ProfileMatch>> {
+    let seed = profile.seed.as_deref()
+        .map(|pattern| SpacedSeed::parse(pattern, profile.k))
+        .transpose()?;
+    let counter = KmerCounter::new(profile.k)
+        .with_alphabet(profile.alphabet)
+        .with_seed(seed)
+        .with_minimizer_window(profile.minimizer_window);
+    let counts = profile.frequencies.iter()
+        .map(|(kmer, &frequency)| (kmer.clone(), profile.kmer_count(frequency).unwrap_or(1).max(1) as usize))
+        .collect();
+    counter.restore(CounterSnapshot::from_counts(profile.k, counts))?;
+
+    let mut others: Vec<String> = self.conn
+        .prepare("SELECT DISTINCT name FROM profiles WHERE name != ?1 AND COALESCE(alphabet, 'dna') = ?2 ORDER BY name")?
+        .query_map(params![profile.name, profile.alphabet.to_string()], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    if let Some(names) = &self.profile_names {
+        others.retain(|name| names.contains(name));
+    }
+    if others.is_empty() {
+        warn!("No other profiles to compare {} with", profile.name);
+        return Ok(Vec::new());
+    }
+
+    self.with_alphabet(profile.alphabet)
+        .with_homopolymer_compression(profile.homopolymer_compressed)
+        .with_profile_names(Some(others))
+        .analyze_sample(&counter)
+ }
+
+ /// Fail when no profile in the database was built with the sample's k-mer
+ /// size and seed, since every comparison would be skipped and the report
+ /// would come back empty with no hint why. DNA and protein never mix, so
+ /// profiles of the other alphabet are left out, and only an error when no
+ /// compared profile is in the sample's.
+ fn check_sample_protocol(&self, counter: &KmerCounter) -> Result<()> {
+    let name_filter = self.profile_name_filter("name", 2);
+    let mut values = vec![Value::Text(self.alphabet.to_string())];
+    values.extend(self.profile_names.iter().flatten().map(|name| Value::Text(name.clone())));
+    let other_alphabet: Option<(String, String)> = self.conn.query_row(
+        &format!(
+            "SELECT name, COALESCE(alphabet, 'dna') FROM profiles WHERE COALESCE(alphabet, 'dna') != ?1{} \
+             AND NOT EXISTS (SELECT 1 FROM profiles WHERE COALESCE(alphabet, 'dna') = ?1{}) LIMIT 1",
+            name_filter, name_filter
+        ),
+        params_from_iter(values),
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).optional()?;
+    if let Some((name, alphabet)) = other_alphabet {
+        bail!(
+            "No {} profiles to compare the sample with; {} is a {} profile, and DNA and protein cannot be compared. \
+             Rerun with the --alphabet the profiles were built with",
+            self.alphabet, name, alphabet
+        );
+    }
+
+    let protocols: Vec<(usize, Option<String>, Option<usize>)> = self.conn
+        .prepare(
+            "SELECT DISTINCT k, seed, minimizer_window FROM profiles WHERE COALESCE(alphabet, 'dna') = ?
+             ORDER BY k, seed, minimizer_window"
+        )?
+        .query_map(params![self.alphabet.to_string()], |row| Ok((row.get::<_, i64>(0)? as usize, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    if protocols.is_empty() {
+        return Ok(());
+    }
+
+    let k = counter.kmer_size();
+    if !protocols.iter().any(|(profile_k, _, _)| *profile_k == k) {
+        let mut sizes: Vec<String> = protocols.iter().map(|(profile_k, _, _)| profile_k.to_string()).collect();
+        sizes.dedup();
+        bail!(
+            "No profiles in the database use k={}; available k-mer sizes: {}. Rerun with --kmer-size {}",
+            k, sizes.join(", "), sizes[0]
+        );
+    }
+
+    let sample_seed = counter.seed().map(|seed| seed.pattern());
+    if !protocols.iter().any(|(profile_k, seed, _)| *profile_k == k && seed.as_deref() == sample_seed) {
+        let mut seeds: Vec<&str> = protocols.iter()
+            .filter(|(profile_k, _, _)| *profile_k == k)
+            .map(|(_, seed, _)| seed.as_deref().unwrap_or("none"))
+            .collect();
+        seeds.dedup();
+        bail!(
+            "No k={} profiles use seed {}; their seeds are: {}. Rerun with a matching --seed",
+            k, sample_seed.unwrap_or("none"), seeds.join(", ")
+        );
+    }
+
+    let sample_window = counter.minimizer_window();
+    if !protocols.iter().any(|(profile_k, seed, window)| {
+        *profile_k == k && seed.as_deref() == sample_seed && *window == sample_window
+    }) {
+        let windows: Vec<String> = protocols.iter()
+            .filter(|(profile_k, seed, _)| *profile_k == k && seed.as_deref() == sample_seed)
+            .map(|(_, _, window)| window.map_or("none".to_string(), |w| w.to_string()))
+            .collect();
+        bail!(
+            "No k={} profiles use minimizer window {}; their windows are: {}. Rerun with a matching --minimizer-window",
+            k, sample_window.map_or("none".to_string(), |w| w.to_string()), windows.join(", ")
+        );
+    }
+
+    Ok(())
+ }
+
+ /// Warn about profiles built by a papro-rusty version incompatible with this
+ /// one, or by one that predates version tracking, since their k-mers may have
+ /// been counted differently. With `strict` set this is an error instead.
+ pub fn check_profile_versions(&self, strict: bool) -> Result<()> {
+    let versions: Vec<(Option<String>, i64)> = self.conn
+        .prepare("SELECT created_by_version, COUNT(*) FROM profiles GROUP BY created_by_version ORDER BY created_by_version")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let problems: Vec<String> = versions.into_iter()
+        .filter(|(version, _)| !version.as_deref().is_some_and(is_compatible_version))
+        .map(|(version, count)| match version {
+            Some(version) => format!("{} profile(s) were built by version {}", count, version),
+            None => format!("{} profile(s) predate version tracking", count),
+        })
+        .collect();
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    if strict {
+        bail!(
+            "{}, which may not be compatible with version {}. Rebuild them or rerun without --strict-versions",
+            problems.join(" and "), TOOL_VERSION
+        );
+    }
+    for problem in problems {
+        warn!("{}, which may not be compatible with version {}", problem, TOOL_VERSION);
+    }
+    Ok(())
+ }
+
+ /// Error if a profile named with `with_profile_names` is not in the
+ /// database at the analyzer's level
+ pub fn check_profile_names(&self) -> Result<()> {
+    let Some(names) = &self.profile_names else {
+        return Ok(());
+    };
+    let mut stmt = self.conn.prepare("SELECT 1 FROM profiles WHERE name = ?1 AND taxonomy_level = ?2 LIMIT 1")?;
+    let missing: Vec<&str> = names.iter()
+        .filter_map(|name| match stmt.exists(params![name, self.taxonomy_level.to_string()]) {
+            Ok(true) => None,
+            Ok(false) => Some(Ok(name.as_str())),
+            Err(e) => Some(Err(e)),
+        })
+        .collect::<rusqlite::Result<_>>()?;
+    if !missing.is_empty() {
+        bail!("No {} level profile named {}", self.taxonomy_level, missing.join(", "));
+    }
+    Ok(())
+ }
+
+ /// `AND name IN (...)` restricting a query to the selected profiles, with
+ /// placeholders numbered from `first`; empty when every profile is compared
+ fn profile_name_filter(&self, column: &str, first: usize) -> String {
+    match &self.profile_names {
+        Some(names) => {
+            let placeholders: Vec<String> = (first..first + names.len()).map(|i| format!("?{}", i)).collect();
+            format!(" AND {} IN ({})", column, placeholders.join(", "))
+        }
+        None => String::new(),
+    }
+ }
+
+ /// Parameters of a query on level, k and alphabet, followed by the selected profile names
+ fn level_params(&self, level: &TaxonomyLevel, k: usize) -> Vec<Value> {
+    let mut values = vec![
+        Value::Text(level.to_string()),
+        Value::Integer(k as i64),
+        Value::Text(self.alphabet.to_string()),
+    ];
+    values.extend(self.profile_names.iter().flatten().map(|name| Value::Text(name.clone())));
+    values
+ }
+
+ /// Analyze a sample against the profiles at a given taxonomy level
+ fn analyze_at_level(
+    &self,
+    counter: &KmerCounter,
+    sample_kmers: &HashMap<String, usize>,
+    level: &TaxonomyLevel,
+ ) -> Result<Vec<ProfileMatch>> {
     info!(
         "Analyzing sample against reference profiles at {} level",
-        self.taxonomy_level
+        level
     );
  
+    // Profiles built at other k-mer sizes, including other sizes of the same
+    // organism, cannot be compared and are left out
+    let k = counter.kmer_size();
+    // Every score is a fraction of the sample's k-mers
+    if sample_kmers.is_empty() {
+        warn!("The sample has no k-mers of size {}; nothing to compare", k);
+        return Ok(Vec::new());
+    }
+    if let Some(index) = self.index.as_ref().filter(|index| index.kmer_size() != k) {
+        bail!("The in-memory index holds k={} profiles but the sample was counted at k={}", index.kmer_size(), k);
+    }
+    // Profiles of the other alphabet are left out the same way
+    let name_filter = self.profile_name_filter("name", 4);
     let profile_count: i64 = self.conn.query_row(
-        "SELECT COUNT(*) FROM profiles WHERE taxonomy_level = ?",
-        params![self.taxonomy_level.to_string()],
+        &format!(
+            "SELECT COUNT(*) FROM profiles WHERE taxonomy_level = ?1 AND k = ?2 AND COALESCE(alphabet, 'dna') = ?3{}",
+            name_filter
+        ),
+        params_from_iter(self.level_params(level, k)),
         |row| row.get(0)
     )?;
  
-    info!("Found {} profiles at {} level", profile_count, self.taxonomy_level);
+    info!("Found {} k={} profiles at {} level", profile_count, k, level);
+
+    // Organisms stored only at other sizes would have been compared if the
+    // sample had been counted at their k
+    let other_size_count: i64 = self.conn.query_row(
+        &format!(
+            "SELECT COUNT(DISTINCT name) FROM profiles p
+             WHERE taxonomy_level = ?1 AND k != ?2 AND COALESCE(alphabet, 'dna') = ?3{} \
+             AND NOT EXISTS (SELECT 1 FROM profiles q WHERE q.name = p.name AND q.taxonomy_level = ?1 AND q.k = ?2)",
+            name_filter
+        ),
+        params_from_iter(self.level_params(level, k)),
+        |row| row.get(0)
+    )?;
+    if other_size_count > 0 {
+        warn!(
+            "Skipped {} {} profiles built only at k-mer sizes other than the sample's k={}",
+            other_size_count, level, k
+        );
+    }
  
     if profile_count == 0 {
-        warn!("No profiles found at {} level in the database", self.taxonomy_level);
+        warn!("No k={} profiles found at {} level in the database", k, level);
         return Ok(Vec::new());
     }
+
+    let entropy_settings: i64 = self.conn.query_row(
+        &format!(
+            "SELECT COUNT(DISTINCT COALESCE(min_entropy, 0.0)) FROM profiles
+             WHERE taxonomy_level = ?1 AND k = ?2 AND COALESCE(alphabet, 'dna') = ?3{}",
+            name_filter
+        ),
+        params_from_iter(self.level_params(level, k)),
+        |row| row.get(0)
+    )?;
+    if entropy_settings > 1 {
+        warn!(
+            "Profiles at {} level were built with different --min-entropy settings; \
+             their uniqueness scores are not directly comparable",
+            level
+        );
+    }
  
-    let mut profile_stmt = self.conn.prepare(
-        "SELECT id, name, k, total_kmers 
+    let mut profile_stmt = self.conn.prepare(&format!(
+"SELECT id, name, total_kmers, seed, COALESCE(homopolymer_compressed, 0), COALESCE(canonical, 0),
+                minimizer_window, COALESCE(normalization, 'occurrence')
          FROM profiles 
-         WHERE taxonomy_level = ?"
-    )?;
+         WHERE taxonomy_level = ?1 AND k = ?2 AND COALESCE(alphabet, 'dna') = ?3{}",
+        name_filter
+    ))?;
  
-    let sample_kmers = counter.get_counts();
     info!("Sample has {} unique k-mers of size {}", 
         sample_kmers.len(), counter.kmer_size());
+
+    // Both strands of a k-mer have the same GC, so canonical counts need no separate figure
+    let sample_gc = (self.alphabet == Alphabet::Dna).then(|| {
+        let (gc, total) = sample_kmers.iter().fold((0.0, 0.0), |(gc, total), (kmer, &count)| {
+            (gc + gc_fraction(kmer.as_bytes()) * count as f64, total + count as f64)
+        });
+        if total > 0.0 { gc / total } else { 0.0 }
+    });
+
+    let sample_sketch = self.sketch_threshold.map(|_| {
+        MinHashSketch::from_kmers(sample_kmers.keys().map(|kmer| kmer.as_bytes()), DEFAULT_SKETCH_SIZE)
+    });
  
     let mut matches = Vec::new();
     let profiles = profile_stmt.query_map(
-        params![self.taxonomy_level.to_string()],
+        params_from_iter(self.level_params(level, k)),
         |row| {
             Ok((
                 row.get::<_, i64>(0)?,
                 row.get::<_, String>(1)?,
                 row.get::<_, i64>(2)?,
-                row.get::<_, i64>(3)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, bool>(4)?,
+                row.get::<_, bool>(5)?,
+                row.get::<_, Option<usize>>(6)?,
+                row.get::<_, String>(7)?,
             ))
         }
     )?;
+
+    // The sample's counts as each kind of profile sees them, built once for
+    // the first profile of that kind
+    let mut sample_views = HashMap::new();
+    // With an in-memory index every profile's overlap comes from one pass over
+    // the sample, made once per view
+    let mut indexed_overlaps: HashMap<(bool, bool), Option<HashMap<i64, KmerOverlap>>> = HashMap::new();
  
+    let sample_seed = counter.seed().map(|seed| seed.pattern());
+    self.progress.reset();
+    self.progress.set_length(profile_count as u64);
+    self.progress.set_message(level.to_string());
     for profile_result in profiles {
-        let (profile_id, name, k, total_kmers) = profile_result?;
+        let (profile_id, name, total_kmers, seed, homopolymer_compressed, canonical, minimizer_window, normalization) =
+            profile_result?;
+        let presence = parse_normalization(&normalization)? == Normalization::Presence;
+        self.progress.inc(1);
         info!("Checking profile '{}' (id={}, k={}, total_kmers={})", 
             name, profile_id, k, total_kmers);
- 
-        if k as usize != counter.kmer_size() {
-            warn!("K-mer size mismatch: profile {} has k={}, sample has k={}", 
-                name, k, counter.kmer_size());
+
+        // Compressed and uncompressed k-mers are different alphabets, so this is never a soft skip
+        if homopolymer_compressed != self.homopolymer_compressed {
+            bail!(
+                "Profile {} was built {} homopolymer compression but the sample {}; \
+                 rerun with matching --homopolymer-compress",
+                name,
+                if homopolymer_compressed { "with" } else { "without" },
+                if self.homopolymer_compressed { "was compressed" } else { "was not" }
+            );
+        }
+
+        if seed.as_deref() != sample_seed {
+            warn!("Seed mismatch: profile {} has seed {}, sample has seed {}",
+                name, seed.as_deref().unwrap_or("none"), sample_seed.unwrap_or("none"));
+            continue;
+        }
+
+        // Minimizers of different windows are different subsets of the same k-mers
+        if minimizer_window != counter.minimizer_window() {
+            warn!("Minimizer window mismatch: profile {} has window {}, sample has window {}",
+                name,
+                minimizer_window.map_or("none".to_string(), |w| w.to_string()),
+                counter.minimizer_window().map_or("none".to_string(), |w| w.to_string()));
             continue;
         }
+
+        if let (Some(threshold), Some(sketch)) = (self.sketch_threshold, &sample_sketch) {
+            match self.estimate_jaccard(sketch, profile_id)? {
+                Some(estimate) if estimate < threshold => {
+                    info!("Skipping profile {}: estimated Jaccard {:.4} below sketch threshold {}",
+                        name, estimate, threshold);
+                    continue;
+                }
+                Some(estimate) => debug!("Profile {} passed sketch filter (estimated Jaccard {:.4})", name, estimate),
+                None => debug!("Profile {} has no sketch, using exact comparison", name),
+            }
+        }
+
+        let sample_kmers = sample_view(sample_kmers, canonical, presence, &mut sample_views);
+        let total_sample_kmers = if presence { sample_kmers.len() } else { counter.total_kmers() };
+
+        // Bloom filters never miss a member, so a low estimate rules the profile
+        // out; a weighted count can exceed the shared k-mers, so it is not bounded
+        if self.min_shared_kmers > 0 && !self.weighted_shared {
+            if let Some(estimate) = self.estimate_shared_kmers(sample_kmers, profile_id)? {
+                if estimate < self.min_shared_kmers {
+                    info!("Skipping profile {}: at most {} shared k-mers by Bloom filter (minimum: {})",
+                        name, estimate, self.min_shared_kmers);
+                    continue;
+                }
+            }
+        }
  
-        match self.compare_with_profile(
-            profile_id,
-            &name,
-            &sample_kmers,
-            counter.total_kmers(),
-            total_kmers as usize,
-        )? {
+        info!("Comparing profile {} (id={})", name, profile_id);
+        let overlap = self.profile_overlap(
+            profile_id, sample_kmers, total_sample_kmers, sample_gc.is_some(),
+            indexed_overlaps.entry((canonical, presence)).or_default(),
+        )?;
+        match self.score_overlap(&name, overlap, sample_kmers, total_sample_kmers, sample_gc)? {
             Some(profile_match) => {
-                info!("Found match: {} (coverage={:.4}%, shared={}, uniqueness={:.4}, confidence={:.4})",
+                info!("Found match: {} (coverage={:.4}%, shared={}, uniqueness={}, confidence={:.4})",
                     name, 
                     profile_match.sample_coverage * 100.0,
                     profile_match.shared_kmers,
-                    profile_match.uniqueness_score,
+                    format_uniqueness(profile_match.uniqueness_score),
                     profile_match.confidence_score
                 );
-                matches.push(profile_match);
+                matches.push(profile_match.with_level(level.clone()));
             }
             None => {
                 info!("Profile {} did not meet thresholds (min_similarity={}, min_shared_kmers={})",
@@ -107,201 +686,849 @@ pub fn analyze_sample(&self, counter: &KmerCounter) -> Result<Vec<ProfileMatch>>
             }
         }
     }
+    self.progress.finish_and_clear();
  
-    // Sort by confidence score
-    matches.sort_by(|a, b| b.confidence_score.partial_cmp(&a.confidence_score).unwrap());
+    // Sort by confidence score, or by cosine similarity when that metric is selected
+    match self.metric {
+        SimilarityMetric::Coverage => {
+            matches.sort_by(|a, b| b.confidence_score.partial_cmp(&a.confidence_score).unwrap());
+        }
+        SimilarityMetric::Cosine => {
+            matches.sort_by(|a, b| b.cosine_similarity.partial_cmp(&a.cosine_similarity).unwrap());
+        }
+    }
     info!("Found {} potential matches", matches.len());
     Ok(matches)
  }
  
- fn compare_with_profile(
+ /// Classify a sample at the most specific level where exactly one profile
+ /// reaches `min_confidence`. Ambiguous levels roll up to the shared parent of
+ /// the confident matches when they have one, otherwise to the next level.
+ pub fn classify_hierarchical(&self, counter: &KmerCounter, min_confidence: f64) -> Result<(Classification, Vec<ProfileMatch>)> {
+    self.check_sample_protocol(counter)?;
+    let sample_kmers = counter.get_counts();
+    let mut evidence = Vec::new();
+    let mut level = Some(TaxonomyLevel::Strain);
+
+    while let Some(current) = level {
+        let matches = self.analyze_at_level(counter, &sample_kmers, &current)?;
+        let confident: Vec<&ProfileMatch> = matches.iter()
+            .filter(|m| m.confidence_score >= min_confidence)
+            .collect();
+
+        match confident.as_slice() {
+            [] => {
+                evidence.push(format!("{}: no match with confidence >= {}", current, min_confidence));
+            }
+            [single] => {
+                let classification = Classification {
+                    level: Some(current),
+                    name: Some(single.name.clone()),
+                    evidence,
+                };
+                return Ok((classification, matches));
+            }
+            several => {
+                let names: Vec<&str> = several.iter().map(|m| m.name.as_str()).collect();
+                evidence.push(format!("{}: {} ambiguous matches ({})", current, several.len(), names.join(", ")));
+
+                // Roll up to the common parent when every confident match shares one
+                let parents: HashSet<Option<String>> = several.iter()
+                    .map(|m| self.get_parent(&m.name))
+                    .collect::<Result<_>>()?;
+                if let [Some(parent)] = parents.into_iter().collect::<Vec<_>>().as_slice() {
+                    let parent_level = self.get_profile_level(parent)?
+                        .or_else(|| current.parent_level());
+                    evidence.push(format!("all ambiguous matches share parent {}", parent));
+                    let parent_matches = match &parent_level {
+                        Some(parent_level) => self.analyze_at_level(counter, &sample_kmers, parent_level)?
+                            .into_iter()
+                            .filter(|m| &m.name == parent)
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    let classification = Classification {
+                        level: parent_level,
+                        name: Some(parent.clone()),
+                        evidence,
+                    };
+                    return Ok((classification, parent_matches));
+                }
+            }
+        }
+
+        level = current.parent_level();
+    }
+
+    evidence.push("no level had a single confident match".to_string());
+    Ok((Classification { level: None, name: None, evidence }, Vec::new()))
+ }
+
+ /// The profile's ancestors by `parent`, broadest first, followed by the
+ /// profile itself. A cycle of parents ends the chain where it repeats.
+ pub fn lineage(&self, name: &str) -> Result<Vec<String>> {
+    let mut lineage = vec![name.to_string()];
+    while let Some(parent) = self.get_parent(&lineage[lineage.len() - 1])? {
+        if lineage.contains(&parent) {
+            warn!("Profile {} has a cycle of parents", name);
+            break;
+        }
+        lineage.push(parent);
+    }
+    lineage.reverse();
+    Ok(lineage)
+ }
+
+ fn get_parent(&self, name: &str) -> Result<Option<String>> {
+    let parent = self.conn.query_row(
+        "SELECT parent FROM profiles WHERE name = ?",
+        params![name],
+        |row| row.get::<_, Option<String>>(0)
+    ).optional()?;
+    Ok(parent.flatten())
+ }
+
+ fn get_profile_level(&self, name: &str) -> Result<Option<TaxonomyLevel>> {
+    let level: Option<String> = self.conn.query_row(
+        "SELECT taxonomy_level FROM profiles WHERE name = ?",
+        params![name],
+        |row| row.get(0)
+    ).optional()?;
+
+    Ok(match level.as_deref() {
+        Some("Genus") => Some(TaxonomyLevel::Genus),
+        Some("Species") => Some(TaxonomyLevel::Species),
+        Some("Strain") => Some(TaxonomyLevel::Strain),
+        _ => None,
+    })
+ }
+ 
+ /// What the sample shares with one profile, from the in-memory index when
+ /// loaded. `indexed_overlaps` caches the index's pass over `sample_kmers`.
+ fn profile_overlap(
     &self,
     profile_id: i64,
-    profile_name: &str,
     sample_kmers: &HashMap<String, usize>,
     total_sample_kmers: usize,
-    total_profile_kmers: usize,
- ) -> Result<Option<ProfileMatch>> {
-    info!("Comparing profile {} (id={})", profile_name, profile_id);
- 
-    let mut kmer_stmt = self.conn.prepare(
-        "SELECT kmer, frequency FROM kmers WHERE profile_id = ?"
-    )?;
+    with_gc: bool,
+    indexed_overlaps: &mut Option<HashMap<i64, KmerOverlap>>,
+ ) -> Result<KmerOverlap> {
+    match &self.index {
+        Some(index) => {
+            let overlaps = indexed_overlaps.get_or_insert_with(|| {
+                index.overlaps(sample_kmers, total_sample_kmers, self.uniqueness)
+            });
+            Ok(overlaps.get(&profile_id).cloned().unwrap_or_default())
+        }
+        None => self.query_overlap(profile_id, sample_kmers, total_sample_kmers, with_gc),
+    }
+ }
+
+ /// Read one profile's k-mers from the database and tally what the sample shares with it
+ fn query_overlap(
+    &self,
+    profile_id: i64,
+    sample_kmers: &HashMap<String, usize>,
+    total_sample_kmers: usize,
+    with_gc: bool,
+ ) -> Result<KmerOverlap> {
+    // Join the cached occurrence counts so uniqueness needs no per-k-mer
+    // queries, unless uniqueness is skipped altogether
+    let mut kmer_stmt = self.conn.prepare(if self.uniqueness {
+        "SELECT k.kmer, k.frequency, COALESCE(o.profile_count, 1)
+         FROM kmers k
+         LEFT JOIN kmer_occurrence o ON o.kmer = k.kmer
+         WHERE k.profile_id = ?"
+    } else {
+        "SELECT kmer, frequency, NULL FROM kmers WHERE profile_id = ?"
+    })?;
  
-    let mut shared_kmers = 0;
+    let mut overlap = KmerOverlap::default();
     let mut profile_unique_kmers = HashSet::new();
-    let mut shared_kmer_list = Vec::new();
  
     for kmer_result in kmer_stmt.query_map(params![profile_id], |row| {
         Ok((
             row.get::<_, String>(0)?,
             row.get::<_, f64>(1)?,
+            row.get::<_, Option<i64>>(2)?,
         ))
     })? {
-        let (kmer, _) = kmer_result?;
-        profile_unique_kmers.insert(kmer.clone());
+        let (kmer, profile_freq, profile_count) = kmer_result?;
+        overlap.profile_norm += profile_freq * profile_freq;
+        if with_gc {
+            overlap.profile_gc += gc_fraction(kmer.as_bytes()) * profile_freq;
+            overlap.profile_total += profile_freq;
+        }
         
-        if let Some(&_sample_count) = sample_kmers.get(&kmer) {
-            shared_kmers += 1;
-            shared_kmer_list.push(kmer);
+        if let Some(&sample_count) = sample_kmers.get(&kmer) {
+            overlap.shared_kmers += 1;
+            let sample_freq = sample_count as f64 / total_sample_kmers as f64;
+            overlap.dot_product += sample_freq * profile_freq;
+            overlap.shared_weight += sample_freq.min(profile_freq);
+            if profile_count.is_some_and(|count| count <= 1) {
+                overlap.unique_matches += 1;
+            }
         }
+        profile_unique_kmers.insert(kmer);
     }
+    overlap.profile_size = profile_unique_kmers.len();
+    Ok(overlap)
+ }
+
+ /// Score a profile from its overlap with the sample, before any threshold
+ fn overlap_scores(
+    &self,
+    profile_name: &str,
+    overlap: &KmerOverlap,
+    sample_kmers: &HashMap<String, usize>,
+    total_sample_kmers: usize,
+ ) -> Result<OverlapScores> {
+    let KmerOverlap { shared_kmers, unique_matches, dot_product, profile_norm, profile_size, .. } = *overlap;
+
+    // Cosine similarity of the frequency vectors; only shared k-mers
+    // contribute to the dot product
+    let sample_norm: f64 = sample_kmers.values()
+        .map(|&count| (count as f64 / total_sample_kmers as f64).powi(2))
+        .sum();
+    let cosine_similarity = if sample_norm > 0.0 && profile_norm > 0.0 {
+        dot_product / (sample_norm.sqrt() * profile_norm.sqrt())
+    } else {
+        0.0
+    };
  
     let sample_size = sample_kmers.len();
-    let profile_size = profile_unique_kmers.len();
     let sample_coverage = shared_kmers as f64 / sample_size as f64;
+    let profile_coverage = shared_kmers as f64 / profile_size as f64;
+    let jaccard_similarity = shared_kmers as f64 / union_size(profile_name, sample_size, profile_size, shared_kmers)? as f64;
+    let containment = shared_kmers as f64 / sample_size.min(profile_size) as f64;
     let size_ratio = sample_size as f64 / profile_size as f64;
  
     // Calculate uniqueness score
-    let uniqueness_score = if !shared_kmer_list.is_empty() {
-        let mut unique_count = 0;
-        for kmer in &shared_kmer_list {
-            let other_profiles: i64 = self.conn.query_row(
-                "SELECT COUNT(DISTINCT profile_id) FROM kmers 
-                 WHERE kmer = ? AND profile_id != ?",
-                params![kmer, profile_id],
-                |row| row.get(0)
-            )?;
-            if other_profiles == 0 {
-                unique_count += 1;
-            }
-        }
-        unique_count as f64 / shared_kmer_list.len() as f64
+    let uniqueness_score = if !self.uniqueness {
+        None
+    } else if shared_kmers > 0 {
+        Some(unique_matches as f64 / shared_kmers as f64)
     } else {
-        0.0
-    };
- 
-    // Calculate confidence score
-    let confidence_score = {
-        let coverage_weight = sample_coverage;
-        let uniqueness_weight = uniqueness_score;
-        let size_weight = 1.0 - (1.0 - size_ratio).abs();
-        
-        (coverage_weight + uniqueness_weight + size_weight) / 3.0
+        Some(0.0)
     };
  
+    let confidence_score = self.confidence_weights.score(sample_coverage, uniqueness_score, size_ratio);
+
+    Ok(OverlapScores {
+        sample_coverage,
+        profile_coverage,
+        jaccard_similarity,
+        containment,
+        size_ratio,
+        cosine_similarity,
+        uniqueness_score,
+        confidence_score,
+    })
+ }
+
+ /// Score a profile from its overlap with the sample, returning a match when
+ /// every threshold is met
+ fn score_overlap(
+    &self,
+    profile_name: &str,
+    overlap: KmerOverlap,
+    sample_kmers: &HashMap<String, usize>,
+    total_sample_kmers: usize,
+    sample_gc: Option<f64>,
+ ) -> Result<Option<ProfileMatch>> {
+    let OverlapScores {
+        sample_coverage,
+        profile_coverage,
+        jaccard_similarity,
+        containment,
+        size_ratio,
+        cosine_similarity,
+        uniqueness_score,
+        confidence_score,
+    } = self.overlap_scores(profile_name, &overlap, sample_kmers, total_sample_kmers)?;
+    let KmerOverlap { shared_kmers, unique_matches, shared_weight, profile_gc, profile_total, .. } = overlap;
+    let weighted_shared_kmers = shared_weight * sample_kmers.len() as f64;
+    let shared_evidence = if self.weighted_shared { weighted_shared_kmers } else { shared_kmers as f64 };
+
     info!(
         "Comparison summary for {}:
         Shared k-mers: {}
         Sample coverage: {:.6}
+        Cosine similarity: {:.6}
         Size ratio: {:.6}
-        Uniqueness score: {:.6}
+        Uniqueness score: {}
         Confidence score: {:.6}",
         profile_name, 
         shared_kmers,
         sample_coverage,
+        cosine_similarity,
         size_ratio,
-        uniqueness_score,
+        format_uniqueness(uniqueness_score),
         confidence_score
     );
+
+    let similarity = match self.metric {
+        SimilarityMetric::Coverage => sample_coverage,
+        SimilarityMetric::Cosine => cosine_similarity,
+    };
  
-    if sample_coverage >= self.min_similarity && shared_kmers >= self.min_shared_kmers {
-        Ok(Some(ProfileMatch::new(
+    if similarity >= self.min_similarity
+        && shared_evidence >= self.min_shared_kmers as f64
+        && containment >= self.min_containment
+        && confidence_score >= self.min_confidence
+    {
+        let profile_match = ProfileMatch::new(
             profile_name.to_string(),
             sample_coverage,
             shared_kmers,
             size_ratio,
-            uniqueness_score,
+            uniqueness_score.unwrap_or(0.0),
             confidence_score,
-        )))
+        )
+        .with_profile_overlap(profile_coverage, jaccard_similarity)
+        .with_containment(containment)
+        .with_unique_matches(unique_matches)
+        .with_cosine_similarity(cosine_similarity);
+        let profile_match = if self.weighted_shared {
+            profile_match.with_weighted_shared_kmers(weighted_shared_kmers)
+        } else {
+            profile_match
+        };
+        let profile_match = match sample_gc {
+            Some(sample_gc) => {
+                let profile_gc = if profile_total > 0.0 { profile_gc / profile_total } else { 0.0 };
+                let profile_match = profile_match.with_gc(sample_gc, profile_gc, self.max_gc_difference);
+                if profile_match.gc_mismatch {
+                    warn!(
+                        "Profile {} matched, but its GC content ({:.1}%) differs from the sample's ({:.1}%) \
+                         by more than {:.1} points; check for contamination or a mislabeled reference",
+                        profile_name, profile_gc * 100.0, sample_gc * 100.0, self.max_gc_difference * 100.0
+                    );
+                }
+                profile_match
+            }
+            None => profile_match,
+        };
+        Ok(Some(if self.uniqueness { profile_match } else { profile_match.without_uniqueness() }))
     } else {
         info!(
             "Profile {} did not meet thresholds:
-            Sample coverage: {:.6} (minimum: {})
-            Shared k-mers: {} (minimum: {})",
+            {} similarity: {:.6} (minimum: {})
+            {}: {} (minimum: {})
+            Containment: {:.6} (minimum: {})
+            Confidence: {:.6} (minimum: {})",
             profile_name, 
-            sample_coverage, 
+            self.metric,
+            similarity, 
             self.min_similarity,
-            shared_kmers, 
-            self.min_shared_kmers
+            if self.weighted_shared { "Weighted shared k-mers" } else { "Shared k-mers" },
+            if self.weighted_shared { format!("{:.1}", weighted_shared_kmers) } else { shared_kmers.to_string() },
+            self.min_shared_kmers,
+            containment,
+            self.min_containment,
+            confidence_score,
+            self.min_confidence
         );
         Ok(None)
     }
  }
 
+    /// Compare the sample's counts, from `counter.get_counts()`, k-mer by
+    /// k-mer with one profile. Returns `None` when no such profile exists at
+    /// the sample's k-mer size.
     pub fn get_detailed_analysis(
         &self,
         counter: &KmerCounter,
+        sample_kmers: &HashMap<String, usize>,
         profile_name: &str,
     ) -> Result<Option<DetailedAnalysis>> {
-        let profile_id: Option<i64> = self.conn.query_row(
-            "SELECT id FROM profiles WHERE name = ?",
-            params![profile_name],
-            |row| row.get(0)
+        let profile: Option<(i64, bool, String)> = self.conn.query_row(
+            "SELECT id, COALESCE(canonical, 0), COALESCE(normalization, 'occurrence')
+             FROM profiles WHERE name = ?1 AND k = ?2",
+            params![profile_name, counter.kmer_size()],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
         ).optional()?;
     
-        let Some(profile_id) = profile_id else {
+        let Some((profile_id, canonical, normalization)) = profile else {
             return Ok(None);
         };
+        let presence = parse_normalization(&normalization)? == Normalization::Presence;
+        let mut sample_views = HashMap::new();
+        let sample_kmers = sample_view(sample_kmers, canonical, presence, &mut sample_views);
     
+        // Profile counts come from the occurrence cache so uniqueness needs no extra queries
         let mut kmer_stmt = self.conn.prepare(
-            "SELECT kmer, frequency FROM kmers WHERE profile_id = ?"
+            "SELECT k.kmer, k.frequency, COALESCE(o.profile_count, 1)
+             FROM kmers k
+             LEFT JOIN kmer_occurrence o ON o.kmer = k.kmer
+             WHERE k.profile_id = ?"
         )?;
     
-        let sample_kmers = counter.get_counts();
-        let total_sample_kmers = counter.total_kmers() as f64;
+        let total_sample_kmers = if presence { sample_kmers.len() } else { counter.total_kmers() } as f64;
     
         let mut analysis = DetailedAnalysis::new();
     
         // Get total profile k-mers for size ratio calculation
-        let total_profile_kmers = self.get_profile_kmer_count(profile_name.to_string())?;
+        let _total_profile_kmers = self.get_profile_kmer_count(profile_name.to_string(), counter.kmer_size())?;
     
         for kmer_result in kmer_stmt.query_map(params![profile_id], |row| {
             Ok((
                 row.get::<_, String>(0)?,
                 row.get::<_, f64>(1)?,
+                row.get::<_, i64>(2)?,
             ))
         })? {
-            let (kmer, ref_freq) = kmer_result?;
+            let (kmer, ref_freq, profile_count) = kmer_result?;
+            let is_unique = profile_count <= 1;
+            if is_unique {
+                analysis.statistics.profile_unique_kmers += 1;
+            }
             
             if let Some(&sample_count) = sample_kmers.get(&kmer) {
                 let sample_freq = sample_count as f64 / total_sample_kmers;
-                analysis.add_shared_kmer(kmer, sample_freq, ref_freq);
+                analysis.add_shared_kmer(kmer, sample_freq, is_unique);
             } else {
                 analysis.add_reference_unique_kmer(kmer, ref_freq);
             }
         }
     
         // Add sample-unique k-mers
-        for (kmer, count) in sample_kmers {
-            if !analysis.has_kmer(&kmer) {
+        for (kmer, &count) in sample_kmers {
+            if !analysis.has_kmer(kmer) {
                 let freq = count as f64 / total_sample_kmers;
                 analysis.add_sample_unique_kmer(kmer.clone(), freq);
             }
         }
     
-        analysis.calculate_statistics();
+        analysis.calculate_statistics(&self.confidence_weights);
         Ok(Some(analysis))
     }
 
-    pub fn get_profile_kmer_count(&self, name: String) -> Result<i64> {
-        // Query total_kmers directly from profiles table and return error if not found
-        let total_kmers: i64 = self.conn.query_row(
-            "SELECT total_kmers FROM profiles WHERE name = ?",
-            params![name],
-            |row| row.get(0)
-        )?;
-    
-        debug!("Profile '{}' has {} total k-mers from database", name, total_kmers);
-    
-        Ok(total_kmers)
-    }
-}
+    /// Split the k-mers the sample shares with its matches into the core
+    /// shared with every match and, per match, the discriminating rest.
+    /// Built from each match's detailed analysis; k-mers come back sorted.
+    pub fn core_kmers(
+        &self,
+        counter: &KmerCounter,
+        sample_kmers: &HashMap<String, usize>,
+        matches: &[ProfileMatch],
+    ) -> Result<CoreKmers> {
+        let mut shared_sets = Vec::with_capacity(matches.len());
+        for m in matches {
+            let analysis = self.get_detailed_analysis(counter, sample_kmers, &m.name)?
+                .with_context(|| format!("Matched profile {} not found at k={}", m.name, counter.kmer_size()))?;
+            shared_sets.push((m.name.clone(), analysis.shared_sequences));
+        }
 
+        let mut core: Vec<String> = match shared_sets.split_first() {
+            Some(((_, first), rest)) => first.iter()
+                .filter(|kmer| rest.iter().all(|(_, shared)| shared.contains(*kmer)))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        core.sort_unstable();
 
-#[derive(Debug, Clone)]
-pub struct SharedKmer {
-    pub sequence: String,
-    pub sample_frequency: f64,
-    pub is_unique: bool,  // True if this k-mer is unique to this profile
-}
+        let discriminating = shared_sets.into_iter()
+            .map(|(name, shared)| {
+                let mut kmers: Vec<String> = shared.into_iter()
+                    .filter(|kmer| core.binary_search(kmer).is_err())
+                    .collect();
+                kmers.sort_unstable();
+                (name, kmers)
+            })
+            .collect();
 
+        Ok(CoreKmers { core, discriminating })
+    }
 
-#[derive(Debug, Clone)]
-pub struct UniqueKmer {
-    pub sequence: String,
-    pub frequency: f64,
-}
+    /// Bin the sample's distinct k-mers found in the database by how many
+    /// profiles hold each, from the k-mer occurrence counts. K-mers are folded
+    /// onto canonical k-mers first when every profile at the sample's k and
+    /// alphabet is.
+    pub fn kmer_specificity(&self, counter: &KmerCounter, sample_kmers: &HashMap<String, usize>) -> Result<KmerSpecificity> {
+        let canonical: bool = self.conn.query_row(
+            "SELECT COALESCE(MIN(COALESCE(canonical, 0)), 0) FROM profiles WHERE k = ?1 AND COALESCE(alphabet, 'dna') = ?2",
+            params![counter.kmer_size(), self.alphabet.to_string()],
+            |row| row.get(0),
+        )?;
+        let mut sample_views = HashMap::new();
+        let sample_kmers = sample_view(sample_kmers, canonical, false, &mut sample_views);
+
+        let mut bins: BTreeMap<usize, usize> = BTreeMap::new();
+        match self.index.as_ref().filter(|index| index.kmer_size() == counter.kmer_size()) {
+            Some(index) => {
+                for kmer in sample_kmers.keys() {
+                    if let Some(profiles) = index.kmer_profile_count(kmer) {
+                        *bins.entry(profiles).or_insert(0) += 1;
+                    }
+                }
+            }
+            None => {
+                let mut stmt = self.conn.prepare("SELECT profile_count FROM kmer_occurrence WHERE kmer = ?1")?;
+                for kmer in sample_kmers.keys() {
+                    if let Some(profiles) = stmt.query_row(params![kmer], |row| row.get::<_, usize>(0)).optional()? {
+                        *bins.entry(profiles).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(KmerSpecificity { bins: bins.into_iter().collect() })
+    }
+
+    /// The sample's counts as the matched profiles store k-mers: folded onto
+    /// canonical k-mers when they are canonical. Mixed matches are an error.
+    fn matched_sample_counts(&self, counter: &KmerCounter, matches: &[ProfileMatch]) -> Result<HashMap<String, usize>> {
+        let mut canonical_stmt = self.conn.prepare(
+            "SELECT COALESCE(canonical, 0) FROM profiles WHERE name = ?1 AND k = ?2"
+        )?;
+        let mut strand_handling = matches.iter()
+            .map(|m| canonical_stmt.query_row(params![m.name, counter.kmer_size()], |row| row.get::<_, bool>(0)))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        strand_handling.dedup();
+        Ok(match strand_handling.as_slice() {
+            [true] => canonical_counts(counter.get_counts()),
+            [false] | [] => counter.get_counts(),
+            _ => bail!("The matches mix canonical and single-strand profiles; run db canonicalize on all of them"),
+        })
+    }
+
+    /// Pool matches under each of their ancestors by `parent`, e.g. the
+    /// species and genus implied by a strain match. An ancestor's coverage
+    /// counts every sample k-mer shared with any of its matched descendants
+    /// once. Ancestors come back by descending coverage.
+    pub fn rollup_lineages(&self, counter: &KmerCounter, matches: &[ProfileMatch]) -> Result<Vec<LineageRollup>> {
+        // Matches under each ancestor, in order of first appearance
+        let mut ancestors: Vec<(String, Vec<usize>)> = Vec::new();
+        for (index, m) in matches.iter().enumerate() {
+            let lineage = self.lineage(&m.name)?;
+            for ancestor in &lineage[..lineage.len() - 1] {
+                match ancestors.iter_mut().find(|(name, _)| name == ancestor) {
+                    Some((_, descendants)) => descendants.push(index),
+                    None => ancestors.push((ancestor.clone(), vec![index])),
+                }
+            }
+        }
+        if ancestors.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let sample_kmers = self.matched_sample_counts(counter, matches)?;
+        let mut kmer_stmt = self.conn.prepare(
+            "SELECT k.kmer FROM kmers k JOIN profiles p ON p.id = k.profile_id WHERE p.name = ?1 AND p.k = ?2"
+        )?;
+        // Sample k-mers each match shares
+        let mut shared = Vec::with_capacity(matches.len());
+        for m in matches {
+            let mut kmers = HashSet::new();
+            for kmer in kmer_stmt.query_map(params![m.name, counter.kmer_size()], |row| row.get::<_, String>(0))? {
+                let kmer = kmer?;
+                if sample_kmers.contains_key(&kmer) {
+                    kmers.insert(kmer);
+                }
+            }
+            shared.push(kmers);
+        }
+
+        let mut rollups = Vec::with_capacity(ancestors.len());
+        for (name, descendants) in ancestors {
+            let pooled: HashSet<&String> = descendants.iter().flat_map(|&index| &shared[index]).collect();
+            let sample_coverage = if sample_kmers.is_empty() { 0.0 } else { pooled.len() as f64 / sample_kmers.len() as f64 };
+            rollups.push(LineageRollup {
+                level: self.get_profile_level(&name)?,
+                shared_kmers: pooled.len(),
+                sample_coverage,
+                matches: descendants.iter().map(|&index| matches[index].name.clone()).collect(),
+                name,
+            });
+        }
+        rollups.sort_by(|a, b| b.sample_coverage.partial_cmp(&a.sample_coverage).unwrap());
+        Ok(rollups)
+    }
+
+    /// Estimate each match's share of the sample with expectation-maximization.
+    /// A sample k-mer found in several matched profiles is split between them in
+    /// proportion to their current abundance over their k-mer count, so unique
+    /// k-mers anchor the estimate and shared ones follow. Abundances sum to 1.
+    pub fn estimate_abundances(&self, counter: &KmerCounter, matches: &mut [ProfileMatch]) -> Result<()> {
+        if matches.len() <= 1 {
+            for m in matches.iter_mut() {
+                m.abundance = Some(1.0);
+            }
+            return Ok(());
+        }
+
+        let sample_kmers = self.matched_sample_counts(counter, matches)?;
+
+        // Which matched profiles contain each sample k-mer
+        let mut owners: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut profile_sizes = Vec::with_capacity(matches.len());
+        let mut kmer_stmt = self.conn.prepare(
+            "SELECT k.kmer FROM kmers k JOIN profiles p ON p.id = k.profile_id WHERE p.name = ?1 AND p.k = ?2"
+        )?;
+        for (index, m) in matches.iter().enumerate() {
+            let mut size = 0usize;
+            for kmer in kmer_stmt.query_map(params![m.name, counter.kmer_size()], |row| row.get::<_, String>(0))? {
+                let kmer = kmer?;
+                size += 1;
+                if let Some((sample_kmer, _)) = sample_kmers.get_key_value(&kmer) {
+                    owners.entry(sample_kmer.as_str()).or_default().push(index);
+                }
+            }
+            profile_sizes.push(size.max(1) as f64);
+        }
+
+        // K-mers with the same owners behave identically, so pool their counts
+        let mut classes: HashMap<Vec<usize>, f64> = HashMap::new();
+        for (kmer, profiles) in owners {
+            *classes.entry(profiles).or_insert(0.0) += sample_kmers[kmer] as f64;
+        }
+
+        let mut abundances = vec![1.0 / matches.len() as f64; matches.len()];
+        for iteration in 0..ABUNDANCE_MAX_ITERATIONS {
+            let mut assigned = vec![0.0; matches.len()];
+            for (profiles, count) in &classes {
+                let weight: f64 = profiles.iter().map(|&j| abundances[j] / profile_sizes[j]).sum();
+                if weight == 0.0 {
+                    continue;
+                }
+                for &j in profiles {
+                    assigned[j] += count * (abundances[j] / profile_sizes[j]) / weight;
+                }
+            }
+
+            let total: f64 = assigned.iter().sum();
+            if total == 0.0 {
+                break;
+            }
+            let change = assigned.iter()
+                .zip(&abundances)
+                .map(|(new, old)| (new / total - old).abs())
+                .fold(0.0, f64::max);
+            for (abundance, new) in abundances.iter_mut().zip(&assigned) {
+                *abundance = new / total;
+            }
+            if change < ABUNDANCE_TOLERANCE {
+                debug!("Abundance EM converged after {} iterations", iteration + 1);
+                break;
+            }
+        }
+
+        for (m, abundance) in matches.iter_mut().zip(abundances) {
+            m.abundance = Some(abundance);
+        }
+        Ok(())
+    }
+
+    /// Resample the sample's k-mer multiset with replacement `replicates`
+    /// times, rescore every match against each resample and record the mean
+    /// and 95% interval of its sample coverage and confidence. The same `seed`
+    /// draws the same resamples. Each resample takes one multinomial draw over
+    /// the distinct k-mers, so its cost does not grow with sequencing depth.
+    pub fn bootstrap_matches(
+        &self,
+        counter: &KmerCounter,
+        matches: &mut [ProfileMatch],
+        replicates: usize,
+        seed: u64,
+    ) -> Result<()> {
+        if matches.is_empty() || replicates == 0 {
+            return Ok(());
+        }
+
+        let mut profiles = Vec::with_capacity(matches.len());
+        for m in matches.iter() {
+            let (profile_id, canonical, normalization) = self.conn.query_row(
+                "SELECT id, COALESCE(canonical, 0), COALESCE(normalization, 'occurrence')
+                 FROM profiles WHERE name = ?1 AND k = ?2",
+                params![m.name, counter.kmer_size()],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, bool>(1)?, row.get::<_, String>(2)?)),
+            ).with_context(|| format!("Profile {} not found at k={}", m.name, counter.kmer_size()))?;
+            profiles.push((profile_id, canonical, parse_normalization(&normalization)? == Normalization::Presence));
+        }
+
+        // Sorting keeps the draws independent of hash map order
+        let sample_kmers = counter.get_counts();
+        let mut kmers: Vec<(&String, usize)> = sample_kmers.iter().map(|(kmer, &count)| (kmer, count)).collect();
+        kmers.sort_unstable();
+        let total: usize = kmers.iter().map(|&(_, count)| count).sum();
+
+        let mut coverages = vec![Vec::with_capacity(replicates); matches.len()];
+        let mut confidences = vec![Vec::with_capacity(replicates); matches.len()];
+        let mut resample: HashMap<String, usize> = HashMap::with_capacity(kmers.len());
+        for replicate in 0..replicates {
+            let mut state = splitmix64(seed ^ splitmix64(replicate as u64));
+            let mut next_unit = || {
+                state = splitmix64(state);
+                unit_interval(state)
+            };
+            // A multinomial draw of `total` k-mers in proportion to their
+            // counts, as one binomial per k-mer conditioned on those before it
+            resample.clear();
+            let (mut draws_left, mut count_left) = (total, total);
+            for &(kmer, count) in &kmers {
+                if draws_left == 0 {
+                    break;
+                }
+                let drawn = binomial(draws_left, count as f64 / count_left as f64, &mut next_unit);
+                draws_left -= drawn;
+                count_left -= count;
+                if drawn > 0 {
+                    resample.insert(kmer.clone(), drawn);
+                }
+            }
+
+            let mut resample_views = HashMap::new();
+            let mut indexed_overlaps: HashMap<(bool, bool), Option<HashMap<i64, KmerOverlap>>> = HashMap::new();
+            for (i, &(profile_id, canonical, presence)) in profiles.iter().enumerate() {
+                let resample = sample_view(&resample, canonical, presence, &mut resample_views);
+                let total = if presence { resample.len() } else { total };
+                let overlap = self.profile_overlap(
+                    profile_id, resample, total, false, indexed_overlaps.entry((canonical, presence)).or_default(),
+                )?;
+                let scores = self.overlap_scores(&matches[i].name, &overlap, resample, total)?;
+                coverages[i].push(scores.sample_coverage);
+                confidences[i].push(scores.confidence_score);
+            }
+        }
+
+        for ((m, coverage), confidence) in matches.iter_mut().zip(coverages).zip(confidences) {
+            m.bootstrap = Some(Bootstrap {
+                replicates,
+                sample_coverage: BootstrapInterval::from_replicates(coverage),
+                confidence_score: BootstrapInterval::from_replicates(confidence),
+            });
+        }
+        Ok(())
+    }
+
+    /// Score profiles at the analyzer's level only by their marker k-mers, the
+    /// k-mers no other profile at that level contains. Closely related strains
+    /// share most k-mers, so only markers can tell them apart. The confidence
+    /// score is the fraction of a profile's markers found in the sample.
+    pub fn analyze_markers(&self, counter: &KmerCounter) -> Result<Vec<ProfileMatch>> {
+        self.check_sample_protocol(counter)?;
+        if counter.unique_kmers() == 0 {
+            warn!("The sample has no k-mers of size {}; nothing to compare", counter.kmer_size());
+            return Ok(Vec::new());
+        }
+        let level = &self.taxonomy_level;
+        let sample_seed = counter.seed().map(|seed| seed.pattern().to_string());
+
+        // Markers are found by comparing profiles k-mer for k-mer, which only
+        // works when they all handle strands the same way
+        let strand_handling: Vec<bool> = self.conn
+            .prepare(
+                "SELECT DISTINCT COALESCE(canonical, 0) FROM profiles
+                 WHERE taxonomy_level = ?1 AND k = ?2 AND seed IS ?3 AND COALESCE(homopolymer_compressed, 0) = ?4
+                   AND minimizer_window IS ?5 AND COALESCE(alphabet, 'dna') = ?6"
+            )?
+            .query_map(
+                params![
+                    level.to_string(), counter.kmer_size() as i64, sample_seed, self.homopolymer_compressed,
+                    counter.minimizer_window(), self.alphabet.to_string(),
+                ],
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        let sample_kmers = match strand_handling.as_slice() {
+            [true] => canonical_counts(counter.get_counts()),
+            [_, _] => bail!(
+                "Profiles at {} level mix canonical and single-strand k-mers; run db canonicalize on all of them",
+                level
+            ),
+            _ => counter.get_counts(),
+        };
+
+        // Marker k-mers of comparable profiles, counted per level rather than from
+        // kmer_occurrence, which also counts the parents of each profile
+        let mut marker_stmt = self.conn.prepare(
+            "SELECT k.kmer, MIN(k.profile_id)
+             FROM kmers k JOIN profiles p ON p.id = k.profile_id
+             WHERE p.taxonomy_level = ?1 AND p.k = ?2 AND p.seed IS ?3
+               AND COALESCE(p.homopolymer_compressed, 0) = ?4 AND p.minimizer_window IS ?5
+               AND COALESCE(p.alphabet, 'dna') = ?6
+             GROUP BY k.kmer
+             HAVING COUNT(*) = 1"
+        )?;
+        let mut markers: HashMap<i64, (usize, usize)> = HashMap::new();
+        let rows = marker_stmt.query_map(
+            params![
+                level.to_string(), counter.kmer_size() as i64, sample_seed, self.homopolymer_compressed,
+                counter.minimizer_window(), self.alphabet.to_string(),
+            ],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+        )?;
+        for row in rows {
+            let (kmer, profile_id) = row?;
+            let (total, found) = markers.entry(profile_id).or_insert((0, 0));
+            *total += 1;
+            if sample_kmers.contains_key(&kmer) {
+                *found += 1;
+            }
+        }
+
+        let mut name_stmt = self.conn.prepare("SELECT name FROM profiles WHERE id = ?")?;
+        let mut matches = Vec::new();
+        for (profile_id, (total, found)) in markers {
+            let marker_fraction = found as f64 / total as f64;
+            if found == 0 || found < self.min_shared_kmers || marker_fraction < self.min_similarity {
+                continue;
+            }
+
+            let name: String = name_stmt.query_row(params![profile_id], |row| row.get(0))?;
+            // Markers are unique within the whole level, so other profiles are only dropped here
+            if self.profile_names.as_ref().is_some_and(|names| !names.contains(&name)) {
+                continue;
+            }
+            info!("Profile {} has {} of {} marker k-mers in the sample", name, found, total);
+            let sample_coverage = found as f64 / sample_kmers.len() as f64;
+            let size_ratio = sample_kmers.len() as f64 / total as f64;
+            matches.push(
+                ProfileMatch::new(name, sample_coverage, found, size_ratio, 1.0, marker_fraction)
+                    .with_profile_overlap(marker_fraction, 0.0)
+                    .with_unique_matches(found)
+                    .with_level(level.clone()),
+            );
+        }
+
+        matches.sort_by(|a, b| {
+            b.confidence_score.total_cmp(&a.confidence_score).then_with(|| a.name.cmp(&b.name))
+        });
+        info!("Found {} marker matches at {} level", matches.len(), level);
+        Ok(matches)
+    }
+
+    pub fn get_profile_kmer_count(&self, name: String, k: usize) -> Result<i64> {
+        // Query total_kmers directly from profiles table and return error if not found
+        let total_kmers: i64 = self.conn.query_row(
+            "SELECT total_kmers FROM profiles WHERE name = ?1 AND k = ?2",
+            params![name, k],
+            |row| row.get(0)
+        )?;
+    
+        debug!("Profile '{}' has {} total k-mers from database", name, total_kmers);
+    
+        Ok(total_kmers)
+    }
+}
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedKmer {
+    pub sequence: String,
+    pub sample_frequency: f64,
+    pub is_unique: bool,  // True if this k-mer is unique to this profile
+}
+
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct UniqueKmer {
+    pub sequence: String,
+    pub frequency: f64,
+}
 
 #[derive(Debug, Clone)]
 pub struct DetailedAnalysis {
@@ -309,9 +1536,32 @@ pub struct DetailedAnalysis {
     pub unique_to_reference: Vec<UniqueKmer>,
     pub unique_to_sample: Vec<UniqueKmer>,
     pub statistics: AnalysisStatistics,
+    /// Sequences in `shared_kmers`, for constant-time membership checks
+    shared_sequences: HashSet<String>,
 }
 
-#[derive(Debug, Clone)]
+/// Distinct sample k-mers found in the database, by how many profiles hold each
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KmerSpecificity {
+    /// `(profiles, k-mers)` pairs by increasing number of profiles
+    pub bins: Vec<(usize, usize)>,
+}
+
+impl KmerSpecificity {
+    /// Sample k-mers held by at least one profile
+    pub fn shared_kmers(&self) -> usize {
+        self.bins.iter().map(|&(_, kmers)| kmers).sum()
+    }
+}
+
+/// K-mers shared by every matched profile, and per profile those outside that core
+#[derive(Debug, Clone, Default)]
+pub struct CoreKmers {
+    pub core: Vec<String>,
+    pub discriminating: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct AnalysisStatistics {
     pub total_shared: usize,
     pub total_unique_reference: usize,
@@ -324,6 +1574,7 @@ pub struct AnalysisStatistics {
     pub shared_unique_kmers: usize,   // How many shared k-mers are unique to this profile
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct FrequencyDistribution {
     pub high_freq: usize,   // >= 0.75
@@ -348,22 +1599,24 @@ impl DetailedAnalysis {
                 profile_unique_kmers: 0,
                 shared_unique_kmers: 0,
             },
+            shared_sequences: HashSet::new(),
         }
     }
 
     fn has_kmer(&self, kmer: &str) -> bool {
-        self.shared_kmers.iter().any(|sk| sk.sequence == kmer)
+        self.shared_sequences.contains(kmer)
     }
 
-    fn add_shared_kmer(&mut self, sequence: String, sample_freq: f64, ref_freq: f64) {
+    fn add_shared_kmer(&mut self, sequence: String, sample_freq: f64, is_unique: bool) {
         info!(
             "Adding shared k-mer: {} (sample_freq={:.6})",
             sequence, sample_freq
         );
+        self.shared_sequences.insert(sequence.clone());
         self.shared_kmers.push(SharedKmer {
             sequence,
             sample_frequency: sample_freq,
-            is_unique: false,  // Will be updated during calculate_statistics
+            is_unique,
         });
     }
 
@@ -381,7 +1634,7 @@ impl DetailedAnalysis {
         });
     }
 
-    fn calculate_statistics(&mut self) {
+    fn calculate_statistics(&mut self, weights: &ConfidenceWeights) {
         self.statistics.total_shared = self.shared_kmers.len();
         self.statistics.total_unique_reference = self.unique_to_reference.len();
         self.statistics.total_unique_sample = self.unique_to_sample.len();
@@ -400,17 +1653,1181 @@ impl DetailedAnalysis {
                 (self.statistics.total_unique_reference + self.statistics.total_shared) as f64;
         }
 
-        // Calculate uniqueness metrics
-        // Note: This would require database access to check k-mer uniqueness
-        // Perhaps pass in pre-calculated uniqueness information or database connection?
+        // Calculate uniqueness metrics; profile_unique_kmers is filled in while loading the profile
+        self.statistics.shared_unique_kmers = self.shared_kmers.iter()
+            .filter(|kmer| kmer.is_unique)
+            .count();
+        self.statistics.uniqueness_score = if self.statistics.total_shared > 0 {
+            self.statistics.shared_unique_kmers as f64 / self.statistics.total_shared as f64
+        } else {
+            0.0
+        };
+
+        self.statistics.confidence_score = weights.score(
+            self.statistics.sample_coverage,
+            Some(self.statistics.uniqueness_score),
+            self.statistics.size_ratio,
+        );
+    }
+}
 
-        // Calculate confidence score
-        self.statistics.confidence_score = {
-            let coverage_weight = self.statistics.sample_coverage;
-            let uniqueness_weight = self.statistics.uniqueness_score;
-            let size_weight = 1.0 - (1.0 - self.statistics.size_ratio).abs();
-            
-            (coverage_weight + uniqueness_weight + size_weight) / 3.0
+/// A profile's scores against the sample, before any threshold
+struct OverlapScores {
+    sample_coverage: f64,
+    profile_coverage: f64,
+    jaccard_similarity: f64,
+    containment: f64,
+    size_ratio: f64,
+    cosine_similarity: f64,
+    uniqueness_score: Option<f64>,
+    confidence_score: f64,
+}
+
+/// The sample's counts as a profile sees them: folded onto canonical k-mers
+/// for a canonical profile, and each k-mer counted once for a presence-normalized
+/// one, so both sides weight k-mers alike. Views are built once into `views`.
+fn sample_view<'a>(
+    sample_kmers: &'a HashMap<String, usize>,
+    canonical: bool,
+    presence: bool,
+    views: &'a mut HashMap<(bool, bool), HashMap<String, usize>>,
+) -> &'a HashMap<String, usize> {
+    if !canonical && !presence {
+        return sample_kmers;
+    }
+    views.entry((canonical, presence)).or_insert_with(|| {
+        let pairs = sample_kmers.iter().map(|(kmer, &count)| (kmer, count));
+        match (canonical, presence) {
+            (true, false) => canonical_counts(pairs),
+            (true, true) => canonical_counts(pairs).into_keys().map(|kmer| (kmer, 1)).collect(),
+            _ => sample_kmers.keys().map(|kmer| (kmer.clone(), 1)).collect(),
+        }
+    })
+}
+
+/// Parse a profile's stored normalization
+fn parse_normalization(name: &str) -> Result<Normalization> {
+    Normalization::from_name(name).with_context(|| format!("Unknown frequency normalization {}", name))
+}
+
+/// Uniqueness score for logs, which may have been skipped
+fn format_uniqueness(uniqueness_score: Option<f64>) -> String {
+    uniqueness_score.map_or("not computed".to_string(), |score| format!("{:.4}", score))
+}
+
+/// Size of the union of the sample and profile k-mer sets. Shared k-mers beyond
+/// both set sizes mean the counts are inconsistent, which is reported rather than
+/// left to wrap around into a meaningless Jaccard.
+fn union_size(profile_name: &str, sample_size: usize, profile_size: usize, shared_kmers: usize) -> Result<usize> {
+    (sample_size + profile_size).checked_sub(shared_kmers).with_context(|| format!(
+        "Inconsistent k-mer counts for profile {}: {} shared k-mers exceed {} sample + {} profile k-mers",
+        profile_name, shared_kmers, sample_size, profile_size
+    ))
+}
+
+/// Number of successes in `trials` draws with success probability `p`, in
+/// expected constant time: inversion for means below 10 and Hörmann's BTRS
+/// transformed rejection otherwise
+fn binomial(trials: usize, p: f64, next_unit: &mut impl FnMut() -> f64) -> usize {
+    if trials == 0 || p <= 0.0 {
+        return 0;
+    }
+    if p >= 1.0 {
+        return trials;
+    }
+    if p > 0.5 {
+        return trials - binomial(trials, 1.0 - p, next_unit);
+    }
+
+    let n = trials as f64;
+    let q = 1.0 - p;
+    if n * p < 10.0 {
+        // Walk up the cumulative distribution; probabilities shrink fast past
+        // the mean, and stop rounding error from walking all the way to n
+        let ratio = p / q;
+        let mut probability = (n * q.ln()).exp();
+        let mut cumulative = probability;
+        let u = next_unit();
+        let mut k = 0;
+        while u > cumulative && k < trials && probability > 0.0 {
+            probability *= ratio * (n - k as f64) / (k + 1) as f64;
+            cumulative += probability;
+            k += 1;
+        }
+        return k;
+    }
+
+    let spq = (n * p * q).sqrt();
+    let b = 1.15 + 2.53 * spq;
+    let a = -0.0873 + 0.0248 * b + 0.01 * p;
+    let c = n * p + 0.5;
+    let v_r = 0.92 - 4.2 / b;
+    let alpha = (2.83 + 5.1 / b) * spq;
+    let lpq = (p / q).ln();
+    let mode = ((n + 1.0) * p).floor();
+    let h = ln_factorial(mode) + ln_factorial(n - mode);
+    loop {
+        let u = next_unit() - 0.5;
+        let v = next_unit();
+        let us = 0.5 - u.abs();
+        let k = ((2.0 * a / us + b) * u + c).floor();
+        if k < 0.0 || k > n {
+            continue;
+        }
+        if us >= 0.07 && v <= v_r {
+            return k as usize;
+        }
+        let v = (v * alpha / (a / (us * us) + b)).ln();
+        if v <= h - ln_factorial(k) - ln_factorial(n - k) + (k - mode) * lpq {
+            return k as usize;
+        }
+    }
+}
+
+/// ln(x!) for a whole number x: summed below 10, Stirling's series above
+fn ln_factorial(x: f64) -> f64 {
+    if x < 10.0 {
+        return (2..=x as usize).map(|i| (i as f64).ln()).sum();
+    }
+    x * x.ln() - x + 0.5 * (2.0 * std::f64::consts::PI * x).ln()
+        + 1.0 / (12.0 * x) - 1.0 / (360.0 * x.powi(3)) + 1.0 / (1260.0 * x.powi(5))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::profile::Profile;
+    use tempfile::{tempdir, TempDir};
+
+    fn setup_database(profiles: &[(&str, &[(&str, f64)])]) -> Result<(TempDir, std::path::PathBuf)> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+
+        for (name, kmers) in profiles {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 3);
+            for (kmer, frequency) in kmers.iter() {
+                profile.frequencies.insert(kmer.to_string(), *frequency);
+            }
+            profile.total_kmers = 100;
+            db.add_profile(&profile)?;
+        }
+
+        Ok((dir, db_path))
+    }
+
+    #[test]
+    fn test_missing_database_is_not_created() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("missing.db");
+
+        assert!(ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species).is_err());
+        assert!(!db_path.exists());
+    }
+
+    #[test]
+    fn test_outdated_schema_is_reported() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[("A", &[("ACT", 1.0)])])?;
+        Connection::open(&db_path)?.execute("ALTER TABLE profiles DROP COLUMN seed", [])?;
+
+        let err = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species).err().unwrap();
+        assert!(err.to_string().contains("older schema"));
+
+        Database::new(&db_path)?;
+        assert!(ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cosine_metric_prefers_matching_abundance() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("Abundant_A", &[("AAA", 0.9), ("CCC", 0.1)]),
+            ("Abundant_C", &[("AAA", 0.1), ("CCC", 0.9)]),
+        ])?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAAAAAAAAA")?;
+        counter.count_sequence(b"CCC")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?
+            .with_metric(SimilarityMetric::Cosine);
+        let matches = analyzer.analyze_sample(&counter)?;
+
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].name, "Abundant_A");
+        assert!(matches[0].cosine_similarity > matches[1].cosine_similarity);
+        // Both profiles share every sample k-mer, so coverage cannot tell them apart
+        assert_eq!(matches[0].sample_coverage, matches[1].sample_coverage);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_containment_uses_smaller_set() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("Contained", &[("AAC", 0.5), ("ACG", 0.5)]),
+            ("Partial", &[("AAC", 0.5), ("TTT", 0.5)]),
+        ])?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AACGTACGGA")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?
+            .with_min_containment(0.9);
+        let matches = analyzer.analyze_sample(&counter)?;
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "Contained");
+        assert!((matches[0].containment - 1.0).abs() < 1e-9);
+        assert!(matches[0].sample_coverage < 0.5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hierarchical_rolls_up_to_shared_parent() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+
+        for (name, level, parent) in [
+            ("Species_X", TaxonomyLevel::Species, None),
+            ("Strain_1", TaxonomyLevel::Strain, Some("Species_X")),
+            ("Strain_2", TaxonomyLevel::Strain, Some("Species_X")),
+        ] {
+            let mut profile = Profile::new(name.to_string(), level, 3);
+            profile.frequencies.insert("AAA".to_string(), 0.5);
+            profile.frequencies.insert("CCC".to_string(), 0.5);
+            profile.total_kmers = 2;
+            profile.parent = parent.map(str::to_string);
+            db.add_profile(&profile)?;
+        }
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAA")?;
+        counter.count_sequence(b"CCC")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Strain)?;
+        let (classification, matches) = analyzer.classify_hierarchical(&counter, 0.0)?;
+
+        assert_eq!(classification.level, Some(TaxonomyLevel::Species));
+        assert_eq!(classification.name.as_deref(), Some("Species_X"));
+        assert!(classification.evidence[0].contains("Strain_1"));
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "Species_X");
+
+        // A single strain above the threshold is assigned directly
+        db.remove_profile("Strain_2", None)?;
+        let (classification, _) = analyzer.classify_hierarchical(&counter, 0.0)?;
+        assert_eq!(classification.level, Some(TaxonomyLevel::Strain));
+        assert_eq!(classification.name.as_deref(), Some("Strain_1"));
+        assert!(classification.evidence.is_empty());
+
+        Ok(())
+    }
+
+    fn add_profile_with_bloom(db: &mut Database, name: &str, kmers: &[String], bloom: bool) -> Result<()> {
+        let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, kmers[0].len());
+        for kmer in kmers {
+            profile.frequencies.insert(kmer.clone(), 1.0 / kmers.len() as f64);
+        }
+        profile.total_kmers = kmers.len();
+        if bloom {
+            profile.bloom = Some(BloomFilter::from_kmers(kmers.iter().map(|k| k.as_bytes()), 0.01));
+        }
+        db.add_profile(&profile)
+    }
+
+    #[test]
+    fn test_bloom_prefilter_skips_disjoint_profiles() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        add_profile_with_bloom(&mut db, "Match", &["AAA".to_string(), "AAC".to_string()], true)?;
+        add_profile_with_bloom(&mut db, "Disjoint", &["GGG".to_string(), "GGT".to_string()], true)?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAAC")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 1, TaxonomyLevel::Species)?;
+        let sample_kmers = counter.get_counts();
+        assert_eq!(analyzer.estimate_shared_kmers(&sample_kmers, 1)?, Some(2));
+
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "Match");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_seed_mismatch_skips_profile() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        for (name, seed) in [("Contiguous", None), ("Spaced", Some("1101"))] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 3);
+            profile.frequencies.insert("ACT".to_string(), 1.0);
+            profile.total_kmers = 1;
+            profile.seed = seed.map(str::to_string);
+            db.add_profile(&profile)?;
+        }
+
+        let seed = crate::kmer::SpacedSeed::parse("1101", 3)?;
+        let counter = KmerCounter::new(3).with_seed(Some(seed));
+        counter.count_sequence(b"ACGT")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "Spaced");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_minimizer_window_must_match() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        for (name, window) in [("AllKmers", None), ("Minimizers", Some(3))] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 3);
+            profile.frequencies.insert("CAT".to_string(), 1.0);
+            profile.total_kmers = 1;
+            profile.minimizer_window = window;
+            db.add_profile(&profile)?;
+        }
+
+        let counter = KmerCounter::new(3).with_minimizer_window(Some(3));
+        counter.count_sequence(b"TGCAT")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "Minimizers");
+
+        let counter = KmerCounter::new(3).with_minimizer_window(Some(5));
+        counter.count_sequence(b"TGCAT")?;
+        let error = analyzer.analyze_sample(&counter).unwrap_err().to_string();
+        assert!(error.contains("minimizer window 5"), "{error}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_homopolymer_compression_mismatch_errors() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[("Uncompressed", &[("ACT", 1.0)])])?;
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"ACT")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        assert_eq!(analyzer.analyze_sample(&counter)?.len(), 1);
+
+        let analyzer = analyzer.with_homopolymer_compression(true);
+        assert!(analyzer.analyze_sample(&counter).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_abundance_em_splits_shared_kmers() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("A", &[("AAC", 0.25), ("ACC", 0.25), ("CCG", 0.25), ("CGG", 0.25)]),
+            ("B", &[("CCG", 0.25), ("CGG", 0.25), ("GGT", 0.25), ("GTT", 0.25)]),
+        ])?;
+        let counter = KmerCounter::new(3);
+        // A-only k-mers x3, shared x4, B-only x1
+        for sequence in ["AACC", "AACC", "AACC", "CCGG", "CCGG", "CCGG", "CCGG", "GGTT"] {
+            counter.count_sequence(sequence.as_bytes())?;
+        }
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let mut matches = analyzer.analyze_sample(&counter)?;
+        matches.sort_by(|a, b| a.name.cmp(&b.name));
+        analyzer.estimate_abundances(&counter, &mut matches)?;
+
+        // Unique evidence is 6 vs 2, and the fixed point a = (6 + 8a) / 16 gives 0.75
+        assert!((matches[0].abundance.unwrap() - 0.75).abs() < 1e-6);
+        assert!((matches[1].abundance.unwrap() - 0.25).abs() < 1e-6);
+
+        analyzer.estimate_abundances(&counter, &mut matches[..1])?;
+        assert_eq!(matches[0].abundance, Some(1.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_markers_only_separates_strains() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        let strains: [(&str, &[&str]); 2] = [
+            ("Strain_1", &["AAC", "ACG", "CGT", "GTA"]),
+            ("Strain_2", &["AAC", "ACG", "CGT", "TTT"]),
+        ];
+        for (name, kmers) in strains {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Strain, 3);
+            for kmer in kmers {
+                profile.frequencies.insert(kmer.to_string(), 0.25);
+            }
+            profile.total_kmers = 4;
+            db.add_profile(&profile)?;
+        }
+        // A species profile sharing Strain_1's marker must not hide it
+        let mut species = Profile::new("Species".to_string(), TaxonomyLevel::Species, 3);
+        species.frequencies.insert("GTA".to_string(), 1.0);
+        species.total_kmers = 1;
+        db.add_profile(&species)?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AACGTA")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Strain)?;
+        let matches = analyzer.analyze_markers(&counter)?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "Strain_1");
+        assert_eq!(matches[0].shared_kmers, 1);
+        assert_eq!(matches[0].confidence_score, 1.0);
+
+        Ok(())
+    }
+
+    /// Times a disjoint sample against 1000 profiles of 1000 k-mers each, with and
+    /// without Bloom filters (release: ~1.8s exact vs ~8ms with filters).
+    /// Run with `cargo test --release bench_bloom_prefilter -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_bloom_prefilter() -> Result<()> {
+        let dir = tempdir()?;
+        let sample = KmerCounter::new(12);
+        sample.count_sequence("ACGT".repeat(500).as_bytes())?;
+
+        for bloom in [false, true] {
+            let db_path = dir.path().join(format!("bloom_{}.db", bloom));
+            let mut db = Database::new(&db_path)?;
+            for i in 0..1000 {
+                let kmers: Vec<String> = (0..1000).map(|j| format!("{:06}{:06}", i, j)).collect();
+                add_profile_with_bloom(&mut db, &format!("Profile_{}", i), &kmers, bloom)?;
+            }
+
+            let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 10, TaxonomyLevel::Species)?;
+            let start = std::time::Instant::now();
+            let matches = analyzer.analyze_sample(&sample)?;
+            println!("bloom={}: {} matches in {:?}", bloom, matches.len(), start.elapsed());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detailed_analysis_marks_unique_kmers() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("Target", &[("AAA", 0.4), ("CCC", 0.4), ("GGG", 0.2)]),
+            ("Other", &[("AAA", 1.0)]),
+        ])?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAA")?;
+        counter.count_sequence(b"CCC")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let analysis = analyzer.get_detailed_analysis(&counter, &counter.get_counts(), "Target")?.unwrap();
+
+        let unique: Vec<&str> = analysis.shared_kmers.iter()
+            .filter(|kmer| kmer.is_unique)
+            .map(|kmer| kmer.sequence.as_str())
+            .collect();
+        assert_eq!(unique, vec!["CCC"]);
+        assert_eq!(analysis.statistics.profile_unique_kmers, 2);
+        assert_eq!(analysis.statistics.shared_unique_kmers, 1);
+        assert!((analysis.statistics.uniqueness_score - 0.5).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_confidence_penalizes_size_ratio_symmetrically() {
+        let confidence_score = |coverage, uniqueness, ratio| ConfidenceWeights::default().score(coverage, Some(uniqueness), ratio);
+        assert!((confidence_score(1.0, 1.0, 1.0) - 1.0).abs() < 1e-12);
+        assert!((confidence_score(1.0, 1.0, 0.25) - 0.75).abs() < 1e-12);
+        assert!((confidence_score(1.0, 1.0, 4.0) - 0.75).abs() < 1e-12);
+
+        // Large samples against small profiles used to go negative
+        let confidence = confidence_score(0.0, 0.0, 1000.0);
+        assert!((0.0..=1.0).contains(&confidence));
+        assert_eq!(confidence_score(0.5, 0.5, f64::NAN), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn test_union_size_rejects_inconsistent_counts() {
+        assert_eq!(union_size("A", 10, 20, 5).unwrap(), 25);
+
+        let error = union_size("A", 2, 3, 6).unwrap_err().to_string();
+        assert!(error.contains("profile A"), "{error}");
+        assert!(error.contains("6 shared"), "{error}");
+    }
+
+    #[test]
+    fn test_detailed_analysis_scales_linearly() -> Result<()> {
+        // Pseudo-random sequence whose 12-mers are nearly all distinct
+        let mut state = 42u64;
+        let sequence: Vec<u8> = (0..100_011).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            b"ACGT"[(state >> 62) as usize]
+        }).collect();
+
+        let profile_kmers: HashSet<String> = sequence[..50_011].windows(12)
+            .map(|kmer| String::from_utf8(kmer.to_vec()).unwrap())
+            .collect();
+        let frequency = 1.0 / profile_kmers.len() as f64;
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        let mut profile = Profile::new("Target".to_string(), TaxonomyLevel::Species, 12);
+        profile.frequencies = profile_kmers.iter().map(|kmer| (kmer.clone(), frequency)).collect();
+        profile.total_kmers = profile_kmers.len();
+        db.add_profile(&profile)?;
+
+        let counter = KmerCounter::new(12);
+        counter.count_sequence(&sequence)?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let start = std::time::Instant::now();
+        let analysis = analyzer.get_detailed_analysis(&counter, &counter.get_counts(), "Target")?.unwrap();
+        // The old quadratic membership scan took ~50s here in a debug build; now ~1s
+        assert!(start.elapsed() < std::time::Duration::from_secs(10), "took {:?}", start.elapsed());
+
+        assert_eq!(analysis.shared_kmers.len(), profile_kmers.len());
+        assert!(analysis.unique_to_reference.is_empty());
+        assert_eq!(
+            analysis.shared_kmers.len() + analysis.unique_to_sample.len(),
+            counter.get_counts().len()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmer_size_mismatch_lists_available_sizes() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[("Target", &[("AAA", 1.0)])])?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+
+        let counter = KmerCounter::new(5);
+        counter.count_sequence(b"AAAAA")?;
+        let error = analyzer.analyze_sample(&counter).unwrap_err().to_string();
+        assert!(error.contains("k=5"), "{error}");
+        assert!(error.contains("--kmer-size 3"), "{error}");
+
+        let seed = crate::kmer::SpacedSeed::parse("1101", 3)?;
+        let counter = KmerCounter::new(3).with_seed(Some(seed));
+        counter.count_sequence(b"AAAA")?;
+        let error = analyzer.analyze_sample(&counter).unwrap_err().to_string();
+        assert!(error.contains("seed 1101"), "{error}");
+        assert!(error.contains("none"), "{error}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_alphabet_mismatch_errors() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        let mut profile = Profile::new("Kinase".to_string(), TaxonomyLevel::Species, 3);
+        profile.frequencies.insert("MKV".to_string(), 1.0);
+        profile.total_kmers = 1;
+        profile.alphabet = Alphabet::Protein;
+        db.add_profile(&profile)?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"ACGT")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let error = analyzer.analyze_sample(&counter).unwrap_err().to_string();
+        assert!(error.contains("Kinase"), "{error}");
+
+        let counter = KmerCounter::new(3).with_alphabet(Alphabet::Protein);
+        counter.count_sequence(b"MKVL")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?
+            .with_alphabet(Alphabet::Protein);
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert_eq!(matches.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_alphabets_compare_only_the_samples() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        // ACG is a valid peptide too, so only the alphabet tells the profiles apart
+        for (name, alphabet, kmers) in [
+            ("E_coli", Alphabet::Dna, ["ACG", "CGT"]),
+            ("Kinase", Alphabet::Protein, ["ACG", "MKV"]),
+        ] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 3);
+            for kmer in kmers {
+                profile.frequencies.insert(kmer.to_string(), 0.5);
+            }
+            profile.total_kmers = 2;
+            profile.alphabet = alphabet;
+            db.add_profile(&profile)?;
+        }
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"ACGT")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "E_coli");
+        assert_eq!(analyzer.analyze_markers(&counter)?.len(), 1);
+
+        let index = Arc::new(analyzer.load_index(3)?);
+        assert_eq!(index.profile_count(), 1);
+
+        let protein = KmerCounter::new(3).with_alphabet(Alphabet::Protein);
+        protein.count_sequence(b"MKVL")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?
+            .with_alphabet(Alphabet::Protein);
+        let matches = analyzer.analyze_sample(&protein)?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "Kinase");
+
+        // Naming only profiles of the other alphabet leaves nothing to compare
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?
+            .with_profile_names(Some(vec!["Kinase".to_string()]));
+        let error = analyzer.analyze_sample(&counter).unwrap_err().to_string();
+        assert!(error.contains("Kinase"), "{error}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_confidence_is_a_separate_gate() -> Result<()> {
+        // Sample coverage and confidence: Contained 2/7 and 0.357, Partial 1/7 and 0.143
+        let (_dir, db_path) = setup_database(&[
+            ("Contained", &[("AAC", 0.5), ("ACG", 0.5)]),
+            ("Partial", &[("AAC", 0.5), ("TTT", 0.5)]),
+        ])?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AACGTACGGA")?;
+
+        let names = |min_similarity: f64, min_confidence: f64| -> Result<Vec<String>> {
+            let analyzer = ProfileAnalyzer::new(&db_path, min_similarity, 0, TaxonomyLevel::Species)?
+                .with_min_confidence(min_confidence);
+            Ok(analyzer.analyze_sample(&counter)?.into_iter().map(|m| m.name).collect())
         };
+
+        assert_eq!(names(0.1, 0.0)?, vec!["Contained", "Partial"]);
+        // Partial clears coverage but not confidence
+        assert_eq!(names(0.1, 0.3)?, vec!["Contained"]);
+        // Partial clears confidence but not coverage
+        assert_eq!(names(0.2, 0.1)?, vec!["Contained"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_profile_versions() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[("Current", &[("AAC", 1.0)]), ("Legacy", &[("ACG", 1.0)])])?;
+        ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?.check_profile_versions(true)?;
+
+        let conn = Connection::open(&db_path)?;
+        conn.execute("UPDATE profiles SET created_by_version = NULL WHERE name = 'Legacy'", [])?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        analyzer.check_profile_versions(false)?;
+        let err = analyzer.check_profile_versions(true).unwrap_err().to_string();
+        assert!(err.contains("1 profile(s) predate version tracking"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_analysis_without_uniqueness() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("Contained", &[("AAC", 0.5), ("ACG", 0.5)]),
+            ("Partial", &[("AAC", 0.5), ("TTT", 0.5)]),
+        ])?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AACGTACGGA")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let full = analyzer.analyze_sample(&counter)?;
+        let fast = analyzer.with_uniqueness(false).analyze_sample(&counter)?;
+        assert_eq!(full.len(), fast.len());
+        for (full, fast) in full.iter().zip(&fast) {
+            assert_eq!(full.name, fast.name);
+            assert!(full.uniqueness_score.is_some());
+            assert_eq!(fast.uniqueness_score, None);
+            assert_eq!(fast.unique_matches, None);
+            let expected = ConfidenceWeights::default().score(full.sample_coverage, None, full.size_ratio);
+            assert!((fast.confidence_score - expected).abs() < 1e-12);
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_profiles_at_other_kmer_sizes_are_skipped() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[("Ecoli", &[("AAC", 0.5), ("ACG", 0.5)])])?;
+        let mut db = Database::new(&db_path)?;
+        let mut profile = Profile::new("Ecoli".to_string(), TaxonomyLevel::Species, 4);
+        profile.frequencies.insert("AACG".to_string(), 1.0);
+        profile.total_kmers = 100;
+        db.add_profile(&profile)?;
+        drop(db);
+
+        for k in [3, 4] {
+            let counter = KmerCounter::new(k);
+            counter.count_sequence(b"AACGTACGGA")?;
+            let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+            let matches = analyzer.analyze_sample(&counter)?;
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].name, "Ecoli");
+            assert_eq!(matches[0].shared_kmers, if k == 3 { 2 } else { 1 });
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_counts_built_once_per_analysis() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        for (name, level, parent) in [
+            ("Genus_X", TaxonomyLevel::Genus, None),
+            ("Species_X", TaxonomyLevel::Species, Some("Genus_X")),
+            ("Species_Y", TaxonomyLevel::Species, Some("Genus_X")),
+            ("Strain_1", TaxonomyLevel::Strain, Some("Species_X")),
+            ("Strain_2", TaxonomyLevel::Strain, Some("Species_X")),
+        ] {
+            let mut profile = Profile::new(name.to_string(), level, 3);
+            profile.frequencies.insert("AAA".to_string(), 0.5);
+            profile.frequencies.insert("CCC".to_string(), 0.5);
+            profile.total_kmers = 2;
+            profile.parent = parent.map(str::to_string);
+            db.add_profile(&profile)?;
+        }
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAACCC")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+
+        assert_eq!(analyzer.analyze_sample(&counter)?.len(), 2);
+        assert_eq!(counter.counts_built(), 1);
+
+        // Every level and profile reads the same counts
+        analyzer.classify_hierarchical(&counter, 0.0)?;
+        assert_eq!(counter.counts_built(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_profile_matches_either_strand() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        let mut profile = Profile::new("Target".to_string(), TaxonomyLevel::Species, 4);
+        for kmer in ["AACG", "ACGG", "CGGT"] {
+            profile.frequencies.insert(kmer.to_string(), 1.0 / 3.0);
+        }
+        profile.total_kmers = 3;
+        db.add_profile(&profile)?;
+
+        // The reverse complement of AACGGT shares no k-mer with it as read
+        let counter = KmerCounter::new(4);
+        counter.count_sequence(b"ACCGTT")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.5, 0, TaxonomyLevel::Species)?;
+        assert!(analyzer.analyze_sample(&counter)?.is_empty());
+
+        db.canonicalize_profile("Target", None)?;
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].shared_kmers, 3);
+        let analysis = analyzer.get_detailed_analysis(&counter, &counter.get_counts(), "Target")?.unwrap();
+        assert_eq!(analysis.shared_kmers.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_mismatch_is_flagged() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("Balanced", &[("AAA", 0.5), ("CCC", 0.5)]),
+        ])?;
+
+        // Two thirds of the sample's k-mers are all-GC
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAA")?;
+        counter.count_sequence(b"CCC")?;
+        counter.count_sequence(b"CCC")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert!((matches[0].sample_gc.unwrap() - 2.0 / 3.0).abs() < 1e-9);
+        assert!((matches[0].profile_gc.unwrap() - 0.5).abs() < 1e-9);
+        assert!(matches[0].gc_mismatch);
+
+        let matches = analyzer.with_max_gc_difference(0.2).analyze_sample(&counter)?;
+        assert!(!matches[0].gc_mismatch);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_names_limit_comparison() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("First", &[("AAA", 0.5), ("CCC", 0.5)]),
+            ("Second", &[("AAA", 0.5), ("GGG", 0.5)]),
+            ("Third", &[("TTT", 1.0)]),
+        ])?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAA")?;
+        counter.count_sequence(b"CCC")?;
+        counter.count_sequence(b"GGG")?;
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 1, TaxonomyLevel::Species)?;
+        assert_eq!(analyzer.analyze_sample(&counter)?.len(), 2);
+
+        let analyzer = analyzer.with_profile_names(Some(vec!["Second".to_string(), "Third".to_string()]));
+        analyzer.check_profile_names()?;
+        let names: Vec<String> = analyzer.analyze_sample(&counter)?.into_iter().map(|m| m.name).collect();
+        assert_eq!(names, vec!["Second".to_string()]);
+
+        let err = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Genus)?
+            .with_profile_names(Some(vec!["First".to_string()]))
+            .check_profile_names()
+            .unwrap_err();
+        assert!(err.to_string().contains("First"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_core_kmers_split_shared_from_discriminating() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("Profile_A", &[("AAC", 0.4), ("ACG", 0.3), ("TTT", 0.3)]),
+            ("Profile_B", &[("AAC", 0.4), ("CGT", 0.3), ("ACG", 0.3)]),
+        ])?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AACGT")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert_eq!(matches.len(), 2);
+
+        let core = analyzer.core_kmers(&counter, &counter.get_counts(), &matches)?;
+        assert_eq!(core.core, vec!["AAC", "ACG"]);
+        let discriminating: HashMap<_, _> = core.discriminating.into_iter().collect();
+        assert!(discriminating["Profile_A"].is_empty());
+        assert_eq!(discriminating["Profile_B"], vec!["CGT"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_index_matches_database_comparison() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("Profile_A", &[("AAC", 0.4), ("ACG", 0.3), ("TTT", 0.3)]),
+            ("Profile_B", &[("AAC", 0.4), ("CGT", 0.3), ("GGG", 0.3)]),
+            ("Profile_C", &[("CCC", 1.0)]),
+        ])?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AACGTTT")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 1, TaxonomyLevel::Species)?;
+        let expected = analyzer.analyze_sample(&counter)?;
+
+        let index = Arc::new(analyzer.load_index(3)?);
+        assert_eq!(index.profile_count(), 3);
+        let indexed = ProfileAnalyzer::new(&db_path, 0.0, 1, TaxonomyLevel::Species)?.with_index(index.clone());
+        let matches = indexed.analyze_sample(&counter)?;
+
+        assert_eq!(matches.len(), 2);
+        for (m, e) in matches.iter().zip(&expected) {
+            assert_eq!((&m.name, m.shared_kmers, m.unique_matches), (&e.name, e.shared_kmers, e.unique_matches));
+            assert!((m.confidence_score - e.confidence_score).abs() < 1e-9);
+            assert!((m.cosine_similarity - e.cosine_similarity).abs() < 1e-9);
+            assert!((m.profile_gc.unwrap() - e.profile_gc.unwrap()).abs() < 1e-9);
+        }
+
+        // The index only serves samples of its own k-mer size
+        let other_k = KmerCounter::new(4);
+        other_k.count_sequence(b"AACGTTT")?;
+        assert!(indexed.analyze_sample(&other_k).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollup_pools_strains_under_species() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        for (name, level, kmers) in [
+            ("Escherichia", TaxonomyLevel::Genus, &["GGG"][..]),
+            ("E_coli", TaxonomyLevel::Species, &["GGG"][..]),
+            ("Strain_1", TaxonomyLevel::Strain, &["AAC", "ACG"][..]),
+            ("Strain_2", TaxonomyLevel::Strain, &["ACG", "CGT"][..]),
+        ] {
+            let mut profile = Profile::new(name.to_string(), level, 3);
+            for kmer in kmers {
+                profile.frequencies.insert(kmer.to_string(), 1.0 / kmers.len() as f64);
+            }
+            profile.total_kmers = kmers.len();
+            db.add_profile(&profile)?;
+        }
+        db.link_profiles("Strain_1", "E_coli")?;
+        db.link_profiles("Strain_2", "E_coli")?;
+        db.link_profiles("E_coli", "Escherichia")?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AACGTTT")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 1, TaxonomyLevel::Strain)?;
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert_eq!(matches.len(), 2);
+
+        let rollups = analyzer.rollup_lineages(&counter, &matches)?;
+        let names: Vec<_> = rollups.iter().map(|rollup| rollup.name.as_str()).collect();
+        assert_eq!(names, vec!["Escherichia", "E_coli"]);
+        // AAC, ACG and CGT of the sample's five k-mers, with ACG counted once
+        assert_eq!(rollups[1].shared_kmers, 3);
+        assert!((rollups[1].sample_coverage - 0.6).abs() < 1e-9);
+        assert_eq!(rollups[1].level, Some(TaxonomyLevel::Species));
+        assert_eq!(rollups[1].matches.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_shorter_than_k_has_no_matches() -> Result<()> {
+        use crate::io::FastxReader;
+
+        let (dir, db_path) = setup_database(&[("Profile_A", &[("AAC", 0.5), ("ACG", 0.5)])])?;
+        let sample_path = dir.path().join("short.fasta");
+        std::fs::write(&sample_path, ">read1\nAC\n>read2\nG\n")?;
+
+        let counter = KmerCounter::new(3);
+        counter.count_from_reader(&FastxReader::new(vec![sample_path]))?;
+        assert_eq!(counter.unique_kmers(), 0);
+
+        // Nothing to score against, rather than NaN coverage at a zero threshold
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        assert!(analyzer.analyze_sample(&counter)?.is_empty());
+        assert!(analyzer.analyze_markers(&counter)?.is_empty());
+        let (classification, matches) = analyzer.classify_hierarchical(&counter, 0.0)?;
+        assert!(matches.is_empty());
+        assert_eq!(classification.name, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bootstrap_is_seeded_and_brackets_the_estimate() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("A", &[("AAC", 0.25), ("ACC", 0.25), ("CCG", 0.25), ("CGG", 0.25)]),
+            ("B", &[("GGT", 0.5), ("GTT", 0.5)]),
+        ])?;
+        let counter = KmerCounter::new(3);
+        for sequence in ["AACCGG", "AACC", "CCGG", "GGTT", "TTTT"] {
+            counter.count_sequence(sequence.as_bytes())?;
+        }
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let mut matches = analyzer.analyze_sample(&counter)?;
+        analyzer.bootstrap_matches(&counter, &mut matches, 200, 7)?;
+        let mut again = matches.clone();
+        analyzer.bootstrap_matches(&counter, &mut again, 200, 7)?;
+
+        for (m, repeat) in matches.iter().zip(&again) {
+            let bootstrap = m.bootstrap.as_ref().unwrap();
+            assert_eq!(bootstrap.replicates, 200);
+            assert_eq!(bootstrap.confidence_score, repeat.bootstrap.as_ref().unwrap().confidence_score);
+            let coverage = bootstrap.sample_coverage;
+            assert!(coverage.low <= m.sample_coverage && m.sample_coverage <= coverage.high);
+            assert!(coverage.low <= coverage.mean && coverage.mean <= coverage.high);
+            // Resampling varies which k-mers are present
+            assert!(coverage.low < coverage.high);
+        }
+
+        analyzer.bootstrap_matches(&counter, &mut again, 200, 8)?;
+        assert!(matches.iter().zip(&again).any(|(m, other)| {
+            m.bootstrap.as_ref().unwrap().sample_coverage != other.bootstrap.as_ref().unwrap().sample_coverage
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_presence_profile_ignores_sample_repeats() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        for (name, normalization) in [("Occurrence", Normalization::Occurrence), ("Presence", Normalization::Presence)] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 3);
+            profile.frequencies.insert("AAA".to_string(), 0.5);
+            profile.frequencies.insert("CCC".to_string(), 0.5);
+            profile.total_kmers = 2;
+            profile.normalization = normalization;
+            db.add_profile(&profile)?;
+        }
+
+        // AAA x8, CCC x1
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAAAAAAAAA")?;
+        counter.count_sequence(b"CCC")?;
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?
+            .with_metric(SimilarityMetric::Cosine);
+        let matches = analyzer.analyze_sample(&counter)?;
+
+        let cosine = |name: &str| matches.iter().find(|m| m.name == name).unwrap().cosine_similarity;
+        assert!((cosine("Presence") - 1.0).abs() < 1e-9);
+        let expected = (8.0 + 1.0) / (65.0f64.sqrt() * 2.0f64.sqrt());
+        assert!((cosine("Occurrence") - expected).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_database_shared_with_analyzer() -> Result<()> {
+        assert!(ProfileAnalyzer::new(":memory:", 0.0, 0, TaxonomyLevel::Species).is_err());
+
+        let mut db = Database::new(":memory:")?;
+        let mut profile = Profile::new("Target".to_string(), TaxonomyLevel::Species, 3);
+        profile.frequencies.insert("ACG".to_string(), 0.5);
+        profile.frequencies.insert("CGT".to_string(), 0.5);
+        profile.total_kmers = 2;
+        db.add_profile(&profile)?;
+
+        let analyzer = ProfileAnalyzer::from_connection(db.into_connection(), 0.5, 0, TaxonomyLevel::Species)?;
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"ACGT")?;
+        let matches = analyzer.analyze_sample(&counter)?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "Target");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmer_specificity_bins_by_profile_count() -> Result<()> {
+        let (_dir, db_path) = setup_database(&[
+            ("A", &[("AAA", 0.5), ("AAC", 0.5)]),
+            ("B", &[("AAA", 0.5), ("CCC", 0.5)]),
+            ("C", &[("AAA", 0.5), ("CCC", 0.5)]),
+        ])?;
+
+        // AAA in three profiles, AAC in one, CCC in two; GGG in none
+        let counter = KmerCounter::new(3);
+        for sequence in [&b"AAAA"[..], b"AAC", b"CCC", b"GGG"] {
+            counter.count_sequence(sequence)?;
+        }
+        let expected = KmerSpecificity { bins: vec![(1, 1), (2, 1), (3, 1)] };
+
+        let mut analyzer = ProfileAnalyzer::new(&db_path, 0.0, 0, TaxonomyLevel::Species)?;
+        let specificity = analyzer.kmer_specificity(&counter, &counter.get_counts())?;
+        assert_eq!(specificity, expected);
+        assert_eq!(specificity.shared_kmers(), 3);
+
+        let index = Arc::new(analyzer.load_index(3)?);
+        analyzer = analyzer.with_index(index);
+        assert_eq!(analyzer.kmer_specificity(&counter, &counter.get_counts())?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_weighted_shared_kmers_follow_abundance() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        // Both share AAA and CCC with the sample, but Diluted puts almost all
+        // its weight on a k-mer the sample lacks
+        for (name, frequencies) in [
+            ("Skewed", vec![("AAA", 0.9), ("CCC", 0.1)]),
+            ("Diluted", vec![("AAA", 0.05), ("CCC", 0.05), ("ACG", 0.9)]),
+        ] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 3);
+            for (kmer, frequency) in frequencies {
+                profile.frequencies.insert(kmer.to_string(), frequency);
+            }
+            profile.total_kmers = profile.frequencies.len();
+            db.add_profile(&profile)?;
+        }
+
+        // AAA x8, CCC x1
+        let counter = KmerCounter::new(3);
+        counter.count_sequence(b"AAAAAAAAAA")?;
+        counter.count_sequence(b"CCC")?;
+
+        let unweighted = ProfileAnalyzer::new(&db_path, 0.0, 1, TaxonomyLevel::Species)?.analyze_sample(&counter)?;
+        assert_eq!(unweighted.len(), 2);
+        assert!(unweighted.iter().all(|m| m.weighted_shared_kmers.is_none()));
+
+        let analyzer = ProfileAnalyzer::new(&db_path, 0.0, 1, TaxonomyLevel::Species)?.with_weighted_shared(true);
+        let index = Arc::new(analyzer.load_index(3)?);
+        let indexed = ProfileAnalyzer::new(&db_path, 0.0, 1, TaxonomyLevel::Species)?
+            .with_weighted_shared(true)
+            .with_index(index);
+        for matches in [analyzer.analyze_sample(&counter)?, indexed.analyze_sample(&counter)?] {
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].name, "Skewed");
+            assert_eq!(matches[0].shared_kmers, 2);
+            let expected = (8.0 / 9.0 + 0.1) * 2.0;
+            assert!((matches[0].weighted_shared_kmers.unwrap() - expected).abs() < 1e-9);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cross_validate_finds_duplicate_profiles() -> Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("test.db");
+        let mut db = Database::new(&db_path)?;
+        for (name, kmers) in [
+            ("Original", vec!["AAC", "ACG", "CGT"]),
+            ("Relabeled", vec!["AAC", "ACG", "CGT"]),
+            ("Distant", vec!["ACG", "GGA", "GAT", "ATT"]),
+        ] {
+            let mut profile = Profile::new(name.to_string(), TaxonomyLevel::Species, 3);
+            for kmer in &kmers {
+                profile.frequencies.insert(kmer.to_string(), 1.0 / kmers.len() as f64);
+            }
+            profile.total_kmers = kmers.len();
+            db.add_profile(&profile)?;
+        }
+
+        let profile = db.get_profile("Original", None)?.unwrap();
+        let matches = ProfileAnalyzer::new(&db_path, 0.0, 1, TaxonomyLevel::Species)?.cross_validate(&profile)?;
+
+        let names: Vec<&str> = matches.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["Relabeled", "Distant"]);
+        assert_eq!(matches[0].shared_kmers, 3);
+        assert!((matches[0].sample_coverage - 1.0).abs() < 1e-9);
+        assert!((matches[1].sample_coverage - 1.0 / 3.0).abs() < 1e-9);
+
+        Ok(())
+    }
+    #[test]
+    fn test_binomial_draws_follow_the_distribution() {
+        let mut state = 0u64;
+        let mut next_unit = || {
+            state = splitmix64(state);
+            unit_interval(state)
+        };
+        // Inversion, rejection and the p > 0.5 mirror of each
+        for (trials, p) in [(20, 0.1), (1_000_000_000, 1e-9), (500, 0.3), (100_000_000, 0.02), (200, 0.9)] {
+            let draws: Vec<f64> = (0..4000).map(|_| binomial(trials, p, &mut next_unit) as f64).collect();
+            assert!(draws.iter().all(|&draw| draw <= trials as f64));
+            let mean = draws.iter().sum::<f64>() / draws.len() as f64;
+            let variance = draws.iter().map(|draw| (draw - mean).powi(2)).sum::<f64>() / draws.len() as f64;
+            let expected = trials as f64 * p;
+            let expected_variance = expected * (1.0 - p);
+            assert!((mean - expected).abs() < 4.0 * (expected_variance / draws.len() as f64).sqrt(), "{} {}", trials, p);
+            assert!((variance / expected_variance - 1.0).abs() < 0.15, "{} {}: {}", trials, p, variance);
+        }
+        assert_eq!(binomial(7, 1.0, &mut next_unit), 7);
+        assert_eq!(binomial(0, 0.5, &mut next_unit), 0);
+        assert!((ln_factorial(12.0) - 479_001_600f64.ln()).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use anyhow::Result;
+use log::info;
+use rusqlite::{params, Connection};
+use crate::kmer::{gc_fraction, Alphabet};
+
+/// What a sample shares with one profile, and the profile's own figures
+/// needed to score the comparison
+#[derive(Debug, Clone, Default)]
+pub(crate) struct KmerOverlap {
+    pub shared_kmers: usize,
+    /// Shared k-mers no other profile holds
+    pub unique_matches: usize,
+    /// Dot product of the sample's and the profile's frequency vectors
+    pub dot_product: f64,
+    /// Sum over shared k-mers of the smaller of the sample's and the profile's frequency
+    pub shared_weight: f64,
+    /// Squared norm of the profile's frequency vector
+    pub profile_norm: f64,
+    /// Distinct k-mers in the profile
+    pub profile_size: usize,
+    /// Frequency-weighted GC of the profile's k-mers, over `profile_total`
+    pub profile_gc: f64,
+    pub profile_total: f64,
+}
+
+/// A k-mer's postings: the profiles holding it with its frequency in each,
+/// and how many profiles of the database hold it
+#[derive(Debug)]
+struct IndexedKmer {
+    profile_count: i64,
+    postings: Vec<(i64, f64)>,
+}
+
+/// Every k-mer of the profiles at one k-mer size, held in memory so that each
+/// sample is compared with all of them in one pass over its k-mers rather
+/// than by reading every profile's rows from the database. Shared between
+/// analyzers of the same database; see [`ProfileAnalyzer::load_index`].
+///
+/// [`ProfileAnalyzer::load_index`]: super::ProfileAnalyzer::load_index
+#[derive(Debug)]
+pub struct ProfileIndex {
+    k: usize,
+    kmers: HashMap<String, IndexedKmer>,
+    /// Each profile's overlap with an empty sample
+    profiles: HashMap<i64, KmerOverlap>,
+}
+
+impl ProfileIndex {
+    pub(crate) fn load(conn: &Connection, k: usize, alphabet: Alphabet) -> Result<Self> {
+        let mut stmt = conn.prepare(
+            "SELECT k.profile_id, k.kmer, k.frequency, COALESCE(o.profile_count, 1)
+             FROM kmers k
+             JOIN profiles p ON p.id = k.profile_id
+             LEFT JOIN kmer_occurrence o ON o.kmer = k.kmer
+             WHERE p.k = ?1 AND COALESCE(p.alphabet, 'dna') = ?2"
+        )?;
+
+        let mut kmers: HashMap<String, IndexedKmer> = HashMap::new();
+        let mut profiles: HashMap<i64, KmerOverlap> = HashMap::new();
+        for row in stmt.query_map(params![k, alphabet.to_string()], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })? {
+            let (profile_id, kmer, frequency, profile_count) = row?;
+            let profile = profiles.entry(profile_id).or_default();
+            profile.profile_norm += frequency * frequency;
+            profile.profile_size += 1;
+            profile.profile_gc += gc_fraction(kmer.as_bytes()) * frequency;
+            profile.profile_total += frequency;
+            kmers.entry(kmer)
+                .or_insert_with(|| IndexedKmer { profile_count, postings: Vec::new() })
+                .postings
+                .push((profile_id, frequency));
+        }
+
+        info!("Loaded {} distinct k-mers of {} k={} profiles into memory", kmers.len(), profiles.len(), k);
+        Ok(ProfileIndex { k, kmers, profiles })
+    }
+
+    /// K-mer size of the indexed profiles
+    pub fn kmer_size(&self) -> usize {
+        self.k
+    }
+
+    /// Number of profiles in the index
+    pub fn profile_count(&self) -> usize {
+        self.profiles.len()
+    }
+
+    /// Number of profiles of the database holding `kmer`, if any indexed profile does
+    pub(crate) fn kmer_profile_count(&self, kmer: &str) -> Option<usize> {
+        self.kmers.get(kmer).map(|indexed| indexed.profile_count as usize)
+    }
+
+    /// The sample's overlap with every indexed profile, from one pass over its
+    /// k-mers. `uniqueness` counts shared k-mers held by no other profile.
+    pub(crate) fn overlaps(
+        &self,
+        sample_kmers: &HashMap<String, usize>,
+        total_sample_kmers: usize,
+        uniqueness: bool,
+    ) -> HashMap<i64, KmerOverlap> {
+        let mut overlaps = self.profiles.clone();
+        for (kmer, &sample_count) in sample_kmers {
+            let Some(indexed) = self.kmers.get(kmer) else { continue };
+            let sample_freq = sample_count as f64 / total_sample_kmers as f64;
+            for &(profile_id, profile_freq) in &indexed.postings {
+                let overlap = overlaps.get_mut(&profile_id).expect("posting of an indexed profile");
+                overlap.shared_kmers += 1;
+                overlap.dot_product += sample_freq * profile_freq;
+                overlap.shared_weight += sample_freq.min(profile_freq);
+                if uniqueness && indexed.profile_count <= 1 {
+                    overlap.unique_matches += 1;
+                }
+            }
+        }
+        overlaps
+    }
+}
@@ -1,5 +1,10 @@
 pub(crate) mod types;
 pub(crate) mod analyzer;
+pub(crate) mod index;
 
-pub use types::{Profile, ProfileMatch, TaxonomyLevel};
-pub use analyzer::ProfileAnalyzer;
\ No newline at end of file
+pub use types::{
+    Bootstrap, BootstrapInterval, Classification, ConfidenceWeights, DistanceMetric, LineageRollup, Normalization, Profile, ProfileMatch, SimilarityMetric,
+    TaxonomyLevel, TOOL_VERSION,
+};
+pub use analyzer::{AnalysisStatistics, CoreKmers, DetailedAnalysis, KmerSpecificity, ProfileAnalyzer, SharedKmer};
+pub use index::ProfileIndex;
\ No newline at end of file
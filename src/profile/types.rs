@@ -1,6 +1,8 @@
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 
+use crate::kmer::{gc_fraction, Alphabet, BloomFilter, MinHashSketch};
+
 /// Represents the taxonomic level for a profile
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum TaxonomyLevel {
@@ -9,6 +11,26 @@ pub enum TaxonomyLevel {
     Strain,
 }
 
+impl TaxonomyLevel {
+    /// Kraken-style rank code
+    pub fn rank_code(&self) -> &'static str {
+        match self {
+            TaxonomyLevel::Genus => "G",
+            TaxonomyLevel::Species => "S",
+            TaxonomyLevel::Strain => "S1",
+        }
+    }
+
+    /// The next level up, or None for the broadest level
+    pub fn parent_level(&self) -> Option<TaxonomyLevel> {
+        match self {
+            TaxonomyLevel::Strain => Some(TaxonomyLevel::Species),
+            TaxonomyLevel::Species => Some(TaxonomyLevel::Genus),
+            TaxonomyLevel::Genus => None,
+        }
+    }
+}
+
 impl std::fmt::Display for TaxonomyLevel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -19,21 +41,248 @@ impl std::fmt::Display for TaxonomyLevel {
     }
 }
 
+/// How a profile's k-mer counts were turned into frequencies
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Normalization {
+    /// A k-mer's count over the total count of all k-mers
+    #[default]
+    Occurrence,
+    /// Every distinct k-mer weighted equally, at 1 over their number
+    Presence,
+}
+
+impl Normalization {
+    /// Parse the name stored in the database (`occurrence` or `presence`)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "occurrence" => Some(Normalization::Occurrence),
+            "presence" => Some(Normalization::Presence),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Normalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Normalization::Occurrence => write!(f, "occurrence"),
+            Normalization::Presence => write!(f, "presence"),
+        }
+    }
+}
+
+/// Similarity score used to threshold and rank matches
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SimilarityMetric {
+    /// Fraction of sample k-mers found in the profile
+    Coverage,
+    /// Cosine similarity of sample and profile k-mer frequencies
+    Cosine,
+}
+
+impl std::fmt::Display for SimilarityMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimilarityMetric::Coverage => write!(f, "Coverage"),
+            SimilarityMetric::Cosine => write!(f, "Cosine"),
+        }
+    }
+}
+
+/// Relative weights of coverage, uniqueness and size agreement in the
+/// confidence score; they are normalized to sum to 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfidenceWeights {
+    coverage: f64,
+    uniqueness: f64,
+    size: f64,
+}
+
+impl ConfidenceWeights {
+    /// Weights must be finite, non-negative and not all zero
+    pub fn new(coverage: f64, uniqueness: f64, size: f64) -> anyhow::Result<Self> {
+        let weights = [coverage, uniqueness, size];
+        if weights.iter().any(|weight| !weight.is_finite() || *weight < 0.0) {
+            anyhow::bail!(
+                "Confidence weights must be non-negative, got coverage={}, uniqueness={}, size={}",
+                coverage, uniqueness, size
+            );
+        }
+        if weights.iter().all(|&weight| weight == 0.0) {
+            anyhow::bail!("At least one confidence weight must be positive");
+        }
+        Ok(ConfidenceWeights { coverage, uniqueness, size })
+    }
+
+    /// Weighted mean of coverage, uniqueness and size agreement, in [0, 1]. Size
+    /// agreement is `min(ratio, 1/ratio)`, so a sample half or twice the
+    /// profile's size scores the same. Uniqueness is left out of the mean when
+    /// it was not computed.
+    pub fn score(&self, sample_coverage: f64, uniqueness_score: Option<f64>, size_ratio: f64) -> f64 {
+        let size_agreement = if size_ratio.is_finite() && size_ratio > 0.0 {
+            size_ratio.min(size_ratio.recip())
+        } else {
+            0.0
+        };
+        let uniqueness = if uniqueness_score.is_some() { self.uniqueness } else { 0.0 };
+
+        let total = self.coverage + uniqueness + self.size;
+        if total == 0.0 {
+            return 0.0;
+        }
+        (self.coverage * sample_coverage + uniqueness * uniqueness_score.unwrap_or(0.0) + self.size * size_agreement)
+            / total
+    }
+
+    /// Whether uniqueness carries all the weight, leaving nothing to score
+    /// when it is not computed
+    pub fn only_uniqueness(&self) -> bool {
+        self.coverage == 0.0 && self.size == 0.0
+    }
+}
+
+impl Default for ConfidenceWeights {
+    /// Equal weights, the score's original definition
+    fn default() -> Self {
+        ConfidenceWeights { coverage: 1.0, uniqueness: 1.0, size: 1.0 }
+    }
+}
+
+/// Dissimilarity between two profiles' k-mer compositions
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DistanceMetric {
+    /// Sum of absolute frequency differences over the sum of all frequencies
+    BrayCurtis,
+    /// One minus the Jaccard similarity of the k-mer sets
+    Jaccard,
+    /// One minus the cosine similarity of the k-mer frequencies
+    Cosine,
+}
+
 /// Represents a profile match with its similarity metrics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ProfileMatch {
     /// Name of the matched profile
     pub name: String,
     /// Percentage of sample k-mers found in profile
     pub sample_coverage: f64,
+    /// Percentage of profile k-mers found in sample
+    pub profile_coverage: f64,
+    /// Jaccard similarity between sample and profile k-mer sets
+    pub jaccard_similarity: f64,
+    /// Fraction of the smaller k-mer set (sample or profile) that is shared
+    pub containment: f64,
+    /// Cosine similarity between sample and profile k-mer frequencies
+    pub cosine_similarity: f64,
     /// Number of k-mers shared between sample and reference
     pub shared_kmers: usize,
+    /// Shared k-mers weighted by the smaller of their sample and profile
+    /// frequencies (with `--weighted-shared`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weighted_shared_kmers: Option<f64>,
+    /// Number of shared k-mers not found in any other profile; `None` when
+    /// uniqueness was not computed
+    pub unique_matches: Option<usize>,
     /// Ratio of sample size to profile size
     pub size_ratio: f64,
-    /// Score for how unique these matches are to this profile
-    pub uniqueness_score: f64,
+    /// Score for how unique these matches are to this profile; `None` when
+    /// uniqueness was not computed
+    pub uniqueness_score: Option<f64>,
     /// Confidence score for this match
     pub confidence_score: f64,
+    /// Taxonomy level of the matched profile
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<TaxonomyLevel>,
+    /// Estimated fraction of the sample from this profile (with `--abundance`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abundance: Option<f64>,
+    /// Count-weighted GC fraction of the sample's k-mers (DNA only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_gc: Option<f64>,
+    /// Frequency-weighted GC fraction of the profile's k-mers (DNA only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile_gc: Option<f64>,
+    /// Whether the sample and profile GC differ by more than the allowed amount
+    pub gc_mismatch: bool,
+    /// Spread of the scores over resamples of the sample's k-mers (with `--bootstrap`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bootstrap: Option<Bootstrap>,
+}
+
+/// Sample coverage and confidence of a match over bootstrap resamples
+#[derive(Debug, Clone, Serialize)]
+pub struct Bootstrap {
+    pub replicates: usize,
+    pub sample_coverage: BootstrapInterval,
+    pub confidence_score: BootstrapInterval,
+}
+
+/// Mean and central 95% interval of a score over bootstrap replicates
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BootstrapInterval {
+    pub mean: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+impl BootstrapInterval {
+    /// Summarize one score per replicate; the bounds are the 2.5th and 97.5th
+    /// percentiles, by nearest rank
+    pub fn from_replicates(mut values: Vec<f64>) -> Self {
+        if values.is_empty() {
+            return BootstrapInterval { mean: 0.0, low: 0.0, high: 0.0 };
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+        BootstrapInterval {
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            low: percentile(0.025),
+            high: percentile(0.975),
+        }
+    }
+}
+
+/// Result of classifying a sample across taxonomy levels
+#[derive(Debug, Clone, Serialize)]
+pub struct Classification {
+    /// Most specific level with a single confident assignment
+    pub level: Option<TaxonomyLevel>,
+    /// Name of the assigned profile
+    pub name: Option<String>,
+    /// Reasons the assignment was rolled up from more specific levels
+    pub evidence: Vec<String>,
+}
+
+/// Matched profiles pooled under a common ancestor
+#[derive(Debug, Clone, Serialize)]
+pub struct LineageRollup {
+    pub name: String,
+    /// Level of the ancestor, if it has a profile of its own
+    pub level: Option<TaxonomyLevel>,
+    /// Matched profiles descending from it
+    pub matches: Vec<String>,
+    /// Distinct sample k-mers shared with any of those matches
+    pub shared_kmers: usize,
+    /// `shared_kmers` over the sample's distinct k-mers
+    pub sample_coverage: f64,
+}
+
+/// Version of papro-rusty recorded on the profiles it builds
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Whether a profile built by `version` can be compared against samples
+/// counted by this build: same major version, or the same minor while the
+/// major version is 0, following semver
+pub fn is_compatible_version(version: &str) -> bool {
+    let major_minor = |version: &str| -> Option<(u64, u64)> {
+        let mut parts = version.split('.');
+        Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+    };
+    match (major_minor(version), major_minor(TOOL_VERSION)) {
+        (Some((0, minor)), Some((0, current_minor))) => minor == current_minor,
+        (Some((major, _)), Some((current_major, _))) => major == current_major,
+        _ => false,
+    }
 }
 
 /// Represents a k-mer profile
@@ -49,6 +298,41 @@ pub struct Profile {
     pub frequencies: HashMap<String, f64>,
     /// Total number of k-mers
     pub total_kmers: usize,
+    /// Optional MinHash sketch for fast approximate comparison
+    pub sketch: Option<MinHashSketch>,
+    /// Optional Bloom filter over the k-mers for fast overlap bounds
+    pub bloom: Option<BloomFilter>,
+    /// Name of the profile one taxonomy level up (e.g. a strain's species)
+    pub parent: Option<String>,
+    /// Free-text note about the profile
+    pub description: Option<String>,
+    /// Input files the profile was built from
+    pub source_files: Vec<String>,
+    /// Spaced seed pattern used to build the k-mers, if any
+    pub seed: Option<String>,
+    /// Window of consecutive k-mers each stored minimizer was chosen from, if
+    /// only minimizers were kept
+    pub minimizer_window: Option<usize>,
+    /// Minimum count k-mers had to reach, if low-count filtering was applied
+    pub min_count: Option<usize>,
+    /// Minimum Shannon entropy k-mers had to reach, if low-complexity filtering was applied
+    pub min_entropy: Option<f64>,
+    /// Whether homopolymer runs were collapsed before k-mer extraction
+    pub homopolymer_compressed: bool,
+    /// Whether each k-mer is stored once for both strands, as the smaller of
+    /// itself and its reverse complement (see `db canonicalize`)
+    pub canonical: bool,
+    /// Residues the k-mers are drawn from
+    pub alphabet: Alphabet,
+    /// Whether frequencies weight k-mers by count or equally
+    pub normalization: Normalization,
+    /// Sequencing depth at the main peak of the k-mer spectrum (0 if unknown)
+    pub estimated_coverage: f64,
+    /// When the profile was stored, if loaded from a database
+    pub created_at: Option<String>,
+    /// Version of papro-rusty that built the profile; `None` for profiles
+    /// created before versions were recorded
+    pub created_by_version: Option<String>,
 }
 
 impl Profile {
@@ -60,9 +344,96 @@ impl Profile {
             k,
             frequencies: HashMap::new(),
             total_kmers: 0,
+            sketch: None,
+            bloom: None,
+            parent: None,
+            description: None,
+            source_files: Vec::new(),
+            seed: None,
+            minimizer_window: None,
+            min_count: None,
+            min_entropy: None,
+            homopolymer_compressed: false,
+            canonical: false,
+            alphabet: Alphabet::Dna,
+            normalization: Normalization::Occurrence,
+            estimated_coverage: 0.0,
+            created_at: None,
+            created_by_version: None,
         }
     }
 
+    /// K-mers by descending frequency, ties broken by sequence, so output
+    /// does not depend on hash map iteration order
+    pub fn sorted_frequencies(&self) -> Vec<(&String, &f64)> {
+        let mut frequencies: Vec<_> = self.frequencies.iter().collect();
+        frequencies.sort_by(|(kmer_a, a), (kmer_b, b)| b.total_cmp(a).then_with(|| kmer_a.cmp(kmer_b)));
+        frequencies
+    }
+
+    /// Times a k-mer of this frequency was seen, recovered from the total as
+    /// raw counts are not stored. None for presence profiles, whose equal
+    /// frequencies no longer carry the counts.
+    pub fn kmer_count(&self, frequency: f64) -> Option<u64> {
+        (self.normalization == Normalization::Occurrence)
+            .then(|| (frequency * self.total_kmers as f64).round() as u64)
+    }
+
+    /// GC content of the profile's k-mers, weighted by their frequencies
+    pub fn gc_content(&self) -> f64 {
+        let total: f64 = self.frequencies.values().sum();
+        if total == 0.0 {
+            return 0.0;
+        }
+        self.frequencies.iter()
+            .map(|(kmer, freq)| gc_fraction(kmer.as_bytes()) * freq)
+            .sum::<f64>() / total
+    }
+
+    /// Whether k-mers of the two profiles can be compared: same size, seed,
+    /// minimizer window, alphabet, strand handling, homopolymer compression
+    /// and frequency normalization
+    pub fn is_comparable(&self, other: &Profile) -> bool {
+        self.k == other.k && self.seed == other.seed && self.alphabet == other.alphabet
+            && self.canonical == other.canonical && self.minimizer_window == other.minimizer_window
+            && self.homopolymer_compressed == other.homopolymer_compressed
+            && self.normalization == other.normalization
+    }
+
+    /// Dissimilarity to another profile, from 0 (identical) to 1 (disjoint)
+    pub fn distance(&self, other: &Profile, metric: DistanceMetric) -> f64 {
+        let shared = || self.frequencies.iter()
+            .filter_map(|(kmer, freq)| other.frequencies.get(kmer).map(|other_freq| (*freq, *other_freq)));
+
+        match metric {
+            DistanceMetric::BrayCurtis => {
+                let total: f64 = self.frequencies.values().chain(other.frequencies.values()).sum();
+                if total == 0.0 {
+                    return 0.0;
+                }
+                // |a - b| over the union equals a + b everywhere except shared k-mers
+                let shared_overlap: f64 = shared().map(|(a, b)| 2.0 * a.min(b)).sum();
+                (total - shared_overlap) / total
+            }
+            DistanceMetric::Jaccard => {
+                let intersection = shared().count();
+                let union = self.frequencies.len() + other.frequencies.len() - intersection;
+                if union == 0 {
+                    return 0.0;
+                }
+                1.0 - intersection as f64 / union as f64
+            }
+            DistanceMetric::Cosine => {
+                let dot: f64 = shared().map(|(a, b)| a * b).sum();
+                let norm = |profile: &Profile| profile.frequencies.values().map(|f| f * f).sum::<f64>().sqrt();
+                let norms = norm(self) * norm(other);
+                if norms == 0.0 {
+                    return 1.0;
+                }
+                1.0 - dot / norms
+            }
+        }
+    }
 }
 
 impl ProfileMatch {
@@ -77,12 +448,77 @@ impl ProfileMatch {
         ProfileMatch {
             name,
             sample_coverage,
+            profile_coverage: 0.0,
+            jaccard_similarity: 0.0,
+            containment: 0.0,
+            cosine_similarity: 0.0,
             shared_kmers,
+            weighted_shared_kmers: None,
+            unique_matches: Some(0),
             size_ratio,
-            uniqueness_score,
+            uniqueness_score: Some(uniqueness_score),
             confidence_score,
+            level: None,
+            abundance: None,
+            sample_gc: None,
+            profile_gc: None,
+            gc_mismatch: false,
+            bootstrap: None,
         }
     }
+
+    /// Set the profile-side coverage and Jaccard similarity of this match
+    pub fn with_profile_overlap(mut self, profile_coverage: f64, jaccard_similarity: f64) -> Self {
+        self.profile_coverage = profile_coverage;
+        self.jaccard_similarity = jaccard_similarity;
+        self
+    }
+
+    /// Set the containment index of this match
+    pub fn with_containment(mut self, containment: f64) -> Self {
+        self.containment = containment;
+        self
+    }
+
+    /// Set the frequency-weighted cosine similarity of this match
+    pub fn with_cosine_similarity(mut self, cosine_similarity: f64) -> Self {
+        self.cosine_similarity = cosine_similarity;
+        self
+    }
+
+    /// Set the abundance-weighted shared k-mer count of this match
+    pub fn with_weighted_shared_kmers(mut self, weighted_shared_kmers: f64) -> Self {
+        self.weighted_shared_kmers = Some(weighted_shared_kmers);
+        self
+    }
+
+    /// Set the number of shared k-mers unique to this profile
+    pub fn with_unique_matches(mut self, unique_matches: usize) -> Self {
+        self.unique_matches = Some(unique_matches);
+        self
+    }
+
+    /// Mark this match's uniqueness as not computed
+    pub fn without_uniqueness(mut self) -> Self {
+        self.unique_matches = None;
+        self.uniqueness_score = None;
+        self
+    }
+
+    /// Set the GC fractions of the sample and profile, flagging the match when
+    /// they differ by more than `max_difference`
+    pub fn with_gc(mut self, sample_gc: f64, profile_gc: f64, max_difference: f64) -> Self {
+        self.sample_gc = Some(sample_gc);
+        self.profile_gc = Some(profile_gc);
+        self.gc_mismatch = (sample_gc - profile_gc).abs() > max_difference;
+        self
+    }
+
+    /// Set the taxonomy level of the matched profile
+    pub fn with_level(mut self, level: TaxonomyLevel) -> Self {
+        self.level = Some(level);
+        self
+    }
  }
 
 #[cfg(test)]
@@ -118,14 +554,116 @@ mod tests {
     assert_eq!(match_result.sample_coverage, 0.95);
     assert_eq!(match_result.shared_kmers, 1000);
     assert!((match_result.size_ratio - 0.85).abs() < f64::EPSILON);
-    assert!((match_result.uniqueness_score - 0.75).abs() < f64::EPSILON);
+    assert_eq!(match_result.uniqueness_score, Some(0.75));
     assert!((match_result.confidence_score - 0.82).abs() < f64::EPSILON);
     }
 
+    #[test]
+    fn test_rank_codes() {
+        assert_eq!(TaxonomyLevel::Genus.rank_code(), "G");
+        assert_eq!(TaxonomyLevel::Species.rank_code(), "S");
+        assert_eq!(TaxonomyLevel::Strain.rank_code(), "S1");
+    }
+
     #[test]
     fn test_taxonomy_level_display() {
         assert_eq!(TaxonomyLevel::Genus.to_string(), "Genus");
         assert_eq!(TaxonomyLevel::Species.to_string(), "Species");
         assert_eq!(TaxonomyLevel::Strain.to_string(), "Strain");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_profile_match_overlap_metrics() {
+        let match_result = ProfileMatch::new("Test Match".to_string(), 0.5, 10, 1.0, 0.2, 0.6)
+            .with_profile_overlap(0.25, 0.2)
+            .with_unique_matches(2);
+
+        assert!((match_result.profile_coverage - 0.25).abs() < f64::EPSILON);
+        assert!((match_result.jaccard_similarity - 0.2).abs() < f64::EPSILON);
+        assert_eq!(match_result.unique_matches, Some(2));
+    }
+
+    #[test]
+    fn test_profile_distances() {
+        let mut a = Profile::new("A".to_string(), TaxonomyLevel::Species, 4);
+        a.frequencies.insert("AAAA".to_string(), 0.5);
+        a.frequencies.insert("CCCC".to_string(), 0.5);
+        let mut b = Profile::new("B".to_string(), TaxonomyLevel::Species, 4);
+        b.frequencies.insert("AAAA".to_string(), 0.25);
+        b.frequencies.insert("GGGG".to_string(), 0.75);
+
+        // (|0.5 - 0.25| + 0.5 + 0.75) / 2.0
+        assert!((a.distance(&b, DistanceMetric::BrayCurtis) - 0.75).abs() < 1e-12);
+        assert!((a.distance(&b, DistanceMetric::Jaccard) - 2.0 / 3.0).abs() < 1e-12);
+        let cosine = 0.125 / (0.5f64.sqrt() * 0.625f64.sqrt());
+        assert!((a.distance(&b, DistanceMetric::Cosine) - (1.0 - cosine)).abs() < 1e-12);
+
+        for metric in [DistanceMetric::BrayCurtis, DistanceMetric::Jaccard, DistanceMetric::Cosine] {
+            assert!(a.distance(&a, metric).abs() < 1e-12);
+        }
+
+        assert!(a.is_comparable(&b));
+        b.homopolymer_compressed = true;
+        assert!(!a.is_comparable(&b));
+    }
+
+    #[test]
+    fn test_gc_content_is_frequency_weighted() {
+        let mut profile = Profile::new("GC".to_string(), TaxonomyLevel::Species, 4);
+        profile.frequencies.insert("GGCC".to_string(), 0.25);
+        profile.frequencies.insert("AATT".to_string(), 0.75);
+        assert!((profile.gc_content() - 0.25).abs() < 1e-12);
+
+        assert_eq!(Profile::new("Empty".to_string(), TaxonomyLevel::Species, 4).gc_content(), 0.0);
+    }
+
+    #[test]
+    fn test_kmer_count_only_for_occurrence_profiles() {
+        let mut profile = Profile::new("Counts".to_string(), TaxonomyLevel::Species, 4);
+        profile.total_kmers = 8;
+        assert_eq!(profile.kmer_count(0.375), Some(3));
+
+        profile.normalization = Normalization::Presence;
+        assert_eq!(profile.kmer_count(0.5), None);
+    }
+
+    #[test]
+    fn test_confidence_weights() {
+        // coverage 0.9, uniqueness 0.3, size agreement 0.5
+        let score = |weights: ConfidenceWeights| weights.score(0.9, Some(0.3), 2.0);
+        assert!((score(ConfidenceWeights::default()) - 1.7 / 3.0).abs() < 1e-12);
+        assert!((score(ConfidenceWeights::new(1.0, 0.0, 0.0).unwrap()) - 0.9).abs() < 1e-12);
+        // Weights are relative: 2:1:1 is (2 * 0.9 + 0.3 + 0.5) / 4
+        assert!((score(ConfidenceWeights::new(2.0, 1.0, 1.0).unwrap()) - 0.65).abs() < 1e-12);
+        assert!((score(ConfidenceWeights::new(0.0, 3.0, 1.0).unwrap()) - 0.35).abs() < 1e-12);
+
+        assert!(ConfidenceWeights::new(-1.0, 1.0, 1.0).is_err());
+        assert!(ConfidenceWeights::new(0.0, 0.0, 0.0).is_err());
+        assert!(ConfidenceWeights::new(f64::NAN, 1.0, 1.0).is_err());
+
+        // Without uniqueness only coverage and size are averaged: (0.9 + 0.5) / 2
+        assert!((ConfidenceWeights::default().score(0.9, None, 2.0) - 0.7).abs() < 1e-12);
+        assert!(ConfidenceWeights::new(0.0, 1.0, 0.0).unwrap().only_uniqueness());
+    }
+
+    #[test]
+    fn test_is_compatible_version() {
+        assert!(is_compatible_version(TOOL_VERSION));
+        let (major, rest) = TOOL_VERSION.split_once('.').unwrap();
+        let minor: u64 = rest.split('.').next().unwrap().parse().unwrap();
+        assert!(is_compatible_version(&format!("{}.{}.99", major, minor)));
+        assert!(!is_compatible_version(&format!("{}.{}.0", major.parse::<u64>().unwrap() + 1, minor)));
+        if major == "0" {
+            assert!(!is_compatible_version(&format!("0.{}.0", minor + 1)));
+        }
+        assert!(!is_compatible_version("unknown"));
+    }
+
+    #[test]
+    fn test_bootstrap_interval_percentiles() {
+        let interval = BootstrapInterval::from_replicates((0..=200).rev().map(|i| i as f64 / 200.0).collect());
+        assert!((interval.mean - 0.5).abs() < 1e-12);
+        assert_eq!(interval.low, 0.025);
+        assert_eq!(interval.high, 0.975);
+    }
+}